@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::snapshot::Snapshot;
+use crate::stats::{aggregate_key, QueryAggregates};
+
+/// Per-fingerprint p95 and average latencies from a saved TUI snapshot, to hold
+/// a new run against.
+pub struct Baseline {
+    queries: HashMap<String, BaselineQuery>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BaselineQuery {
+    avg_ms: f64,
+    /// None for snapshots saved before per-query p95 was kept: average only.
+    p95_ms: Option<f64>,
+}
+
+/// A fingerprint whose p95 or average latency grew past the tolerance.
+#[derive(Debug, PartialEq)]
+pub struct Regression {
+    pub fingerprint: String,
+    pub baseline_avg_ms: f64,
+    pub current_avg_ms: f64,
+    /// (baseline, current), when the baseline has a p95.
+    pub p95_ms: Option<(f64, f64)>,
+}
+
+impl Regression {
+    /// The larger of the p95 and average growth.
+    fn change_pct(&self) -> f64 {
+        let avg = growth_pct(self.baseline_avg_ms, self.current_avg_ms);
+        self.p95_ms.map_or(avg, |(baseline, current)| {
+            avg.max(growth_pct(baseline, current))
+        })
+    }
+}
+
+fn growth_pct(baseline_ms: f64, current_ms: f64) -> f64 {
+    if baseline_ms > 0.0 {
+        (current_ms / baseline_ms - 1.0) * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Outcome of `Baseline::compare`.
+#[derive(Debug)]
+pub struct Comparison {
+    /// Fingerprints seen in both runs.
+    pub compared: usize,
+    /// Of those, the ones the baseline has no p95 for.
+    pub average_only: usize,
+    /// Baseline fingerprints this run never executed.
+    pub missing: usize,
+    /// Worst first.
+    pub regressions: Vec<Regression>,
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let snapshot = Snapshot::load(path)?;
+        Ok(Self {
+            queries: snapshot
+                .top_queries
+                .into_iter()
+                .filter(|q| q.count > 0)
+                .map(|q| {
+                    (
+                        aggregate_key(&q.fingerprint, q.group.as_deref()),
+                        BaselineQuery {
+                            avg_ms: q.avg_ms,
+                            p95_ms: q.p95_ms,
+                        },
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Flag fingerprints whose p95 or average is more than `tolerance` (0.2 = 20%)
+    /// above the baseline's. A zero baseline value has no meaningful ratio and is
+    /// skipped.
+    pub fn compare(
+        &self,
+        fingerprints: &HashMap<String, QueryAggregates>,
+        tolerance: f64,
+    ) -> Comparison {
+        let exceeds = |baseline_ms: f64, current_ms: f64| {
+            baseline_ms > 0.0 && current_ms > baseline_ms * (1.0 + tolerance)
+        };
+        let mut compared = 0;
+        let mut average_only = 0;
+        let mut regressions = Vec::new();
+        for (fingerprint, baseline) in &self.queries {
+            let Some(current) = fingerprints.get(fingerprint).filter(|q| q.count > 0) else {
+                continue;
+            };
+            compared += 1;
+            let current_avg_ms =
+                current.total_duration.as_secs_f64() * 1000.0 / current.count as f64;
+            let current_p95_ms = current
+                .latencies
+                .percentile(0.95)
+                .map(|p95| p95.as_secs_f64() * 1000.0);
+            let p95_ms = match (baseline.p95_ms, current_p95_ms) {
+                (Some(baseline), Some(current)) => Some((baseline, current)),
+                _ => {
+                    average_only += 1;
+                    None
+                }
+            };
+            let p95_regressed =
+                p95_ms.is_some_and(|(baseline, current)| exceeds(baseline, current));
+            if p95_regressed || exceeds(baseline.avg_ms, current_avg_ms) {
+                regressions.push(Regression {
+                    fingerprint: fingerprint.clone(),
+                    baseline_avg_ms: baseline.avg_ms,
+                    current_avg_ms,
+                    p95_ms,
+                });
+            }
+        }
+        regressions.sort_by(|a, b| b.change_pct().total_cmp(&a.change_pct()));
+        Comparison {
+            compared,
+            average_only,
+            missing: self.queries.len() - compared,
+            regressions,
+        }
+    }
+}
+
+impl Comparison {
+    pub fn report(&self, tolerance: f64) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "--- baseline comparison (p95 and average latency, tolerance {:.0}%) ---",
+            tolerance * 100.0
+        );
+        let _ = write!(out, "Compared {} fingerprints", self.compared);
+        if self.missing > 0 {
+            let _ = write!(out, " ({} in the baseline were not run)", self.missing);
+        }
+        let _ = writeln!(out);
+        if self.average_only > 0 {
+            let _ = writeln!(
+                out,
+                "{} had no p95 in the baseline and were compared on average only",
+                self.average_only
+            );
+        }
+        if self.regressions.is_empty() {
+            let _ = writeln!(out, "No regressions.");
+        }
+        for r in &self.regressions {
+            let p95 = match r.p95_ms {
+                Some((baseline, current)) => {
+                    format!(
+                        "p95 {baseline:.2}ms -> {current:.2}ms ({:+.0}%), ",
+                        growth_pct(baseline, current)
+                    )
+                }
+                None => String::new(),
+            };
+            let _ = writeln!(
+                out,
+                "REGRESSED  {p95}avg {:.2}ms -> {:.2}ms ({:+.0}%)  {}",
+                r.baseline_avg_ms,
+                r.current_avg_ms,
+                growth_pct(r.baseline_avg_ms, r.current_avg_ms),
+                r.fingerprint
+            );
+        }
+        out
+    }
+}
+
+/// Parse `--tolerance`: `20%` or `20`, as a fraction (0.2).
+pub fn parse_tolerance(s: &str) -> Result<f64, String> {
+    let pct: f64 = s
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| format!("invalid tolerance {s:?}, expected a percentage like 20%"))?;
+    if !pct.is_finite() || pct < 0.0 {
+        return Err(format!(
+            "tolerance must be a non-negative percentage, got {s:?}"
+        ));
+    }
+    Ok(pct / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::percentile::LatencyHistogram;
+    use std::time::Duration;
+
+    fn aggregates(fingerprint: &str, latencies_ms: &[u64]) -> (String, QueryAggregates) {
+        let latencies: Vec<Duration> = latencies_ms
+            .iter()
+            .map(|&ms| Duration::from_millis(ms))
+            .collect();
+        let mut histogram = LatencyHistogram::default();
+        latencies
+            .iter()
+            .for_each(|&latency| histogram.record(latency));
+        (
+            fingerprint.to_string(),
+            QueryAggregates {
+                fingerprint: fingerprint.to_string(),
+                group: None,
+                count: latencies.len() as u64,
+                total_duration: latencies.iter().sum(),
+                min_duration: latencies.iter().copied().min().unwrap_or_default(),
+                max_duration: latencies.iter().copied().max().unwrap_or_default(),
+                columns: None,
+                result_format: None,
+                max_sql_bytes: 0,
+                latencies: histogram,
+            },
+        )
+    }
+
+    fn baseline(queries: &[(&str, f64, Option<f64>)]) -> Baseline {
+        Baseline {
+            queries: queries
+                .iter()
+                .map(|&(fp, avg_ms, p95_ms)| (fp.to_string(), BaselineQuery { avg_ms, p95_ms }))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_compare_flags_regressions_beyond_tolerance() {
+        let baseline = baseline(&[
+            ("select a", 10.0, None),
+            ("select b", 10.0, None),
+            ("select c", 10.0, None),
+            ("select gone", 1.0, None),
+        ]);
+        let current: HashMap<_, _> = [
+            aggregates("select a", &[11; 4]), // +10%: within tolerance
+            aggregates("select b", &[15; 2]), // +50%
+            aggregates("select c", &[30]),    // +200%
+            aggregates("select new", &[500]),
+        ]
+        .into_iter()
+        .collect();
+
+        let comparison = baseline.compare(&current, 0.2);
+        assert_eq!(comparison.compared, 3);
+        assert_eq!(comparison.average_only, 3);
+        assert_eq!(comparison.missing, 1);
+        let regressed: Vec<&str> = comparison
+            .regressions
+            .iter()
+            .map(|r| r.fingerprint.as_str())
+            .collect();
+        assert_eq!(regressed, ["select c", "select b"]);
+
+        let report = comparison.report(0.2);
+        assert!(report.contains("REGRESSED  avg 10.00ms -> 30.00ms (+200%)  select c"));
+        assert!(report.contains("(1 in the baseline were not run)"));
+        assert!(report.contains("3 had no p95 in the baseline and were compared on average only"));
+        assert!(baseline.compare(&current, 2.5).regressions.is_empty());
+    }
+
+    #[test]
+    fn test_compare_gates_on_p95() {
+        let baseline = baseline(&[
+            ("select tail", 10.0, Some(12.0)),
+            ("select steady", 10.0, Some(12.0)),
+        ]);
+        // The average holds (9.5ms), but 2 in 20 queries now take 50ms.
+        let mut tail = vec![5; 18];
+        tail.extend([50, 50]);
+        let current: HashMap<_, _> = [
+            aggregates("select tail", &tail),
+            aggregates("select steady", &[10; 20]),
+        ]
+        .into_iter()
+        .collect();
+
+        let comparison = baseline.compare(&current, 0.2);
+        assert_eq!(comparison.average_only, 0);
+        let [regression] = comparison.regressions.as_slice() else {
+            panic!("{:?}", comparison.regressions);
+        };
+        assert_eq!(regression.fingerprint, "select tail");
+        let (baseline_p95, current_p95) = regression.p95_ms.unwrap();
+        assert_eq!(baseline_p95, 12.0);
+        assert!((current_p95 - 50.0).abs() < 2.0, "{current_p95}");
+        assert!(comparison
+            .report(0.2)
+            .contains("REGRESSED  p95 12.00ms -> "));
+    }
+
+    #[test]
+    fn test_parse_tolerance() {
+        assert_eq!(parse_tolerance("20%"), Ok(0.2));
+        assert_eq!(parse_tolerance("5"), Ok(0.05));
+        assert!(parse_tolerance("-5%").is_err());
+        assert!(parse_tolerance("fast").is_err());
+    }
+}
@@ -0,0 +1,922 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use regex_automata::meta::Regex;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, warn};
+
+use crate::protocol::postgres::PostgresParser;
+use crate::protocol::{Direction, ProtoEvent, ProtocolParser};
+use crate::proxy::{consume_intercepted, drain_events, ProxyMessage};
+
+/// First bytes of every capture file.
+const MAGIC: &[u8; 8] = b"DBPCAP02";
+/// Captures written before frames carried a sequence number; still readable.
+const MAGIC_V1: &[u8; 8] = b"DBPCAP01";
+
+/// kind (1) + seq (8) + conn_id (8) + offset µs (8) + length (4).
+const FRAME_HEADER_LEN: usize = 29;
+const FRAME_HEADER_LEN_V1: usize = 21;
+
+/// Traffic a filtered capture holds back for a connection that hasn't matched
+/// yet. Past this it gives up on the connection rather than grow without bound.
+const MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+
+/// What a captured frame holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Bytes read from the client.
+    Frontend = 0,
+    /// Bytes read from the upstream.
+    Backend = 1,
+    /// A client connected; data is its address.
+    Opened = 2,
+    Closed = 3,
+}
+
+impl FrameKind {
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(Self::Frontend),
+            1 => Some(Self::Backend),
+            2 => Some(Self::Opened),
+            3 => Some(Self::Closed),
+            _ => None,
+        }
+    }
+}
+
+/// One record of a capture file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// Order in which the relays saw the bytes, across all connections and both
+    /// directions. Taken from one counter the moment a relay reads data, before it
+    /// forwards it, so anything a frame caused — the server's reply to a query —
+    /// always has a higher `seq`. Offsets come from separate clock reads and can
+    /// tie or, across threads, disagree slightly; `seq` is the authoritative order.
+    pub seq: u64,
+    pub conn_id: u64,
+    /// Time since the capture started.
+    pub offset: Duration,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.kind as u8);
+        out.extend_from_slice(&self.seq.to_be_bytes());
+        out.extend_from_slice(&self.conn_id.to_be_bytes());
+        out.extend_from_slice(&(self.offset.as_micros() as u64).to_be_bytes());
+        out.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.data);
+    }
+}
+
+/// Handle for recording traffic (`--record`). Cheap to clone; frames are
+/// written by a background task so the relays never wait on disk.
+#[derive(Clone)]
+pub struct Capture {
+    tx: mpsc::UnboundedSender<Frame>,
+    started: Instant,
+    /// Next frame's `seq`, shared by every relay.
+    seq: Arc<AtomicU64>,
+}
+
+impl Capture {
+    /// Create `path` and start the writer task. Only connections passing
+    /// `filter` reach the file. An existing file is never overwritten, and the
+    /// new one is readable by its owner only: captures hold SQL, bound values
+    /// and credentials as they crossed the wire.
+    pub async fn create(path: &Path, filter: CaptureFilter) -> anyhow::Result<Self> {
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options
+            .open(path)
+            .await
+            .map_err(|e| anyhow::anyhow!("creating capture {}: {e}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC).await?;
+        writer.flush().await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            if let Err(e) = write_frames(writer, rx, FrameFilter::new(filter)).await {
+                error!("Capture write error: {e}");
+            }
+        });
+        Ok(Self {
+            tx,
+            started: Instant::now(),
+            seq: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn record(&self, conn_id: u64, kind: FrameKind, data: &[u8]) {
+        let frame = Frame {
+            kind,
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            conn_id,
+            offset: self.started.elapsed(),
+            data: data.to_vec(),
+        };
+        let _ = self.tx.send(frame);
+    }
+}
+
+/// `--capture-conn` / `--capture-filter`: which connections a capture keeps.
+/// A connection is kept if it matches either; with neither set, all are.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureFilter {
+    pub conn_ids: Vec<u64>,
+    /// Matched against the client address and every query the connection runs.
+    pub pattern: Option<Regex>,
+}
+
+impl CaptureFilter {
+    fn is_empty(&self) -> bool {
+        self.conn_ids.is_empty() && self.pattern.is_none()
+    }
+
+    fn matches_text(&self, text: &str) -> bool {
+        self.pattern
+            .as_ref()
+            .is_some_and(|pattern| pattern.is_match(text))
+    }
+}
+
+/// Parse `--capture-filter`, so a bad pattern is a CLI error rather than a capture that never matches.
+pub fn parse_capture_filter(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| format!("invalid regex {s:?}: {e}"))
+}
+
+/// Where a connection stands with the filter.
+enum ConnFilter {
+    Recording,
+    Dropped,
+    /// Undecided: frames so far, and a parser to look for a matching query.
+    Pending {
+        frames: Vec<Frame>,
+        bytes: usize,
+        parser: Box<PostgresParser>,
+        frontend: BytesMut,
+    },
+}
+
+/// Applies a `CaptureFilter` in the writer task. Queries are only known once
+/// parsed, so a connection's frames are held back until it matches — then
+/// written from the start, handshake included, so the capture still replays —
+/// and discarded if it closes without matching.
+struct FrameFilter {
+    filter: CaptureFilter,
+    conns: HashMap<u64, ConnFilter>,
+}
+
+impl FrameFilter {
+    fn new(filter: CaptureFilter) -> Self {
+        Self {
+            filter,
+            conns: HashMap::new(),
+        }
+    }
+
+    /// Frames to write now: none, this one, or a matched connection's backlog.
+    fn admit(&mut self, frame: Frame) -> Vec<Frame> {
+        if self.filter.is_empty() {
+            return vec![frame];
+        }
+        let conn_id = frame.conn_id;
+        let closed = frame.kind == FrameKind::Closed;
+        let state = self.conns.entry(conn_id).or_insert_with(|| {
+            if self.filter.conn_ids.contains(&conn_id) {
+                ConnFilter::Recording
+            } else {
+                ConnFilter::Pending {
+                    frames: Vec::new(),
+                    bytes: 0,
+                    parser: Box::new(PostgresParser::new()),
+                    frontend: BytesMut::new(),
+                }
+            }
+        });
+
+        let admitted = match state {
+            ConnFilter::Recording => vec![frame],
+            ConnFilter::Dropped => Vec::new(),
+            ConnFilter::Pending {
+                frames,
+                bytes,
+                parser,
+                frontend,
+            } => {
+                let matched = match frame.kind {
+                    FrameKind::Opened => self
+                        .filter
+                        .matches_text(&String::from_utf8_lossy(&frame.data)),
+                    FrameKind::Frontend => {
+                        frontend.extend_from_slice(&frame.data);
+                        if parser
+                            .handle_startup_intercept(frontend, Direction::Frontend)
+                            .is_some()
+                        {
+                            consume_intercepted(frontend);
+                        }
+                        drain_events(parser.as_mut(), frontend, Direction::Frontend).iter().any(|event| {
+                            matches!(event, ProtoEvent::QueryStart { sql, .. } if self.filter.matches_text(sql))
+                        })
+                    }
+                    FrameKind::Backend | FrameKind::Closed => false,
+                };
+                *bytes += frame.data.len();
+                frames.push(frame);
+                if matched {
+                    let backlog = std::mem::take(frames);
+                    *state = ConnFilter::Recording;
+                    backlog
+                } else {
+                    if *bytes > MAX_PENDING_BYTES {
+                        warn!("Capture: connection {conn_id} sent {bytes} bytes without matching the filter; not recording it");
+                        *state = ConnFilter::Dropped;
+                    }
+                    Vec::new()
+                }
+            }
+        };
+        if closed {
+            self.conns.remove(&conn_id);
+        }
+        admitted
+    }
+}
+
+async fn write_frames(
+    mut writer: BufWriter<tokio::fs::File>,
+    mut rx: mpsc::UnboundedReceiver<Frame>,
+    mut filter: FrameFilter,
+) -> anyhow::Result<()> {
+    let mut encoded = Vec::new();
+    while let Some(frame) = rx.recv().await {
+        encoded.clear();
+        for frame in filter.admit(frame) {
+            frame.encode(&mut encoded);
+        }
+        writer.write_all(&encoded).await?;
+        // Flush whenever we catch up, so an interrupted run keeps what it saw.
+        if rx.is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read every frame of a capture file, in `seq` order. A frame cut short (the
+/// recording process was killed mid-write) ends the capture.
+pub fn read_capture(path: &Path) -> anyhow::Result<Vec<Frame>> {
+    let content = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("reading capture {}: {e}", path.display()))?;
+    let header_len = if content.starts_with(MAGIC) {
+        FRAME_HEADER_LEN
+    } else if content.starts_with(MAGIC_V1) {
+        FRAME_HEADER_LEN_V1
+    } else {
+        anyhow::bail!("{} is not a dbprobe capture", path.display());
+    };
+    let has_seq = header_len == FRAME_HEADER_LEN;
+
+    let mut frames = Vec::new();
+    let mut rest = &content[MAGIC.len()..];
+    while rest.len() >= header_len {
+        let kind = FrameKind::from_byte(rest[0])
+            .ok_or_else(|| anyhow::anyhow!("{}: unknown frame kind {}", path.display(), rest[0]))?;
+        let mut fields = rest[1..header_len - 4]
+            .chunks_exact(8)
+            .map(|field| u64::from_be_bytes(field.try_into().unwrap()));
+        // v1 frames have no seq; file order is the best there is.
+        let seq = if has_seq {
+            fields.next().unwrap()
+        } else {
+            frames.len() as u64
+        };
+        let (conn_id, micros) = (fields.next().unwrap(), fields.next().unwrap());
+        let len = u32::from_be_bytes(rest[header_len - 4..header_len].try_into().unwrap()) as usize;
+        let Some(data) = rest.get(header_len..header_len + len) else {
+            break;
+        };
+        frames.push(Frame {
+            kind,
+            seq,
+            conn_id,
+            offset: Duration::from_micros(micros),
+            data: data.to_vec(),
+        });
+        rest = &rest[header_len + len..];
+    }
+    if !rest.is_empty() {
+        warn!("{}: ignoring truncated final frame", path.display());
+    }
+    // The writer sees frames in channel order, which can trail `seq` order by a
+    // frame or two when both relays record at once.
+    frames.sort_by_key(|f| f.seq);
+    Ok(frames)
+}
+
+/// How a capture is played back.
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayOptions {
+    /// Shift each query's completion by a random amount up to this, either way.
+    pub jitter: Duration,
+    pub seed: u64,
+    /// `--replay-speed`: 2.0 plays twice as fast. Only the waits between frames
+    /// scale; latencies stay as recorded.
+    pub speed: f64,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            jitter: Duration::ZERO,
+            seed: 0,
+            speed: 1.0,
+        }
+    }
+}
+
+/// Parse `--replay-speed`: `2`, `2x` or `0.5x`.
+pub fn parse_replay_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s
+        .trim()
+        .trim_end_matches('x')
+        .parse()
+        .map_err(|_| format!("invalid replay speed {s:?}, expected a multiplier like 2x"))?;
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!("replay speed must be positive, got {s:?}"));
+    }
+    Ok(speed)
+}
+
+/// Pauses a replay from the TUI. While paused the recording's clock stands
+/// still, and `release` lets frames through one at a time for stepping.
+#[derive(Clone)]
+pub struct ReplayControl(Arc<watch::Sender<ReplayGate>>);
+
+#[derive(Clone, Copy, Debug, Default)]
+struct ReplayGate {
+    paused: bool,
+    /// Frames that may still play while paused.
+    released: usize,
+}
+
+impl Default for ReplayControl {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(ReplayGate::default())))
+    }
+}
+
+impl ReplayControl {
+    pub fn pause(&self) {
+        self.0.send_modify(|gate| gate.paused = true);
+    }
+
+    /// Play on at `--replay-speed` from where the pause left the recording.
+    pub fn resume(&self) {
+        self.0.send_modify(|gate| *gate = ReplayGate::default());
+    }
+
+    /// While paused, let the next `frames` frames play immediately.
+    pub fn release(&self, frames: usize) {
+        self.0.send_modify(|gate| gate.released += frames);
+    }
+
+    /// Paused, with every released frame played.
+    pub fn is_held(&self) -> bool {
+        let gate = *self.0.borrow();
+        gate.paused && gate.released == 0
+    }
+}
+
+/// When each frame is due: `offset` into the recording, scaled by the speed,
+/// with paused time left out.
+struct ReplayClock {
+    control: ReplayControl,
+    gate: watch::Receiver<ReplayGate>,
+    speed: f64,
+    /// The recording was at `base_offset` at `base`.
+    base: tokio::time::Instant,
+    base_offset: Duration,
+}
+
+impl ReplayClock {
+    fn new(control: ReplayControl, speed: f64) -> Self {
+        let gate = control.0.subscribe();
+        Self {
+            control,
+            gate,
+            speed,
+            base: tokio::time::Instant::now(),
+            base_offset: Duration::ZERO,
+        }
+    }
+
+    /// Wait until the frame at `offset` is due, or released while paused.
+    async fn until(&mut self, offset: Duration) {
+        loop {
+            if self.gate.borrow_and_update().paused {
+                // Stop the recording's clock where the pause caught it.
+                let now = tokio::time::Instant::now();
+                let reached =
+                    self.base_offset + now.saturating_duration_since(self.base).mul_f64(self.speed);
+                self.base_offset = reached.min(offset);
+                let Ok(gate) = self
+                    .gate
+                    .wait_for(|gate| !gate.paused || gate.released > 0)
+                    .await
+                    .map(|gate| *gate)
+                else {
+                    return;
+                };
+                self.base = tokio::time::Instant::now();
+                if gate.paused {
+                    self.control
+                        .0
+                        .send_modify(|gate| gate.released = gate.released.saturating_sub(1));
+                    self.base_offset = offset;
+                    return;
+                }
+                continue;
+            }
+            let due = self.base + offset.saturating_sub(self.base_offset).div_f64(self.speed);
+            tokio::select! {
+                _ = tokio::time::sleep_until(due) => return,
+                changed = self.gate.changed() => {
+                    if changed.is_err() {
+                        tokio::time::sleep_until(due).await;
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// splitmix64 — plenty for jitter, and reproducible from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform offset in `[-max, max]` microseconds.
+    fn jitter_micros(&mut self, max: Duration) -> i64 {
+        let max = max.as_micros() as u64;
+        if max == 0 {
+            return 0;
+        }
+        (self.next_u64() % (2 * max + 1)) as i64 - max as i64
+    }
+}
+
+/// Per-connection parsing state, as the relays keep it.
+struct ReplayConn {
+    parser: PostgresParser,
+    frontend: BytesMut,
+    backend: BytesMut,
+    /// Timestamp of the last event sent, so jitter never reorders a connection.
+    last_at: Option<Instant>,
+}
+
+impl ReplayConn {
+    fn new() -> Self {
+        Self {
+            parser: PostgresParser::new(),
+            frontend: BytesMut::new(),
+            backend: BytesMut::new(),
+            last_at: None,
+        }
+    }
+}
+
+/// Turns a capture's data frames back into protocol events, one parser per
+/// recorded connection.
+#[derive(Default)]
+pub struct CaptureDecoder {
+    conns: HashMap<u64, ReplayConn>,
+}
+
+impl CaptureDecoder {
+    /// Events parsed from a Frontend or Backend frame; none for Opened/Closed,
+    /// which only start and forget the connection's parser.
+    pub fn events(&mut self, frame: &Frame) -> Vec<ProtoEvent> {
+        let conn_id = frame.conn_id;
+        match frame.kind {
+            FrameKind::Opened => {
+                self.conns.insert(conn_id, ReplayConn::new());
+                Vec::new()
+            }
+            FrameKind::Closed => {
+                self.conns.remove(&conn_id);
+                Vec::new()
+            }
+            FrameKind::Frontend => {
+                let conn = self.conns.entry(conn_id).or_insert_with(ReplayConn::new);
+                conn.frontend.extend_from_slice(&frame.data);
+                if conn
+                    .parser
+                    .handle_startup_intercept(&conn.frontend, Direction::Frontend)
+                    .is_some()
+                {
+                    consume_intercepted(&mut conn.frontend);
+                }
+                drain_events(&mut conn.parser, &mut conn.frontend, Direction::Frontend)
+            }
+            FrameKind::Backend => {
+                let conn = self.conns.entry(conn_id).or_insert_with(ReplayConn::new);
+                conn.backend.extend_from_slice(&frame.data);
+                drain_events(&mut conn.parser, &mut conn.backend, Direction::Backend)
+            }
+        }
+    }
+}
+
+/// Play a capture into the event channel at its recorded pace (times
+/// `--replay-speed`), as if the traffic were flowing through the proxy now.
+/// `control` pauses and steps it.
+pub async fn replay(
+    frames: Vec<Frame>,
+    options: ReplayOptions,
+    control: ReplayControl,
+    tx: mpsc::UnboundedSender<ProxyMessage>,
+) {
+    let started = tokio::time::Instant::now();
+    let mut clock = ReplayClock::new(control, options.speed);
+    let mut rng = Rng(options.seed);
+    let mut decoder = CaptureDecoder::default();
+
+    for frame in frames {
+        clock.until(frame.offset).await;
+        let conn_id = frame.conn_id;
+        let recorded_at = started.into_std() + frame.offset;
+
+        let events = decoder.events(&frame);
+        match frame.kind {
+            FrameKind::Opened => {
+                let client_addr = String::from_utf8_lossy(&frame.data).parse::<SocketAddr>();
+                match client_addr {
+                    Ok(client_addr) => {
+                        let _ = tx.send(ProxyMessage::ConnectionOpened {
+                            conn_id,
+                            client_addr,
+                        });
+                    }
+                    Err(e) => warn!("Replay: bad client address for connection {conn_id}: {e}"),
+                }
+                continue;
+            }
+            FrameKind::Closed => {
+                let _ = tx.send(ProxyMessage::ConnectionClosed { conn_id });
+                continue;
+            }
+            FrameKind::Frontend | FrameKind::Backend => {}
+        }
+
+        let conn = decoder.conns.get_mut(&conn_id).expect("inserted above");
+        for event in events {
+            let mut at = recorded_at;
+            if matches!(
+                event,
+                ProtoEvent::QueryComplete { .. } | ProtoEvent::QueryError { .. }
+            ) {
+                let shift = rng.jitter_micros(options.jitter);
+                let magnitude = Duration::from_micros(shift.unsigned_abs());
+                at = if shift < 0 {
+                    at.checked_sub(magnitude).unwrap_or(at)
+                } else {
+                    at + magnitude
+                };
+            }
+            at = conn.last_at.map_or(at, |last| at.max(last));
+            conn.last_at = Some(at);
+            let _ = tx.send(ProxyMessage::Event { conn_id, event, at });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(tag: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![tag];
+        buf.extend_from_slice(&(payload.len() as u32 + 4).to_be_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    /// A connection running `SELECT 1` `count` times, each taking 20ms.
+    fn select_frames(count: u64) -> Vec<Frame> {
+        let mut seq = 0;
+        let mut frame = |kind, ms, data: Vec<u8>| {
+            seq += 1;
+            Frame {
+                kind,
+                seq,
+                conn_id: 1,
+                offset: Duration::from_millis(ms),
+                data,
+            }
+        };
+        let mut startup = 8u32.to_be_bytes().to_vec();
+        startup.extend_from_slice(&196_608u32.to_be_bytes());
+
+        let mut frames = vec![
+            frame(FrameKind::Opened, 0, b"127.0.0.1:40000".to_vec()),
+            frame(FrameKind::Frontend, 0, startup),
+        ];
+        for i in 0..count {
+            frames.push(frame(
+                FrameKind::Frontend,
+                1 + i * 30,
+                message(b'Q', b"SELECT 1\0"),
+            ));
+            let mut response = message(b'C', b"SELECT 1\0");
+            response.extend(message(b'Z', b"I"));
+            frames.push(frame(FrameKind::Backend, 21 + i * 30, response));
+        }
+        frames.push(frame(FrameKind::Closed, count * 30, Vec::new()));
+        frames
+    }
+
+    /// Durations of the queries a replay produces.
+    async fn replayed_durations(frames: Vec<Frame>, options: ReplayOptions) -> Vec<Duration> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        replay(frames, options, ReplayControl::default(), tx).await;
+
+        let mut started = None;
+        let mut durations = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                ProxyMessage::Event {
+                    event: ProtoEvent::QueryStart { .. },
+                    at,
+                    ..
+                } => started = Some(at),
+                ProxyMessage::Event {
+                    event: ProtoEvent::QueryComplete { .. },
+                    at,
+                    ..
+                } => {
+                    durations.push(at - started.take().unwrap());
+                }
+                _ => {}
+            }
+        }
+        durations
+    }
+
+    #[tokio::test]
+    async fn test_paused_replay_plays_released_frames_only() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let control = ReplayControl::default();
+        control.pause();
+        let replay = tokio::spawn(replay(
+            select_frames(2),
+            ReplayOptions::default(),
+            control.clone(),
+            tx,
+        ));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+        control.release(1);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ProxyMessage::ConnectionOpened { conn_id: 1, .. })
+        ));
+        assert!(rx.try_recv().is_err());
+        assert!(control.is_held());
+
+        control.resume();
+        tokio::time::timeout(Duration::from_secs(5), replay)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            std::iter::from_fn(|| rx.try_recv().ok()).last(),
+            Some(ProxyMessage::ConnectionClosed { conn_id: 1 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay_speed_keeps_recorded_latencies() {
+        let started = Instant::now();
+        let options = ReplayOptions {
+            speed: 10.0,
+            ..Default::default()
+        };
+        let durations = replayed_durations(select_frames(10), options).await;
+        // 300ms of traffic in about 30ms, each query still taking 20ms.
+        assert!(
+            started.elapsed() < Duration::from_millis(200),
+            "{:?}",
+            started.elapsed()
+        );
+        assert_eq!(durations, vec![Duration::from_millis(20); 10]);
+    }
+
+    #[test]
+    fn test_parse_replay_speed() {
+        assert_eq!(parse_replay_speed("2x"), Ok(2.0));
+        assert_eq!(parse_replay_speed("0.5"), Ok(0.5));
+        assert!(parse_replay_speed("0").is_err());
+        assert!(parse_replay_speed("fast").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_capture_round_trip() {
+        let path = std::env::temp_dir().join(format!("dbprobe-capture-{}.dbp", std::process::id()));
+        let capture = Capture::create(&path, CaptureFilter::default())
+            .await
+            .unwrap();
+        capture.record(3, FrameKind::Opened, b"10.0.0.1:5000");
+        capture.record(3, FrameKind::Frontend, &message(b'Q', b"SELECT 1\0"));
+        capture.record(3, FrameKind::Closed, &[]);
+        drop(capture);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let frames = read_capture(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        // An existing capture is not overwritten.
+        assert!(Capture::create(&path, CaptureFilter::default())
+            .await
+            .is_err());
+        std::fs::remove_file(&path).ok();
+        let kinds: Vec<_> = frames.iter().map(|f| f.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![FrameKind::Opened, FrameKind::Frontend, FrameKind::Closed]
+        );
+        assert_eq!(frames[0].data, b"10.0.0.1:5000");
+        assert!(frames.iter().all(|f| f.conn_id == 3));
+        assert!(frames.windows(2).all(|w| w[0].offset <= w[1].offset));
+        assert_eq!(
+            frames.iter().map(|f| f.seq).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_capture_filter_keeps_matching_connections_whole() {
+        // Connection 1 runs SELECT 1 from 127.0.0.1; 2 and 3 are copies from elsewhere.
+        let conn = |conn_id, addr: &'static [u8]| {
+            select_frames(2).into_iter().map(move |mut f| {
+                f.conn_id = conn_id;
+                if f.kind == FrameKind::Opened {
+                    f.data = addr.to_vec();
+                }
+                f
+            })
+        };
+        let frames: Vec<Frame> = conn(1, b"127.0.0.1:40000")
+            .chain(conn(2, b"10.0.0.2:40000"))
+            .chain(conn(3, b"10.0.0.3:40000"))
+            .collect();
+        let kept = |filter: CaptureFilter| {
+            let mut filter = FrameFilter::new(filter);
+            let mut kept: Vec<(u64, u64)> = Vec::new();
+            for frame in frames.clone() {
+                kept.extend(filter.admit(frame).iter().map(|f| (f.conn_id, f.seq)));
+            }
+            kept
+        };
+        let whole = |conn_id| {
+            frames
+                .iter()
+                .filter(|f| f.conn_id == conn_id)
+                .map(|f| (conn_id, f.seq))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kept(CaptureFilter::default()).len(), frames.len());
+        assert_eq!(
+            kept(CaptureFilter {
+                conn_ids: vec![2],
+                pattern: None
+            }),
+            whole(2)
+        );
+        let pattern = |p: &str| Some(parse_capture_filter(p).unwrap());
+        assert_eq!(
+            kept(CaptureFilter {
+                conn_ids: vec![],
+                pattern: pattern(r"^10\.0\.0\.3:")
+            }),
+            whole(3)
+        );
+        // Matched on the first query: the held-back handshake comes first, in order.
+        let by_query = kept(CaptureFilter {
+            conn_ids: vec![],
+            pattern: pattern("(?i)^select"),
+        });
+        assert_eq!(by_query, [whole(1), whole(2), whole(3)].concat());
+        assert!(kept(CaptureFilter {
+            conn_ids: vec![],
+            pattern: pattern("UPDATE|DELETE")
+        })
+        .is_empty());
+        assert_eq!(
+            kept(CaptureFilter {
+                conn_ids: vec![1],
+                pattern: pattern("10.0.0.3")
+            }),
+            [whole(1), whole(3)].concat()
+        );
+        assert!(parse_capture_filter("FROM (orders").is_err());
+    }
+
+    #[test]
+    fn test_read_capture_orders_by_seq_and_reads_v1() {
+        let path =
+            std::env::temp_dir().join(format!("dbprobe-capture-seq-{}.dbp", std::process::id()));
+        // The writer received the reply before the query it answers.
+        let frame = |kind, seq, data: &[u8]| Frame {
+            kind,
+            seq,
+            conn_id: 1,
+            offset: Duration::ZERO,
+            data: data.to_vec(),
+        };
+        let mut content = MAGIC.to_vec();
+        frame(FrameKind::Backend, 1, b"reply").encode(&mut content);
+        frame(FrameKind::Frontend, 0, b"query").encode(&mut content);
+        std::fs::write(&path, &content).unwrap();
+        let frames = read_capture(&path).unwrap();
+        assert_eq!(
+            frames.iter().map(|f| f.data.as_slice()).collect::<Vec<_>>(),
+            [b"query", b"reply"]
+        );
+
+        // v1 frames: kind, conn_id, offset, length, data.
+        let mut content = MAGIC_V1.to_vec();
+        for data in [b"first", b"later"] {
+            content.push(FrameKind::Frontend as u8);
+            content.extend_from_slice(&7u64.to_be_bytes());
+            content.extend_from_slice(&1500u64.to_be_bytes());
+            content.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            content.extend_from_slice(data);
+        }
+        std::fs::write(&path, &content).unwrap();
+        let frames = read_capture(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (frames[1].seq, frames[1].conn_id, frames[1].offset),
+            (1, 7, Duration::from_micros(1500))
+        );
+        assert_eq!(frames[1].data, b"later");
+    }
+
+    #[tokio::test]
+    async fn test_replay_reproduces_recorded_latency() {
+        let durations = replayed_durations(select_frames(2), ReplayOptions::default()).await;
+        assert_eq!(durations, vec![Duration::from_millis(20); 2]);
+    }
+
+    #[tokio::test]
+    async fn test_replay_jitter_is_bounded_and_seeded() {
+        let options = ReplayOptions {
+            jitter: Duration::from_millis(5),
+            seed: 42,
+            ..Default::default()
+        };
+        let first = replayed_durations(select_frames(10), options).await;
+        let again = replayed_durations(select_frames(10), options).await;
+        let other =
+            replayed_durations(select_frames(10), ReplayOptions { seed: 7, ..options }).await;
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert!(first.iter().all(|d| (15..=25).contains(&d.as_millis())));
+        assert!(first.iter().any(|d| *d != Duration::from_millis(20)));
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::proxy::ProxyMessage;
+
+/// Settings that can change while dbprobe runs (via `--config` + SIGHUP).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiveConfig {
+    pub threshold_ms: u64,
+    pub bell_threshold_ms: Option<u64>,
+}
+
+/// Read by the output sinks on every event; written on reload.
+pub type SharedConfig = Arc<RwLock<LiveConfig>>;
+
+/// Contents of the `--config` TOML file. Every key is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub threshold_ms: Option<u64>,
+    pub bell_threshold_ms: Option<u64>,
+    /// Only read at startup — the listen socket can't be moved live.
+    pub listen: Option<u16>,
+    /// Only read at startup — existing connections keep their upstream.
+    pub upstream: Option<String>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading config {}: {e}", path.display()))?;
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("parsing config {}: {e}", path.display()))
+    }
+}
+
+impl LiveConfig {
+    /// Apply the reloadable keys present in `file`; returns a description of each change.
+    pub fn apply(&mut self, file: &ConfigFile) -> Vec<String> {
+        let mut changes = Vec::new();
+        if let Some(ms) = file.threshold_ms.filter(|&ms| ms != self.threshold_ms) {
+            changes.push(format!("threshold {}ms → {ms}ms", self.threshold_ms));
+            self.threshold_ms = ms;
+        }
+        if file.bell_threshold_ms.is_some() && file.bell_threshold_ms != self.bell_threshold_ms {
+            let old = self
+                .bell_threshold_ms
+                .map_or("off".to_string(), |ms| format!("{ms}ms"));
+            let new = file
+                .bell_threshold_ms
+                .map_or("off".to_string(), |ms| format!("{ms}ms"));
+            changes.push(format!("bell threshold {old} → {new}"));
+            self.bell_threshold_ms = file.bell_threshold_ms;
+        }
+        changes
+    }
+}
+
+/// Re-read `path` on every SIGHUP and apply the reloadable subset. Outcomes are
+/// reported as warnings on the event stream so they show up in either output mode.
+#[cfg(unix)]
+pub async fn reload_on_sighup(
+    path: PathBuf,
+    startup: ConfigFile,
+    config: SharedConfig,
+    tx: mpsc::UnboundedSender<ProxyMessage>,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        let message = match ConfigFile::load(&path) {
+            Ok(file) => {
+                let mut notes = config.write().unwrap().apply(&file);
+                if file.listen != startup.listen {
+                    notes.push("listen port change ignored until restart".into());
+                }
+                if file.upstream != startup.upstream {
+                    notes.push("upstream change ignored until restart".into());
+                }
+                if notes.is_empty() {
+                    format!("Reloaded {}: no changes", path.display())
+                } else {
+                    format!("Reloaded {}: {}", path.display(), notes.join(", "))
+                }
+            }
+            Err(e) => format!("Config reload failed, keeping current settings: {e}"),
+        };
+        info!("{message}");
+        let _ = tx.send(ProxyMessage::Warning {
+            conn_id: 0,
+            message,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_reports_only_changes() {
+        let mut live = LiveConfig {
+            threshold_ms: 100,
+            bell_threshold_ms: None,
+        };
+        let file: ConfigFile =
+            toml::from_str("threshold_ms = 50\nbell_threshold_ms = 2000\nlisten = 6000").unwrap();
+
+        let changes = live.apply(&file);
+        assert_eq!(
+            changes,
+            vec!["threshold 100ms → 50ms", "bell threshold off → 2000ms"]
+        );
+        assert_eq!(
+            live,
+            LiveConfig {
+                threshold_ms: 50,
+                bell_threshold_ms: Some(2000)
+            }
+        );
+
+        // Same file again, and keys left out, change nothing.
+        assert!(live.apply(&file).is_empty());
+        assert!(live.apply(&ConfigFile::default()).is_empty());
+        assert!(toml::from_str::<ConfigFile>("treshold_ms = 5").is_err());
+    }
+}
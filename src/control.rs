@@ -0,0 +1,581 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+use crate::output::{round_ms, DisplayEvent, DisplayEventKind};
+use crate::proxy::{set_upstream, SharedUpstream};
+
+/// How many recent events the control API can serve.
+pub const EVENT_LOG_CAPACITY: usize = 1000;
+
+/// Default `limit` for `GET /events`.
+const DEFAULT_EVENT_LIMIT: usize = 100;
+
+/// Requests larger than this are rejected — we only serve small GETs.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Bounded ring of the most recent display events, shared between the
+/// display loop (writer) and the control API (reader).
+#[derive(Clone)]
+pub struct EventLog {
+    inner: Arc<Mutex<VecDeque<DisplayEvent>>>,
+    capacity: usize,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, event: &DisplayEvent) {
+        let mut events = self.inner.lock().unwrap();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+    }
+
+    /// Most recent events matching `query`, oldest first.
+    fn recent(&self, query: &EventQuery) -> Vec<DisplayEvent> {
+        let events = self.inner.lock().unwrap();
+        let mut matched: Vec<DisplayEvent> = events
+            .iter()
+            .rev()
+            .filter(|e| query.matches(e))
+            .take(query.limit)
+            .cloned()
+            .collect();
+        matched.reverse();
+        matched
+    }
+}
+
+/// Set once the proxy is accepting client connections; served as `GET /healthz`.
+pub type Readiness = Arc<AtomicBool>;
+
+/// Everything request handlers can read.
+#[derive(Clone)]
+pub struct ControlState {
+    pub log: EventLog,
+    /// Decimal places for millisecond values in responses.
+    pub precision: u32,
+    pub ready: Readiness,
+    /// Served by `GET /upstream`, repointed by `POST /upstream?addr=host:port`.
+    pub upstream: SharedUpstream,
+    /// `--control-allow-write`: without it, `POST /upstream` is refused.
+    pub allow_write: bool,
+}
+
+/// Query parameters for `GET /events`.
+#[derive(Debug, PartialEq)]
+struct EventQuery {
+    limit: usize,
+    conn: Option<u64>,
+    /// Only events with a duration of at least this many milliseconds.
+    min_ms: Option<u64>,
+}
+
+impl EventQuery {
+    fn parse(query: &str) -> Result<Self, String> {
+        let mut parsed = Self {
+            limit: DEFAULT_EVENT_LIMIT,
+            conn: None,
+            min_ms: None,
+        };
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid value for {key}: {value:?}"))
+            };
+            match key {
+                "limit" => parsed.limit = number()? as usize,
+                "conn" => parsed.conn = Some(number()?),
+                "min_ms" => parsed.min_ms = Some(number()?),
+                _ => return Err(format!("unknown parameter: {key}")),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn matches(&self, event: &DisplayEvent) -> bool {
+        if self.conn.is_some_and(|c| c != event.conn_id) {
+            return false;
+        }
+        match self.min_ms {
+            Some(min_ms) => event_duration(event).is_some_and(|d| d.as_millis() >= min_ms as u128),
+            None => true,
+        }
+    }
+}
+
+fn event_duration(event: &DisplayEvent) -> Option<std::time::Duration> {
+    match &event.kind {
+        DisplayEventKind::Query { duration, .. } => Some(*duration),
+        DisplayEventKind::Error { duration, .. } => *duration,
+        DisplayEventKind::SlowTransaction(timing) => Some(timing.total),
+        _ => None,
+    }
+}
+
+fn event_to_json(event: &DisplayEvent, precision: u32) -> Value {
+    let ms = |d: &std::time::Duration| round_ms(*d, precision);
+    let mut value = json!({
+        "time": event.wall_time.to_rfc3339(),
+        "conn_id": event.conn_id,
+        "client_addr": event.client_addr.map(|a| a.to_string()),
+    });
+    let fields = match &event.kind {
+        DisplayEventKind::Query {
+            sql,
+            operation,
+            duration,
+            rows,
+            seq,
+            trace_id,
+            result_bytes,
+            ..
+        } => json!({
+            "kind": "query",
+            "sql": sql,
+            "operation": operation,
+            "duration_ms": ms(duration),
+            "rows": rows,
+            "result_bytes": result_bytes,
+            "seq": seq,
+            "trace_id": trace_id,
+        }),
+        DisplayEventKind::Error {
+            sql,
+            duration,
+            code,
+            message,
+        } => json!({
+            "kind": "error",
+            "sql": sql,
+            "duration_ms": duration.as_ref().map(ms),
+            "code": code,
+            "message": message,
+        }),
+        DisplayEventKind::Copy {
+            bytes,
+            elapsed,
+            done,
+        } => json!({
+            "kind": "copy",
+            "bytes": bytes,
+            "elapsed_ms": ms(elapsed),
+            "done": done,
+        }),
+        DisplayEventKind::SlowTransaction(timing) => json!({
+            "kind": "slow_transaction",
+            "statements": timing.statements,
+            "duration_ms": ms(&timing.total),
+            "busy_ms": ms(&timing.busy),
+            "idle_ms": ms(&timing.idle()),
+            "max_gap_ms": ms(&timing.max_gap),
+        }),
+        DisplayEventKind::ConnectionOpened => json!({ "kind": "connection_opened" }),
+        DisplayEventKind::ConnectionClosed => json!({ "kind": "connection_closed" }),
+        DisplayEventKind::ClientCertificate { subject } => {
+            json!({ "kind": "client_certificate", "subject": subject })
+        }
+        DisplayEventKind::Notification { channel, payload } => json!({
+            "kind": "notification",
+            "channel": channel,
+            "payload": payload,
+        }),
+        DisplayEventKind::Warning(message) => json!({ "kind": "warning", "message": message }),
+    };
+    if let (Value::Object(map), Value::Object(extra)) = (&mut value, fields) {
+        map.extend(extra);
+    }
+    value
+}
+
+/// Serve the control API on localhost. Runs until the process exits.
+pub async fn run_control_api(port: u16, state: ControlState) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Control API listening on 127.0.0.1:{port}");
+    serve(listener, state).await
+}
+
+async fn serve(listener: TcpListener, state: ControlState) -> anyhow::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &state).await {
+                debug!("Control API request from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+/// The parts of a request's head the endpoints look at.
+pub(crate) struct RequestHead {
+    pub method: String,
+    pub target: String,
+    origin: Option<String>,
+    host: Option<String>,
+}
+
+impl RequestHead {
+    /// Browsers send an Origin with cross-site requests (fetch, form POSTs), and a
+    /// DNS-rebound page reaches us under its own Host. Neither is a local tool.
+    fn is_foreign(&self) -> bool {
+        let loopback_host = |host: &str| {
+            let name = match host.strip_prefix('[') {
+                Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+                None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+            };
+            name.eq_ignore_ascii_case("localhost")
+                || name
+                    .parse::<std::net::IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback())
+        };
+        self.origin.is_some()
+            || self
+                .host
+                .as_deref()
+                .is_some_and(|host| !loopback_host(host))
+    }
+}
+
+/// Read a request's head, or None when it runs past `MAX_REQUEST_BYTES`.
+/// Shared with the metrics endpoint.
+pub(crate) async fn read_request_line(
+    stream: &mut TcpStream,
+) -> anyhow::Result<Option<RequestHead>> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+    }
+
+    let request = String::from_utf8_lossy(&buf);
+    let mut lines = request.lines();
+    let mut parts = lines.next().unwrap_or_default().split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let mut head = RequestHead {
+        method: method.to_string(),
+        target: target.to_string(),
+        origin: None,
+        host: None,
+    };
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = Some(value.trim().to_string());
+        if name.eq_ignore_ascii_case("origin") {
+            head.origin = value;
+        } else if name.eq_ignore_ascii_case("host") {
+            head.host = value;
+        }
+    }
+    Ok(Some(head))
+}
+
+/// Minimal HTTP/1.x handling: one request per connection, response then close.
+async fn handle_request(mut stream: TcpStream, state: &ControlState) -> anyhow::Result<()> {
+    let Some(head) = read_request_line(&mut stream).await? else {
+        return write_response(&mut stream, 413, &json!({ "error": "request too large" })).await;
+    };
+    // Events hold SQL and the upstream setter redirects logins: local tools only.
+    if head.is_foreign() {
+        return write_response(
+            &mut stream,
+            403,
+            &json!({ "error": "requests with an Origin or a non-loopback Host are refused" }),
+        )
+        .await;
+    }
+    let (path, query) = head.target.split_once('?').unwrap_or((&head.target, ""));
+
+    let (status, body) = match (head.method.as_str(), path) {
+        ("GET", "/events") => match EventQuery::parse(query) {
+            Ok(query) => {
+                let events: Vec<Value> = state
+                    .log
+                    .recent(&query)
+                    .iter()
+                    .map(|e| event_to_json(e, state.precision))
+                    .collect();
+                (200, Value::Array(events))
+            }
+            Err(e) => (400, json!({ "error": e })),
+        },
+        // Readiness probe: 503 until the proxy is listening (e.g. while waiting on the upstream).
+        ("GET", "/healthz") if state.ready.load(Ordering::Relaxed) => {
+            (200, json!({ "status": "ready" }))
+        }
+        ("GET", "/healthz") => (503, json!({ "status": "starting" })),
+        ("GET", "/upstream") => (200, json!({ "upstream": *state.upstream.read().unwrap() })),
+        ("POST", "/upstream") if !state.allow_write => (
+            403,
+            json!({ "error": "changing the upstream needs --control-allow-write" }),
+        ),
+        ("POST", "/upstream") => {
+            let addr = query.split('&').find_map(|pair| pair.strip_prefix("addr="));
+            match addr
+                .ok_or_else(|| "missing parameter: addr".to_string())
+                .and_then(|addr| set_upstream(&state.upstream, addr))
+            {
+                Ok(previous) => (
+                    200,
+                    json!({ "upstream": *state.upstream.read().unwrap(), "previous": previous }),
+                ),
+                Err(e) => (400, json!({ "error": e })),
+            }
+        }
+        (_, "/events" | "/healthz" | "/upstream") => {
+            (405, json!({ "error": "method not allowed" }))
+        }
+        _ => (404, json!({ "error": "not found" })),
+    };
+    write_response(&mut stream, status, &body).await
+}
+
+async fn write_response(stream: &mut TcpStream, status: u16, body: &Value) -> anyhow::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Payload Too Large",
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::Operation;
+    use crate::output::DEFAULT_PRECISION;
+    use std::time::Duration;
+
+    fn query_event(conn_id: u64, ms: u64) -> DisplayEvent {
+        DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id,
+            client_addr: None,
+            kind: DisplayEventKind::Query {
+                sql: format!("SELECT {ms}"),
+                operation: Operation::Select,
+                duration: Duration::from_millis(ms),
+                rows: Some(1),
+                seq: 1,
+                trace_id: None,
+                params: Vec::new(),
+                result_bytes: 0,
+                large_result: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_event_log_is_bounded() {
+        let log = EventLog::new(3);
+        for ms in 0..5 {
+            log.push(&query_event(1, ms));
+        }
+        let all = log.recent(&EventQuery::parse("").unwrap());
+        let durations: Vec<_> = all
+            .iter()
+            .filter_map(event_duration)
+            .map(|d| d.as_millis())
+            .collect();
+        assert_eq!(durations, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_event_query_filters() {
+        let log = EventLog::new(10);
+        log.push(&query_event(1, 10));
+        log.push(&query_event(2, 80));
+        log.push(&query_event(7, 5));
+        log.push(&query_event(7, 60));
+        log.push(&query_event(7, 90));
+
+        let conn7 = log.recent(&EventQuery::parse("conn=7").unwrap());
+        assert_eq!(conn7.len(), 3);
+
+        let slow = log.recent(&EventQuery::parse("min_ms=50").unwrap());
+        assert_eq!(slow.len(), 3);
+
+        let both = log.recent(&EventQuery::parse("conn=7&min_ms=50&limit=1").unwrap());
+        assert_eq!(both.len(), 1);
+        assert_eq!(event_duration(&both[0]), Some(Duration::from_millis(90)));
+
+        assert!(EventQuery::parse("limit=abc").is_err());
+        assert!(EventQuery::parse("bogus=1").is_err());
+    }
+
+    fn state(log: EventLog) -> ControlState {
+        ControlState {
+            log,
+            precision: DEFAULT_PRECISION,
+            ready: Readiness::default(),
+            upstream: Arc::new(std::sync::RwLock::new("localhost:5432".into())),
+            allow_write: true,
+        }
+    }
+
+    async fn get(addr: std::net::SocketAddr, target: &str) -> String {
+        request(addr, "GET", target).await
+    }
+
+    async fn request(addr: std::net::SocketAddr, method: &str, target: &str) -> String {
+        request_with_headers(addr, method, target, "Host: localhost\r\n").await
+    }
+
+    async fn request_with_headers(
+        addr: std::net::SocketAddr,
+        method: &str,
+        target: &str,
+        headers: &str,
+    ) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("{method} {target} HTTP/1.1\r\n{headers}\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_healthz_reports_readiness() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = state(EventLog::new(1));
+        let ready = state.ready.clone();
+        tokio::spawn(serve(listener, state));
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 503"));
+        ready.store(true, Ordering::Relaxed);
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn test_get_events_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let log = EventLog::new(10);
+        log.push(&query_event(7, 120));
+        tokio::spawn(serve(listener, state(log)));
+
+        let response = get(addr, "/events?limit=100&conn=7").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let events: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(events[0]["kind"], "query");
+        assert_eq!(events[0]["conn_id"], 7);
+        assert_eq!(events[0]["sql"], "SELECT 120");
+        assert_eq!(events[0]["operation"], "SELECT");
+    }
+
+    #[tokio::test]
+    async fn test_upstream_can_be_changed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = state(EventLog::new(1));
+        let upstream = state.upstream.clone();
+        tokio::spawn(serve(listener, state));
+
+        assert!(get(addr, "/upstream")
+            .await
+            .ends_with(r#"{"upstream":"localhost:5432"}"#));
+        let response = request(addr, "POST", "/upstream?addr=replica:5432").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains(r#""previous":"localhost:5432""#));
+        assert_eq!(*upstream.read().unwrap(), "replica:5432");
+
+        assert!(request(addr, "POST", "/upstream?addr=replica")
+            .await
+            .starts_with("HTTP/1.1 400"));
+        assert!(request(addr, "POST", "/upstream")
+            .await
+            .starts_with("HTTP/1.1 400"));
+        assert_eq!(*upstream.read().unwrap(), "replica:5432");
+    }
+
+    #[tokio::test]
+    async fn test_upstream_change_is_refused_from_browsers_and_by_default() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let writable = state(EventLog::new(1));
+        let upstream = writable.upstream.clone();
+        tokio::spawn(serve(listener, writable));
+
+        // A page's no-cors fetch carries its Origin; a DNS-rebound one, its own Host.
+        for headers in [
+            "Host: 127.0.0.1:9000\r\nOrigin: https://evil.example\r\n",
+            "Host: evil.example:9000\r\n",
+        ] {
+            let response =
+                request_with_headers(addr, "POST", "/upstream?addr=evil:5432", headers).await;
+            assert!(
+                response.starts_with("HTTP/1.1 403"),
+                "{headers:?}: {response}"
+            );
+            assert!(request_with_headers(addr, "GET", "/events", headers)
+                .await
+                .starts_with("HTTP/1.1 403"));
+        }
+        for headers in ["Host: [::1]:9000\r\n", "Host: 127.0.0.1\r\n", ""] {
+            assert!(
+                request_with_headers(addr, "GET", "/upstream", headers)
+                    .await
+                    .starts_with("HTTP/1.1 200"),
+                "{headers:?}"
+            );
+        }
+        assert_eq!(*upstream.read().unwrap(), "localhost:5432");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            ControlState {
+                allow_write: false,
+                ..state(EventLog::new(1))
+            },
+        ));
+        assert!(request(addr, "POST", "/upstream?addr=replica:5432")
+            .await
+            .starts_with("HTTP/1.1 403"));
+        assert!(get(addr, "/upstream")
+            .await
+            .ends_with(r#"{"upstream":"localhost:5432"}"#));
+    }
+}
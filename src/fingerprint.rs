@@ -1,9 +1,41 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Context;
+use serde::Serialize;
+
+/// Set once at startup from `--normalize-schema`.
+static NORMALIZE_SCHEMA: AtomicBool = AtomicBool::new(false);
+
+/// Make `fingerprint` strip schema qualifiers, so `tenant_1.orders` and
+/// `tenant_2.orders` share a fingerprint.
+pub fn set_normalize_schema(enabled: bool) {
+    NORMALIZE_SCHEMA.store(enabled, Ordering::Relaxed);
+}
+
+/// `--allowed-fingerprints`: one fingerprint or example query per line, blank
+/// lines and `#` comments skipped. Every line is fingerprinted, so both forms
+/// match, and `--normalize-schema` must already be set.
+pub fn read_allowlist(path: &Path) -> anyhow::Result<HashSet<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("reading allowed fingerprints {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(fingerprint)
+        .collect())
+}
+
 /// Normalize SQL into a fingerprint by replacing literals with placeholders.
 ///
 /// - String literals 'foo' → $S
-/// - Numeric literals → $N
+/// - Numeric literals → $N (including 1.5e-10, .5, 0x1F, and a sign in value position: -5)
 /// - IN (...) lists → IN ($...)
+/// - ARRAY[...] / ROW(...) of literals → ARRAY[$...] / ROW($...)
 /// - Lowercases SQL keywords (rough heuristic: lowercases everything)
+/// - With `--normalize-schema`, drops schema qualifiers (see `strip_schema_qualifiers`)
 pub fn fingerprint(sql: &str) -> String {
     let mut result = String::with_capacity(sql.len());
     let bytes = sql.as_bytes();
@@ -32,7 +64,11 @@ pub fn fingerprint(sql: &str) -> String {
                 }
             }
             // Dollar-quoted string $tag$...$tag$
-            b'$' if i + 1 < len && (bytes[i + 1] == b'$' || bytes[i + 1].is_ascii_alphabetic() || bytes[i + 1] == b'_') => {
+            b'$' if i + 1 < len
+                && (bytes[i + 1] == b'$'
+                    || bytes[i + 1].is_ascii_alphabetic()
+                    || bytes[i + 1] == b'_') =>
+            {
                 // Check if this is a dollar-quoted string or a parameter placeholder
                 if let Some(tag_end) = find_dollar_tag_end(bytes, i) {
                     let tag = &sql[i..=tag_end];
@@ -52,31 +88,516 @@ pub fn fingerprint(sql: &str) -> String {
                     i += 1;
                 }
             }
-            // Numeric literal
-            b'0'..=b'9' => {
-                // Check if preceded by an identifier char (part of a name, not a number)
-                let prev_is_ident = i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_');
-                if prev_is_ident {
-                    result.push(bytes[i] as char);
+            // Numeric literal, possibly signed. Preceded by an identifier char, a digit
+            // is part of a name; after a value, a sign is an operator (`a - 5`).
+            b'0'..=b'9' | b'.' | b'-' | b'+'
+                if !(i > 0 && is_ident_byte(bytes[i - 1]))
+                    && number_end(bytes, i + usize::from(matches!(bytes[i], b'-' | b'+')))
+                        .is_some()
+                    && (bytes[i].is_ascii_digit()
+                        || bytes[i] == b'.'
+                        || sign_in_value_position(bytes, i)) =>
+            {
+                let start = i + usize::from(matches!(bytes[i], b'-' | b'+'));
+                result.push_str("$N");
+                i = number_end(bytes, start).unwrap_or(start);
+            }
+            // Everything else
+            ch => {
+                result.push(ch as char);
+                i += 1;
+            }
+        }
+    }
+
+    // Normalize IN ($N, $N, ...) → IN ($...), and likewise ARRAY[...] / ROW(...) literals
+    let result = collapse_literal_lists(&result, "IN", b'(', b')', " ($...)");
+    let result = collapse_literal_lists(&result, "ARRAY", b'[', b']', "[$...]");
+    let result = collapse_literal_lists(&result, "ROW", b'(', b')', "($...)").to_lowercase();
+    if NORMALIZE_SCHEMA.load(Ordering::Relaxed) {
+        strip_schema_qualifiers(&result)
+    } else {
+        result
+    }
+}
+
+/// Normalize a server error message so variants of the same error group together.
+///
+/// - Double-quoted identifiers "users_pkey" → "$I"
+/// - Single-quoted values 'alice' → $S
+/// - Standalone numbers → $N
+///
+/// `duplicate key value violates unique constraint "users_pkey"` and the same
+/// message for `"orders_pkey"` both become `... unique constraint "$I"`.
+pub fn normalize_error(message: &str) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut chars = message.chars().peekable();
+    let mut prev: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                let closing = c;
+                for inner in chars.by_ref() {
+                    if inner == closing {
+                        break;
+                    }
+                }
+                result.push_str(if c == '"' { "\"$I\"" } else { "$S" });
+            }
+            '0'..='9' if !prev.is_some_and(|p| p.is_alphanumeric() || p == '_') => {
+                while chars
+                    .peek()
+                    .is_some_and(|n| n.is_ascii_digit() || *n == '.')
+                {
+                    chars.next();
+                }
+                result.push_str("$N");
+            }
+            _ => result.push(c),
+        }
+        prev = Some(c);
+    }
+
+    result
+}
+
+/// What a statement does, by its leading keyword. Serialized as `SELECT`, `DDL`, ...
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Operation {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    /// CREATE, ALTER, DROP, TRUNCATE, COMMENT, GRANT, REVOKE.
+    Ddl,
+    Other,
+}
+
+impl Operation {
+    fn from_keyword(word: &str) -> Self {
+        match word.to_ascii_lowercase().as_str() {
+            "select" | "values" | "table" => Operation::Select,
+            "insert" => Operation::Insert,
+            "update" => Operation::Update,
+            "delete" => Operation::Delete,
+            "create" | "alter" | "drop" | "truncate" | "comment" | "grant" | "revoke" => {
+                Operation::Ddl
+            }
+            _ => Operation::Other,
+        }
+    }
+}
+
+/// Whether a SELECT's column list has a bare `*` or `alias.*`, so its width
+/// follows the table definition rather than the query text.
+pub fn selects_star(sql: &str) -> bool {
+    if operation(sql) != Operation::Select {
+        return false;
+    }
+    let fp = fingerprint(sql);
+    let Some(start) = fp.find("select ") else {
+        return false;
+    };
+    let list = &fp[start + "select ".len()..];
+    let list = list.split(" from ").next().unwrap_or(list);
+    let list = list.strip_prefix("distinct ").unwrap_or(list);
+    list.split(',')
+        .map(str::trim)
+        .any(|item| item == "*" || item.ends_with(".*"))
+}
+
+/// Keywords after which a name is a table reference.
+const TABLE_KEYWORDS: [&str; 7] = [
+    "from", "join", "into", "update", "table", "truncate", "only",
+];
+
+/// Keywords that end a FROM list, after which a comma no longer separates tables.
+const CLAUSE_KEYWORDS: [&str; 13] = [
+    "where",
+    "on",
+    "using",
+    "set",
+    "values",
+    "select",
+    "group",
+    "order",
+    "having",
+    "limit",
+    "returning",
+    "union",
+    "window",
+];
+
+/// Drop the schema part of qualified names in a fingerprint: `tenant_42.orders`
+/// becomes `orders`. Only dotted identifier chains are touched, never numbers or
+/// placeholders. A three-part name (`schema.table.column`) always loses its first
+/// part. A two-part name loses it only where a table is expected — after FROM,
+/// JOIN, INTO, UPDATE and the like, or later in a comma-separated FROM list —
+/// because elsewhere it is usually `alias.column`.
+pub fn strip_schema_qualifiers(fp: &str) -> String {
+    let bytes = fp.as_bytes();
+    let len = bytes.len();
+    let mut result = String::with_capacity(len);
+    let mut copied = 0;
+    let mut expect_table = false;
+    let mut in_table_list = false;
+    let mut i = 0;
+
+    while i < len {
+        let b = bytes[i];
+        let name_start = (b.is_ascii_alphabetic() || b == b'_' || b == b'"')
+            && !(i > 0 && (is_ident_byte(bytes[i - 1]) || bytes[i - 1] == b'$'));
+        if !name_start {
+            match b {
+                b',' if in_table_list => expect_table = true,
+                b';' => {
+                    expect_table = false;
+                    in_table_list = false;
+                }
+                _ if b.is_ascii_whitespace() => {}
+                _ => expect_table = false,
+            }
+            i += 1;
+            continue;
+        }
+
+        // Collect `part.part.part`, where each part is a bare or "quoted" name
+        let mut parts = Vec::new();
+        let mut end = i;
+        loop {
+            let part_end = name_part_end(bytes, end);
+            parts.push(end);
+            end = part_end;
+            let continues = end + 1 < len
+                && bytes[end] == b'.'
+                && (bytes[end + 1].is_ascii_alphabetic()
+                    || bytes[end + 1] == b'_'
+                    || bytes[end + 1] == b'"');
+            if !continues {
+                break;
+            }
+            end += 1;
+        }
+
+        if parts.len() == 1 {
+            let word = &fp[i..end];
+            if TABLE_KEYWORDS.contains(&word) {
+                expect_table = true;
+                in_table_list = true;
+            } else if CLAUSE_KEYWORDS.contains(&word) {
+                expect_table = false;
+                in_table_list = false;
+            } else {
+                expect_table = false;
+            }
+        } else {
+            if parts.len() >= 3 || expect_table {
+                result.push_str(&fp[copied..i]);
+                copied = parts[1];
+            }
+            expect_table = false;
+        }
+        i = end;
+    }
+
+    result.push_str(&fp[copied..]);
+    result
+}
+
+/// End of one bare or double-quoted name starting at `start`.
+fn name_part_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start;
+    if bytes[i] == b'"' {
+        i += 1;
+        while i < bytes.len() {
+            if bytes[i] == b'"' {
+                // "" is an escaped quote inside the name
+                if i + 1 < bytes.len() && bytes[i + 1] == b'"' {
+                    i += 2;
+                    continue;
+                }
+                return i + 1;
+            }
+            i += 1;
+        }
+        return i;
+    }
+    while i < bytes.len() && is_ident_byte(bytes[i]) {
+        i += 1;
+    }
+    i
+}
+
+/// Classify a statement by its leading keyword, skipping comments and opening
+/// parentheses. For `WITH ...` the operation is that of the main statement after
+/// the CTEs: `WITH old AS (SELECT ...) DELETE ...` is a DELETE.
+pub fn operation(sql: &str) -> Operation {
+    let bytes = sql.as_bytes();
+    let len = bytes.len();
+    let mut i = 0;
+    let mut depth = 0usize;
+    let mut in_with = false;
+
+    while i < len {
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < len && bytes[i] != b'\n' {
                     i += 1;
-                } else {
-                    result.push_str("$N");
-                    // Skip the whole number (including decimals)
-                    while i < len && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // Block comments nest in Postgres.
+                let mut nesting = 0;
+                while i < len {
+                    if bytes[i..].starts_with(b"/*") {
+                        nesting += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        nesting -= 1;
+                        i += 2;
+                        if nesting == 0 {
+                            break;
+                        }
+                    } else {
                         i += 1;
                     }
                 }
             }
-            // Everything else
-            ch => {
-                result.push(ch as char);
+            quote @ (b'\'' | b'"') => {
+                i += 1;
+                while i < len {
+                    i += 1;
+                    if bytes[i - 1] == quote {
+                        if bytes.get(i) != Some(&quote) {
+                            break;
+                        }
+                        i += 1;
+                    }
+                }
+            }
+            b'$' => match find_dollar_tag_end(bytes, i) {
+                Some(tag_end) => {
+                    let tag = &bytes[i..=tag_end];
+                    i = tag_end + 1;
+                    i = bytes[i..]
+                        .windows(tag.len())
+                        .position(|w| w == tag)
+                        .map_or(len, |p| i + p + tag.len());
+                }
+                None => i += 1,
+            },
+            b'(' => {
+                depth += 1;
                 i += 1;
             }
+            b')' => {
+                depth = depth.saturating_sub(1);
+                i += 1;
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                let start = i;
+                while i < len && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+                let word = &sql[start..i];
+                if !in_with {
+                    if word.eq_ignore_ascii_case("with") {
+                        in_with = true;
+                        continue;
+                    }
+                    return Operation::from_keyword(word);
+                }
+                // After WITH, CTE bodies are parenthesized: the first statement
+                // keyword at the top level starts the main statement.
+                if depth == 0 {
+                    if word.eq_ignore_ascii_case("merge") {
+                        return Operation::Other;
+                    }
+                    match Operation::from_keyword(word) {
+                        Operation::Other | Operation::Ddl => {}
+                        op => return op,
+                    }
+                }
+            }
+            _ => i += 1,
         }
     }
+    Operation::Other
+}
 
-    // Normalize IN ($N, $N, ...) → IN ($...)
-    normalize_in_lists(&result).to_lowercase()
+/// Split a simple-query string into its statements, the way the server runs
+/// them: one CommandComplete comes back per statement. Semicolons inside quotes,
+/// dollar-quoted bodies and comments don't split. Pieces with nothing but
+/// whitespace and comments are dropped, since the server skips them too.
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    for_each_statement(sql.as_bytes(), |start, end| {
+        statements.push(sql[start..end].trim())
+    });
+    statements
+}
+
+/// How many statements `split_statements` would find, without copying or
+/// allocating: all `--no-sql` needs to expect one CommandComplete per statement.
+pub fn count_statements(sql: &[u8]) -> usize {
+    let mut count = 0;
+    for_each_statement(sql, |_, _| count += 1);
+    count
+}
+
+/// The scan behind both: calls `statement` with the byte range of each statement.
+fn for_each_statement(bytes: &[u8], mut statement: impl FnMut(usize, usize)) {
+    let len = bytes.len();
+    let mut start = 0;
+    let mut has_content = false;
+    let mut i = 0;
+
+    while i < len {
+        match bytes[i] {
+            b';' => {
+                if has_content {
+                    statement(start, i);
+                }
+                start = i + 1;
+                has_content = false;
+                i += 1;
+                continue;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = bytes[i..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map_or(len, |p| i + p + 1);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // Block comments nest in PostgreSQL.
+                let mut depth = 0;
+                while i < len {
+                    if bytes[i] == b'/' && bytes.get(i + 1) == Some(&b'*') {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                continue;
+            }
+            quote @ (b'\'' | b'"') => {
+                // '' and "" escape the quote, which this handles as two adjacent literals.
+                i += 1;
+                while i < len && bytes[i] != quote {
+                    i += 1;
+                }
+            }
+            b'$' if !(i > 0 && is_ident_byte(bytes[i - 1])) => {
+                if let Some(tag_end) = find_dollar_tag_end(bytes, i) {
+                    let tag = &bytes[i..=tag_end];
+                    i = bytes[tag_end + 1..]
+                        .windows(tag.len())
+                        .position(|w| w == tag)
+                        .map_or(len, |p| tag_end + 1 + p + tag.len() - 1);
+                }
+            }
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        has_content = true;
+        i += 1;
+    }
+    if has_content {
+        statement(start, len);
+    }
+}
+
+/// End (exclusive) of the numeric literal starting at `start`: `42`, `9.99`, `.5`,
+/// `1.5e-10`, `0x1F`. None if no number starts there.
+fn number_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let digits = |mut j: usize, radix: u32| {
+        while bytes.get(j).is_some_and(|&b| (b as char).is_digit(radix)) {
+            j += 1;
+        }
+        j
+    };
+
+    if bytes.get(start) == Some(&b'0') && matches!(bytes.get(start + 1), Some(b'x' | b'X')) {
+        let end = digits(start + 2, 16);
+        return (end > start + 2).then_some(end);
+    }
+
+    let mut j = digits(start, 10);
+    let int_digits = j - start;
+    if bytes.get(j) == Some(&b'.') {
+        j = digits(j + 1, 10);
+    }
+    // At least one digit, before or after the point.
+    if int_digits == 0 && j <= start + 1 {
+        return None;
+    }
+    if matches!(bytes.get(j), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(j + 1), Some(b'-' | b'+')));
+        let exp_end = digits(j + 1 + sign, 10);
+        if exp_end > j + 1 + sign {
+            j = exp_end;
+        }
+    }
+    Some(j)
+}
+
+/// Whether the `-`/`+` at `i` is a sign (`= -5`, `(-5`, `THEN -1`) rather than
+/// a binary operator following a value (`a - 5`, `count(*) + 1`).
+fn sign_in_value_position(bytes: &[u8], i: usize) -> bool {
+    let before = bytes[..i].trim_ascii_end();
+    let Some(&prev) = before.last() else {
+        return true;
+    };
+    if matches!(prev, b')' | b']' | b'\'' | b'"') {
+        return false;
+    }
+    if !is_ident_byte(prev) {
+        return true;
+    }
+    // A preceding word is a value (column, number) unless it's a keyword that takes one.
+    let word_start = before
+        .iter()
+        .rposition(|&b| !is_ident_byte(b))
+        .map_or(0, |p| p + 1);
+    const VALUE_KEYWORDS: &[&str] = &[
+        "select",
+        "where",
+        "and",
+        "or",
+        "not",
+        "when",
+        "then",
+        "else",
+        "between",
+        "limit",
+        "offset",
+        "set",
+        "values",
+        "return",
+        "returning",
+        "is",
+        "like",
+        "by",
+        "having",
+        "on",
+    ];
+    VALUE_KEYWORDS
+        .iter()
+        .any(|kw| before[word_start..].eq_ignore_ascii_case(kw.as_bytes()))
 }
 
 fn find_dollar_tag_end(bytes: &[u8], start: usize) -> Option<usize> {
@@ -95,66 +616,226 @@ fn find_dollar_tag_end(bytes: &[u8], start: usize) -> Option<usize> {
     }
 }
 
-fn normalize_in_lists(sql: &str) -> String {
-    // Replace IN ($N, $N, $N) or IN ($S, $S) with IN ($...)
-    // Simple regex-like approach
-    let mut result = String::with_capacity(sql.len());
-    let upper = sql.to_uppercase();
+/// Collapse a literal-only list following `keyword` into a single placeholder,
+/// e.g. `IN ($N, $N)` → `IN ($...)`, `ARRAY[$N, $N]` → `ARRAY[$...]`.
+/// Lists containing anything other than placeholders (column references,
+/// expressions, subqueries) are left as-is.
+fn collapse_literal_lists(
+    sql: &str,
+    keyword: &str,
+    open: u8,
+    close: u8,
+    replacement: &str,
+) -> String {
+    // Work on bytes: only ASCII is matched or replaced, so UTF-8 in identifiers survives.
     let bytes = sql.as_bytes();
+    let kw = keyword.as_bytes();
     let len = bytes.len();
+    let mut result = Vec::with_capacity(len);
     let mut i = 0;
 
     while i < len {
-        // Look for "IN" followed by whitespace and "("
-        if i + 2 < len && &upper[i..i + 2] == "IN" && (i == 0 || !bytes[i - 1].is_ascii_alphanumeric()) {
-            let mut j = i + 2;
+        let at_keyword = i + kw.len() <= len
+            && bytes[i..i + kw.len()].eq_ignore_ascii_case(kw)
+            && (i == 0 || !is_ident_byte(bytes[i - 1]));
+        if at_keyword {
+            let mut j = i + kw.len();
             // Skip whitespace
             while j < len && bytes[j].is_ascii_whitespace() {
                 j += 1;
             }
-            if j < len && bytes[j] == b'(' {
-                // Check if the content is only $N/$S separated by commas and spaces
-                j += 1;
-                let mut all_placeholders = true;
-                let mut has_placeholder = false;
-                while j < len && bytes[j] != b')' {
-                    match bytes[j] {
-                        b'$' => {
-                            has_placeholder = true;
-                            j += 1;
-                            if j < len && (bytes[j] == b'N' || bytes[j] == b'S') {
-                                j += 1;
-                            } else {
-                                all_placeholders = false;
-                                break;
-                            }
-                        }
-                        b',' | b' ' => j += 1,
-                        _ => {
-                            all_placeholders = false;
-                            break;
-                        }
-                    }
-                }
-                if j < len && bytes[j] == b')' && all_placeholders && has_placeholder {
-                    result.push_str(&sql[i..i + 2]); // "IN" or "in"
-                    result.push_str(" ($...)");
-                    i = j + 1;
+            if j < len && bytes[j] == open {
+                if let Some(end) = placeholder_list_end(bytes, j + 1, open, close) {
+                    result.extend_from_slice(&bytes[i..i + kw.len()]); // keep original case
+                    result.extend_from_slice(replacement.as_bytes());
+                    i = end + 1;
                     continue;
                 }
             }
         }
-        result.push(bytes[i] as char);
+        result.push(bytes[i]);
         i += 1;
     }
 
-    result
+    String::from_utf8(result).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// If the list starting at `start` holds only `$N`/`$S` placeholders (nested
+/// `open`/`close` allowed, e.g. `ARRAY[[1,2],[3,4]]`), return the index of its closing byte.
+fn placeholder_list_end(bytes: &[u8], start: usize, open: u8, close: u8) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut has_placeholder = false;
+    let mut j = start;
+
+    while j < bytes.len() {
+        match bytes[j] {
+            b'$' if matches!(bytes.get(j + 1), Some(b'N' | b'S')) => {
+                has_placeholder = true;
+                j += 2;
+            }
+            b if b == open => {
+                depth += 1;
+                j += 1;
+            }
+            b if b == close => {
+                if depth == 0 {
+                    return has_placeholder.then_some(j);
+                }
+                depth -= 1;
+                j += 1;
+            }
+            b',' | b' ' => j += 1,
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_strip_schema_qualifiers() {
+        let strip = |sql: &str| strip_schema_qualifiers(&fingerprint(sql));
+        assert_eq!(
+            strip("SELECT * FROM public.users WHERE id = 1"),
+            "select * from users where id = $n"
+        );
+        assert_eq!(
+            strip("SELECT u.id, u.* FROM app.users u WHERE u.id = 1"),
+            "select u.id, u.* from users u where u.id = $n"
+        );
+        assert_eq!(
+            strip("SELECT o.id FROM s.orders o, s.extra e JOIN s.items i ON i.order_id = o.id WHERE o.x = 1.5"),
+            "select o.id from orders o, extra e join items i on i.order_id = o.id where o.x = $n"
+        );
+        assert_eq!(
+            strip("SELECT a.b.c FROM \"My Schema\".\"T\""),
+            "select b.c from \"t\""
+        );
+        assert_eq!(
+            strip("INSERT INTO audit.log (a) VALUES (1)"),
+            "insert into log (a) values ($n)"
+        );
+        assert_eq!(
+            strip("UPDATE t1.accounts SET n = 2"),
+            "update accounts set n = $n"
+        );
+        // Function calls and alias.column outside table position stay qualified
+        assert_eq!(
+            strip("SELECT pg_catalog.now(), t.a FROM t"),
+            "select pg_catalog.now(), t.a from t"
+        );
+    }
+
+    #[test]
+    fn test_multi_tenant_queries_share_a_fingerprint() {
+        let a =
+            strip_schema_qualifiers(&fingerprint("SELECT * FROM tenant_42.orders WHERE id = 7"));
+        let b =
+            strip_schema_qualifiers(&fingerprint("select * from tenant_7.orders where id = 42"));
+        assert_eq!(a, b);
+        assert_eq!(a, "select * from orders where id = $n");
+    }
+
+    #[test]
+    fn test_allowlist_accepts_fingerprints_and_queries() {
+        let path =
+            std::env::temp_dir().join(format!("dbprobe-allowlist-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# reviewed 2026-10\nselect * from users where id = $n\n\n  SELECT name FROM items WHERE sku IN ('a', 'b')  \n",
+        )
+        .unwrap();
+        let allowed = read_allowlist(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(allowed.len(), 2);
+        assert!(allowed.contains(&fingerprint("SELECT * FROM users WHERE id = 42")));
+        assert!(allowed.contains(&fingerprint("SELECT name FROM items WHERE sku IN ('x')")));
+    }
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements("SELECT 1; SELECT 2; SELECT 3"),
+            ["SELECT 1", "SELECT 2", "SELECT 3"]
+        );
+        assert_eq!(split_statements("SELECT 1;"), ["SELECT 1"]);
+        assert_eq!(split_statements(" ; SELECT 1;; -- done\n"), ["SELECT 1"]);
+        assert_eq!(
+            split_statements("INSERT INTO t VALUES ('a;b', 'it''s'); SELECT \"x;y\" FROM t"),
+            [
+                "INSERT INTO t VALUES ('a;b', 'it''s')",
+                "SELECT \"x;y\" FROM t"
+            ]
+        );
+        assert_eq!(
+            split_statements("DO $body$ BEGIN PERFORM 1; END $body$; SELECT $1"),
+            ["DO $body$ BEGIN PERFORM 1; END $body$", "SELECT $1"]
+        );
+        assert_eq!(
+            split_statements("SELECT 1 /* a; /* b; */ c; */; SELECT 2 -- x;\n"),
+            ["SELECT 1 /* a; /* b; */ c; */", "SELECT 2 -- x;"]
+        );
+    }
+
+    #[test]
+    fn test_selects_star() {
+        assert!(selects_star("SELECT * FROM users WHERE id = 1"));
+        assert!(selects_star(
+            "select u.*, o.total from users u join orders o on o.user_id = u.id"
+        ));
+        assert!(selects_star("SELECT DISTINCT * FROM t"));
+        assert!(!selects_star("SELECT id, email FROM users"));
+        assert!(!selects_star("SELECT count(*) FROM users"));
+        assert!(!selects_star("SELECT 2 * 3"));
+        assert!(!selects_star("DELETE FROM users RETURNING *"));
+    }
+
+    #[test]
+    fn test_operation() {
+        let cases = [
+            ("SELECT * FROM users", Operation::Select),
+            ("  select 1", Operation::Select),
+            ("(SELECT 1) UNION (SELECT 2)", Operation::Select),
+            ("VALUES (1), (2)", Operation::Select),
+            ("INSERT INTO t VALUES (1)", Operation::Insert),
+            ("update t set x = 1", Operation::Update),
+            ("DELETE FROM t WHERE id = $1", Operation::Delete),
+            ("CREATE INDEX i ON t (x)", Operation::Ddl),
+            ("ALTER TABLE t ADD COLUMN y int", Operation::Ddl),
+            ("DROP TABLE t", Operation::Ddl),
+            ("TRUNCATE t", Operation::Ddl),
+            ("BEGIN", Operation::Other),
+            ("EXPLAIN SELECT 1", Operation::Other),
+            ("", Operation::Other),
+            // Leading comments, including nested block comments.
+            ("-- fetch users\nSELECT * FROM users", Operation::Select),
+            ("/* app: billing /* nested */ */ UPDATE t SET x = 1", Operation::Update),
+            // CTEs take the operation of the main statement.
+            ("WITH a AS (SELECT 1) SELECT * FROM a", Operation::Select),
+            ("WITH old AS (SELECT id FROM t WHERE x < 0) DELETE FROM t USING old", Operation::Delete),
+            (
+                "WITH RECURSIVE a(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM a), \
+                 b AS MATERIALIZED (SELECT ')' AS p) INSERT INTO t SELECT n FROM a",
+                Operation::Insert,
+            ),
+            ("WITH moved AS (DELETE FROM a RETURNING *) INSERT INTO b SELECT * FROM moved", Operation::Insert),
+            ("WITH s AS (SELECT 1) MERGE INTO t USING s ON true WHEN MATCHED THEN UPDATE SET x = 1", Operation::Other),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(operation(sql), expected, "{sql}");
+        }
+        assert_eq!(serde_json::to_value(Operation::Ddl).unwrap(), "DDL");
+        assert_eq!(serde_json::to_value(Operation::Select).unwrap(), "SELECT");
+    }
+
     #[test]
     fn test_string_literals() {
         assert_eq!(
@@ -171,6 +852,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_numeric_literal_forms() {
+        assert_eq!(
+            fingerprint("SELECT 1e6, 1.5e-10, 2E+3"),
+            "select $n, $n, $n"
+        );
+        assert_eq!(
+            fingerprint("SELECT .5 FROM t WHERE x > 0.25"),
+            "select $n from t where x > $n"
+        );
+        assert_eq!(fingerprint("SELECT 0x1F, 0XfF"), "select $n, $n");
+        // `e` not followed by an exponent stays: 1 AS e... is a number then a name.
+        assert_eq!(fingerprint("SELECT 1 e"), "select $n e");
+    }
+
+    #[test]
+    fn test_signed_numeric_literals() {
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE x = -5"),
+            "select * from t where x = $n"
+        );
+        assert_eq!(
+            fingerprint("INSERT INTO t VALUES (-1, +2.5e3)"),
+            "insert into t values ($n, $n)"
+        );
+        assert_eq!(
+            fingerprint("SELECT CASE WHEN a THEN -1 ELSE -2 END"),
+            "select case when a then $n else $n end"
+        );
+        assert_eq!(fingerprint("SELECT -3"), "select $n");
+        // Same shape whatever the sign.
+        assert_eq!(
+            fingerprint("WHERE x BETWEEN -5 AND 10"),
+            fingerprint("WHERE x BETWEEN 5 AND 10")
+        );
+    }
+
+    #[test]
+    fn test_subtraction_keeps_operator() {
+        assert_eq!(fingerprint("SELECT a - 5 FROM t"), "select a - $n from t");
+        assert_eq!(
+            fingerprint("SELECT a-5, b+1 FROM t"),
+            "select a-$n, b+$n from t"
+        );
+        assert_eq!(
+            fingerprint("SELECT count(*) - 1, arr[1] + 2"),
+            "select count(*) - $n, arr[$n] + $n"
+        );
+        assert_eq!(fingerprint("SELECT 10 - 5"), "select $n - $n");
+        assert_eq!(fingerprint("SELECT 1 -- comment"), "select $n -- comment");
+    }
+
     #[test]
     fn test_mixed() {
         assert_eq!(
@@ -202,4 +935,79 @@ mod tests {
             "select * from t where name = $s"
         );
     }
+
+    #[test]
+    fn test_array_literals_collapse() {
+        let a = fingerprint("SELECT * FROM t WHERE id = ANY(ARRAY[1,2,3])");
+        let b = fingerprint("SELECT * FROM t WHERE id = ANY(ARRAY[4,5])");
+        assert_eq!(a, "select * from t where id = any(array[$...])");
+        assert_eq!(a, b);
+        assert_eq!(
+            fingerprint("SELECT ARRAY[[1, 2], [3, 4]]"),
+            "select array[$...]"
+        );
+    }
+
+    #[test]
+    fn test_row_literals_collapse() {
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE (a, b) = ROW(1, 'a')"),
+            "select * from t where (a, b) = row($...)"
+        );
+    }
+
+    #[test]
+    fn test_array_row_with_columns_preserved() {
+        assert_eq!(
+            fingerprint("SELECT ARRAY[a, 1] FROM t"),
+            "select array[a, $n] from t"
+        );
+        assert_eq!(
+            fingerprint("SELECT ROW(id, 2) FROM t"),
+            "select row(id, $n) from t"
+        );
+        assert_eq!(
+            fingerprint("SELECT ARRAY(SELECT 1)"),
+            "select array(select $n)"
+        );
+        // "arrow" / "narrow" are identifiers, not the ROW keyword
+        assert_eq!(
+            fingerprint("SELECT arrow(1) FROM t"),
+            "select arrow($n) from t"
+        );
+    }
+
+    #[test]
+    fn test_non_ascii_identifier_does_not_panic() {
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE x IN (1, 2)"),
+            "select * from t where x in ($...)"
+        );
+        let fp = fingerprint("SELECT café FROM t WHERE x IN (1)");
+        assert!(fp.ends_with("in ($...)"));
+    }
+
+    #[test]
+    fn test_normalize_error_identifiers() {
+        assert_eq!(
+            normalize_error("duplicate key value violates unique constraint \"users_pkey\""),
+            "duplicate key value violates unique constraint \"$I\""
+        );
+        assert_eq!(
+            normalize_error("duplicate key value violates unique constraint \"users_pkey\""),
+            normalize_error("duplicate key value violates unique constraint \"orders_pkey\"")
+        );
+    }
+
+    #[test]
+    fn test_normalize_error_values() {
+        assert_eq!(
+            normalize_error("invalid input syntax for type integer: 'abc'"),
+            "invalid input syntax for type integer: $S"
+        );
+        assert_eq!(
+            normalize_error("value 70000 is out of range for type int2"),
+            "value $N is out of range for type int2"
+        );
+    }
 }
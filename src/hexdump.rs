@@ -0,0 +1,174 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protocol::Direction;
+
+/// Bytes shown per chunk; the rest of a read is summarized in one line.
+const MAX_CHUNK_BYTES: usize = 256;
+
+/// Chunks dumped per second across all connections; the rest are counted and reported.
+const MAX_CHUNKS_PER_SEC: u64 = 20;
+
+/// Which traffic `--hexdump` prints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum HexdumpDirections {
+    /// Client -> server
+    Frontend,
+    /// Server -> client
+    Backend,
+    Both,
+}
+
+/// Throttled hexdump of relayed bytes to stderr, for a quick look at the wire
+/// without a `--record` capture. Shared by every relay task.
+#[derive(Clone)]
+pub struct Hexdump {
+    directions: HexdumpDirections,
+    limiter: Arc<Mutex<ChunkLimiter>>,
+}
+
+/// Per-second chunk budget, so a busy proxy can't flood the terminal.
+struct ChunkLimiter {
+    window_start: Instant,
+    printed: u64,
+    suppressed: u64,
+}
+
+impl ChunkLimiter {
+    /// Whether this chunk fits the budget, plus the chunks dropped in a window that just ended.
+    fn allow(&mut self, now: Instant) -> (bool, Option<u64>) {
+        let mut dropped = None;
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.printed = 0;
+            dropped = (self.suppressed > 0).then(|| std::mem::take(&mut self.suppressed));
+        }
+        if self.printed < MAX_CHUNKS_PER_SEC {
+            self.printed += 1;
+            (true, dropped)
+        } else {
+            self.suppressed += 1;
+            (false, dropped)
+        }
+    }
+}
+
+impl Hexdump {
+    pub fn new(directions: HexdumpDirections) -> Self {
+        Self {
+            directions,
+            limiter: Arc::new(Mutex::new(ChunkLimiter {
+                window_start: Instant::now(),
+                printed: 0,
+                suppressed: 0,
+            })),
+        }
+    }
+
+    /// Dump one read from a relay, if its direction was asked for and the budget allows.
+    pub fn dump(&self, conn_id: u64, direction: Direction, data: &[u8]) {
+        let wanted = match self.directions {
+            HexdumpDirections::Frontend => direction == Direction::Frontend,
+            HexdumpDirections::Backend => direction == Direction::Backend,
+            HexdumpDirections::Both => true,
+        };
+        if !wanted {
+            return;
+        }
+
+        // Hold the lock while writing so chunks from different relays don't interleave.
+        let mut limiter = self.limiter.lock().unwrap();
+        let (allowed, dropped) = limiter.allow(Instant::now());
+        let mut out = String::new();
+        if let Some(n) = dropped {
+            let _ = writeln!(out, "({n} hexdump chunks suppressed)");
+        }
+        if allowed {
+            out.push_str(&format_chunk(conn_id, direction, data));
+        }
+        if !out.is_empty() {
+            let _ = std::io::stderr().lock().write_all(out.as_bytes());
+        }
+    }
+}
+
+/// Header line, then `offset  hex  |ascii|` rows of 16 bytes, capped at `MAX_CHUNK_BYTES`.
+fn format_chunk(conn_id: u64, direction: Direction, data: &[u8]) -> String {
+    let time = chrono::Local::now().format("%H:%M:%S%.3f");
+    let mut out = format!("{time} [conn:{conn_id}] {direction} {} bytes\n", data.len());
+    let shown = &data[..data.len().min(MAX_CHUNK_BYTES)];
+    for (i, row) in shown.chunks(16).enumerate() {
+        let _ = write!(out, "  {:08x} ", i * 16);
+        for col in 0..16 {
+            if col == 8 {
+                out.push(' ');
+            }
+            match row.get(col) {
+                Some(b) => {
+                    let _ = write!(out, " {b:02x}");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        let ascii: String = row
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(out, "  |{ascii}|");
+    }
+    if data.len() > shown.len() {
+        let _ = writeln!(out, "  ... {} more bytes", data.len() - shown.len());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_chunk() {
+        let mut query = vec![b'Q', 0, 0, 0, 13];
+        query.extend_from_slice(b"SELECT 1;\0");
+        let out = format_chunk(3, Direction::Frontend, &query);
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].ends_with("[conn:3] -> 15 bytes"));
+        assert_eq!(
+            lines[1],
+            "  00000000  51 00 00 00 0d 53 45 4c  45 43 54 20 31 3b 00     |Q....SELECT 1;.|"
+        );
+        assert_eq!(lines.len(), 2);
+
+        let big = vec![0xffu8; MAX_CHUNK_BYTES + 100];
+        let out = format_chunk(1, Direction::Backend, &big);
+        assert_eq!(out.lines().count(), 1 + MAX_CHUNK_BYTES / 16 + 1);
+        assert!(out.ends_with("  ... 100 more bytes\n"));
+    }
+
+    #[test]
+    fn test_limiter_reports_suppressed_chunks() {
+        let start = Instant::now();
+        let mut limiter = ChunkLimiter {
+            window_start: start,
+            printed: 0,
+            suppressed: 0,
+        };
+        for _ in 0..MAX_CHUNKS_PER_SEC {
+            assert_eq!(limiter.allow(start), (true, None));
+        }
+        assert_eq!(limiter.allow(start), (false, None));
+        assert_eq!(limiter.allow(start), (false, None));
+        assert_eq!(
+            limiter.allow(start + Duration::from_secs(1)),
+            (true, Some(2))
+        );
+    }
+}
@@ -1,19 +1,40 @@
+mod baseline;
+mod capture;
+mod config;
+mod control;
 mod fingerprint;
+mod hexdump;
+mod metrics;
 mod output;
+mod percentile;
 mod protocol;
 mod proxy;
+mod shadow;
+mod snapshot;
 mod stats;
+mod tls;
+mod tracecontext;
+mod truncate;
 
 use std::io::IsTerminal;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use tokio::sync::mpsc;
 use tracing::info;
 
-use output::raw::RawSink;
-use output::OutputSink;
-use proxy::ProxyMessage;
-use stats::StatsCollector;
+use config::{ConfigFile, LiveConfig};
+use control::{ControlState, EventLog, Readiness};
+use metrics::Metrics;
+use output::logs::{LogBuffer, LogBufferLayer};
+use output::raw::{FlushMode, RawFormat, RawOptions, RawSink, TimestampFormat, TimestampSource};
+use output::summary::{SummaryFormat, SummaryOutput};
+use output::trace::TraceWriter;
+use output::tui::TuiOptions;
+use output::{ConnEvents, ConnLabel, DisplayEvent, DisplayEventKind, OutputSink};
+use proxy::{HealthCheck, ProxyMessage};
+use stats::{GroupBy, StatsCollector, StatsConfig};
 
 #[derive(Clone, Copy, Debug, ValueEnum)]
 enum Mode {
@@ -22,8 +43,14 @@ enum Mode {
 }
 
 #[derive(Parser, Debug)]
-#[command(name = "dbprobe", about = "Lightweight database wire protocol interceptor")]
+#[command(
+    name = "dbprobe",
+    about = "Lightweight database wire protocol interceptor"
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Local port to listen on
     #[arg(short = 'l', long = "listen", default_value = "5433")]
     listen_port: u16,
@@ -39,11 +66,447 @@ struct Cli {
     /// Highlight queries slower than this threshold (ms)
     #[arg(short = 't', long = "threshold", default_value = "100")]
     threshold_ms: u64,
+
+    /// Sliding window (seconds) over which qps is averaged
+    #[arg(long = "qps-window", default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    qps_window_secs: u64,
+
+    /// Flag transactions open longer than this, BEGIN to COMMIT/ROLLBACK (ms)
+    #[arg(long = "slow-tx-threshold", value_name = "MS", default_value = "1000")]
+    slow_tx_threshold_ms: u64,
+
+    /// Warn when a SELECT * returns at least this many columns
+    #[arg(long = "wide-result-columns", value_name = "N", default_value = "50")]
+    wide_result_columns: u16,
+
+    /// Show the size of results at least this many MB on the wire
+    #[arg(long = "large-result-mb", value_name = "MB", default_value = "10")]
+    large_result_mb: u64,
+
+    /// Warn about queries whose SQL is at least this many KB
+    #[arg(long = "large-query-kb", value_name = "KB", default_value = "64")]
+    large_query_kb: usize,
+
+    /// Warn when clients open more than this many connections per second (over --qps-window),
+    /// a sign of a missing connection pooler
+    #[arg(long = "churn-threshold", value_name = "N", default_value = "50")]
+    churn_threshold: u32,
+
+    /// Warn about query fingerprints that are new, or no longer seen, within this many minutes
+    #[arg(long = "shape-window", value_name = "MINS")]
+    shape_window_mins: Option<u64>,
+
+    /// Reset the aggregates every DURATION (e.g. 5m), so they describe recent traffic
+    #[arg(long = "window", value_name = "DURATION", value_parser = stats::parse_span)]
+    window: Option<std::time::Duration>,
+
+    /// TUI: with --window, keep each finished window's stats as a tab
+    #[arg(long = "window-tabs", requires = "window")]
+    window_tabs: bool,
+
+    /// Flag queries whose fingerprint isn't listed in FILE (one fingerprint or example query per line)
+    #[arg(long = "allowed-fingerprints", value_name = "FILE")]
+    allowed_fingerprints: Option<std::path::PathBuf>,
+
+    /// Drop schema qualifiers when fingerprinting, so tenant_1.orders and tenant_2.orders group together
+    #[arg(long = "normalize-schema")]
+    normalize_schema: bool,
+
+    /// Appended to SQL and fingerprints cut to fit (counts toward the limit)
+    #[arg(long = "ellipsis", value_name = "STR", default_value = truncate::DEFAULT_INDICATOR)]
+    ellipsis: String,
+
+    /// Ring the terminal bell and flash the header for queries slower than this (ms)
+    #[arg(long = "bell-threshold", value_name = "MS")]
+    bell_threshold_ms: Option<u64>,
+
+    /// With --bell-threshold, only flash the header (no audible bell)
+    #[arg(long = "no-bell")]
+    no_bell: bool,
+
+    /// How connections are labelled in the CONN column
+    #[arg(long = "conn-label", value_enum, default_value = "id")]
+    conn_label: ConnLabel,
+
+    /// Connection open/close lines in the event log: none, count (a summary every 10s) or full
+    #[arg(long = "conn-events", value_enum, default_value = "full")]
+    conn_events: ConnEvents,
+
+    /// Raw mode: print at most N lines/sec (errors and slow queries always print)
+    #[arg(long = "max-line-rate", value_name = "N")]
+    max_line_rate: Option<u64>,
+
+    /// Raw mode: when output reaches stdout — immediate (every line), line (every
+    /// event) or block (when the buffer fills, at least once a second while busy)
+    #[arg(
+        long = "flush",
+        value_enum,
+        value_name = "MODE",
+        default_value = "line"
+    )]
+    flush: FlushMode,
+
+    /// Time queries without extracting their SQL, for latency-only monitoring at the
+    /// lowest overhead. Top Queries and other per-fingerprint reports stay empty
+    #[arg(long = "no-sql", conflicts_with_all = ["allowed_fingerprints", "shape_window_mins", "group_by", "shadow"])]
+    no_sql: bool,
+
+    /// Split the query aggregates by application_name or connection, besides the fingerprint
+    #[arg(
+        long = "group-by",
+        value_enum,
+        value_name = "KEY",
+        default_value = "fingerprint"
+    )]
+    group_by: GroupBy,
+
+    /// Raw mode: text lines, or json (one object per event, for jq)
+    #[arg(
+        long = "format",
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text"
+    )]
+    format: RawFormat,
+
+    /// Raw mode: stamp lines with when dbprobe saw them, or both that and the server's
+    /// estimated time (minus half the upstream connect time), to line up with server logs
+    #[arg(
+        long = "timestamps",
+        value_enum,
+        value_name = "SOURCE",
+        default_value = "observed"
+    )]
+    timestamps: TimestampSource,
+
+    /// Raw mode: local time, or postgres (log_line_prefix's %m, in UTC)
+    #[arg(
+        long = "timestamp-format",
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "local"
+    )]
+    timestamp_format: TimestampFormat,
+
+    /// Serve the control API (GET /events) on 127.0.0.1:PORT
+    #[arg(long = "control-port", value_name = "PORT")]
+    control_port: Option<u16>,
+
+    /// Let the control API change the upstream (POST /upstream); off by default
+    #[arg(long = "control-allow-write", requires = "control_port")]
+    control_allow_write: bool,
+
+    /// Serve Prometheus metrics (GET /metrics) on ADDR, e.g. 127.0.0.1:9090
+    #[arg(long = "metrics-addr", value_name = "ADDR")]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Decimal places for latencies in snapshots and JSON output
+    #[arg(long = "precision", value_name = "N", default_value_t = output::DEFAULT_PRECISION,
+          value_parser = clap::value_parser!(u32).range(0..=9))]
+    precision: u32,
+
+    /// Raw mode: print an end-of-run summary on exit
+    #[arg(long = "summary")]
+    summary: bool,
+
+    /// Only aggregate stats (no per-event output) and print the summary on exit
+    #[arg(long = "stats-only")]
+    stats_only: bool,
+
+    /// Format of the --summary / --stats-only report: text, or json (one object per line)
+    #[arg(
+        long = "summary-format",
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text"
+    )]
+    summary_format: SummaryFormat,
+
+    /// Write the summary to FILE instead of stdout (implies --summary)
+    #[arg(long = "summary-file", value_name = "FILE")]
+    summary_file: Option<std::path::PathBuf>,
+
+    /// TOML settings file; overrides the matching flags. Re-read on SIGHUP
+    /// (threshold_ms and bell_threshold_ms apply live)
+    #[arg(long = "config", value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+
+    /// Wait for the upstream to pass this check before listening: tcp, or handshake
+    /// (answers a Postgres SSLRequest)
+    #[arg(long = "upstream-health-check", value_enum, value_name = "CHECK",
+          num_args = 0..=1, default_missing_value = "tcp")]
+    upstream_health_check: Option<HealthCheck>,
+
+    /// Accept TLS from clients (answer their SSLRequest with S) and terminate it here
+    #[arg(long = "listen-tls", requires_all = ["tls_cert", "tls_key"], conflicts_with_all = ["replay", "shadow", "probe_only"])]
+    listen_tls: bool,
+
+    /// With --listen-tls, the certificate chain to present (PEM, leaf first)
+    #[arg(long = "cert", value_name = "PEM", requires = "listen_tls")]
+    tls_cert: Option<std::path::PathBuf>,
+
+    /// With --listen-tls, the private key for --cert (PEM)
+    #[arg(long = "key", value_name = "PEM", requires = "listen_tls")]
+    tls_key: Option<std::path::PathBuf>,
+
+    /// With --listen-tls, ask clients for a certificate signed by the CAs in this PEM file
+    /// and show its subject per connection; clients without one are still accepted
+    #[arg(
+        long = "tls-client-cert",
+        value_name = "CA_PEM",
+        requires = "listen_tls"
+    )]
+    tls_client_cert: Option<std::path::PathBuf>,
+
+    /// Connect to the upstream over TLS (Postgres SSL negotiation)
+    #[arg(long = "upstream-tls", conflicts_with_all = ["replay", "shadow"])]
+    upstream_tls: bool,
+
+    /// With --upstream-tls, verify the server against the CA certificates in this PEM file
+    /// instead of the bundled Mozilla roots
+    #[arg(
+        long = "upstream-tls-ca",
+        value_name = "PEM",
+        requires = "upstream_tls"
+    )]
+    upstream_tls_ca: Option<std::path::PathBuf>,
+
+    /// With --upstream-tls, skip server certificate verification (local testing only)
+    #[arg(
+        long = "upstream-tls-insecure",
+        requires = "upstream_tls",
+        conflicts_with = "upstream_tls_ca"
+    )]
+    upstream_tls_insecure: bool,
+
+    /// With --upstream-health-check, give up after this many seconds
+    #[arg(long = "startup-timeout", value_name = "SECS", default_value = "30")]
+    startup_timeout_secs: u64,
+
+    /// TUI latency color bounds in ms (green/yellow/red/bold red), e.g. 1,10,100
+    #[arg(long = "latency-tiers", value_name = "A,B,C", value_parser = output::tui::parse_latency_tiers)]
+    latency_tiers: Option<[f64; 3]>,
+
+    /// TUI: collapse the histogram and top queries into one line (toggle with c;
+    /// automatic on short terminals)
+    #[arg(long = "compact-layout")]
+    compact_layout: bool,
+
+    /// TUI: fix each row's ELAPSED when it arrives instead of recomputing it every frame
+    #[arg(long = "freeze-elapsed")]
+    freeze_elapsed: bool,
+
+    /// TUI: color queries by how they compare with their fingerprint's usual latency
+    /// (red at 3x its average) instead of by absolute tiers
+    #[arg(long = "relative-coloring")]
+    relative_coloring: bool,
+
+    /// TUI: show identical consecutive errors on a connection as one row with a count
+    #[arg(long = "merge-errors")]
+    merge_errors: bool,
+
+    /// Send clients a dbprobe_version ParameterStatus during the startup handshake
+    #[arg(long = "announce")]
+    announce: bool,
+
+    /// Write queries and connection events to FILE in Chrome Trace Event format (chrome://tracing, Perfetto)
+    #[arg(
+        long = "trace-json",
+        value_name = "FILE",
+        conflicts_with = "stats_only"
+    )]
+    trace_json: Option<std::path::PathBuf>,
+
+    /// Record all proxied traffic to FILE for later --replay
+    #[arg(long = "record", value_name = "FILE", conflicts_with = "replay")]
+    record: Option<std::path::PathBuf>,
+
+    /// With --record, only record this connection (repeatable)
+    #[arg(long = "capture-conn", value_name = "ID", requires = "record")]
+    capture_conn: Vec<u64>,
+
+    /// With --record, only record connections whose client address or a query matches REGEX
+    #[arg(long = "capture-filter", value_name = "REGEX", requires = "record", value_parser = capture::parse_capture_filter)]
+    capture_filter: Option<regex_automata::meta::Regex>,
+
+    /// Show each client's StartupMessage and refuse the connection; no upstream is dialed
+    #[arg(long = "probe-only", conflicts_with_all = ["replay", "upstream_health_check"])]
+    probe_only: bool,
+
+    /// Replay a --record capture instead of proxying (no listener, no upstream)
+    #[arg(long = "replay", value_name = "FILE")]
+    replay: Option<std::path::PathBuf>,
+
+    /// With --replay, shift each query's duration by a random amount up to this (ms)
+    #[arg(
+        long = "replay-jitter",
+        value_name = "MS",
+        default_value = "0",
+        requires = "replay"
+    )]
+    replay_jitter_ms: u64,
+
+    /// Seed for --replay-jitter, to reproduce a run (random if omitted)
+    #[arg(long = "replay-seed", value_name = "N", requires = "replay")]
+    replay_seed: Option<u64>,
+
+    /// With --replay, play the capture this many times faster, e.g. 2x or 0.5x
+    #[arg(long = "replay-speed", value_name = "X", default_value = "1", requires = "replay",
+          value_parser = capture::parse_replay_speed)]
+    replay_speed: f64,
+
+    /// Run the queries of a --record capture against --upstream and compare timings and rows (read-only unless --shadow-writes)
+    #[arg(long = "shadow", value_name = "FILE", conflicts_with_all = ["replay", "record", "probe_only"])]
+    shadow: Option<std::path::PathBuf>,
+
+    /// With --shadow, also run INSERT/UPDATE/DELETE/DDL from the capture. They really execute
+    #[arg(long = "shadow-writes", requires = "shadow")]
+    shadow_writes: bool,
+
+    /// Terminate connections idle inside a transaction for longer than this (off by default)
+    #[arg(long = "max-idle-in-transaction", value_name = "SECS")]
+    max_idle_in_transaction_secs: Option<u64>,
+
+    /// Print a throttled hexdump of relayed bytes to stderr
+    #[arg(long = "hexdump", value_name = "DIRECTION")]
+    hexdump: Option<hexdump::HexdumpDirections>,
+
+    /// Compare per-query p95 and average latency against a saved snapshot on exit; fail on regressions
+    #[arg(long = "baseline", value_name = "SNAPSHOT")]
+    baseline: Option<std::path::PathBuf>,
+
+    /// With --baseline, allowed growth of a query's p95 or average latency before it counts as regressed
+    #[arg(long = "tolerance", value_name = "PCT", default_value = "20%", requires = "baseline",
+          value_parser = baseline::parse_tolerance)]
+    tolerance: f64,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Combine snapshots (e.g. one per shard) into one; open it with --replay
+    Merge {
+        /// Snapshot files saved from the TUI
+        #[arg(required = true, num_args = 2.., value_name = "SNAPSHOT")]
+        inputs: Vec<std::path::PathBuf>,
+
+        /// Where to write the merged snapshot
+        #[arg(short = 'o', long = "output", value_name = "FILE")]
+        output: std::path::PathBuf,
+    },
+    /// Play a --record capture back through the parser and outputs, offline;
+    /// the same as --replay FILE, and takes the same options before `replay`
+    Replay {
+        /// Capture file written by --record
+        #[arg(value_name = "FILE")]
+        file: std::path::PathBuf,
+    },
+}
+
+/// Latency thresholds above this are almost certainly a unit mix-up (seconds
+/// typed as ms would still fit; minutes wouldn't).
+const MAX_THRESHOLD_MS: u64 = 3_600_000;
+
+impl Cli {
+    /// Range checks clap's types can't express, run once the config file has
+    /// been applied, so bad values fail at startup with a clear message.
+    fn validate(&mut self) -> anyhow::Result<()> {
+        if let Some(Command::Replay { file }) = self.command.take() {
+            if self.replay.is_some()
+                || self.record.is_some()
+                || self.shadow.is_some()
+                || self.probe_only
+            {
+                anyhow::bail!(
+                    "replay: can't be combined with --replay, --record, --shadow or --probe-only"
+                );
+            }
+            self.replay = Some(file);
+        }
+        self.upstream = self.upstream.trim().to_string();
+        if self.replay.is_none() {
+            proxy::check_upstream_addr(&self.upstream)
+                .map_err(|e| anyhow::anyhow!("--upstream: {e}"))?;
+        }
+        if self.listen_port == 0 {
+            anyhow::bail!("--listen: port must be between 1 and 65535");
+        }
+        match self.control_port {
+            Some(0) => anyhow::bail!("--control-port: port must be between 1 and 65535"),
+            Some(port) if port == self.listen_port => {
+                anyhow::bail!("--control-port {port} is also the --listen port")
+            }
+            _ => {}
+        }
+        if self.metrics_addr.is_some_and(|addr| {
+            addr.port() == self.listen_port || Some(addr.port()) == self.control_port
+        }) {
+            anyhow::bail!("--metrics-addr: port is already used by --listen or --control-port");
+        }
+
+        for (flag, ms) in [
+            ("--threshold", Some(self.threshold_ms)),
+            ("--slow-tx-threshold", Some(self.slow_tx_threshold_ms)),
+            ("--bell-threshold", self.bell_threshold_ms),
+        ] {
+            if ms.is_some_and(|ms| ms > MAX_THRESHOLD_MS) {
+                anyhow::bail!(
+                    "{flag}: {}ms is over an hour; thresholds are in milliseconds",
+                    ms.unwrap_or_default()
+                );
+            }
+        }
+
+        for (flag, value) in [
+            ("--shape-window", self.shape_window_mins),
+            ("--max-line-rate", self.max_line_rate),
+            (
+                "--max-idle-in-transaction",
+                self.max_idle_in_transaction_secs,
+            ),
+            ("--large-result-mb", Some(self.large_result_mb)),
+            ("--large-query-kb", Some(self.large_query_kb as u64)),
+            (
+                "--wide-result-columns",
+                Some(u64::from(self.wide_result_columns)),
+            ),
+            ("--churn-threshold", Some(u64::from(self.churn_threshold))),
+        ] {
+            if value == Some(0) {
+                anyhow::bail!("{flag}: must be at least 1");
+            }
+        }
+        if self.upstream_health_check.is_some() && self.startup_timeout_secs == 0 {
+            anyhow::bail!("--startup-timeout: must be at least 1 second to wait for the upstream");
+        }
+        Ok(())
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+
+    if let Some(Command::Merge { inputs, output }) = &cli.command {
+        return merge_snapshots(inputs, output, cli.precision);
+    }
+
+    let config_file = match &cli.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+    if let Some(port) = config_file.listen {
+        cli.listen_port = port;
+    }
+    if let Some(upstream) = &config_file.upstream {
+        cli.upstream = upstream.clone();
+    }
+    cli.validate()?;
+    let mut live_config = LiveConfig {
+        threshold_ms: cli.threshold_ms,
+        bell_threshold_ms: cli.bell_threshold_ms,
+    };
+    live_config.apply(&config_file);
+    let live_config = Arc::new(RwLock::new(live_config));
 
     let mode = cli.mode.unwrap_or_else(|| {
         if std::io::stdout().is_terminal() {
@@ -53,41 +516,298 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    let use_tui = matches!(mode, Mode::Tui);
+    // A baseline check or a shadow run ends in a report and an exit code, so it never runs the TUI.
+    let use_tui = matches!(mode, Mode::Tui)
+        && !cli.stats_only
+        && cli.baseline.is_none()
+        && cli.shadow.is_none();
+    let baseline = cli
+        .baseline
+        .as_deref()
+        .map(baseline::Baseline::load)
+        .transpose()?;
 
-    if !use_tui {
+    // A replay reads a capture file; there's nothing to listen on or forward to.
+    // A snapshot (e.g. from `dbprobe merge`) has no traffic to replay and opens as a TUI tab.
+    let replay_snapshot = cli
+        .replay
+        .clone()
+        .filter(|path| snapshot::is_snapshot_file(path));
+    if let Some(path) = replay_snapshot.as_ref().filter(|_| !use_tui) {
+        anyhow::bail!(
+            "{} is a snapshot, which can only be opened in the TUI (-m tui)",
+            path.display()
+        );
+    }
+    let replay_frames = match &cli.replay {
+        Some(path) if replay_snapshot.is_none() => Some(capture::read_capture(path)?),
+        _ => None,
+    };
+    let shadow_frames = cli
+        .shadow
+        .as_deref()
+        .map(capture::read_capture)
+        .transpose()?;
+    if cli.replay.is_none() && cli.shadow.is_none() && !cli.probe_only {
+        proxy::check_upstream_loop(cli.listen_port, &cli.upstream).await?;
+    }
+
+    let env_filter = || {
+        tracing_subscriber::EnvFilter::from_default_env()
+            .add_directive("dbprobe=info".parse().unwrap())
+    };
+    let logs = LogBuffer::new(output::logs::LOG_BUFFER_CAPACITY);
+    if use_tui {
+        // The dashboard owns the terminal, so logs go to the TUI's log pane instead.
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::util::SubscriberInitExt;
+
+        tracing_subscriber::registry()
+            .with(env_filter())
+            .with(LogBufferLayer::new(logs.clone()))
+            .init();
+    } else {
         tracing_subscriber::fmt()
-            .with_env_filter(
-                tracing_subscriber::EnvFilter::from_default_env()
-                    .add_directive("dbprobe=info".parse().unwrap()),
-            )
+            .with_env_filter(env_filter())
             .with_target(false)
             .init();
 
-        info!(
-            "dbprobe starting — listening on :{}, forwarding to {}",
-            cli.listen_port, cli.upstream
-        );
+        match &cli.replay {
+            Some(path) => info!("dbprobe replaying {}", path.display()),
+            None if cli.shadow.is_some() => {}
+            None if cli.probe_only => info!(
+                "dbprobe probing — listening on :{}, refusing every connection",
+                cli.listen_port
+            ),
+            None => info!(
+                "dbprobe starting — listening on :{}, forwarding to {}",
+                cli.listen_port, cli.upstream
+            ),
+        }
+    }
+
+    fingerprint::set_normalize_schema(cli.normalize_schema);
+    truncate::set_indicator(&cli.ellipsis);
+    let allowed_fingerprints = cli
+        .allowed_fingerprints
+        .as_deref()
+        .map(fingerprint::read_allowlist)
+        .transpose()?
+        .map(Arc::new);
+
+    let stats_config = StatsConfig {
+        qps_window: std::time::Duration::from_secs(cli.qps_window_secs),
+        slow_tx_threshold: std::time::Duration::from_millis(cli.slow_tx_threshold_ms),
+        wide_result_columns: cli.wide_result_columns,
+        large_result_bytes: cli.large_result_mb * 1024 * 1024,
+        large_query_bytes: cli.large_query_kb * 1024,
+        shape_window: cli
+            .shape_window_mins
+            .map(|mins| std::time::Duration::from_secs(mins * 60)),
+        window: cli.window,
+        allowed_fingerprints,
+        group_by: cli.group_by,
+        churn_threshold: cli.churn_threshold,
+        no_sql: cli.no_sql,
+    };
+
+    if let (Some(path), Some(frames)) = (&cli.shadow, shadow_frames) {
+        let options = shadow::ShadowOptions {
+            upstream: cli.upstream.clone(),
+            allow_writes: cli.shadow_writes,
+        };
+        shadow::announce(path, &options);
+        let comparison = shadow::run_shadow(frames, &options, stats_config).await;
+        print!("{}", comparison.report(&options));
+        let mismatches = comparison.mismatches();
+        if mismatches > 0 {
+            anyhow::bail!(
+                "{mismatches} fingerprints returned different rows or new errors against {}",
+                cli.upstream
+            );
+        }
+        return Ok(());
     }
 
     let (tx, rx) = mpsc::unbounded_channel::<ProxyMessage>();
 
+    #[cfg(unix)]
+    if let Some(path) = cli.config.clone() {
+        let (config, tx) = (live_config.clone(), tx.clone());
+        tokio::spawn(async move {
+            if let Err(e) = config::reload_on_sighup(path, config_file, config, tx).await {
+                tracing::error!("Config reload error: {e}");
+            }
+        });
+    }
+
+    let precision = cli.precision;
+    let ready = Readiness::default();
+    let upstream: proxy::SharedUpstream =
+        std::sync::Arc::new(std::sync::RwLock::new(cli.upstream.clone()));
+    let event_log = cli.control_port.map(|port| {
+        let log = EventLog::new(control::EVENT_LOG_CAPACITY);
+        let state = ControlState {
+            log: log.clone(),
+            precision,
+            ready: ready.clone(),
+            upstream: upstream.clone(),
+            allow_write: cli.control_allow_write,
+        };
+        tokio::spawn(async move {
+            if let Err(e) = control::run_control_api(port, state).await {
+                tracing::error!("Control API error: {e}");
+            }
+        });
+        log
+    });
+
+    let metrics = cli.metrics_addr.map(|addr| {
+        let metrics = Metrics::default();
+        let served = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::run_metrics_endpoint(addr, served).await {
+                tracing::error!("Metrics endpoint error: {e}");
+            }
+        });
+        metrics
+    });
+
+    // The control API is already up, so /healthz reports 503 while we wait.
+    if let Some(check) = cli.upstream_health_check.filter(|_| cli.replay.is_none()) {
+        let timeout = std::time::Duration::from_secs(cli.startup_timeout_secs);
+        if use_tui {
+            // No log output in TUI mode, and the dashboard isn't up yet.
+            eprintln!(
+                "Waiting for upstream {} (up to {}s)...",
+                cli.upstream, cli.startup_timeout_secs
+            );
+        }
+        proxy::wait_for_upstream(&cli.upstream, check, timeout).await?;
+    }
+
+    let trace = cli
+        .trace_json
+        .as_deref()
+        .map(TraceWriter::create)
+        .transpose()?;
+
     let listen_addr = format!("0.0.0.0:{}", cli.listen_port);
-    let upstream_addr = cli.upstream.clone();
+    let capture = match &cli.record {
+        Some(path) => {
+            let filter = capture::CaptureFilter {
+                conn_ids: cli.capture_conn.clone(),
+                pattern: cli.capture_filter.clone(),
+            };
+            Some(capture::Capture::create(path, filter).await?)
+        }
+        None => None,
+    };
+    if cli.upstream_tls_insecure {
+        tracing::warn!("--upstream-tls-insecure: the upstream's certificate is not verified");
+    }
+    let upstream_tls = cli
+        .upstream_tls
+        .then(|| tls::UpstreamTls::new(cli.upstream_tls_ca.as_deref(), cli.upstream_tls_insecure))
+        .transpose()?;
+    let listen_tls = match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) if cli.listen_tls => Some(tls::ListenTls::new(
+            cert,
+            key,
+            cli.tls_client_cert.as_deref(),
+        )?),
+        _ => None,
+    };
+    let proxy_options = proxy::ProxyOptions {
+        capture,
+        max_idle_in_transaction: cli
+            .max_idle_in_transaction_secs
+            .map(std::time::Duration::from_secs),
+        hexdump: cli.hexdump.map(hexdump::Hexdump::new),
+        announce: cli.announce,
+        probe_only: cli.probe_only,
+        no_sql: cli.no_sql,
+        upstream_tls,
+        listen_tls,
+    };
+    // The TUI quits when the channel closes; keep it open after a replay ends.
+    let _hold_open = use_tui.then(|| tx.clone());
+    // `p` in the TUI pauses the replay itself, so stepping doesn't fall behind it.
+    let replay_control = replay_frames
+        .as_ref()
+        .map(|_| capture::ReplayControl::default());
 
-    let proxy_handle = tokio::spawn(async move {
-        if let Err(e) = proxy::run_proxy(&listen_addr, upstream_addr, tx).await {
-            tracing::error!("Proxy error: {e}");
+    let proxy_handle = match replay_frames {
+        None if replay_snapshot.is_some() => tokio::spawn(std::future::pending::<()>()),
+        Some(frames) => {
+            let seed = cli.replay_seed.unwrap_or_else(|| {
+                let seed = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64);
+                if cli.replay_jitter_ms > 0 {
+                    info!("Replay jitter seed: {seed}");
+                }
+                seed
+            });
+            let options = capture::ReplayOptions {
+                jitter: std::time::Duration::from_millis(cli.replay_jitter_ms),
+                seed,
+                speed: cli.replay_speed,
+            };
+            let control = replay_control.clone().unwrap_or_default();
+            ready.store(true, std::sync::atomic::Ordering::Relaxed);
+            tokio::spawn(async move {
+                capture::replay(frames, options, control, tx).await;
+                // Outlive the replay so the output loop decides when to stop.
+                std::future::pending::<()>().await;
+            })
         }
-    });
+        None => {
+            let upstream = upstream.clone();
+            tokio::spawn(async move {
+                if let Err(e) =
+                    proxy::run_proxy(&listen_addr, upstream, ready, proxy_options, tx).await
+                {
+                    tracing::error!("Proxy error: {e}");
+                }
+            })
+        }
+    };
 
-    if use_tui {
-        let tui_handle = tokio::spawn(output::tui::run_tui(
-            rx,
-            cli.listen_port,
-            cli.upstream.clone(),
-            cli.threshold_ms,
-        ));
+    let summary_output =
+        SummaryOutput::new(cli.summary_format, cli.summary_file.clone(), precision);
+
+    if cli.stats_only {
+        let stats_handle = tokio::spawn(run_stats_only(rx, stats_config, summary_output, metrics));
+
+        let stats = tokio::select! {
+            _ = proxy_handle => None,
+            stats = stats_handle => stats.ok(),
+        };
+        check_baseline(baseline.as_ref(), cli.tolerance, stats.as_ref())?;
+    } else if use_tui {
+        let options = TuiOptions {
+            listen_port: cli.listen_port,
+            upstream,
+            config: live_config,
+            bell_audible: !cli.no_bell,
+            conn_label: cli.conn_label,
+            event_log,
+            metrics,
+            precision,
+            latency_tiers: cli.latency_tiers,
+            compact_layout: cli.compact_layout,
+            freeze_elapsed: cli.freeze_elapsed,
+            relative_coloring: cli.relative_coloring,
+            merge_errors: cli.merge_errors,
+            snapshot: replay_snapshot,
+            replay: replay_control,
+            logs,
+            trace,
+            window_tabs: cli.window_tabs,
+            conn_events: cli.conn_events,
+        };
+        let tui_handle = tokio::spawn(output::tui::run_tui(rx, options, stats_config));
 
         tokio::select! {
             result = tui_handle => {
@@ -98,42 +818,312 @@ async fn main() -> anyhow::Result<()> {
             _ = proxy_handle => {}
         }
     } else {
-        let event_handle = tokio::spawn(run_raw_mode(rx));
+        let sink = RawSink::new(RawOptions {
+            config: live_config,
+            bell_audible: !cli.no_bell,
+            conn_label: cli.conn_label,
+            max_line_rate: cli.max_line_rate,
+            flush: cli.flush,
+            conn_events: cli.conn_events,
+            format: cli.format,
+            precision,
+            timestamps: cli.timestamps,
+            timestamp_format: cli.timestamp_format,
+        });
+        let summary = (cli.summary || cli.summary_file.is_some()).then_some(summary_output);
+        let event_handle = tokio::spawn(run_raw_mode(
+            rx,
+            stats_config,
+            sink,
+            event_log,
+            metrics,
+            trace,
+            summary,
+        ));
 
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
-                info!("Shutting down...");
-            }
-            _ = proxy_handle => {}
-            _ = event_handle => {}
-        }
+        // Ctrl-C is handled inside the event loop so the summary can still print.
+        let stats = tokio::select! {
+            _ = proxy_handle => None,
+            stats = event_handle => stats.ok(),
+        };
+        check_baseline(baseline.as_ref(), cli.tolerance, stats.as_ref())?;
     }
 
     Ok(())
 }
 
-async fn run_raw_mode(mut rx: mpsc::UnboundedReceiver<ProxyMessage>) {
-    let mut stats = StatsCollector::new();
-    let mut sink = RawSink::new();
+async fn run_raw_mode(
+    mut rx: mpsc::UnboundedReceiver<ProxyMessage>,
+    stats_config: StatsConfig,
+    mut sink: RawSink,
+    event_log: Option<EventLog>,
+    metrics: Option<Metrics>,
+    mut trace: Option<TraceWriter>,
+    mut summary: Option<SummaryOutput>,
+) -> StatsCollector {
+    let mut started = Instant::now();
+    let mut stats = StatsCollector::with_config(stats_config);
+    let mut emit = |sink: &mut RawSink, event: &DisplayEvent| {
+        if let Some(log) = &event_log {
+            log.push(event);
+        }
+        if let Some(trace) = &mut trace {
+            trace.handle_event(event);
+        }
+        sink.handle_event(event);
+    };
 
-    while let Some(msg) = rx.recv().await {
+    while let Some(msg) = next_message(&mut rx).await {
+        if stats.roll_window(Instant::now()) {
+            if let Some(summary) = &mut summary {
+                // Keep the summary after the lines it covers.
+                sink.flush();
+                summary.write(&stats, started.elapsed());
+            }
+            stats.reset();
+            started = Instant::now();
+        }
         match msg {
-            ProxyMessage::ConnectionOpened { conn_id } => {
-                let event = stats.connection_opened(conn_id);
-                sink.handle_event(&event);
+            ProxyMessage::ConnectionOpened {
+                conn_id,
+                client_addr,
+            } => {
+                for event in stats.connection_opened(conn_id, Some(client_addr)) {
+                    emit(&mut sink, &event);
+                }
             }
             ProxyMessage::ConnectionClosed { conn_id } => {
-                if let Some(event) = stats.connection_dropped(conn_id) {
-                    sink.handle_event(&event);
+                for event in stats.connection_dropped(conn_id) {
+                    emit(&mut sink, &event);
                 }
             }
-            ProxyMessage::Event { conn_id, event } => {
-                if let Some(display_event) = stats.process_event(conn_id, event) {
-                    sink.handle_event(&display_event);
+            ProxyMessage::Event { conn_id, event, at } => {
+                if let Some(display_event) = stats.process_event(conn_id, event, at) {
+                    emit(&mut sink, &display_event);
+                }
+            }
+            ProxyMessage::UpstreamConnected {
+                conn_id,
+                connect_time,
+            } => sink.upstream_connected(conn_id, connect_time),
+            ProxyMessage::ClientCertificate { conn_id, subject } => {
+                emit(&mut sink, &stats.client_certificate(conn_id, subject));
+            }
+            ProxyMessage::Warning { conn_id, message } => {
+                emit(&mut sink, &stats.proxy_warning(conn_id, message));
+            }
+            ProxyMessage::ClientBackpressure {
+                conn_id,
+                waited,
+                at,
+            } => {
+                if let Some(event) = stats.client_backpressure(conn_id, waited, at) {
+                    emit(&mut sink, &event);
                 }
             }
         }
+        if let Some(metrics) = &metrics {
+            metrics.publish(&stats);
+        }
     }
 
     sink.shutdown();
+    if let Some(trace) = &mut trace {
+        trace.shutdown();
+    }
+    if let Some(summary) = &mut summary {
+        summary.write(&stats, started.elapsed());
+    }
+    stats
+}
+
+/// Aggregate only: no display events are built and nothing is printed until exit,
+/// or until each `--window` ends.
+async fn run_stats_only(
+    mut rx: mpsc::UnboundedReceiver<ProxyMessage>,
+    stats_config: StatsConfig,
+    mut summary: SummaryOutput,
+    metrics: Option<Metrics>,
+) -> StatsCollector {
+    let mut started = Instant::now();
+    let mut stats = StatsCollector::with_config(stats_config);
+
+    while let Some(msg) = next_message(&mut rx).await {
+        if stats.roll_window(Instant::now()) {
+            summary.write(&stats, started.elapsed());
+            stats.reset();
+            started = Instant::now();
+        }
+        match msg {
+            ProxyMessage::ConnectionOpened {
+                conn_id,
+                client_addr,
+            } => {
+                for event in stats.connection_opened(conn_id, Some(client_addr)) {
+                    if let DisplayEventKind::Warning(message) = event.kind {
+                        tracing::warn!("{message}");
+                    }
+                }
+            }
+            ProxyMessage::ConnectionClosed { conn_id } => {
+                stats.connection_dropped(conn_id);
+            }
+            ProxyMessage::Event { conn_id, event, at } => stats.record_event(conn_id, event, at),
+            ProxyMessage::UpstreamConnected { .. } | ProxyMessage::ClientCertificate { .. } => {}
+            ProxyMessage::Warning { message, .. } => tracing::warn!("{message}"),
+            ProxyMessage::ClientBackpressure {
+                conn_id,
+                waited,
+                at,
+            } => {
+                stats.client_backpressure(conn_id, waited, at);
+            }
+        }
+        if let Some(metrics) = &metrics {
+            metrics.publish(&stats);
+        }
+    }
+
+    summary.write(&stats, started.elapsed());
+    stats
+}
+
+/// `--baseline`: print the comparison and fail the run if any query regressed.
+fn check_baseline(
+    baseline: Option<&baseline::Baseline>,
+    tolerance: f64,
+    stats: Option<&StatsCollector>,
+) -> anyhow::Result<()> {
+    let (Some(baseline), Some(stats)) = (baseline, stats) else {
+        return Ok(());
+    };
+    let comparison = baseline.compare(&stats.fingerprints, tolerance);
+    print!("{}", comparison.report(tolerance));
+    match comparison.regressions.len() {
+        0 => Ok(()),
+        n => anyhow::bail!(
+            "{n} queries regressed beyond {:.0}% of the baseline",
+            tolerance * 100.0
+        ),
+    }
+}
+
+/// `dbprobe merge`: write one snapshot combining `inputs`.
+fn merge_snapshots(
+    inputs: &[std::path::PathBuf],
+    output: &std::path::Path,
+    precision: u32,
+) -> anyhow::Result<()> {
+    let snapshots = inputs
+        .iter()
+        .map(|path| snapshot::Snapshot::load(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let merged = snapshot::Snapshot::merge(snapshots, precision);
+    merged
+        .save(output)
+        .map_err(|e| anyhow::anyhow!("writing {}: {e}", output.display()))?;
+    println!(
+        "Merged {} snapshots into {}: {} queries, {} fingerprints, {} events",
+        inputs.len(),
+        output.display(),
+        merged.total_queries,
+        merged.top_queries.len(),
+        merged.recent_events.len()
+    );
+    Ok(())
+}
+
+/// Next proxy message, or None once the proxy is gone or Ctrl-C is pressed.
+async fn next_message(rx: &mut mpsc::UnboundedReceiver<ProxyMessage>) -> Option<ProxyMessage> {
+    tokio::select! {
+        msg = rx.recv() => msg,
+        _ = tokio::signal::ctrl_c() => {
+            info!("Shutting down...");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(args: &[&str]) -> Result<(), String> {
+        let mut cli = Cli::try_parse_from(std::iter::once("dbprobe").chain(args.iter().copied()))
+            .map_err(|e| e.to_string())?;
+        cli.validate().map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_replay_subcommand_sets_replay() {
+        let mut cli = Cli::try_parse_from([
+            "dbprobe",
+            "-u",
+            "not-an-address",
+            "-m",
+            "raw",
+            "replay",
+            "traffic.dbp",
+        ])
+        .unwrap();
+        cli.validate().unwrap();
+        assert_eq!(
+            cli.replay.as_deref(),
+            Some(std::path::Path::new("traffic.dbp"))
+        );
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn test_out_of_range_options_are_rejected() {
+        assert_eq!(validate(&[]), Ok(()));
+        assert_eq!(validate(&["-u", " db:5432 ", "-t", "3600000"]), Ok(()));
+
+        for (args, expected) in [
+            (
+                &["-t", "3600001"][..],
+                "--threshold: 3600001ms is over an hour",
+            ),
+            (
+                &["--bell-threshold", "99999999"][..],
+                "--bell-threshold: 99999999ms",
+            ),
+            (
+                &["-u", "db"][..],
+                "--upstream: invalid upstream \"db\", expected host:port",
+            ),
+            (&["-u", "db:0"][..], "expected host:port"),
+            (
+                &["-l", "0"][..],
+                "--listen: port must be between 1 and 65535",
+            ),
+            (
+                &["--control-port", "5433"][..],
+                "--control-port 5433 is also the --listen port",
+            ),
+            (
+                &["--metrics-addr", "127.0.0.1:5433"][..],
+                "--metrics-addr: port is already used",
+            ),
+            (
+                &["--shape-window", "0"][..],
+                "--shape-window: must be at least 1",
+            ),
+            (
+                &["--max-line-rate", "0"][..],
+                "--max-line-rate: must be at least 1",
+            ),
+            (
+                &["--upstream-health-check", "tcp", "--startup-timeout", "0"][..],
+                "--startup-timeout: must be at least 1 second",
+            ),
+            (
+                &["--record", "out.dbp", "replay", "in.dbp"][..],
+                "replay: can't be combined with",
+            ),
+        ] {
+            let err = validate(args).unwrap_err();
+            assert!(err.contains(expected), "{args:?}: {err}");
+        }
+    }
 }
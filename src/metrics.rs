@@ -0,0 +1,207 @@
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+use crate::control::read_request_line;
+use crate::stats::StatsCollector;
+
+/// Upper bounds of `StatsCollector::latency_buckets`, in seconds; the last
+/// bucket is everything slower, Prometheus's `+Inf`. Both include their bound.
+const BUCKET_BOUNDS: [&str; 5] = ["0.001", "0.005", "0.01", "0.05", "0.1"];
+
+/// The numbers `/metrics` serves, copied out of the stats by the output loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct MetricsSnapshot {
+    queries: u64,
+    errors: u64,
+    active_connections: u64,
+    latency_buckets: [u64; 6],
+    latency_sum: Duration,
+}
+
+/// Latest stats snapshot, shared between the output loop (writer) and the
+/// metrics endpoint (reader). Publishing copies a few counters, so the loop
+/// never waits on a scrape.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<MetricsSnapshot>>,
+}
+
+impl Metrics {
+    pub fn publish(&self, stats: &StatsCollector) {
+        *self.inner.lock().unwrap() = MetricsSnapshot {
+            queries: stats.total_queries,
+            errors: stats.total_errors,
+            active_connections: stats.active_connections,
+            latency_buckets: stats.latency_buckets,
+            latency_sum: stats.latency_sum,
+        };
+    }
+
+    /// Prometheus text exposition format.
+    fn render(&self) -> String {
+        let snapshot = *self.inner.lock().unwrap();
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP dbprobe_queries_total Queries completed.");
+        let _ = writeln!(out, "# TYPE dbprobe_queries_total counter");
+        let _ = writeln!(out, "dbprobe_queries_total {}", snapshot.queries);
+        let _ = writeln!(
+            out,
+            "# HELP dbprobe_errors_total Queries that ended in an ErrorResponse."
+        );
+        let _ = writeln!(out, "# TYPE dbprobe_errors_total counter");
+        let _ = writeln!(out, "dbprobe_errors_total {}", snapshot.errors);
+        let _ = writeln!(
+            out,
+            "# HELP dbprobe_active_connections Client connections open through the proxy."
+        );
+        let _ = writeln!(out, "# TYPE dbprobe_active_connections gauge");
+        let _ = writeln!(
+            out,
+            "dbprobe_active_connections {}",
+            snapshot.active_connections
+        );
+        let _ = writeln!(
+            out,
+            "# HELP dbprobe_query_duration_seconds Client-perceived query latency."
+        );
+        let _ = writeln!(out, "# TYPE dbprobe_query_duration_seconds histogram");
+        let mut cumulative = 0;
+        for (bound, count) in BUCKET_BOUNDS
+            .iter()
+            .chain(&["+Inf"])
+            .zip(snapshot.latency_buckets)
+        {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "dbprobe_query_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "dbprobe_query_duration_seconds_sum {}",
+            snapshot.latency_sum.as_secs_f64()
+        );
+        let _ = writeln!(out, "dbprobe_query_duration_seconds_count {cumulative}");
+        out
+    }
+}
+
+/// Serve `GET /metrics` on `addr`. Runs until the process exits.
+pub async fn run_metrics_endpoint(addr: SocketAddr, metrics: Metrics) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Metrics listening on http://{addr}/metrics");
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &metrics).await {
+                debug!("Metrics request from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, metrics: &Metrics) -> anyhow::Result<()> {
+    let (status, body) = match read_request_line(&mut stream).await? {
+        None => ("413 Payload Too Large", "request too large\n".to_string()),
+        Some(head) => match (
+            head.method.as_str(),
+            head.target
+                .split_once('?')
+                .map_or(head.target.as_str(), |(path, _)| path),
+        ) {
+            ("GET", "/metrics") => ("200 OK", metrics.render()),
+            (_, "/metrics") => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+            _ => ("404 Not Found", "not found\n".to_string()),
+        },
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtoEvent;
+    use crate::stats::StatsConfig;
+    use std::time::Instant;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_serves_stats() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(1, None);
+        let start = Instant::now();
+        // 5ms sits on a bound and must count toward le="0.005".
+        for ms in [5, 30, 250] {
+            stats.record_event(
+                1,
+                ProtoEvent::QueryStart {
+                    sql: "SELECT 1".into(),
+                    sql_bytes: 0,
+                    portal: None,
+                    params: Vec::new(),
+                },
+                start,
+            );
+            let done = start + Duration::from_millis(ms);
+            stats.record_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                },
+                done,
+            );
+        }
+        let metrics = Metrics::default();
+        metrics.publish(&stats);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = metrics.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_request(stream, &server).await.unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        for line in [
+            "dbprobe_queries_total 3",
+            "dbprobe_errors_total 0",
+            "dbprobe_active_connections 1",
+            "dbprobe_query_duration_seconds_bucket{le=\"0.001\"} 0",
+            "dbprobe_query_duration_seconds_bucket{le=\"0.005\"} 1",
+            "dbprobe_query_duration_seconds_bucket{le=\"0.05\"} 2",
+            "dbprobe_query_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "dbprobe_query_duration_seconds_sum 0.285",
+            "dbprobe_query_duration_seconds_count 3",
+        ] {
+            assert!(
+                response.lines().any(|l| l == line),
+                "missing {line:?} in:\n{response}"
+            );
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// How many log lines the TUI keeps.
+pub const LOG_BUFFER_CAPACITY: usize = 500;
+
+/// One captured `tracing` event.
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    pub time: String,
+    pub level: Level,
+    pub message: String,
+}
+
+/// Bounded buffer of dbprobe's own log output, filled by `LogBufferLayer` and
+/// shown in the TUI's log pane (stdout belongs to the dashboard there).
+#[derive(Clone)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<LogState>>,
+    capacity: usize,
+}
+
+struct LogState {
+    lines: VecDeque<LogLine>,
+    /// WARN and ERROR lines ever logged, including ones since dropped.
+    problems: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(LogState {
+                lines: VecDeque::with_capacity(capacity),
+                problems: 0,
+            })),
+            capacity,
+        }
+    }
+
+    fn push(&self, line: LogLine) {
+        let mut state = self.inner.lock().unwrap();
+        if line.level <= Level::WARN {
+            state.problems += 1;
+        }
+        if state.lines.len() >= self.capacity {
+            state.lines.pop_front();
+        }
+        state.lines.push_back(line);
+    }
+
+    /// The most recent `n` lines, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<LogLine> {
+        let state = self.inner.lock().unwrap();
+        state
+            .lines
+            .iter()
+            .skip(state.lines.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+
+    /// Total WARN/ERROR lines so far — the TUI compares it with what was on
+    /// screen to flag new problems while the pane is hidden.
+    pub fn problem_count(&self) -> usize {
+        self.inner.lock().unwrap().problems
+    }
+}
+
+/// `tracing` layer that appends every event to a `LogBuffer`.
+pub struct LogBufferLayer {
+    buffer: LogBuffer,
+}
+
+impl LogBufferLayer {
+    pub fn new(buffer: LogBuffer) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        self.buffer.push(LogLine {
+            time: chrono::Local::now().format("%H:%M:%S%.3f").to_string(),
+            level: *event.metadata().level(),
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+/// Renders an event as `message key=value ...`, like the fmt layer does.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    /// ` key=value` for each other field.
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_layer_captures_events() {
+        let buffer = LogBuffer::new(2);
+        let subscriber = tracing_subscriber::registry().with(LogBufferLayer::new(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::warn!(conn_id = 7, "Connection 7 error: reset");
+            tracing::error!("Proxy error: bind failed");
+        });
+
+        let lines = buffer.recent(10);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].level, Level::WARN);
+        assert_eq!(lines[0].message, "Connection 7 error: reset conn_id=7");
+        assert_eq!(lines[1].message, "Proxy error: bind failed");
+        assert_eq!(buffer.problem_count(), 2);
+
+        tracing::subscriber::with_default(
+            tracing_subscriber::registry().with(LogBufferLayer::new(buffer.clone())),
+            || tracing::warn!("dropped later"),
+        );
+        assert_eq!(buffer.recent(10).len(), 2);
+        assert_eq!(buffer.problem_count(), 3);
+    }
+}
@@ -1,37 +1,397 @@
+pub mod logs;
 pub mod raw;
+pub mod summary;
+pub mod trace;
 pub mod tui;
 
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Serializer};
+
+use crate::fingerprint::Operation;
+use crate::stats::TransactionTiming;
 
 /// Event after correlation — ready for display.
 #[derive(Clone, Debug)]
 pub struct DisplayEvent {
     pub wall_time: chrono::DateTime<chrono::Local>,
     pub conn_id: u64,
+    /// Client address of the connection, when known.
+    pub client_addr: Option<SocketAddr>,
     pub kind: DisplayEventKind,
 }
 
-#[derive(Clone, Debug)]
+/// What identifies a connection in the CONN column.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConnLabel {
+    /// Numeric connection id (`7`)
+    #[default]
+    Id,
+    /// Client address (`127.0.0.1:54321`)
+    Addr,
+    /// Both (`7 127.0.0.1:54321`)
+    Both,
+}
+
+impl ConnLabel {
+    /// Format a connection for display, falling back to the id when the address is unknown.
+    pub fn format(self, conn_id: u64, client_addr: Option<SocketAddr>) -> String {
+        match (self, client_addr) {
+            (ConnLabel::Addr, Some(addr)) => addr.to_string(),
+            (ConnLabel::Both, Some(addr)) => format!("{conn_id} {addr}"),
+            _ => conn_id.to_string(),
+        }
+    }
+
+    /// Like `format`, with the query's number on its connection when it has one (`7/#5`).
+    pub fn format_query(
+        self,
+        conn_id: u64,
+        client_addr: Option<SocketAddr>,
+        seq: Option<u64>,
+    ) -> String {
+        let label = self.format(conn_id, client_addr);
+        match seq {
+            Some(seq) => format!("{label}/#{seq}"),
+            None => label,
+        }
+    }
+
+    /// Column width that fits an IPv4 address with port, plus a query number.
+    pub fn width(self) -> u16 {
+        match self {
+            ConnLabel::Id => 10,
+            ConnLabel::Addr => 27,
+            ConnLabel::Both => 33,
+        }
+    }
+}
+
+/// Serialized as an object tagged with `kind` (`query`, `error`, ...), durations
+/// as unrounded `*_ms` floats; see `--format json`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum DisplayEventKind {
     Query {
         sql: String,
+        /// From the leading keyword, so consumers can filter without parsing SQL.
+        operation: Operation,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_ms")]
         duration: Duration,
         rows: Option<u64>,
+        /// 1 for the first query on the connection, 2 for the next, and so on.
+        seq: u64,
+        /// From a sqlcommenter `traceparent` comment, to find the query's trace.
+        trace_id: Option<String>,
+        /// Values bound to `$1`, `$2`, ... for an Execute; None is NULL.
+        params: Vec<Option<String>>,
+        /// Server bytes the result took on the wire.
+        result_bytes: u64,
+        /// `result_bytes` reached `--large-result-mb`; sinks show the size.
+        large_result: bool,
     },
     Error {
-        #[allow(dead_code)]
         sql: Option<String>,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_opt_ms")]
         duration: Option<Duration>,
         code: String,
         message: String,
     },
+    /// COPY throughput — live while `done` is false, final total once CopyDone arrives.
+    Copy {
+        bytes: u64,
+        #[serde(rename = "elapsed_ms", serialize_with = "serialize_ms")]
+        elapsed: Duration,
+        done: bool,
+    },
+    /// An explicit transaction that stayed open longer than the slow-transaction threshold.
+    SlowTransaction(TransactionTiming),
     ConnectionOpened,
     ConnectionClosed,
+    /// Subject of the certificate the client presented (`--tls-client-cert`).
+    ClientCertificate {
+        subject: String,
+    },
+    /// LISTEN/NOTIFY: a notification delivered to this connection.
+    Notification {
+        channel: String,
+        payload: String,
+    },
+    #[serde(serialize_with = "serialize_warning")]
     Warning(String),
 }
 
+pub(crate) fn serialize_ms<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn serialize_opt_ms<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_ms(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// `{"kind": "warning", "message": ...}`: a tagged variant can't hold a bare string.
+fn serialize_warning<S: Serializer>(message: &String, serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry("message", message)?;
+    map.end()
+}
+
+/// " ($1='42', $2=NULL)" after an Execute's SQL; empty when nothing was bound.
+pub fn format_params(params: &[Option<String>]) -> String {
+    if params.is_empty() {
+        return String::new();
+    }
+    let values: Vec<String> = params
+        .iter()
+        .enumerate()
+        .map(|(i, value)| match value {
+            Some(value) => format!("${}='{value}'", i + 1),
+            None => format!("${}=NULL", i + 1),
+        })
+        .collect();
+    format!(" ({})", values.join(", "))
+}
+
+/// "40.0MB".
+pub fn format_mb(bytes: u64) -> String {
+    format!("{:.1}MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// "COPY: 340.0MB @ 120.0MB/s" while running, "COPY done: 340.0MB in 2.8s (120.0MB/s)" at the end.
+pub fn format_copy(bytes: u64, elapsed: Duration, done: bool) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { mb / secs } else { 0.0 };
+    if done {
+        format!("COPY done: {mb:.1}MB in {secs:.1}s ({rate:.1}MB/s)")
+    } else {
+        format!("COPY: {mb:.1}MB @ {rate:.1}MB/s")
+    }
+}
+
+/// "SLOW TX: 3 stmts in 2.31s — 0.02s executing, 2.29s idle (max gap 1.80s)"
+pub fn format_slow_transaction(timing: &TransactionTiming) -> String {
+    format!(
+        "SLOW TX: {} stmts in {:.2}s — {:.2}s executing, {:.2}s idle (max gap {:.2}s)",
+        timing.statements,
+        timing.total.as_secs_f64(),
+        timing.busy.as_secs_f64(),
+        timing.idle().as_secs_f64(),
+        timing.max_gap.as_secs_f64(),
+    )
+}
+
+/// Default decimals for latencies written to snapshots and JSON.
+pub const DEFAULT_PRECISION: u32 = 3;
+
+/// Milliseconds rounded to `decimals` places, for serialized output only —
+/// aggregation keeps full `Duration` precision.
+pub fn round_ms(duration: Duration, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals as i32);
+    (duration.as_secs_f64() * 1000.0 * scale).round() / scale
+}
+
 /// Processes display events.
 pub trait OutputSink: Send + 'static {
     fn handle_event(&mut self, event: &DisplayEvent);
     fn shutdown(&mut self);
 }
+
+/// Minimum gap between two bells, so a burst of slow queries rings once.
+const BELL_MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Rate-limited alert for queries slower than a critical threshold.
+pub struct SlowQueryBell {
+    threshold: Duration,
+    /// Emit `\x07`; when false only the visual flash is used.
+    pub audible: bool,
+    last_rung: Option<Instant>,
+}
+
+impl SlowQueryBell {
+    pub fn new(threshold_ms: u64, audible: bool) -> Self {
+        Self {
+            threshold: Duration::from_millis(threshold_ms),
+            audible,
+            last_rung: None,
+        }
+    }
+
+    /// True if `event` is a query over the threshold and the bell hasn't rung recently.
+    pub fn check(&mut self, event: &DisplayEvent) -> bool {
+        let DisplayEventKind::Query { duration, .. } = &event.kind else {
+            return false;
+        };
+        if *duration < self.threshold {
+            return false;
+        }
+        let now = Instant::now();
+        if self
+            .last_rung
+            .is_some_and(|t| now.duration_since(t) < BELL_MIN_INTERVAL)
+        {
+            return false;
+        }
+        self.last_rung = Some(now);
+        true
+    }
+
+    /// Follow a (possibly reloaded) bell threshold, keeping the rate-limit state.
+    pub fn sync(bell: &mut Option<SlowQueryBell>, threshold_ms: Option<u64>, audible: bool) {
+        match threshold_ms {
+            Some(ms) => {
+                bell.get_or_insert_with(|| SlowQueryBell::new(ms, audible))
+                    .threshold = Duration::from_millis(ms)
+            }
+            None => *bell = None,
+        }
+    }
+}
+
+/// How connection open/close events reach the event log (`--conn-events`).
+/// Active connection counts and other outputs (control API, trace) see every event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConnEvents {
+    /// Not shown at all
+    None,
+    /// A periodic "N opened, M closed" line instead of one line each
+    Count,
+    /// One line per open and close
+    #[default]
+    Full,
+}
+
+/// How often `--conn-events count` reports.
+const CONN_SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Applies `--conn-events` for a sink. Only opens and closes are filtered:
+/// errors and warnings about a connection always show.
+pub struct ConnEventFilter {
+    mode: ConnEvents,
+    opened: u64,
+    closed: u64,
+    window_started: Instant,
+}
+
+impl ConnEventFilter {
+    pub fn new(mode: ConnEvents) -> Self {
+        Self {
+            mode,
+            opened: 0,
+            closed: 0,
+            window_started: Instant::now(),
+        }
+    }
+
+    /// False for an open or close the mode hides; those are counted instead.
+    pub fn admit(&mut self, event: &DisplayEvent) -> bool {
+        if self.mode == ConnEvents::Full {
+            return true;
+        }
+        match event.kind {
+            DisplayEventKind::ConnectionOpened => self.opened += 1,
+            DisplayEventKind::ConnectionClosed => self.closed += 1,
+            _ => return true,
+        }
+        false
+    }
+
+    /// With `count`, "12 connections opened, 10 closed in last 10s" once per
+    /// interval that had any.
+    pub fn summary(&mut self, now: Instant) -> Option<String> {
+        let elapsed = now.saturating_duration_since(self.window_started);
+        if self.mode != ConnEvents::Count || elapsed < CONN_SUMMARY_INTERVAL {
+            return None;
+        }
+        let (opened, closed) = (
+            std::mem::take(&mut self.opened),
+            std::mem::take(&mut self.closed),
+        );
+        self.window_started = now;
+        (opened + closed > 0).then(|| {
+            let noun = if opened == 1 {
+                "connection"
+            } else {
+                "connections"
+            };
+            format!(
+                "{opened} {noun} opened, {closed} closed in last {}s",
+                elapsed.as_secs()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conn_event_filter_modes() {
+        let event = |kind| DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id: 1,
+            client_addr: None,
+            kind,
+        };
+        let warning = event(DisplayEventKind::Warning("conn 1: query abandoned".into()));
+        let start = Instant::now();
+        let later = start + CONN_SUMMARY_INTERVAL;
+
+        let mut full = ConnEventFilter::new(ConnEvents::Full);
+        assert!(full.admit(&event(DisplayEventKind::ConnectionOpened)));
+        assert_eq!(full.summary(later), None);
+
+        let mut none = ConnEventFilter::new(ConnEvents::None);
+        assert!(!none.admit(&event(DisplayEventKind::ConnectionOpened)));
+        assert!(none.admit(&warning));
+        assert_eq!(none.summary(later), None);
+
+        let mut count = ConnEventFilter::new(ConnEvents::Count);
+        count.window_started = start;
+        for _ in 0..12 {
+            assert!(!count.admit(&event(DisplayEventKind::ConnectionOpened)));
+        }
+        for _ in 0..10 {
+            assert!(!count.admit(&event(DisplayEventKind::ConnectionClosed)));
+        }
+        assert!(count.admit(&warning));
+        assert_eq!(count.summary(start + Duration::from_secs(9)), None);
+        assert_eq!(
+            count.summary(later).as_deref(),
+            Some("12 connections opened, 10 closed in last 10s")
+        );
+        // Quiet intervals say nothing.
+        assert_eq!(count.summary(later + CONN_SUMMARY_INTERVAL), None);
+    }
+
+    #[test]
+    fn test_format_params() {
+        assert_eq!(format_params(&[]), "");
+        assert_eq!(
+            format_params(&[Some("42".into()), None, Some("\\x00ff".into())]),
+            " ($1='42', $2=NULL, $3='\\x00ff')"
+        );
+    }
+
+    #[test]
+    fn test_round_ms() {
+        let d = Duration::from_nanos(2_333_333);
+        assert_eq!(round_ms(d, 3), 2.333);
+        assert_eq!(round_ms(d, 1), 2.3);
+        assert_eq!(round_ms(d, 0), 2.0);
+        assert_eq!(round_ms(Duration::from_micros(1500), 0), 2.0);
+        assert_eq!(serde_json::to_string(&round_ms(d, 3)).unwrap(), "2.333");
+    }
+}
@@ -1,44 +1,663 @@
-use super::{DisplayEvent, DisplayEventKind, OutputSink};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::SharedConfig;
+
+use super::{
+    format_copy, format_mb, format_params, format_slow_transaction, round_ms, ConnEventFilter,
+    ConnEvents, ConnLabel, DisplayEvent, DisplayEventKind, OutputSink, SlowQueryBell,
+};
+
+/// Startup settings for raw output, from the CLI.
+pub struct RawOptions {
+    /// Threshold and bell settings; reloadable.
+    pub config: SharedConfig,
+    /// Ring audibly when the bell is enabled (false with --no-bell).
+    pub bell_audible: bool,
+    pub conn_label: ConnLabel,
+    /// Print at most this many routine lines per second; errors and slow queries always print.
+    pub max_line_rate: Option<u64>,
+    pub flush: FlushMode,
+    pub conn_events: ConnEvents,
+    pub format: RawFormat,
+    /// Decimals for `*_ms` fields in JSON lines.
+    pub precision: u32,
+    pub timestamps: TimestampSource,
+    pub timestamp_format: TimestampFormat,
+}
+
+/// What each raw line looks like (`--format`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RawFormat {
+    #[default]
+    Text,
+    /// One JSON object per event, for `jq` and log shippers.
+    Json,
+}
+
+/// Which clocks each raw line is stamped with (`--timestamps`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampSource {
+    /// When dbprobe saw the message.
+    #[default]
+    Observed,
+    /// That, and the server's estimated time: observed minus the connection's
+    /// one-way latency, taken as half its upstream TCP connect time.
+    Both,
+}
+
+/// How raw line timestamps are written (`--timestamp-format`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampFormat {
+    /// Local time: `HH:MM:SS.mmm` in text lines, RFC 3339 in JSON.
+    #[default]
+    Local,
+    /// Postgres's `%m` log_line_prefix escape, in UTC: `2024-05-01 12:00:00.123 UTC`.
+    Postgres,
+}
+
+/// One `--format json` line: the event's kind-specific fields next to when and where.
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    ts: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_ts: Option<String>,
+    conn_id: u64,
+    client_addr: Option<SocketAddr>,
+    #[serde(flatten)]
+    kind: &'a DisplayEventKind,
+}
+
+/// When buffered output reaches stdout (`--flush`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FlushMode {
+    /// After every line written.
+    Immediate,
+    /// After each event's lines, so `dbprobe | grep` sees every event as it happens.
+    #[default]
+    Line,
+    /// When the buffer fills, or at the next event once `BLOCK_FLUSH_INTERVAL` has
+    /// passed: fewest writes, for high-throughput capture to a file.
+    Block,
+}
+
+/// Size of the stdout buffer; in block mode, how much output is batched per write.
+const OUTPUT_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Longest block mode holds output while events keep arriving.
+const BLOCK_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Simple stdout line-by-line output, pipe-friendly.
-pub struct RawSink;
+pub struct RawSink {
+    config: SharedConfig,
+    bell_audible: bool,
+    bell: Option<SlowQueryBell>,
+    conn_label: ConnLabel,
+    limiter: Option<LineLimiter>,
+    out: BufWriter<Box<dyn Write + Send>>,
+    flush: FlushMode,
+    last_flush: Instant,
+    conn_events: ConnEventFilter,
+    format: RawFormat,
+    precision: u32,
+    timestamps: TimestampSource,
+    timestamp_format: TimestampFormat,
+    /// Estimated one-way latency to the server, per open connection.
+    one_way: HashMap<u64, Duration>,
+}
+
+/// Per-second line budget so a slow terminal can't fall arbitrarily behind the proxy.
+struct LineLimiter {
+    max_per_sec: u64,
+    window_start: Instant,
+    printed: u64,
+    suppressed: u64,
+}
+
+impl LineLimiter {
+    fn new(max_per_sec: u64) -> Self {
+        Self {
+            max_per_sec,
+            window_start: Instant::now(),
+            printed: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Start a new window if a second has passed. Returns lines suppressed in the old one.
+    fn roll(&mut self) -> Option<u64> {
+        if self.window_start.elapsed() < Duration::from_secs(1) {
+            return None;
+        }
+        self.window_start = Instant::now();
+        self.printed = 0;
+        self.take_suppressed()
+    }
+
+    fn allow(&mut self, important: bool) -> bool {
+        if important || self.printed < self.max_per_sec {
+            self.printed += 1;
+            true
+        } else {
+            self.suppressed += 1;
+            false
+        }
+    }
+
+    fn take_suppressed(&mut self) -> Option<u64> {
+        (self.suppressed > 0).then(|| std::mem::take(&mut self.suppressed))
+    }
+}
 
 impl RawSink {
-    pub fn new() -> Self {
-        Self
+    pub fn new(options: RawOptions) -> Self {
+        Self::with_writer(options, Box::new(std::io::stdout()))
+    }
+
+    fn with_writer(options: RawOptions, writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            config: options.config,
+            bell_audible: options.bell_audible,
+            bell: None,
+            conn_label: options.conn_label,
+            limiter: options.max_line_rate.map(LineLimiter::new),
+            out: BufWriter::with_capacity(OUTPUT_BUFFER_BYTES, writer),
+            flush: options.flush,
+            last_flush: Instant::now(),
+            conn_events: ConnEventFilter::new(options.conn_events),
+            format: options.format,
+            precision: options.precision,
+            timestamps: options.timestamps,
+            timestamp_format: options.timestamp_format,
+            one_way: HashMap::new(),
+        }
+    }
+
+    /// The proxy dialed this connection's upstream in `connect_time`: about one round trip.
+    pub fn upstream_connected(&mut self, conn_id: u64, connect_time: Duration) {
+        self.one_way.insert(conn_id, connect_time / 2);
+    }
+
+    fn format_time(&self, at: chrono::DateTime<chrono::Local>) -> String {
+        match (self.timestamp_format, self.format) {
+            (TimestampFormat::Postgres, _) => at
+                .with_timezone(&chrono::Utc)
+                .format("%Y-%m-%d %H:%M:%S%.3f UTC")
+                .to_string(),
+            (TimestampFormat::Local, RawFormat::Text) => at.format("%H:%M:%S%.3f").to_string(),
+            (TimestampFormat::Local, RawFormat::Json) => at.to_rfc3339(),
+        }
+    }
+
+    /// The server's clock when it sent what dbprobe saw at `at`, if asked for and known.
+    fn server_time(
+        &self,
+        at: chrono::DateTime<chrono::Local>,
+        one_way: Option<Duration>,
+    ) -> Option<String> {
+        let one_way = chrono::Duration::from_std(
+            one_way.filter(|_| self.timestamps == TimestampSource::Both)?,
+        )
+        .ok()?;
+        Some(self.format_time(at - one_way))
+    }
+
+    /// `value` on one line, with its `*_ms` fields rounded to `--precision`.
+    fn json_line(&mut self, mut value: Value) {
+        if let Value::Object(map) = &mut value {
+            for (key, field) in map.iter_mut() {
+                if let (true, Some(ms)) = (key.ends_with("_ms"), field.as_f64()) {
+                    *field = round_ms(Duration::from_secs_f64(ms / 1000.0), self.precision).into();
+                }
+            }
+        }
+        self.line(format_args!("{value}"));
+    }
+
+    /// Lines about the output itself rather than one event; `kind` tags them in JSON.
+    fn notice(&mut self, kind: &str, text: fmt::Arguments) {
+        match self.format {
+            RawFormat::Text => self.line(text),
+            RawFormat::Json => {
+                let ts = self.format_time(chrono::Local::now());
+                self.json_line(
+                    serde_json::json!({ "ts": ts, "kind": kind, "message": text.to_string() }),
+                );
+            }
+        }
+    }
+
+    /// Write errors are ignored: a closed pipe (`dbprobe | head`) shouldn't stop the proxy.
+    fn line(&mut self, args: fmt::Arguments) {
+        let _ = self.out.write_fmt(args);
+        let _ = self.out.write_all(b"\n");
+        if self.flush == FlushMode::Immediate {
+            self.flush();
+        }
+    }
+
+    /// Push buffered lines to stdout, e.g. before printing a summary directly.
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+        self.last_flush = Instant::now();
+    }
+
+    fn end_event(&mut self) {
+        match self.flush {
+            FlushMode::Immediate => {}
+            FlushMode::Line => self.flush(),
+            FlushMode::Block if self.last_flush.elapsed() >= BLOCK_FLUSH_INTERVAL => self.flush(),
+            FlushMode::Block => {}
+        }
+    }
+
+    /// Errors and slow queries are never dropped by the line limiter.
+    fn is_important(event: &DisplayEvent, threshold_ms: u64) -> bool {
+        match &event.kind {
+            DisplayEventKind::Error { .. } | DisplayEventKind::SlowTransaction(_) => true,
+            DisplayEventKind::Query { duration, .. } => {
+                duration.as_millis() >= threshold_ms as u128
+            }
+            _ => false,
+        }
     }
 }
 
 impl OutputSink for RawSink {
     fn handle_event(&mut self, event: &DisplayEvent) {
-        let time = event.wall_time.format("%H:%M:%S%.3f");
-        let conn = event.conn_id;
+        let one_way = match event.kind {
+            DisplayEventKind::ConnectionClosed => self.one_way.remove(&event.conn_id),
+            _ => self.one_way.get(&event.conn_id).copied(),
+        };
+        let live = *self.config.read().unwrap();
+        SlowQueryBell::sync(&mut self.bell, live.bell_threshold_ms, self.bell_audible);
+
+        // Ring on stderr so piped stdout stays clean.
+        if let Some(bell) = &mut self.bell {
+            if bell.check(event) && bell.audible {
+                let mut stderr = std::io::stderr();
+                let _ = stderr.write_all(b"\x07");
+                let _ = stderr.flush();
+            }
+        }
+
+        // Hidden opens and closes don't count against --max-line-rate.
+        if let Some(summary) = self.conn_events.summary(Instant::now()) {
+            let time = self.format_time(chrono::Local::now());
+            match self.format {
+                RawFormat::Text => self.line(format_args!("{time} [conns]            {summary}")),
+                RawFormat::Json => self.notice("connections", format_args!("{summary}")),
+            }
+        }
+        if !self.conn_events.admit(event) {
+            self.end_event();
+            return;
+        }
+
+        let important = Self::is_important(event, live.threshold_ms);
+        if let Some(limiter) = &mut self.limiter {
+            let suppressed = limiter.roll();
+            let allowed = limiter.allow(important);
+            if let Some(n) = suppressed {
+                self.notice("suppressed", format_args!("({n} lines suppressed)"));
+            }
+            if !allowed {
+                self.end_event();
+                return;
+            }
+        }
+
+        if self.format == RawFormat::Json {
+            let ts = self.format_time(event.wall_time);
+            let server_ts = self.server_time(event.wall_time, one_way);
+            let line = JsonLine {
+                ts,
+                server_ts,
+                conn_id: event.conn_id,
+                client_addr: event.client_addr,
+                kind: &event.kind,
+            };
+            if let Ok(value) = serde_json::to_value(line) {
+                self.json_line(value);
+            }
+            self.end_event();
+            return;
+        }
+
+        let mut time = self.format_time(event.wall_time);
+        if let Some(server) = self.server_time(event.wall_time, one_way) {
+            time = format!("{time} srv~{server}");
+        }
+        let conn = self.conn_label.format(event.conn_id, event.client_addr);
 
         match &event.kind {
-            DisplayEventKind::Query { sql, duration, rows } => {
+            DisplayEventKind::Query {
+                sql,
+                duration,
+                rows,
+                seq,
+                trace_id,
+                params,
+                result_bytes,
+                large_result,
+                ..
+            } => {
                 let ms = duration.as_secs_f64() * 1000.0;
-                let rows_str = rows.map(|r| format!(" [{r} rows]")).unwrap_or_default();
-                println!("{time} [conn:{conn}] {ms:>8.1}ms  {sql}{rows_str}");
+                let rows_str = match (rows, large_result) {
+                    (Some(r), true) => format!(" [{r} rows, {}]", format_mb(*result_bytes)),
+                    (None, true) => format!(" [{}]", format_mb(*result_bytes)),
+                    (Some(r), false) => format!(" [{r} rows]"),
+                    (None, false) => String::new(),
+                };
+                let trace_str = trace_id
+                    .as_ref()
+                    .map(|id| format!(" trace={id}"))
+                    .unwrap_or_default();
+                let params = format_params(params);
+                self.line(format_args!(
+                    "{time} [conn:{conn}/#{seq}] {ms:>8.1}ms  {sql}{params}{rows_str}{trace_str}"
+                ));
             }
-            DisplayEventKind::Error { code, message, duration, .. } => {
+            DisplayEventKind::Error {
+                code,
+                message,
+                duration,
+                ..
+            } => {
                 let dur_str = duration
                     .map(|d| format!("{:>8.1}ms", d.as_secs_f64() * 1000.0))
                     .unwrap_or_else(|| "        ".to_string());
-                println!("{time} [conn:{conn}] {dur_str}  ERR {code}: {message}");
+                self.line(format_args!(
+                    "{time} [conn:{conn}] {dur_str}  ERR {code}: {message}"
+                ));
+            }
+            DisplayEventKind::Copy {
+                bytes,
+                elapsed,
+                done,
+            } => {
+                self.line(format_args!(
+                    "{time} [conn:{conn}]            {}",
+                    format_copy(*bytes, *elapsed, *done)
+                ));
+            }
+            DisplayEventKind::SlowTransaction(timing) => {
+                let ms = timing.total.as_secs_f64() * 1000.0;
+                self.line(format_args!(
+                    "{time} [conn:{conn}] {ms:>8.1}ms  {}",
+                    format_slow_transaction(timing)
+                ));
             }
             DisplayEventKind::ConnectionOpened => {
-                println!("{time} [conn:{conn}]            ++ connection opened");
+                self.line(format_args!(
+                    "{time} [conn:{conn}]            ++ connection opened"
+                ));
             }
             DisplayEventKind::ConnectionClosed => {
-                println!("{time} [conn:{conn}]            -- connection closed");
+                self.line(format_args!(
+                    "{time} [conn:{conn}]            -- connection closed"
+                ));
+            }
+            DisplayEventKind::ClientCertificate { subject } => {
+                self.line(format_args!(
+                    "{time} [conn:{conn}]            client cert: {subject}"
+                ));
+            }
+            DisplayEventKind::Notification { channel, payload } => {
+                self.line(format_args!(
+                    "{time} [conn:{conn}]            NOTIFY {channel}: {payload}"
+                ));
             }
             DisplayEventKind::Warning(msg) => {
-                println!("{time} [conn:{conn}]            WARN: {msg}");
+                self.line(format_args!("{time} [conn:{conn}]            WARN: {msg}"));
             }
         }
+        self.end_event();
     }
 
     fn shutdown(&mut self) {
-        // No-op
+        if let Some(n) = self.limiter.as_mut().and_then(LineLimiter::take_suppressed) {
+            self.notice("suppressed", format_args!("({n} lines suppressed)"));
+        }
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LiveConfig;
+    use std::sync::{Arc, Mutex, RwLock};
+
+    /// Records what reaches the underlying writer, i.e. what has been flushed.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn lines(&self) -> usize {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count()
+        }
+    }
+
+    fn sink(flush: FlushMode, captured: &Captured) -> RawSink {
+        sink_with_format(flush, RawFormat::Text, captured)
+    }
+
+    fn sink_with_format(flush: FlushMode, format: RawFormat, captured: &Captured) -> RawSink {
+        let options = RawOptions {
+            config: Arc::new(RwLock::new(LiveConfig {
+                threshold_ms: 100,
+                bell_threshold_ms: None,
+            })),
+            bell_audible: false,
+            conn_label: ConnLabel::default(),
+            max_line_rate: None,
+            flush,
+            conn_events: ConnEvents::Full,
+            format,
+            precision: 3,
+            timestamps: TimestampSource::Observed,
+            timestamp_format: TimestampFormat::Local,
+        };
+        RawSink::with_writer(options, Box::new(captured.clone()))
+    }
+
+    fn opened() -> DisplayEvent {
+        DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id: 1,
+            client_addr: None,
+            kind: DisplayEventKind::ConnectionOpened,
+        }
+    }
+
+    #[test]
+    fn test_flush_modes() {
+        for mode in [FlushMode::Immediate, FlushMode::Line] {
+            let captured = Captured::default();
+            let mut sink = sink(mode, &captured);
+            sink.handle_event(&opened());
+            assert_eq!(captured.lines(), 1, "{mode:?}");
+        }
+
+        // Block mode holds lines until the buffer fills, a second passes, or shutdown.
+        let captured = Captured::default();
+        let mut sink = sink(FlushMode::Block, &captured);
+        for _ in 0..10 {
+            sink.handle_event(&opened());
+        }
+        assert_eq!(captured.lines(), 0);
+        sink.last_flush -= BLOCK_FLUSH_INTERVAL;
+        sink.handle_event(&opened());
+        assert_eq!(captured.lines(), 11);
+        sink.handle_event(&opened());
+        sink.shutdown();
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 12);
+        assert!(output
+            .lines()
+            .all(|line| line.ends_with("++ connection opened")));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let captured = Captured::default();
+        let mut sink = sink_with_format(FlushMode::Line, RawFormat::Json, &captured);
+        let query = DisplayEventKind::Query {
+            sql: "SELECT * FROM users WHERE id = $1".into(),
+            operation: crate::fingerprint::Operation::Select,
+            duration: Duration::from_micros(12_345_678),
+            rows: Some(1),
+            seq: 3,
+            trace_id: None,
+            params: vec![Some("42".into())],
+            result_bytes: 64,
+            large_result: false,
+        };
+        let error = DisplayEventKind::Error {
+            sql: None,
+            duration: None,
+            code: "57014".into(),
+            message: "canceled".into(),
+        };
+        let notification = DisplayEventKind::Notification {
+            channel: "orders".into(),
+            payload: "7".into(),
+        };
+        for kind in [
+            query,
+            error,
+            DisplayEventKind::Warning("slow".into()),
+            DisplayEventKind::ConnectionOpened,
+            notification,
+        ] {
+            sink.handle_event(&DisplayEvent { kind, ..opened() });
+        }
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0]["ts"].is_string());
+        assert_eq!(lines[0]["conn_id"], 1);
+        assert_eq!(lines[0]["kind"], "query");
+        assert_eq!(lines[0]["operation"], "SELECT");
+        assert_eq!(lines[0]["duration_ms"], 12345.678);
+        assert_eq!(lines[0]["rows"], 1);
+        assert_eq!(lines[0]["params"], serde_json::json!(["42"]));
+        assert_eq!(lines[1]["kind"], "error");
+        assert_eq!(lines[1]["code"], "57014");
+        assert_eq!(lines[1]["duration_ms"], Value::Null);
+        assert_eq!(
+            lines[2],
+            serde_json::json!({ "ts": lines[2]["ts"], "conn_id": 1, "client_addr": null, "kind": "warning", "message": "slow" })
+        );
+        assert_eq!(lines[3]["kind"], "connection_opened");
+        assert_eq!(
+            (
+                &lines[4]["kind"],
+                &lines[4]["channel"],
+                &lines[4]["payload"]
+            ),
+            (&"notification".into(), &"orders".into(), &"7".into())
+        );
+    }
+
+    #[test]
+    fn test_server_time_estimate() {
+        use chrono::TimeZone;
+        let at = chrono::Utc
+            .with_ymd_and_hms(2024, 5, 1, 12, 0, 0)
+            .unwrap()
+            .with_timezone(&chrono::Local);
+        let event = |kind| DisplayEvent {
+            wall_time: at,
+            conn_id: 1,
+            client_addr: None,
+            kind,
+        };
+        let captured = Captured::default();
+        let mut sink = sink(FlushMode::Line, &captured);
+        sink.timestamps = TimestampSource::Both;
+        sink.timestamp_format = TimestampFormat::Postgres;
+        sink.upstream_connected(1, Duration::from_millis(8));
+        sink.handle_event(&event(DisplayEventKind::Warning("slow".into())));
+        sink.handle_event(&event(DisplayEventKind::ConnectionClosed));
+        // Unknown once the connection is gone.
+        sink.handle_event(&event(DisplayEventKind::Warning("late".into())));
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "2024-05-01 12:00:00.000 UTC srv~2024-05-01 11:59:59.996 UTC [conn:1]            WARN: slow");
+        assert!(
+            lines[1].starts_with(
+                "2024-05-01 12:00:00.000 UTC srv~2024-05-01 11:59:59.996 UTC [conn:1]"
+            ),
+            "{}",
+            lines[1]
+        );
+        assert_eq!(
+            lines[2],
+            "2024-05-01 12:00:00.000 UTC [conn:1]            WARN: late"
+        );
+
+        let captured = Captured::default();
+        let mut sink = sink_with_format(FlushMode::Line, RawFormat::Json, &captured);
+        sink.timestamps = TimestampSource::Both;
+        sink.handle_event(&event(DisplayEventKind::ConnectionOpened));
+        sink.upstream_connected(1, Duration::from_millis(8));
+        sink.handle_event(&event(DisplayEventKind::Warning("slow".into())));
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["server_ts"], Value::Null);
+        let ts = |v: &Value| chrono::DateTime::parse_from_rfc3339(v.as_str().unwrap()).unwrap();
+        assert_eq!(
+            ts(&lines[1]["ts"]) - ts(&lines[1]["server_ts"]),
+            chrono::Duration::milliseconds(4)
+        );
+    }
+
+    #[test]
+    fn test_notification_line() {
+        let captured = Captured::default();
+        let mut sink = sink(FlushMode::Line, &captured);
+        let kind = DisplayEventKind::Notification {
+            channel: "orders".into(),
+            payload: "{\"id\": 7}".into(),
+        };
+        sink.handle_event(&DisplayEvent { kind, ..opened() });
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            output
+                .trim_end()
+                .ends_with("[conn:1]            NOTIFY orders: {\"id\": 7}"),
+            "{output}"
+        );
     }
 }
@@ -0,0 +1,422 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::output::round_ms;
+use crate::protocol::Direction;
+use crate::snapshot::{LatencyBuckets, SnapshotQuery};
+use crate::stats::{aggregate_key, MessageCounts, StatsCollector};
+
+/// Fingerprints listed in the end-of-run summary.
+const SUMMARY_TOP_QUERIES: usize = 10;
+
+/// How `--summary` and `--stats-only` print their report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SummaryFormat {
+    #[default]
+    Text,
+    /// One JSON object per report, on a single line.
+    Json,
+}
+
+/// The summary as `--summary-format json` writes it. Shares its query and
+/// bucket types with snapshots so scripts can read either.
+#[derive(Serialize)]
+pub struct SummaryReport {
+    pub duration_secs: f64,
+    pub total_queries: u64,
+    pub avg_qps: f64,
+    pub total_errors: u64,
+    pub abandoned_queries: u64,
+    pub backpressure_stalls: u64,
+    /// Connections labeled as a pooler's server connections.
+    pub pooled_connections: u64,
+    pub latency_buckets: LatencyBuckets,
+    /// Same p50/p95/p99 as the text summary; absent before any query completes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentiles_ms: Option<Percentiles>,
+    pub message_counts: MessageCounts,
+    /// By total time, longest first.
+    pub top_queries: Vec<SnapshotQuery>,
+    /// By count, most frequent first.
+    pub errors: Vec<SummaryError>,
+    /// Shapes `--allowed-fingerprints` doesn't list, most frequent first.
+    pub unexpected_shapes: Vec<UnexpectedShape>,
+}
+
+#[derive(Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+#[derive(Serialize)]
+pub struct UnexpectedShape {
+    pub fingerprint: String,
+    pub count: u64,
+}
+
+#[derive(Serialize)]
+pub struct SummaryError {
+    pub code: String,
+    pub pattern: String,
+    pub count: u64,
+}
+
+impl SummaryReport {
+    pub fn new(stats: &StatsCollector, elapsed: Duration, precision: u32) -> Self {
+        let secs = elapsed.as_secs_f64();
+        let mut errors: Vec<_> = stats
+            .errors
+            .values()
+            .map(|e| SummaryError {
+                code: e.code.clone(),
+                pattern: e.pattern.clone(),
+                count: e.count,
+            })
+            .collect();
+        errors.sort_by_key(|e| std::cmp::Reverse(e.count));
+        Self {
+            duration_secs: secs,
+            total_queries: stats.total_queries,
+            avg_qps: if secs > 0.0 {
+                stats.total_queries as f64 / secs
+            } else {
+                0.0
+            },
+            total_errors: stats.total_errors,
+            abandoned_queries: stats.abandoned_queries,
+            backpressure_stalls: stats.backpressure_stalls,
+            pooled_connections: stats.pooled_connections,
+            latency_buckets: LatencyBuckets::from_array(&stats.latency_buckets),
+            percentiles_ms: stats.percentiles().map(|(p50, p95, p99)| Percentiles {
+                p50: round_ms(p50, precision),
+                p95: round_ms(p95, precision),
+                p99: round_ms(p99, precision),
+            }),
+            message_counts: stats.message_counts.clone(),
+            top_queries: stats
+                .top_queries(SUMMARY_TOP_QUERIES)
+                .into_iter()
+                .map(|q| SnapshotQuery::from_aggregates(q, precision))
+                .collect(),
+            errors,
+            unexpected_shapes: unexpected_shapes(stats)
+                .into_iter()
+                .map(|(fingerprint, count)| UnexpectedShape {
+                    fingerprint: fingerprint.clone(),
+                    count,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Most frequent first, ties by fingerprint so the report is stable.
+fn unexpected_shapes(stats: &StatsCollector) -> Vec<(&String, u64)> {
+    let mut shapes: Vec<_> = stats
+        .unexpected_shapes
+        .iter()
+        .map(|(fp, &count)| (fp, count))
+        .collect();
+    shapes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    shapes
+}
+
+/// Where and how summaries go: stdout or `--summary-file`, text or JSON. With
+/// `--window` there is one report per window; the file collects all of them.
+pub struct SummaryOutput {
+    pub format: SummaryFormat,
+    pub path: Option<PathBuf>,
+    pub precision: u32,
+    /// Whether the file has been started, so later reports append to it.
+    started: bool,
+}
+
+impl SummaryOutput {
+    pub fn new(format: SummaryFormat, path: Option<PathBuf>, precision: u32) -> Self {
+        Self {
+            format,
+            path,
+            precision,
+            started: false,
+        }
+    }
+
+    pub fn render(&self, stats: &StatsCollector, elapsed: Duration) -> String {
+        match self.format {
+            SummaryFormat::Text => format_summary(stats, elapsed),
+            SummaryFormat::Json => {
+                let report = SummaryReport::new(stats, elapsed, self.precision);
+                serde_json::to_string(&report)
+                    .map(|json| json + "\n")
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    pub fn write(&mut self, stats: &StatsCollector, elapsed: Duration) {
+        let report = self.render(stats, elapsed);
+        let Some(path) = &self.path else {
+            print!("{report}");
+            return;
+        };
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(self.started)
+            .truncate(!self.started)
+            .open(path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, report.as_bytes()));
+        match result {
+            Ok(()) => self.started = true,
+            Err(e) => tracing::error!("Writing summary to {}: {e}", path.display()),
+        }
+    }
+}
+
+/// End-of-run report: totals, latency distribution, top queries and error groups.
+pub fn format_summary(stats: &StatsCollector, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let avg_qps = if secs > 0.0 {
+        stats.total_queries as f64 / secs
+    } else {
+        0.0
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "--- dbprobe summary ---");
+    let _ = writeln!(out, "Duration:  {secs:.1}s");
+    let _ = writeln!(
+        out,
+        "Queries:   {} ({avg_qps:.1} qps avg)",
+        stats.total_queries
+    );
+    let _ = writeln!(out, "Errors:    {}", stats.total_errors);
+    if stats.abandoned_queries > 0 {
+        let _ = writeln!(
+            out,
+            "Abandoned: {} (connection closed before the query completed)",
+            stats.abandoned_queries
+        );
+    }
+    if stats.backpressure_stalls > 0 {
+        let _ = writeln!(
+            out,
+            "Stalls:    {} (server data waited on a slow client)",
+            stats.backpressure_stalls
+        );
+    }
+    if stats.pooled_connections > 0 {
+        let _ = writeln!(out, "Pooled:    {} connections looked like a pooler's (per-connection numbers cover many app sessions)", stats.pooled_connections);
+    }
+
+    let labels = ["≤1ms", "1-5ms", "5-10ms", "10-50ms", "50-100ms", ">100ms"];
+    let buckets: Vec<String> = labels
+        .iter()
+        .zip(stats.latency_buckets.iter())
+        .map(|(label, count)| format!("{label} {count}"))
+        .collect();
+    let _ = writeln!(out, "Latency:   {}", buckets.join(" | "));
+    if let Some((p50, p95, p99)) = stats.percentiles() {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let _ = writeln!(
+            out,
+            "           p50 {:.1}ms | p95 {:.1}ms | p99 {:.1}ms",
+            ms(p50),
+            ms(p95),
+            ms(p99)
+        );
+    }
+    if stats.total_queries > 0 {
+        let labels = ["<1KB", "1-8KB", "8-64KB", "64KB-1MB", ">1MB"];
+        let sizes: Vec<String> = labels
+            .iter()
+            .zip(stats.sql_size_buckets.iter())
+            .map(|(label, count)| format!("{label} {count}"))
+            .collect();
+        let _ = writeln!(out, "SQL size:  {}", sizes.join(" | "));
+    }
+
+    if !stats.message_counts.is_empty() {
+        let protocol = stats
+            .message_counts
+            .query_protocol()
+            .map(|p| format!(" ({p} protocol)"))
+            .unwrap_or_default();
+        let _ = writeln!(out, "\nMessages{protocol}:");
+        for (direction, label) in [
+            (Direction::Frontend, "client"),
+            (Direction::Backend, "server"),
+        ] {
+            let counts: Vec<String> = stats
+                .message_counts
+                .ranked(direction)
+                .into_iter()
+                .map(|(name, count)| format!("{name} {count}"))
+                .collect();
+            let _ = writeln!(out, "  {label}: {}", counts.join(", "));
+        }
+    }
+
+    let top = stats.top_queries(SUMMARY_TOP_QUERIES);
+    if !top.is_empty() {
+        let _ = writeln!(out, "\nTop queries by total time:");
+        let _ = writeln!(
+            out,
+            "  {:>8}  {:>10}  {:>10}  QUERY",
+            "COUNT", "AVG ms", "TOTAL ms"
+        );
+        for q in &top {
+            let total_ms = q.total_duration.as_secs_f64() * 1000.0;
+            let avg_ms = total_ms / q.count.max(1) as f64;
+            // Shapes with oversized SQL are usually generated; say how big they get.
+            let size = if q.max_sql_bytes >= stats.large_query_bytes() {
+                format!(" [up to {:.1}KB of SQL]", q.max_sql_bytes as f64 / 1024.0)
+            } else {
+                String::new()
+            };
+            let query = aggregate_key(&q.fingerprint, q.group.as_deref());
+            let _ = writeln!(
+                out,
+                "  {:>8}  {avg_ms:>10.2}  {total_ms:>10.1}  {query}{size}",
+                q.count
+            );
+        }
+    }
+
+    let unexpected = unexpected_shapes(stats);
+    if !unexpected.is_empty() {
+        let _ = writeln!(
+            out,
+            "\nUnexpected query shapes (not in --allowed-fingerprints):"
+        );
+        for (fingerprint, count) in unexpected {
+            let _ = writeln!(out, "  {count:>8}  {fingerprint}");
+        }
+    }
+
+    if !stats.errors.is_empty() {
+        let mut groups: Vec<_> = stats.errors.values().collect();
+        groups.sort_unstable_by_key(|e| std::cmp::Reverse(e.count));
+        let _ = writeln!(out, "\nErrors by pattern:");
+        for e in groups {
+            let _ = writeln!(out, "  {:>8}  {}  {}", e.count, e.code, e.pattern);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtoEvent;
+    use crate::stats::StatsConfig;
+    use std::time::Instant;
+
+    #[test]
+    fn test_summary_lists_totals_and_top_queries() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(1, None);
+        let now = Instant::now();
+        for id in 1..=3 {
+            stats.record_event(
+                1,
+                ProtoEvent::QueryStart {
+                    sql: format!("SELECT * FROM users WHERE id = {id}"),
+                    sql_bytes: 0,
+                    portal: None,
+                    params: Vec::new(),
+                },
+                now,
+            );
+            stats.record_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                },
+                now,
+            );
+        }
+
+        let summary = format_summary(&stats, Duration::from_secs(2));
+        assert!(summary.contains("Queries:   3 (1.5 qps avg)"));
+        assert!(summary.contains("select * from users where id = $n"));
+        assert!(!summary.contains("Errors by pattern"));
+    }
+
+    #[test]
+    fn test_json_summary_is_one_object_per_report() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(1, None);
+        let now = Instant::now();
+        stats.record_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: "SELECT 1".into(),
+                sql_bytes: 0,
+                portal: None,
+                params: Vec::new(),
+            },
+            now,
+        );
+        stats.record_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SELECT 1".into(),
+                rows: Some(1),
+                bytes: 0,
+            },
+            now,
+        );
+        stats.record_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: "SELECT * FROM nope".into(),
+                sql_bytes: 0,
+                portal: None,
+                params: Vec::new(),
+            },
+            now,
+        );
+        stats.record_event(
+            1,
+            ProtoEvent::QueryError {
+                severity: "ERROR".into(),
+                code: "42P01".into(),
+                message: "relation \"nope\" does not exist".into(),
+            },
+            now,
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("dbprobe-summary-{}.json", std::process::id()));
+        let mut output = SummaryOutput::new(SummaryFormat::Json, Some(path.clone()), 3);
+        output.write(&stats, Duration::from_secs(4));
+        output.write(&stats, Duration::from_secs(8));
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let reports: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(reports.len(), 2);
+        let report = &reports[0];
+        assert_eq!(report["total_queries"], 1);
+        assert_eq!(report["avg_qps"], 0.25);
+        assert_eq!(report["total_errors"], 1);
+        assert_eq!(report["latency_buckets"]["under_1ms"], 1);
+        assert_eq!(report["percentiles_ms"]["p50"], 0.0);
+        assert_eq!(report["percentiles_ms"]["p99"], 0.0);
+        assert_eq!(report["top_queries"][0]["fingerprint"], "select $n");
+        assert_eq!(report["errors"][0]["code"], "42P01");
+        assert_eq!(reports[1]["duration_secs"], 8.0);
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::fingerprint::fingerprint;
+
+use super::{DisplayEvent, DisplayEventKind, OutputSink};
+
+/// `--trace-json`: events in the Chrome Trace Event format, for chrome://tracing
+/// or Perfetto. Queries are complete ("X") events on one track per connection;
+/// connection open/close are instant ("i") events.
+///
+/// The file is a JSON array written one event per line. Both viewers accept an
+/// array with no closing bracket, so a file cut short by a crash still loads;
+/// `shutdown` closes it properly.
+pub struct TraceWriter {
+    out: Option<BufWriter<File>>,
+    /// Timestamps are microseconds since this moment.
+    started: chrono::DateTime<chrono::Local>,
+    first: bool,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("creating trace file {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(b"[\n")?;
+        Ok(Self {
+            out: Some(out),
+            started: chrono::Local::now(),
+            first: true,
+        })
+    }
+
+    /// Microseconds from the start of the trace to `end - duration`.
+    fn ts(&self, event: &DisplayEvent, duration: std::time::Duration) -> i64 {
+        let end = (event.wall_time - self.started)
+            .num_microseconds()
+            .unwrap_or(0);
+        (end - duration.as_micros() as i64).max(0)
+    }
+
+    fn trace_events(&self, event: &DisplayEvent) -> Vec<Value> {
+        let tid = event.conn_id;
+        match &event.kind {
+            DisplayEventKind::Query {
+                sql,
+                operation,
+                duration,
+                rows,
+                seq,
+                trace_id,
+                result_bytes,
+                ..
+            } => vec![json!({
+                "name": fingerprint(sql),
+                "cat": "query",
+                "ph": "X",
+                "ts": self.ts(event, *duration),
+                "dur": duration.as_micros() as u64,
+                "pid": 1,
+                "tid": tid,
+                "args": { "sql": sql, "operation": operation, "rows": rows, "result_bytes": result_bytes, "seq": seq, "trace_id": trace_id },
+            })],
+            DisplayEventKind::Error {
+                sql,
+                duration,
+                code,
+                message,
+            } => {
+                let duration = duration.unwrap_or_default();
+                vec![json!({
+                    "name": sql.as_deref().map(fingerprint).unwrap_or_else(|| format!("ERROR {code}")),
+                    "cat": "error",
+                    "ph": "X",
+                    "ts": self.ts(event, duration),
+                    "dur": duration.as_micros() as u64,
+                    "pid": 1,
+                    "tid": tid,
+                    "args": { "sql": sql, "code": code, "message": message },
+                })]
+            }
+            DisplayEventKind::ConnectionOpened => {
+                let name = match event.client_addr {
+                    Some(addr) => format!("conn {tid} ({addr})"),
+                    None => format!("conn {tid}"),
+                };
+                vec![
+                    // Label the connection's track.
+                    json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": tid, "args": { "name": name } }),
+                    instant("connection opened", self.ts(event, Default::default()), tid),
+                ]
+            }
+            DisplayEventKind::ConnectionClosed => {
+                vec![instant(
+                    "connection closed",
+                    self.ts(event, Default::default()),
+                    tid,
+                )]
+            }
+            DisplayEventKind::Copy { .. }
+            | DisplayEventKind::SlowTransaction(_)
+            | DisplayEventKind::ClientCertificate { .. }
+            | DisplayEventKind::Notification { .. }
+            | DisplayEventKind::Warning(_) => Vec::new(),
+        }
+    }
+
+    fn write(&mut self, values: Vec<Value>) -> std::io::Result<()> {
+        let Some(out) = self.out.as_mut() else {
+            return Ok(());
+        };
+        for value in values {
+            if !self.first {
+                out.write_all(b",\n")?;
+            }
+            self.first = false;
+            serde_json::to_writer(&mut *out, &value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A thread-scoped instant event.
+fn instant(name: &str, ts: i64, tid: u64) -> Value {
+    json!({ "name": name, "cat": "connection", "ph": "i", "s": "t", "ts": ts, "pid": 1, "tid": tid })
+}
+
+impl OutputSink for TraceWriter {
+    fn handle_event(&mut self, event: &DisplayEvent) {
+        let values = self.trace_events(event);
+        if let Err(e) = self.write(values) {
+            // Keep proxying; just stop tracing.
+            tracing::warn!("Trace file write failed, tracing stopped: {e}");
+            self.out = None;
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(mut out) = self.out.take() {
+            if let Err(e) = out.write_all(b"\n]\n").and_then(|_| out.flush()) {
+                tracing::warn!("Trace file write failed: {e}");
+            }
+        }
+    }
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::Operation;
+    use std::time::Duration;
+
+    #[test]
+    fn test_trace_is_valid_chrome_trace_json() {
+        let path = std::env::temp_dir().join(format!("dbprobe-trace-{}.json", std::process::id()));
+        let mut writer = TraceWriter::create(&path).unwrap();
+        let at = |ms| writer.started + chrono::Duration::milliseconds(ms);
+        let events = [
+            DisplayEvent {
+                wall_time: at(10),
+                conn_id: 7,
+                client_addr: "127.0.0.1:5000".parse().ok(),
+                kind: DisplayEventKind::ConnectionOpened,
+            },
+            DisplayEvent {
+                wall_time: at(50),
+                conn_id: 7,
+                client_addr: None,
+                kind: DisplayEventKind::Query {
+                    sql: "SELECT * FROM users WHERE id = 1".into(),
+                    operation: Operation::Select,
+                    duration: Duration::from_millis(30),
+                    rows: Some(1),
+                    seq: 1,
+                    trace_id: None,
+                    params: Vec::new(),
+                    result_bytes: 0,
+                    large_result: false,
+                },
+            },
+            DisplayEvent {
+                wall_time: at(60),
+                conn_id: 7,
+                client_addr: None,
+                kind: DisplayEventKind::Warning("ignored".into()),
+            },
+            DisplayEvent {
+                wall_time: at(70),
+                conn_id: 7,
+                client_addr: None,
+                kind: DisplayEventKind::ConnectionClosed,
+            },
+        ];
+        for event in &events {
+            writer.handle_event(event);
+        }
+        writer.shutdown();
+
+        let trace: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(trace.len(), 4);
+        assert_eq!(trace[0]["args"]["name"], "conn 7 (127.0.0.1:5000)");
+        assert_eq!(trace[1]["ph"], "i");
+        let query = &trace[2];
+        assert_eq!(query["ph"], "X");
+        assert_eq!(query["name"], "select * from users where id = $n");
+        assert_eq!(query["ts"], 20_000);
+        assert_eq!(query["dur"], 30_000);
+        assert_eq!(query["tid"], 7);
+        assert_eq!(query["args"]["rows"], 1);
+        assert_eq!(trace[3]["name"], "connection closed");
+    }
+}
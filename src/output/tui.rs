@@ -1,71 +1,523 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant};
 
+use chrono::NaiveTime;
 use crossterm::event::{self, Event, KeyCode, KeyModifiers};
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
 use crossterm::ExecutableCommand;
 use ratatui::prelude::*;
-use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, BarChart};
-use serde::{Deserialize, Serialize};
+use ratatui::widgets::{Bar, BarChart, BarGroup, Block, Borders, Cell, Paragraph, Row, Table};
 use tokio::sync::mpsc;
+use tracing::Level;
 
-use crate::proxy::ProxyMessage;
-use crate::stats::{FrozenStats, QueryAggregates, StatsCollector};
-use super::{DisplayEvent, DisplayEventKind};
+use super::logs::LogBuffer;
+use super::trace::TraceWriter;
+use super::{
+    format_copy, format_mb, format_params, format_slow_transaction, ConnEventFilter, ConnEvents,
+    ConnLabel, DisplayEvent, DisplayEventKind, OutputSink, SlowQueryBell,
+};
+use crate::capture::ReplayControl;
+use crate::config::SharedConfig;
+use crate::control::EventLog;
+use crate::fingerprint::normalize_error;
+use crate::metrics::Metrics;
+use crate::protocol::{Direction, ResultFormat};
+use crate::proxy::{set_upstream, ProxyMessage, SharedUpstream};
+use crate::snapshot::{LatencyBuckets, Snapshot, SnapshotEvent, SnapshotQuery, SNAPSHOT_VERSION};
+use crate::stats::{
+    aggregate_key, ErrorAggregates, FrozenStats, MessageCounts, QueryAggregates, StatsCollector,
+    StatsConfig,
+};
+use crate::tracecontext::short_trace_id;
+use crate::truncate::truncate;
 
-#[derive(Serialize, Deserialize)]
-struct Snapshot {
-    timestamp: String,
-    total_queries: u64,
-    total_errors: u64,
-    active_connections: u64,
-    latency_buckets: LatencyBuckets,
-    top_queries: Vec<SnapshotQuery>,
-    recent_events: Vec<SnapshotEvent>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct LatencyBuckets {
-    under_1ms: u64,
-    ms_1_5: u64,
-    ms_5_10: u64,
-    ms_10_50: u64,
-    ms_50_100: u64,
-    over_100ms: u64,
-}
-
-#[derive(Serialize, Deserialize)]
-struct SnapshotQuery {
-    fingerprint: String,
-    count: u64,
-    avg_ms: f64,
-    min_ms: f64,
-    max_ms: f64,
-}
+const MAX_EVENTS: usize = 10_000;
 
-#[derive(Serialize, Deserialize)]
-struct SnapshotEvent {
-    time: String,
-    conn_id: u64,
-    latency: String,
-    message: String,
+/// What a row represents — drives filtering and in-place updates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RowKind {
+    Query,
+    Error,
+    Connection,
+    Warning,
+    /// COPY throughput; a live row (`done == false`) is updated in place.
+    Copy {
+        done: bool,
+    },
+    /// Transaction over the slow-transaction threshold; also listed in its own panel.
+    SlowTransaction,
+    /// LISTEN/NOTIFY delivery.
+    Notification,
+    /// dbprobe's own status messages (save/import results).
+    Status,
 }
 
-const MAX_EVENTS: usize = 10_000;
-
 #[derive(Clone)]
 struct QueryRow {
+    kind: RowKind,
     time: String,
     instant: Instant,
     conn_id: u64,
+    client_addr: Option<SocketAddr>,
     latency: String,
     /// Raw SQL for query events (used for fingerprint toggle), None for non-query rows.
     raw_sql: Option<String>,
     rows_suffix: String,
+    /// Bound parameter values after the SQL; hidden with fingerprints, which
+    /// are meant to leave literals out.
+    params: String,
     /// Pre-formatted display text for non-query events; ignored when raw_sql is Some.
     display: String,
     style: Style,
+    /// Query/error latency, kept numerically so aggregates can be recomputed from rows.
+    duration: Option<Duration>,
+    /// (SQLSTATE, message) for error rows.
+    error: Option<(String, String)>,
+    /// ELAPSED cell as of when the row arrived, shown with `--freeze-elapsed`.
+    elapsed: String,
+    /// Query number on its connection; unknown for imported rows.
+    seq: Option<u64>,
+    /// Latency as a multiple of its fingerprint's average before it arrived,
+    /// once there are enough earlier samples; for `--relative-coloring`.
+    baseline_ratio: Option<f64>,
+    /// From the query's sqlcommenter `traceparent`; shortened after the SQL.
+    trace_id: Option<String>,
+    /// Identical consecutive errors merged into this row by `--merge-errors`.
+    repeats: u64,
+}
+
+impl QueryRow {
+    /// A row of `kind` showing `display`, stamped now, with nothing else set.
+    fn new(kind: RowKind, display: String, style: Style) -> Self {
+        QueryRow {
+            elapsed: String::new(),
+            kind,
+            time: chrono::Local::now().format("%H:%M:%S%.3f").to_string(),
+            instant: Instant::now(),
+            conn_id: 0,
+            client_addr: None,
+            latency: String::new(),
+            raw_sql: None,
+            rows_suffix: String::new(),
+            params: String::new(),
+            display,
+            style,
+            duration: None,
+            error: None,
+            seq: None,
+            baseline_ratio: None,
+            trace_id: None,
+            repeats: 1,
+        }
+    }
+
+    /// A dbprobe status line (save/import results) injected into the log.
+    fn status(message: String) -> Self {
+        Self::new(RowKind::Status, message, Style::default().fg(Color::Cyan))
+    }
+
+    /// The row plus the strings it owns, for the header's memory estimate.
+    fn approx_bytes(&self) -> usize {
+        let error = self
+            .error
+            .as_ref()
+            .map_or(0, |(code, message)| code.capacity() + message.capacity());
+        size_of::<Self>()
+            + self.time.capacity()
+            + self.latency.capacity()
+            + self.raw_sql.as_ref().map_or(0, String::capacity)
+            + self.trace_id.as_ref().map_or(0, String::capacity)
+            + self.rows_suffix.capacity()
+            + self.params.capacity()
+            + self.display.capacity()
+            + self.elapsed.capacity()
+            + error
+    }
+}
+
+fn rows_memory_bytes(rows: &VecDeque<QueryRow>) -> usize {
+    (rows.capacity() - rows.len()) * size_of::<QueryRow>()
+        + rows.iter().map(QueryRow::approx_bytes).sum::<usize>()
+}
+
+/// Inclusive wall-clock window (time of day) applied to a frozen tab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TimeRange {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeRange {
+    /// Parse `HH:MM:SS[.mmm]-HH:MM:SS[.mmm]` (a space also separates the two ends).
+    fn parse(input: &str) -> Option<Self> {
+        let (start, end) = input
+            .trim()
+            .split_once(['-', ' '])
+            .map(|(a, b)| (a.trim(), b.trim()))?;
+        let start = parse_time_of_day(start)?;
+        let end = parse_time_of_day(end)?;
+        (start <= end).then_some(Self { start, end })
+    }
+
+    fn contains(&self, row: &QueryRow) -> bool {
+        parse_time_of_day(&row.time).is_some_and(|t| self.start <= t && t <= self.end)
+    }
+}
+
+fn parse_time_of_day(s: &str) -> Option<NaiveTime> {
+    ["%H:%M:%S%.f", "%H:%M:%S", "%H:%M"]
+        .iter()
+        .find_map(|fmt| NaiveTime::parse_from_str(s, fmt).ok())
+}
+
+/// A frozen tab narrowed to a time range: filtered rows plus aggregates recomputed from them.
+struct RangeView {
+    range: TimeRange,
+    events: VecDeque<QueryRow>,
+    stats: FrozenStats,
+}
+
+impl RangeView {
+    fn new(range: TimeRange, events: &VecDeque<QueryRow>, full_stats: &FrozenStats) -> Self {
+        let events: VecDeque<QueryRow> = events
+            .iter()
+            .filter(|r| range.contains(r))
+            .cloned()
+            .collect();
+        let mut stats = FrozenStats {
+            active_connections: full_stats.active_connections,
+            pooled_connections: full_stats.pooled_connections,
+            applications: full_stats.applications.clone(),
+            client_hosts: full_stats.client_hosts.clone(),
+            ..FrozenStats::default()
+        };
+        for row in &events {
+            match (&row.raw_sql, row.duration, &row.error) {
+                (Some(sql), Some(duration), _) => stats.add_query(sql, duration),
+                (_, _, Some((code, message))) => {
+                    for _ in 0..row.repeats {
+                        stats.add_error(code, message);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self {
+            range,
+            events,
+            stats,
+        }
+    }
+}
+
+/// Which rows the query table shows; cycled with `E`, remembered per tab.
+/// Only the display is filtered — aggregation sees every event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum RowFilter {
+    #[default]
+    All,
+    Errors,
+    ErrorsAndWarnings,
+}
+
+impl RowFilter {
+    fn next(self) -> Self {
+        match self {
+            RowFilter::All => RowFilter::Errors,
+            RowFilter::Errors => RowFilter::ErrorsAndWarnings,
+            RowFilter::ErrorsAndWarnings => RowFilter::All,
+        }
+    }
+
+    fn shows(self, kind: RowKind) -> bool {
+        match self {
+            RowFilter::All => true,
+            RowFilter::Errors => kind == RowKind::Error,
+            RowFilter::ErrorsAndWarnings => matches!(kind, RowKind::Error | RowKind::Warning),
+        }
+    }
+
+    /// Query table title note.
+    fn label(self) -> Option<&'static str> {
+        match self {
+            RowFilter::All => None,
+            RowFilter::Errors => Some("errors only"),
+            RowFilter::ErrorsAndWarnings => Some("errors + warnings"),
+        }
+    }
+}
+
+/// Order of the query table while the live tab is paused; cycled with `o`.
+/// Sorts a view of the rows: `events` keeps arrival order, and resuming
+/// goes back to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum RowSort {
+    #[default]
+    Arrival,
+    /// Slowest first; rows without a latency go last.
+    Latency,
+    /// By connection id, in arrival order within each.
+    Conn,
+}
+
+impl RowSort {
+    fn next(self) -> Self {
+        match self {
+            RowSort::Arrival => RowSort::Latency,
+            RowSort::Latency => RowSort::Conn,
+            RowSort::Conn => RowSort::Arrival,
+        }
+    }
+
+    /// Query table title note.
+    fn label(self) -> Option<&'static str> {
+        match self {
+            RowSort::Arrival => None,
+            RowSort::Latency => Some("sorted by latency ↓"),
+            RowSort::Conn => Some("sorted by conn"),
+        }
+    }
+}
+
+/// Order of the Top Queries panel, per tab; cycled with `O`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum TopSort {
+    #[default]
+    TotalTime,
+    Count,
+    AvgTime,
+    MaxTime,
+}
+
+impl TopSort {
+    fn next(self) -> Self {
+        match self {
+            TopSort::TotalTime => TopSort::Count,
+            TopSort::Count => TopSort::AvgTime,
+            TopSort::AvgTime => TopSort::MaxTime,
+            TopSort::MaxTime => TopSort::TotalTime,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            TopSort::TotalTime => "total time",
+            TopSort::Count => "count",
+            TopSort::AvgTime => "avg time",
+            TopSort::MaxTime => "max time",
+        }
+    }
+
+    /// Largest first.
+    fn sort(self, queries: &mut [QueryAggregates]) {
+        match self {
+            TopSort::TotalTime => {
+                queries.sort_unstable_by_key(|q| std::cmp::Reverse(q.total_duration))
+            }
+            TopSort::Count => queries.sort_unstable_by_key(|q| std::cmp::Reverse(q.count)),
+            TopSort::AvgTime => queries.sort_unstable_by_key(|q| {
+                std::cmp::Reverse(q.total_duration / q.count.max(1) as u32)
+            }),
+            TopSort::MaxTime => queries.sort_unstable_by_key(|q| std::cmp::Reverse(q.max_duration)),
+        }
+    }
+}
+
+/// `/`: show only rows whose SQL or text matches a regex. Per tab, like `RowFilter`.
+#[derive(Clone, Debug)]
+struct TextFilter {
+    pattern: String,
+    regex: regex_automata::meta::Regex,
+}
+
+impl TextFilter {
+    fn new(pattern: &str) -> Result<Self, String> {
+        let regex = regex_automata::meta::Regex::new(pattern).map_err(|e| e.to_string())?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    fn matches(&self, row: &QueryRow) -> bool {
+        row.raw_sql
+            .as_deref()
+            .is_some_and(|sql| self.regex.is_match(sql))
+            || self.regex.is_match(&row.display)
+    }
+}
+
+/// Indices into `events` of the rows the table shows, in display order.
+fn shown_rows(
+    events: &VecDeque<QueryRow>,
+    filter: RowFilter,
+    text: Option<&TextFilter>,
+    sort: RowSort,
+) -> Vec<usize> {
+    let mut shown: Vec<usize> = (0..events.len())
+        .filter(|&i| filter.shows(events[i].kind) && text.is_none_or(|t| t.matches(&events[i])))
+        .collect();
+    // Stable, so ties keep arrival order.
+    match sort {
+        RowSort::Arrival => {}
+        RowSort::Latency => shown.sort_by_key(|&i| std::cmp::Reverse(events[i].duration)),
+        RowSort::Conn => shown.sort_by_key(|&i| events[i].conn_id),
+    }
+    shown
+}
+
+/// Narrowest and widest `+`/`-` make a column.
+const MIN_COLUMN_WIDTH: u16 = 4;
+const MAX_COLUMN_WIDTH: u16 = 40;
+
+/// Widths of the TIME, CONN, LATENCY and ELAPSED columns; QUERY takes what's
+/// left. `[`/`]` pick a column, `+`/`-` resize it, `A` toggles auto-fit. Kept
+/// for the session and shared by every tab.
+#[derive(Default)]
+struct ColumnWidths {
+    /// Set with `+`/`-`; wins over both the default and the auto-fit width.
+    manual: [Option<u16>; 4],
+    /// Column `+`/`-` resize, underlined in the header.
+    focus: Option<usize>,
+    /// Size each column to the widest cell on screen.
+    auto_fit: bool,
+    /// As last drawn, so `+`/`-` start from what's on screen.
+    drawn: [u16; 4],
+}
+
+impl ColumnWidths {
+    fn move_focus(&mut self, forward: bool) {
+        self.focus = Some(match (self.focus, forward) {
+            (None, _) => 1,
+            (Some(i), true) => (i + 1) % 4,
+            (Some(i), false) => (i + 3) % 4,
+        });
+    }
+
+    /// Widen (or narrow) the focused column, CONN if none is.
+    fn adjust(&mut self, delta: i16) {
+        let i = *self.focus.get_or_insert(1);
+        let width = self.manual[i]
+            .unwrap_or(self.drawn[i])
+            .saturating_add_signed(delta);
+        self.manual[i] = Some(width.clamp(MIN_COLUMN_WIDTH, MAX_COLUMN_WIDTH));
+    }
+
+    /// Auto-fit on or off; either way, manual widths go.
+    fn toggle_auto_fit(&mut self) {
+        self.auto_fit = !self.auto_fit;
+        self.manual = [None; 4];
+    }
+
+    /// Widths to draw with, from the defaults and the widest cell per column.
+    fn resolve(&mut self, defaults: [u16; 4], fitted: [u16; 4]) -> [u16; 4] {
+        let base = if self.auto_fit { fitted } else { defaults };
+        self.drawn = std::array::from_fn(|i| self.manual[i].unwrap_or(base[i]));
+        self.drawn
+    }
+}
+
+/// What the TIME column shows; cycled with `T`, remembered per tab.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum TimeFormat {
+    /// Wall clock, as captured.
+    #[default]
+    Absolute,
+    /// Since the first row in the tab.
+    Relative,
+    /// Since the previous row.
+    Delta,
+}
+
+impl TimeFormat {
+    fn next(self) -> Self {
+        match self {
+            TimeFormat::Absolute => TimeFormat::Relative,
+            TimeFormat::Relative => TimeFormat::Delta,
+            TimeFormat::Delta => TimeFormat::Absolute,
+        }
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            TimeFormat::Absolute => "TIME",
+            TimeFormat::Relative => "TIME +start",
+            TimeFormat::Delta => "TIME +prev",
+        }
+    }
+
+    /// TIME cell for `events[index]`. Offsets use the stored wall-clock time so
+    /// they also work for imported snapshots, whose instants are all import time.
+    fn format(self, events: &VecDeque<QueryRow>, index: usize) -> String {
+        let row = &events[index];
+        let base = match self {
+            TimeFormat::Absolute => return row.time.clone(),
+            TimeFormat::Relative => events.front(),
+            TimeFormat::Delta => index.checked_sub(1).and_then(|i| events.get(i)),
+        };
+        let offset = base
+            .and_then(|b| time_of_day_offset(b, row))
+            .unwrap_or_default();
+        format!("+{:.3}s", offset.as_secs_f64())
+    }
+}
+
+/// Wall-clock time from `from` to `to`, by their TIME cells; a negative span
+/// means midnight was crossed in between.
+fn time_of_day_offset(from: &QueryRow, to: &QueryRow) -> Option<Duration> {
+    let delta = parse_time_of_day(&to.time)? - parse_time_of_day(&from.time)?;
+    let delta = if delta < chrono::TimeDelta::zero() {
+        delta + chrono::TimeDelta::days(1)
+    } else {
+        delta
+    };
+    delta.to_std().ok()
+}
+
+/// ELAPSED cell: time since the first query by the monotonic clock. Without that
+/// reference (before the first query, after `r`, or in an imported snapshot)
+/// it falls back to wall-clock time since `first_row`, the oldest row in the buffer.
+fn elapsed_cell(first_query_at: Option<Instant>, first_row: &QueryRow, row: &QueryRow) -> String {
+    let elapsed = match first_query_at {
+        Some(first) => row.instant.checked_duration_since(first),
+        None => time_of_day_offset(first_row, row),
+    };
+    elapsed.map(format_elapsed).unwrap_or_default()
+}
+
+/// How long an action's result stays on the status line.
+const STATUS_LINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StatusTone {
+    /// A setting changed: filter, sort, colors.
+    Info,
+    /// An action succeeded: saved, imported, repointed.
+    Done,
+    Failed,
+}
+
+/// Result of the last key action, shown above the footer until it times out
+/// or the next key is pressed. Kept out of the event log so traffic doesn't
+/// scroll it away.
+struct StatusLine {
+    message: String,
+    tone: StatusTone,
+    shown_at: Instant,
+}
+
+/// Where `*` last jumped: the `rank`-th slowest query (0-based) of tab `tab`.
+#[derive(Clone, Copy)]
+struct SlowestJump {
+    tab: usize,
+    rank: usize,
+    total: usize,
+    /// Index of the row in the tab's events.
+    row: usize,
 }
 
 struct FrozenTab {
@@ -75,28 +527,229 @@ struct FrozenTab {
     scroll_offset: usize,
     auto_scroll: bool,
     show_fingerprints: bool,
+    time_format: TimeFormat,
+    row_filter: RowFilter,
+    text_filter: Option<TextFilter>,
+    top_sort: TopSort,
+    /// Active time-range filter; drawing uses its rows/stats instead of the full capture.
+    range: Option<RangeView>,
+    /// Created with `F`: holds the stats only, `events` stays empty.
+    stats_only: bool,
+    /// A finished `--window`, kept by `--window-tabs`.
+    window: bool,
 }
 
 /// Shared context for draw methods — abstracts over live and frozen tabs.
 struct DrawContext<'a> {
     events: &'a VecDeque<QueryRow>,
     fingerprints: &'a HashMap<String, QueryAggregates>,
+    errors: &'a HashMap<String, ErrorAggregates>,
     latency_buckets: &'a [u64; 6],
+    /// p50/p95/p99 for the header; None with no latencies to go on.
+    percentiles: Option<(Duration, Duration, Duration)>,
     total_queries: u64,
     total_errors: u64,
     active_connections: u64,
+    /// Queries in flight now (live tab only) and the most at once; max is None when unknown.
+    in_flight: Option<u64>,
+    max_in_flight: Option<u64>,
+    /// Of those, labeled as a pooler's server connections.
+    pooled_connections: u64,
+    applications: &'a HashSet<String>,
+    client_hosts: &'a HashSet<IpAddr>,
+    message_counts: &'a MessageCounts,
     first_query_at: Option<Instant>,
     scroll_offset: &'a mut usize,
     auto_scroll: bool,
     show_fingerprints: bool,
+    time_format: TimeFormat,
+    row_filter: RowFilter,
+    text_filter: Option<&'a TextFilter>,
+    top_sort: TopSort,
+    row_sort: RowSort,
     is_frozen: bool,
-    qps: Option<u64>,
+    qps: Option<f64>,
+    /// Connections opened per second; live tab only, like `qps`.
+    conn_churn: Option<f64>,
+    conn_label: ConnLabel,
+    /// Header note for a time-range-filtered frozen tab.
+    range_label: Option<String>,
+    scheme: &'a LatencyScheme,
+    threshold_ms: u64,
+    /// Show each row's stored ELAPSED instead of recomputing it.
+    freeze_elapsed: bool,
+    relative_coloring: bool,
+    /// Stats-only frozen tab: the query table shows a placeholder.
+    stats_only: bool,
+    /// Row picked by `*`, drawn highlighted.
+    highlight_row: Option<usize>,
+    columns: &'a mut ColumnWidths,
+    /// Live tab with `--window`: "window: 5m (3m elapsed)".
+    window_label: Option<String>,
 }
 
 enum InputMode {
     Normal,
-    SavePrompt { buffer: String, cursor: usize },
-    ImportPrompt { buffer: String, cursor: usize },
+    SavePrompt {
+        buffer: String,
+        cursor: usize,
+    },
+    ImportPrompt {
+        buffer: String,
+        cursor: usize,
+    },
+    RangePrompt {
+        buffer: String,
+        cursor: usize,
+    },
+    UpstreamPrompt {
+        buffer: String,
+        cursor: usize,
+    },
+    /// `/`: regex for the active tab's rows, and why the last one didn't compile.
+    FilterPrompt {
+        buffer: String,
+        cursor: usize,
+        error: Option<String>,
+    },
+    /// `?`: the keybinding reference, scrolled by this many lines.
+    Help {
+        scroll: usize,
+    },
+    /// `v` with no `$PAGER` or `$EDITOR`: a query's full SQL.
+    Query {
+        text: String,
+        scroll: usize,
+    },
+}
+
+/// Every key, by category, for the `?` overlay. The footer only fits a reminder;
+/// this is the full list, so new keys belong here too.
+const HELP_SECTIONS: &[(&str, &[(&str, &str)])] = &[
+    ("Navigation", &[
+        ("j / ↓", "Scroll down one row"),
+        ("k / ↑", "Scroll up one row"),
+        ("PgDn / PgUp", "Scroll by 20 rows"),
+        ("G / End", "Jump to the newest row and follow new ones"),
+        ("g / Home", "Jump to the oldest row"),
+        ("*", "Jump to the slowest query in the buffer; repeat for the next slowest"),
+        ("v", "Open the top query (newest when following) in $PAGER or $EDITOR, or a full-screen view"),
+    ]),
+    ("Tabs", &[
+        ("t", "Freeze the current view into a new tab"),
+        ("F", "Freeze the stats only, without events"),
+        ("Tab / Shift-Tab", "Next / previous tab"),
+        ("1-9", "Go to tab by number"),
+        ("x", "Close the active frozen tab"),
+        ("w", "Frozen tab: show only a time range (HH:MM:SS-HH:MM:SS)"),
+    ]),
+    ("Filters & display", &[
+        ("E", "Cycle rows: all, errors only, errors and warnings"),
+        ("/", "Show only rows whose SQL or message matches a regex (per tab)"),
+        ("f", "Show SQL fingerprints instead of full queries"),
+        ("T", "TIME column: clock, since first event, since previous event"),
+        ("L", "Cycle latency color schemes (OLTP, OLAP, --latency-tiers)"),
+        ("c", "Toggle the compact bottom summary"),
+        ("l", "Toggle dbprobe's log pane"),
+        ("m", "Toggle protocol message counts in place of the bottom panels"),
+    ]),
+    ("Columns", &[
+        ("[ / ]", "Focus the previous / next column"),
+        ("+ / -", "Widen / narrow the focused column (CONN if none)"),
+        ("A", "Toggle auto-fit to the widest cell on screen"),
+        ("Esc", "Clear the tab's / filter, then the column focus"),
+    ]),
+    ("Actions", &[
+        ("p", "Pause / resume the live tab"),
+        ("space / .", "While paused: step 1 / 10 held events"),
+        ("o", "While paused: sort rows by latency, by conn, or by arrival"),
+        ("O", "Sort Top Queries by total time, count, avg time, or max time (per tab)"),
+        ("r", "Reset stats and clear the live tab"),
+        ("s", "Save a snapshot to a file"),
+        ("i", "Import a snapshot as a tab"),
+        ("u", "Change the upstream for new connections"),
+        ("?", "Show / hide this help"),
+        ("q / Ctrl-C", "Quit"),
+    ]),
+];
+
+fn help_lines() -> Vec<Line<'static>> {
+    let heading = Style::default()
+        .fg(Color::Cyan)
+        .add_modifier(Modifier::BOLD);
+    let key = Style::default().fg(Color::Yellow);
+    let mut lines = Vec::new();
+    for (i, (section, keys)) in HELP_SECTIONS.iter().enumerate() {
+        if i > 0 {
+            lines.push(Line::default());
+        }
+        lines.push(Line::styled(*section, heading));
+        for (keys, description) in keys.iter() {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {keys:<16}"), key),
+                Span::raw(*description),
+            ]));
+        }
+    }
+    lines
+}
+
+/// How long the header stays highlighted after the bell fires.
+const FLASH_DURATION: Duration = Duration::from_millis(400);
+
+/// Height of the histogram/top-queries panels in the full layout.
+const BOTTOM_PANELS_HEIGHT: u16 = 11;
+
+/// Fewest query-table rows the full layout leaves.
+const MIN_TABLE_ROWS: u16 = 10;
+
+/// Height of the log pane (`l`), borders included.
+const LOG_PANE_HEIGHT: u16 = 8;
+
+/// Terminals shorter than this get the compact layout unless `c` says otherwise —
+/// at 24 rows the full layout leaves the log just 7 visible events.
+const AUTO_COMPACT_BELOW_ROWS: u16 = 30;
+
+/// `--window-tabs` keeps this many finished windows; older ones are dropped.
+const MAX_WINDOW_TABS: usize = 12;
+
+/// Startup settings for the TUI, from the CLI.
+pub struct TuiOptions {
+    pub listen_port: u16,
+    /// Shown in the header; `u` repoints it for new connections.
+    pub upstream: SharedUpstream,
+    /// Threshold and bell settings; reloadable.
+    pub config: SharedConfig,
+    /// Ring audibly when the bell is enabled (false with --no-bell).
+    pub bell_audible: bool,
+    pub conn_label: ConnLabel,
+    /// Shared with the control API, when it's enabled.
+    pub event_log: Option<EventLog>,
+    /// `--metrics-addr`: stats published for the metrics endpoint.
+    pub metrics: Option<Metrics>,
+    /// Decimal places for latencies written to snapshots.
+    pub precision: u32,
+    /// `--latency-tiers`; added as a "custom" color scheme and selected at startup.
+    pub latency_tiers: Option<[f64; 3]>,
+    /// `--compact-layout`: start with the bottom panels collapsed to one line.
+    pub compact_layout: bool,
+    /// `--freeze-elapsed`: ELAPSED is fixed when a row arrives, not recomputed each frame.
+    pub freeze_elapsed: bool,
+    /// `--relative-coloring`: color queries against their own fingerprint's usual latency.
+    pub relative_coloring: bool,
+    /// `--merge-errors`: fold identical consecutive errors on a connection into one row.
+    pub merge_errors: bool,
+    /// `--replay` of a snapshot: imported as a tab at startup.
+    pub snapshot: Option<std::path::PathBuf>,
+    /// `--replay` of a capture: `p` pauses the playback, not just the tab.
+    pub replay: Option<ReplayControl>,
+    /// dbprobe's own log output, shown in the log pane.
+    pub logs: LogBuffer,
+    /// `--trace-json` output.
+    pub trace: Option<TraceWriter>,
+    /// `--window-tabs`: keep each finished `--window` as a stats-only tab.
+    pub window_tabs: bool,
+    pub conn_events: ConnEvents,
 }
 
 pub struct TuiApp {
@@ -105,59 +758,325 @@ pub struct TuiApp {
     scroll_offset: usize,
     auto_scroll: bool,
     paused: bool,
+    /// Events that arrived while paused, released one at a time by stepping.
+    held_events: VecDeque<DisplayEvent>,
+    /// Events stepped through since the pause began.
+    stepped: usize,
+    /// Held events dropped, oldest first, to keep `held_events` within MAX_EVENTS.
+    held_dropped: usize,
+    /// `--replay` of a capture, paused along with the tab.
+    replay: Option<ReplayControl>,
+    /// Steps still to show once the paused replay plays the frames for them.
+    owed_steps: usize,
     show_fingerprints: bool,
+    time_format: TimeFormat,
+    row_filter: RowFilter,
+    text_filter: Option<TextFilter>,
+    top_sort: TopSort,
+    /// Only while paused; resuming resets it.
+    row_sort: RowSort,
     listen_port: u16,
-    upstream: String,
-    threshold_ms: u64,
+    upstream: SharedUpstream,
+    config: SharedConfig,
+    bell_audible: bool,
+    bell: Option<SlowQueryBell>,
+    conn_label: ConnLabel,
+    event_log: Option<EventLog>,
+    metrics: Option<Metrics>,
+    trace: Option<TraceWriter>,
+    precision: u32,
+    /// Color schemes cycled with `L`; `schemes[scheme]` is active.
+    schemes: Vec<LatencyScheme>,
+    scheme: usize,
+    /// Bottom panels collapsed to a one-line summary; None follows the terminal height.
+    compact: Option<bool>,
+    /// Whether the last draw was compact, so `c` can flip what's on screen.
+    compact_drawn: bool,
+    status: Option<StatusLine>,
+    logs: LogBuffer,
+    show_logs: bool,
+    /// `logs.problem_count()` when the pane was last visible; more means unseen warnings.
+    logs_problems_seen: usize,
+    /// Protocol message counts in place of the bottom panels.
+    show_messages: bool,
+    freeze_elapsed: bool,
+    relative_coloring: bool,
+    merge_errors: bool,
+    columns: ColumnWidths,
+    /// Header is drawn highlighted until this instant.
+    flash_until: Option<Instant>,
+    /// A bell is due; written to the terminal after the next draw.
+    ring_pending: bool,
+    /// `$PAGER`, else `$EDITOR`, for `v`; None shows queries in the TUI.
+    viewer: Option<String>,
+    /// SQL `v` picked for the external viewer; the loop runs it with the terminal released.
+    open_query: Option<String>,
     should_quit: bool,
     frozen_tabs: Vec<FrozenTab>,
     /// 0 = live tab, 1+ = frozen_tabs[active_tab - 1]
     active_tab: usize,
     next_tab_id: usize,
     input_mode: InputMode,
+    /// Set by `*`; cleared by any other key.
+    slowest: Option<SlowestJump>,
+    window_tabs: bool,
+    /// When the current `--window` began, for tab labels.
+    window_opened: chrono::DateTime<chrono::Local>,
+    conn_events: ConnEventFilter,
 }
 
 impl TuiApp {
-    fn new(listen_port: u16, upstream: String, threshold_ms: u64) -> Self {
-        Self {
+    fn new(options: TuiOptions, stats_config: StatsConfig) -> Self {
+        let TuiOptions {
+            listen_port,
+            upstream,
+            config,
+            bell_audible,
+            conn_label,
+            event_log,
+            metrics,
+            precision,
+            latency_tiers,
+            compact_layout,
+            freeze_elapsed,
+            relative_coloring,
+            merge_errors,
+            snapshot,
+            replay,
+            logs,
+            trace,
+            window_tabs,
+            conn_events,
+        } = options;
+        let mut schemes = vec![LatencyScheme::OLTP, LatencyScheme::OLAP];
+        schemes.extend(latency_tiers.map(LatencyScheme::custom));
+        // Custom tiers were asked for explicitly, so start with them.
+        let scheme = schemes.len() - 1;
+        let mut app = Self {
             events: VecDeque::with_capacity(MAX_EVENTS),
-            stats: StatsCollector::new(),
+            stats: StatsCollector::with_config(stats_config),
             scroll_offset: 0,
             auto_scroll: true,
             paused: false,
+            held_events: VecDeque::new(),
+            stepped: 0,
+            held_dropped: 0,
+            replay,
+            owed_steps: 0,
             show_fingerprints: false,
+            time_format: TimeFormat::Absolute,
+            row_filter: RowFilter::All,
+            text_filter: None,
+            top_sort: TopSort::TotalTime,
+            row_sort: RowSort::Arrival,
             listen_port,
             upstream,
-            threshold_ms,
+            config,
+            bell_audible,
+            bell: None,
+            conn_label,
+            event_log,
+            metrics,
+            trace,
+            precision,
+            schemes,
+            scheme,
+            compact: compact_layout.then_some(true),
+            compact_drawn: compact_layout,
+            status: None,
+            logs,
+            show_logs: false,
+            logs_problems_seen: 0,
+            show_messages: false,
+            freeze_elapsed,
+            relative_coloring,
+            merge_errors,
+            columns: ColumnWidths::default(),
+            flash_until: None,
+            ring_pending: false,
+            viewer: ["PAGER", "EDITOR"]
+                .into_iter()
+                .find_map(|var| std::env::var(var).ok().filter(|v| !v.trim().is_empty())),
+            open_query: None,
             should_quit: false,
             frozen_tabs: Vec::new(),
             active_tab: 0,
             next_tab_id: 1,
             input_mode: InputMode::Normal,
+            slowest: None,
+            window_tabs,
+            window_opened: chrono::Local::now(),
+            conn_events: ConnEventFilter::new(conn_events),
+        };
+        if let Some(path) = snapshot {
+            app.import_from_path(&path.to_string_lossy());
         }
+        app
     }
 
     fn push_event(&mut self, display_event: &DisplayEvent) {
+        if let Some(log) = &self.event_log {
+            log.push(display_event);
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.handle_event(display_event);
+        }
+
+        let bell_threshold_ms = self.config.read().unwrap().bell_threshold_ms;
+        SlowQueryBell::sync(&mut self.bell, bell_threshold_ms, self.bell_audible);
+
+        // Alert even while paused — that's when nobody is watching the log.
+        if let Some(bell) = &mut self.bell {
+            if bell.check(display_event) {
+                self.flash_until = Some(Instant::now() + FLASH_DURATION);
+                self.ring_pending |= bell.audible;
+            }
+        }
+
+        if !self.conn_events.admit(display_event) {
+            return;
+        }
         if self.paused {
+            // Hold instead of dropping so the sequence can be stepped through.
+            if self.held_events.len() >= MAX_EVENTS {
+                self.held_events.pop_front();
+                self.held_dropped += 1;
+            }
+            self.held_events.push_back(display_event.clone());
+            // A step the replay was playing frames for.
+            let owed = std::mem::take(&mut self.owed_steps);
+            if owed > 0 {
+                self.step(owed);
+            }
             return;
         }
+        self.append_row(display_event);
+    }
+
+    /// Release up to `n` held events into the log while staying paused. A
+    /// paused replay plays on frame by frame for the steps past the held events.
+    fn step(&mut self, n: usize) {
+        let mut shown = 0;
+        while shown < n {
+            let Some(event) = self.held_events.pop_front() else {
+                break;
+            };
+            self.append_row(&event);
+            self.stepped += 1;
+            shown += 1;
+        }
+        if self.replay.is_some() {
+            self.owed_steps = n - shown;
+            self.release_replay();
+        }
+    }
+
+    /// Let the paused replay play as many frames as steps are owed, once it has
+    /// played the last batch. A frame may carry no event, so this runs every tick.
+    fn release_replay(&self) {
+        if let Some(replay) = self
+            .replay
+            .as_ref()
+            .filter(|replay| self.owed_steps > 0 && replay.is_held())
+        {
+            replay.release(self.owed_steps);
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        if self.paused {
+            // Resume: flush everything held, then continue live.
+            self.owed_steps = 0;
+            self.step(self.held_events.len());
+            self.stepped = 0;
+            self.held_dropped = 0;
+            self.row_sort = RowSort::Arrival;
+        }
+        self.paused = !self.paused;
+        if let Some(replay) = &self.replay {
+            if self.paused {
+                replay.pause();
+            } else {
+                replay.resume();
+            }
+        }
+    }
+
+    /// (current, total, dropped) position while paused, for "event i of N" in the header.
+    fn pause_position(&self) -> Option<(usize, usize, usize)> {
+        self.paused.then(|| {
+            (
+                self.stepped,
+                self.stepped + self.held_events.len(),
+                self.held_dropped,
+            )
+        })
+    }
 
+    fn append_row(&mut self, display_event: &DisplayEvent) {
         let time = display_event.wall_time.format("%H:%M:%S%.3f").to_string();
         let conn_id = display_event.conn_id;
 
-        let (latency, raw_sql, rows_suffix, display, style) = match &display_event.kind {
-            DisplayEventKind::Query { sql, duration, rows } => {
+        let mut row_duration = None;
+        let mut error = None;
+        let mut query_seq = None;
+        let mut baseline_ratio = None;
+        let mut row_trace_id = None;
+        let mut params = String::new();
+        let (kind, latency, raw_sql, rows_suffix, display, style) = match &display_event.kind {
+            DisplayEventKind::Query {
+                sql,
+                duration,
+                rows,
+                seq,
+                trace_id,
+                params: bound,
+                result_bytes,
+                large_result,
+                ..
+            } => {
+                query_seq = Some(*seq);
+                params = format_params(bound);
+                baseline_ratio =
+                    fingerprint_baseline_ratio(&self.stats.fingerprints, sql, *duration);
                 let ms = duration.as_secs_f64() * 1000.0;
                 let latency = format!("{ms:.1}ms");
-                let rows_suffix = rows.map(|r| format!(" [{r}]")).unwrap_or_default();
-                let style = latency_style(ms, self.threshold_ms);
-                (latency, Some(sql.clone()), rows_suffix, String::new(), style)
+                let rows_suffix = match (rows, large_result) {
+                    (Some(r), true) => format!(" [{r} rows, {}]", format_mb(*result_bytes)),
+                    (None, true) => format!(" [{}]", format_mb(*result_bytes)),
+                    (Some(r), false) => format!(" [{r}]"),
+                    (None, false) => String::new(),
+                };
+                row_trace_id = trace_id.clone();
+                let style = query_style(
+                    self.scheme(),
+                    ms,
+                    self.threshold_ms(),
+                    baseline_ratio.filter(|_| self.relative_coloring),
+                );
+                row_duration = Some(*duration);
+                (
+                    RowKind::Query,
+                    latency,
+                    Some(sql.clone()),
+                    rows_suffix,
+                    String::new(),
+                    style,
+                )
             }
-            DisplayEventKind::Error { code, message, duration, .. } => {
+            DisplayEventKind::Error {
+                code,
+                message,
+                duration,
+                ..
+            } => {
                 let dur = duration
                     .map(|d| format!("{:.1}ms", d.as_secs_f64() * 1000.0))
                     .unwrap_or_default();
+                row_duration = *duration;
+                error = Some((code.clone(), message.clone()));
                 (
+                    RowKind::Error,
                     dur,
                     None,
                     String::new(),
@@ -165,27 +1084,119 @@ impl TuiApp {
                     Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
                 )
             }
-            DisplayEventKind::ConnectionOpened => {
-                ("".into(), None, String::new(), "++ connection opened".into(), Style::default().fg(Color::DarkGray))
-            }
-            DisplayEventKind::ConnectionClosed => {
-                ("".into(), None, String::new(), "-- connection closed".into(), Style::default().fg(Color::DarkGray))
-            }
-            DisplayEventKind::Warning(msg) => {
-                ("".into(), None, String::new(), format!("WARN: {msg}"), Style::default().fg(Color::Yellow))
+            DisplayEventKind::Copy {
+                bytes,
+                elapsed,
+                done,
+            } => (
+                RowKind::Copy { done: *done },
+                "".into(),
+                None,
+                String::new(),
+                format_copy(*bytes, *elapsed, *done),
+                Style::default().fg(Color::Cyan),
+            ),
+            DisplayEventKind::SlowTransaction(timing) => {
+                row_duration = Some(timing.total);
+                (
+                    RowKind::SlowTransaction,
+                    format!("{:.1}ms", timing.total.as_secs_f64() * 1000.0),
+                    None,
+                    String::new(),
+                    format_slow_transaction(timing),
+                    slow_transaction_style(),
+                )
             }
+            DisplayEventKind::ConnectionOpened => (
+                RowKind::Connection,
+                "".into(),
+                None,
+                String::new(),
+                "++ connection opened".into(),
+                Style::default().fg(Color::DarkGray),
+            ),
+            DisplayEventKind::ConnectionClosed => (
+                RowKind::Connection,
+                "".into(),
+                None,
+                String::new(),
+                "-- connection closed".into(),
+                Style::default().fg(Color::DarkGray),
+            ),
+            DisplayEventKind::ClientCertificate { subject } => (
+                RowKind::Connection,
+                "".into(),
+                None,
+                String::new(),
+                format!("client cert: {subject}"),
+                Style::default().fg(Color::DarkGray),
+            ),
+            DisplayEventKind::Notification { channel, payload } => (
+                RowKind::Notification,
+                "".into(),
+                None,
+                String::new(),
+                format!("NOTIFY {channel}: {payload}"),
+                notification_style(),
+            ),
+            DisplayEventKind::Warning(msg) => (
+                RowKind::Warning,
+                "".into(),
+                None,
+                String::new(),
+                format!("WARN: {msg}"),
+                Style::default().fg(Color::Yellow),
+            ),
         };
 
-        self.events.push_back(QueryRow {
+        if let (true, Some((code, message))) = (self.merge_errors, &error) {
+            if self.merge_error(conn_id, code, message) {
+                return;
+            }
+        }
+
+        // A running COPY updates its own row rather than appending a new one each tick.
+        if matches!(kind, RowKind::Copy { .. })
+            && self
+                .events
+                .back()
+                .is_some_and(|r| r.kind == RowKind::Copy { done: false } && r.conn_id == conn_id)
+        {
+            self.events.pop_back();
+        }
+
+        let now = Instant::now();
+        let mut row = QueryRow {
+            kind,
             time,
-            instant: Instant::now(),
+            instant: now,
             conn_id,
+            client_addr: display_event.client_addr,
             latency,
             raw_sql,
             rows_suffix,
+            params,
             display,
             style,
-        });
+            duration: row_duration,
+            error,
+            elapsed: String::new(),
+            seq: query_seq,
+            baseline_ratio,
+            trace_id: row_trace_id,
+            repeats: 1,
+        };
+        row.elapsed = elapsed_cell(
+            self.stats.first_query_at,
+            self.events.front().unwrap_or(&row),
+            &row,
+        );
+        self.push_row(row);
+    }
+
+    /// Append to the log, dropping the oldest row past MAX_EVENTS.
+    fn push_row(&mut self, row: QueryRow) {
+        self.events.push_back(row);
 
         if self.events.len() > MAX_EVENTS {
             self.events.pop_front();
@@ -199,6 +1210,25 @@ impl TuiApp {
         }
     }
 
+    /// Fold an error into the newest row when that row is the same error from
+    /// the same connection. Only the display merges: stats saw every one.
+    fn merge_error(&mut self, conn_id: u64, code: &str, message: &str) -> bool {
+        let Some(last) = self.events.back_mut() else {
+            return false;
+        };
+        let same = last.conn_id == conn_id
+            && last
+                .error
+                .as_ref()
+                .is_some_and(|(c, m)| c == code && normalize_error(m) == normalize_error(message));
+        if !same {
+            return false;
+        }
+        last.repeats += 1;
+        last.display = format!("ERR {code} ×{}: {message}", last.repeats);
+        true
+    }
+
     fn scroll_to_bottom(&mut self) {
         // Will be calculated during render based on visible area
         self.scroll_offset = usize::MAX;
@@ -216,11 +1246,82 @@ impl TuiApp {
             scroll_offset: self.scroll_offset,
             auto_scroll: self.auto_scroll,
             show_fingerprints: self.show_fingerprints,
+            time_format: self.time_format,
+            row_filter: self.row_filter,
+            text_filter: self.text_filter.clone(),
+            top_sort: self.top_sort,
+            range: None,
+            stats_only: false,
+            window: false,
         });
         // Stay on live tab — state kept; user can reset with 'r'
         self.active_tab = 0;
     }
 
+    /// Like `create_tab`, but freezes only the aggregate stats. Skipping the
+    /// event clone keeps this cheap enough for frequent before/after snapshots.
+    fn create_stats_tab(&mut self) {
+        let label = format!("Tab {} [stats]", self.next_tab_id);
+        self.next_tab_id += 1;
+        self.frozen_tabs.push(FrozenTab {
+            label,
+            events: VecDeque::new(),
+            stats: self.stats.freeze(),
+            scroll_offset: 0,
+            auto_scroll: true,
+            show_fingerprints: self.show_fingerprints,
+            time_format: self.time_format,
+            row_filter: self.row_filter,
+            text_filter: self.text_filter.clone(),
+            top_sort: self.top_sort,
+            range: None,
+            stats_only: true,
+            window: false,
+        });
+        self.active_tab = 0;
+    }
+
+    /// `--window`: once the window ends, reset the aggregates, first keeping them
+    /// as a stats-only tab with `--window-tabs`. The event log is left alone.
+    fn roll_window(&mut self, now: Instant) {
+        if !self.stats.roll_window(now) {
+            return;
+        }
+        let opened = std::mem::replace(&mut self.window_opened, chrono::Local::now());
+        if self.window_tabs {
+            if self.frozen_tabs.iter().filter(|t| t.window).count() >= MAX_WINDOW_TABS {
+                let oldest = self.frozen_tabs.iter().position(|t| t.window).unwrap_or(0);
+                self.frozen_tabs.remove(oldest);
+                if self.active_tab == oldest + 1 {
+                    self.active_tab = 0;
+                } else if self.active_tab > oldest + 1 {
+                    self.active_tab -= 1;
+                }
+            }
+            self.frozen_tabs.push(FrozenTab {
+                label: format!(
+                    "{}–{}",
+                    opened.format("%H:%M:%S"),
+                    self.window_opened.format("%H:%M:%S")
+                ),
+                events: VecDeque::new(),
+                stats: self.stats.freeze(),
+                scroll_offset: 0,
+                auto_scroll: true,
+                show_fingerprints: self.show_fingerprints,
+                time_format: self.time_format,
+                row_filter: self.row_filter,
+                text_filter: self.text_filter.clone(),
+                top_sort: self.top_sort,
+                range: None,
+                stats_only: true,
+                window: true,
+            });
+        }
+        self.stats.reset();
+        self.push_status_message("Window ended: aggregates reset".to_string());
+    }
+
     fn close_tab(&mut self) {
         if self.active_tab == 0 {
             return; // Can't close live tab
@@ -247,25 +1348,242 @@ impl TuiApp {
     /// for the active tab — either live state or a frozen tab.
     fn active_scroll_state(&mut self) -> (&mut usize, &mut bool, &mut bool) {
         if self.active_tab == 0 {
-            (&mut self.scroll_offset, &mut self.auto_scroll, &mut self.show_fingerprints)
+            (
+                &mut self.scroll_offset,
+                &mut self.auto_scroll,
+                &mut self.show_fingerprints,
+            )
         } else {
             let tab = &mut self.frozen_tabs[self.active_tab - 1];
-            (&mut tab.scroll_offset, &mut tab.auto_scroll, &mut tab.show_fingerprints)
+            (
+                &mut tab.scroll_offset,
+                &mut tab.auto_scroll,
+                &mut tab.show_fingerprints,
+            )
         }
     }
 
-    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
-        if !matches!(self.input_mode, InputMode::Normal) {
-            self.handle_input_key(code);
+    /// Rows of the active tab as drawn: a frozen tab's time-range view if it has one.
+    fn active_events(&self) -> &VecDeque<QueryRow> {
+        match self.active_tab.checked_sub(1).map(|i| &self.frozen_tabs[i]) {
+            None => &self.events,
+            Some(FrozenTab {
+                range: Some(view), ..
+            }) => &view.events,
+            Some(tab) => &tab.events,
+        }
+    }
+
+    /// Scroll to the slowest query row of the active tab; pressed again, the
+    /// next slowest, wrapping around.
+    fn jump_to_slowest(&mut self) {
+        let filter = *self.active_row_filter();
+        let text = self.active_text_filter().clone();
+        let sort = if self.active_tab == 0 {
+            self.row_sort
+        } else {
+            RowSort::Arrival
+        };
+        let events = self.active_events();
+        // (position among the rows shown, index in events, latency)
+        let mut ranked: Vec<(usize, usize, Duration)> =
+            shown_rows(events, filter, text.as_ref(), sort)
+                .into_iter()
+                .enumerate()
+                .filter(|&(_, i)| events[i].kind == RowKind::Query)
+                .filter_map(|(pos, i)| events[i].duration.map(|d| (pos, i, d)))
+                .collect();
+        if ranked.is_empty() {
+            self.slowest = None;
             return;
         }
+        // Stable, so equal latencies keep log order.
+        ranked.sort_by_key(|&(_, _, d)| std::cmp::Reverse(d));
+        let rank = match self.slowest {
+            Some(jump) if jump.tab == self.active_tab => (jump.rank + 1) % ranked.len(),
+            _ => 0,
+        };
+        let (position, row, _) = ranked[rank];
+        self.slowest = Some(SlowestJump {
+            tab: self.active_tab,
+            rank,
+            total: ranked.len(),
+            row,
+        });
+        let (offset, auto_scroll, _) = self.active_scroll_state();
+        *auto_scroll = false;
+        *offset = position;
+    }
 
-        match code {
+    /// `v`: the full SQL of the query at the top of the view, or the newest one
+    /// while following, in the external viewer or the TUI's own.
+    fn view_query(&mut self) {
+        let filter = *self.active_row_filter();
+        let sort = if self.active_tab == 0 {
+            self.row_sort
+        } else {
+            RowSort::Arrival
+        };
+        let (offset, following) = {
+            let (offset, auto_scroll, _) = self.active_scroll_state();
+            (*offset, *auto_scroll)
+        };
+        let text = self.active_text_filter().clone();
+        let events = self.active_events();
+        let shown = shown_rows(events, filter, text.as_ref(), sort);
+        let is_query =
+            |&&i: &&usize| events[i].kind == RowKind::Query && events[i].raw_sql.is_some();
+        let row = if following {
+            shown.iter().rev().find(is_query)
+        } else {
+            shown
+                .iter()
+                .skip(offset.min(shown.len().saturating_sub(1)))
+                .find(is_query)
+        };
+        let Some(row) = row.map(|&i| &events[i]) else {
+            self.set_status("No query to view", StatusTone::Info);
+            return;
+        };
+        let mut text = row.raw_sql.clone().unwrap_or_default();
+        // A comment, so the file is still valid SQL in an editor.
+        if !row.params.is_empty() {
+            text.push_str(&format!("\n\n-- bound:{}", row.params));
+        }
+        text.push('\n');
+        match self.viewer {
+            Some(_) => self.open_query = Some(text),
+            None => self.input_mode = InputMode::Query { text, scroll: 0 },
+        }
+    }
+
+    fn scheme(&self) -> &LatencyScheme {
+        &self.schemes[self.scheme]
+    }
+
+    fn cycle_latency_scheme(&mut self) {
+        self.scheme = (self.scheme + 1) % self.schemes.len();
+        self.set_status(
+            format!("Latency colors: {}", self.scheme().describe()),
+            StatusTone::Info,
+        );
+    }
+
+    fn threshold_ms(&self) -> u64 {
+        self.config.read().unwrap().threshold_ms
+    }
+
+    fn active_time_format(&mut self) -> &mut TimeFormat {
+        match self.active_tab.checked_sub(1) {
+            None => &mut self.time_format,
+            Some(i) => &mut self.frozen_tabs[i].time_format,
+        }
+    }
+
+    fn active_row_filter(&mut self) -> &mut RowFilter {
+        match self.active_tab.checked_sub(1) {
+            None => &mut self.row_filter,
+            Some(i) => &mut self.frozen_tabs[i].row_filter,
+        }
+    }
+
+    fn active_top_sort(&mut self) -> &mut TopSort {
+        match self.active_tab.checked_sub(1) {
+            None => &mut self.top_sort,
+            Some(i) => &mut self.frozen_tabs[i].top_sort,
+        }
+    }
+
+    fn active_text_filter(&mut self) -> &mut Option<TextFilter> {
+        match self.active_tab.checked_sub(1) {
+            None => &mut self.text_filter,
+            Some(i) => &mut self.frozen_tabs[i].text_filter,
+        }
+    }
+
+    /// Apply the `/` prompt's regex to the active tab; empty clears it.
+    fn apply_text_filter(&mut self, pattern: &str) -> Result<(), String> {
+        let filter = match pattern {
+            "" => None,
+            pattern => Some(TextFilter::new(pattern)?),
+        };
+        *self.active_text_filter() = filter;
+        // What the view was scrolled to may no longer be shown.
+        let (offset, auto_scroll, _) = self.active_scroll_state();
+        *auto_scroll = true;
+        *offset = usize::MAX;
+        Ok(())
+    }
+
+    fn cycle_row_filter(&mut self) {
+        let filter = self.active_row_filter();
+        *filter = filter.next();
+        let message = match filter.label() {
+            Some(label) => format!("Showing {label}"),
+            None => "Showing all rows".to_string(),
+        };
+        // Positions refer to the filtered rows, so start again from the newest.
+        let (offset, auto_scroll, _) = self.active_scroll_state();
+        *auto_scroll = true;
+        *offset = usize::MAX;
+        self.set_status(message, StatusTone::Info);
+    }
+
+    fn cycle_row_sort(&mut self) {
+        if self.active_tab != 0 || !self.paused {
+            self.set_status(
+                "Sorting is for the paused live tab — p pauses",
+                StatusTone::Failed,
+            );
+            return;
+        }
+        self.row_sort = self.row_sort.next();
+        let message = match self.row_sort.label() {
+            Some(label) => format!("Rows {label}"),
+            None => "Rows in arrival order".to_string(),
+        };
+        // Sorted views start from their top: the slowest query, the lowest conn.
+        self.auto_scroll = false;
+        self.scroll_offset = 0;
+        self.set_status(message, StatusTone::Info);
+    }
+
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        self.status = None;
+        if let InputMode::Help { scroll } | InputMode::Query { scroll, .. } = &mut self.input_mode {
+            match code {
+                KeyCode::Esc | KeyCode::Char('?' | 'q' | 'v') => {
+                    self.input_mode = InputMode::Normal
+                }
+                KeyCode::Char('j') | KeyCode::Down => *scroll = scroll.saturating_add(1),
+                KeyCode::Char('k') | KeyCode::Up => *scroll = scroll.saturating_sub(1),
+                KeyCode::PageDown => *scroll = scroll.saturating_add(10),
+                KeyCode::PageUp => *scroll = scroll.saturating_sub(10),
+                KeyCode::Char('g') | KeyCode::Home => *scroll = 0,
+                KeyCode::Char('G') | KeyCode::End => *scroll = usize::MAX,
+                _ => {}
+            }
+            return;
+        }
+        if !matches!(self.input_mode, InputMode::Normal) {
+            self.handle_input_key(code);
+            return;
+        }
+
+        if code != KeyCode::Char('*') {
+            self.slowest = None;
+        }
+
+        match code {
             KeyCode::Char('q') => self.should_quit = true,
-            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => self.should_quit = true,
+            KeyCode::Char('?') => self.input_mode = InputMode::Help { scroll: 0 },
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                self.should_quit = true
+            }
 
             // Tab management
             KeyCode::Char('t') => self.create_tab(),
+            KeyCode::Char('F') => self.create_stats_tab(),
             KeyCode::Tab => self.next_tab(),
             KeyCode::BackTab => self.prev_tab(),
             KeyCode::Char('x') => self.close_tab(),
@@ -288,6 +1606,8 @@ impl TuiApp {
                 *auto_scroll = false;
                 *offset = offset.saturating_sub(1);
             }
+            KeyCode::Char('*') => self.jump_to_slowest(),
+            KeyCode::Char('v') => self.view_query(),
             KeyCode::Char('G') | KeyCode::End => {
                 let (offset, auto_scroll, _) = self.active_scroll_state();
                 *auto_scroll = true;
@@ -314,28 +1634,109 @@ impl TuiApp {
                 let (_, _, show_fp) = self.active_scroll_state();
                 *show_fp = !*show_fp;
             }
+            KeyCode::Char('E') => self.cycle_row_filter(),
+            // Column widths — shared by all tabs
+            KeyCode::Char(']') => self.columns.move_focus(true),
+            KeyCode::Char('[') => self.columns.move_focus(false),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.columns.adjust(1),
+            KeyCode::Char('-') => self.columns.adjust(-1),
+            KeyCode::Char('A') => {
+                self.columns.toggle_auto_fit();
+                let state = if self.columns.auto_fit {
+                    "auto-fit"
+                } else {
+                    "default widths"
+                };
+                self.set_status(format!("Columns: {state}"), StatusTone::Info);
+            }
+            KeyCode::Char('/') => {
+                let buffer = self
+                    .active_text_filter()
+                    .as_ref()
+                    .map(|f| f.pattern.clone())
+                    .unwrap_or_default();
+                let cursor = buffer.len();
+                self.input_mode = InputMode::FilterPrompt {
+                    buffer,
+                    cursor,
+                    error: None,
+                };
+            }
+            // Esc clears the tab's text filter first, then the column focus.
+            KeyCode::Esc if self.active_text_filter().is_some() => {
+                let _ = self.apply_text_filter("");
+            }
+            KeyCode::Esc => self.columns.focus = None,
+            KeyCode::Char('L') => self.cycle_latency_scheme(),
+            KeyCode::Char('c') => self.compact = Some(!self.compact_drawn),
+            KeyCode::Char('l') => self.show_logs = !self.show_logs,
+            KeyCode::Char('m') => self.show_messages = !self.show_messages,
+            // TIME column: absolute → since first event → since previous event
+            KeyCode::Char('T') => {
+                let format = self.active_time_format();
+                *format = format.next();
+            }
 
             // Pause and reset — live tab only
-            KeyCode::Char('p') => {
-                if self.active_tab == 0 {
-                    self.paused = !self.paused;
-                }
+            KeyCode::Char('p') if self.active_tab == 0 => self.toggle_pause(),
+            KeyCode::Char('o') => self.cycle_row_sort(),
+            KeyCode::Char('O') => {
+                let sort = self.active_top_sort();
+                *sort = sort.next();
+                let message = format!("Top queries by {}", sort.label());
+                self.set_status(message, StatusTone::Info);
             }
-            KeyCode::Char('r') => {
-                if self.active_tab == 0 {
-                    self.stats.reset();
-                    self.events.clear();
-                    self.scroll_offset = 0;
-                    self.auto_scroll = true;
-                }
+            // Step through held events while paused
+            KeyCode::Char(' ') if self.active_tab == 0 && self.paused => self.step(1),
+            KeyCode::Char('.') if self.active_tab == 0 && self.paused => self.step(10),
+            KeyCode::Char('r') if self.active_tab == 0 => {
+                self.stats.reset();
+                self.events.clear();
+                self.scroll_offset = 0;
+                self.auto_scroll = true;
             }
             KeyCode::Char('s') => {
-                let default = format!("dbprobe-{}.json", chrono::Local::now().format("%Y%m%dT%H%M%S"));
+                let default = format!(
+                    "dbprobe-{}.json",
+                    chrono::Local::now().format("%Y%m%dT%H%M%S")
+                );
                 let cursor = default.len();
-                self.input_mode = InputMode::SavePrompt { buffer: default, cursor };
+                self.input_mode = InputMode::SavePrompt {
+                    buffer: default,
+                    cursor,
+                };
             }
             KeyCode::Char('i') => {
-                self.input_mode = InputMode::ImportPrompt { buffer: String::new(), cursor: 0 };
+                self.input_mode = InputMode::ImportPrompt {
+                    buffer: String::new(),
+                    cursor: 0,
+                };
+            }
+            // Time-range filter — frozen tabs only (they hold the full history)
+            KeyCode::Char('w')
+                if self.active_tab > 0 && self.frozen_tabs[self.active_tab - 1].stats_only =>
+            {
+                self.set_status("Stats-only tab has no events to filter", StatusTone::Failed);
+            }
+            KeyCode::Char('w') if self.active_tab > 0 => {
+                let buffer = self.frozen_tabs[self.active_tab - 1]
+                    .range
+                    .as_ref()
+                    .map(|v| {
+                        format!(
+                            "{}-{}",
+                            v.range.start.format("%H:%M:%S%.3f"),
+                            v.range.end.format("%H:%M:%S%.3f")
+                        )
+                    })
+                    .unwrap_or_default();
+                let cursor = buffer.len();
+                self.input_mode = InputMode::RangePrompt { buffer, cursor };
+            }
+            KeyCode::Char('u') => {
+                let buffer = self.upstream.read().unwrap().clone();
+                let cursor = buffer.len();
+                self.input_mode = InputMode::UpstreamPrompt { buffer, cursor };
             }
             _ => {}
         }
@@ -343,9 +1744,12 @@ impl TuiApp {
 
     fn handle_input_key(&mut self, code: KeyCode) {
         let (buffer, cursor) = match &mut self.input_mode {
-            InputMode::SavePrompt { buffer, cursor } |
-            InputMode::ImportPrompt { buffer, cursor } => (buffer, cursor),
-            InputMode::Normal => return,
+            InputMode::SavePrompt { buffer, cursor }
+            | InputMode::ImportPrompt { buffer, cursor }
+            | InputMode::RangePrompt { buffer, cursor }
+            | InputMode::UpstreamPrompt { buffer, cursor }
+            | InputMode::FilterPrompt { buffer, cursor, .. } => (buffer, cursor),
+            InputMode::Normal | InputMode::Help { .. } | InputMode::Query { .. } => return,
         };
 
         match code {
@@ -353,31 +1757,25 @@ impl TuiApp {
                 buffer.insert(*cursor, c);
                 *cursor += c.len_utf8();
             }
-            KeyCode::Backspace => {
-                if *cursor > 0 {
-                    // Find previous char boundary
-                    let mut new_cursor = *cursor - 1;
-                    while new_cursor > 0 && !buffer.is_char_boundary(new_cursor) {
-                        new_cursor -= 1;
-                    }
-                    buffer.drain(new_cursor..*cursor);
-                    *cursor = new_cursor;
+            KeyCode::Backspace if *cursor > 0 => {
+                // Find previous char boundary
+                let mut new_cursor = *cursor - 1;
+                while new_cursor > 0 && !buffer.is_char_boundary(new_cursor) {
+                    new_cursor -= 1;
                 }
+                buffer.drain(new_cursor..*cursor);
+                *cursor = new_cursor;
             }
-            KeyCode::Left => {
-                if *cursor > 0 {
+            KeyCode::Left if *cursor > 0 => {
+                *cursor -= 1;
+                while *cursor > 0 && !buffer.is_char_boundary(*cursor) {
                     *cursor -= 1;
-                    while *cursor > 0 && !buffer.is_char_boundary(*cursor) {
-                        *cursor -= 1;
-                    }
                 }
             }
-            KeyCode::Right => {
-                if *cursor < buffer.len() {
+            KeyCode::Right if *cursor < buffer.len() => {
+                *cursor += 1;
+                while *cursor < buffer.len() && !buffer.is_char_boundary(*cursor) {
                     *cursor += 1;
-                    while *cursor < buffer.len() && !buffer.is_char_boundary(*cursor) {
-                        *cursor += 1;
-                    }
                 }
             }
             KeyCode::Enter => {
@@ -394,7 +1792,18 @@ impl TuiApp {
                             self.import_from_path(&buffer);
                         }
                     }
-                    InputMode::Normal => {}
+                    InputMode::RangePrompt { buffer, .. } => self.apply_time_range(&buffer),
+                    InputMode::UpstreamPrompt { buffer, .. } => self.change_upstream(&buffer),
+                    InputMode::FilterPrompt { buffer, cursor, .. } => {
+                        if let Err(error) = self.apply_text_filter(&buffer) {
+                            self.input_mode = InputMode::FilterPrompt {
+                                buffer,
+                                cursor,
+                                error: Some(error),
+                            };
+                        }
+                    }
+                    InputMode::Normal | InputMode::Help { .. } | InputMode::Query { .. } => {}
                 }
             }
             KeyCode::Esc => {
@@ -407,210 +1816,234 @@ impl TuiApp {
     fn save_to_path(&mut self, path: &str) {
         let now = chrono::Local::now();
 
-        // Build snapshot from active tab's data
-        let (buckets, total_queries, total_errors, active_connections, top_queries, events) =
-            if self.active_tab == 0 {
-                (
-                    &self.stats.latency_buckets,
-                    self.stats.total_queries,
-                    self.stats.total_errors,
-                    self.stats.active_connections,
-                    self.stats.top_queries(20),
+        // Build snapshot from what the active tab shows: a frozen tab's time
+        // range narrows both rows and aggregates, the row filter and sort the rows.
+        let conn_churn = (self.active_tab == 0).then(|| self.stats.conn_churn());
+        let (
+            buckets,
+            total_queries,
+            total_errors,
+            active_connections,
+            max_in_flight,
+            top_queries,
+            events,
+            applications,
+            hosts,
+            messages,
+            shown,
+        ) = if self.active_tab == 0 {
+            (
+                &self.stats.latency_buckets,
+                self.stats.total_queries,
+                self.stats.total_errors,
+                self.stats.active_connections,
+                self.stats.max_in_flight,
+                self.stats.top_queries(20),
+                &self.events,
+                &self.stats.applications,
+                &self.stats.client_hosts,
+                &self.stats.message_counts,
+                shown_rows(
                     &self.events,
-                )
-            } else if let Some(tab) = self.frozen_tabs.get(self.active_tab - 1) {
-                (
-                    &tab.stats.latency_buckets,
-                    tab.stats.total_queries,
-                    tab.stats.total_errors,
-                    tab.stats.active_connections,
-                    tab.stats.top_queries(20),
-                    &tab.events,
-                )
-            } else {
-                return;
+                    self.row_filter,
+                    self.text_filter.as_ref(),
+                    self.row_sort,
+                ),
+            )
+        } else if let Some(tab) = self.frozen_tabs.get(self.active_tab - 1) {
+            let (stats, events) = match &tab.range {
+                Some(view) => (&view.stats, &view.events),
+                None => (&tab.stats, &tab.events),
             };
+            (
+                &stats.latency_buckets,
+                stats.total_queries,
+                stats.total_errors,
+                stats.active_connections,
+                stats.max_in_flight,
+                stats.top_queries(20),
+                events,
+                &stats.applications,
+                &stats.client_hosts,
+                &stats.message_counts,
+                shown_rows(
+                    events,
+                    tab.row_filter,
+                    tab.text_filter.as_ref(),
+                    RowSort::Arrival,
+                ),
+            )
+        } else {
+            return;
+        };
+        let mut applications: Vec<String> = applications.iter().cloned().collect();
+        applications.sort();
+        let mut hosts: Vec<&IpAddr> = hosts.iter().collect();
+        hosts.sort();
 
         let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
             timestamp: now.to_rfc3339(),
             total_queries,
             total_errors,
             active_connections,
-            latency_buckets: LatencyBuckets {
-                under_1ms: buckets[0],
-                ms_1_5: buckets[1],
-                ms_5_10: buckets[2],
-                ms_10_50: buckets[3],
-                ms_50_100: buckets[4],
-                over_100ms: buckets[5],
-            },
-            top_queries: top_queries.into_iter().map(|q| {
-                let avg_ms = if q.count > 0 {
-                    q.total_duration.as_secs_f64() * 1000.0 / q.count as f64
-                } else {
-                    0.0
-                };
-                SnapshotQuery {
-                    fingerprint: q.fingerprint,
-                    count: q.count,
-                    avg_ms,
-                    min_ms: q.min_duration.as_secs_f64() * 1000.0,
-                    max_ms: q.max_duration.as_secs_f64() * 1000.0,
-                }
-            }).collect(),
-            recent_events: events.iter().map(|row| {
-                let message = match &row.raw_sql {
-                    Some(sql) => format!("{sql}{}", row.rows_suffix),
-                    None => row.display.clone(),
-                };
-                SnapshotEvent {
-                    time: row.time.clone(),
-                    conn_id: row.conn_id,
-                    latency: row.latency.clone(),
-                    message,
-                }
-            }).collect(),
-        };
-
-        let message = match serde_json::to_string_pretty(&snapshot)
-            .map_err(io::Error::other)
-            .and_then(|json| std::fs::write(path, json))
-        {
-            Ok(()) => format!("Saved snapshot to {path}"),
-            Err(e) => format!("Save failed: {e}"),
+            conn_churn,
+            max_in_flight,
+            applications,
+            client_hosts: hosts.iter().map(|ip| ip.to_string()).collect(),
+            message_counts: messages.clone(),
+            latency_buckets: LatencyBuckets::from_array(buckets),
+            top_queries: top_queries
+                .into_iter()
+                .map(|q| SnapshotQuery::from_aggregates(q, self.precision))
+                .collect(),
+            recent_events: shown
+                .into_iter()
+                .map(|i| &events[i])
+                .map(|row| {
+                    let message = match &row.raw_sql {
+                        Some(sql) => format!("{sql}{}{}", row.params, row.rows_suffix),
+                        None => row.display.clone(),
+                    };
+                    SnapshotEvent {
+                        time: row.time.clone(),
+                        conn_id: row.conn_id,
+                        latency: row.latency.clone(),
+                        message,
+                        sql: row.raw_sql.clone(),
+                        params: row.params.clone(),
+                        rows_suffix: row.rows_suffix.clone(),
+                    }
+                })
+                .collect(),
         };
 
-        self.events.push_back(QueryRow {
-            time: now.format("%H:%M:%S%.3f").to_string(),
-            instant: Instant::now(),
-            conn_id: 0,
-            latency: String::new(),
-            raw_sql: None,
-            rows_suffix: String::new(),
-            display: message,
-            style: Style::default().fg(Color::Cyan),
-        });
-
-        if self.auto_scroll {
-            self.scroll_to_bottom();
+        match snapshot.save(std::path::Path::new(path)) {
+            Ok(()) => self.set_status(format!("Saved snapshot to {path}"), StatusTone::Done),
+            Err(e) => self.set_status(format!("Save failed: {e}"), StatusTone::Failed),
         }
     }
 
     fn import_from_path(&mut self, path: &str) {
-        let content = match std::fs::read_to_string(path) {
-            Ok(c) => c,
-            Err(e) => {
-                self.push_status_message(format!("Import failed: {e}"));
-                return;
-            }
-        };
-
-        let snapshot: Snapshot = match serde_json::from_str(&content) {
+        let snapshot = match Snapshot::load(std::path::Path::new(path)) {
             Ok(s) => s,
             Err(e) => {
-                self.push_status_message(format!("Import failed: invalid JSON: {e}"));
+                self.set_status(format!("Import failed: {e}"), StatusTone::Failed);
                 return;
             }
         };
 
-        // Reconstruct latency buckets
-        let latency_buckets = [
-            snapshot.latency_buckets.under_1ms,
-            snapshot.latency_buckets.ms_1_5,
-            snapshot.latency_buckets.ms_5_10,
-            snapshot.latency_buckets.ms_10_50,
-            snapshot.latency_buckets.ms_50_100,
-            snapshot.latency_buckets.over_100ms,
-        ];
-
+        let latency_buckets = snapshot.latency_buckets.to_array();
         // Reconstruct fingerprint aggregates from top_queries
-        let mut fingerprints = HashMap::new();
-        for q in &snapshot.top_queries {
-            let total_duration = Duration::from_secs_f64(q.avg_ms * q.count as f64 / 1000.0);
-            fingerprints.insert(q.fingerprint.clone(), QueryAggregates {
-                fingerprint: q.fingerprint.clone(),
-                count: q.count,
-                total_duration,
-                min_duration: Duration::from_secs_f64(q.min_ms / 1000.0),
-                max_duration: Duration::from_secs_f64(q.max_ms / 1000.0),
-            });
-        }
+        let fingerprints = snapshot
+            .top_queries
+            .iter()
+            .map(|q| {
+                (
+                    aggregate_key(&q.fingerprint, q.group.as_deref()),
+                    q.to_aggregates(),
+                )
+            })
+            .collect();
 
         let stats = FrozenStats {
             fingerprints,
+            errors: HashMap::new(),
             latency_buckets,
+            latency_histogram: Default::default(),
             total_queries: snapshot.total_queries,
             total_errors: snapshot.total_errors,
             active_connections: snapshot.active_connections,
+            pooled_connections: 0,
+            max_in_flight: snapshot.max_in_flight,
+            applications: snapshot.applications.into_iter().collect(),
+            client_hosts: snapshot
+                .client_hosts
+                .iter()
+                .filter_map(|ip| ip.parse().ok())
+                .collect(),
+            message_counts: snapshot.message_counts,
             first_query_at: None,
         };
 
         // Reconstruct event rows
         let now = Instant::now();
-        let events: VecDeque<QueryRow> = snapshot.recent_events.into_iter().map(|ev| {
-            let msg = &ev.message;
-
-            if msg.starts_with("ERR ") {
-                QueryRow {
-                    time: ev.time,
-                    instant: now,
-                    conn_id: ev.conn_id,
-                    latency: ev.latency,
-                    raw_sql: None,
-                    rows_suffix: String::new(),
-                    display: msg.clone(),
-                    style: Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-                }
-            } else if msg.starts_with("++ ") || msg.starts_with("-- ") {
-                QueryRow {
-                    time: ev.time,
-                    instant: now,
-                    conn_id: ev.conn_id,
-                    latency: ev.latency,
-                    raw_sql: None,
-                    rows_suffix: String::new(),
-                    display: msg.clone(),
-                    style: Style::default().fg(Color::DarkGray),
-                }
-            } else if msg.starts_with("WARN:") {
-                QueryRow {
-                    time: ev.time,
-                    instant: now,
-                    conn_id: ev.conn_id,
-                    latency: ev.latency,
-                    raw_sql: None,
-                    rows_suffix: String::new(),
-                    display: msg.clone(),
-                    style: Style::default().fg(Color::Yellow),
-                }
-            } else {
-                // Query event — split trailing " [N]" into rows_suffix
-                let (sql, rows_suffix) = if let Some(bracket_pos) = msg.rfind(" [") {
-                    if msg.ends_with(']') {
-                        (msg[..bracket_pos].to_string(), msg[bracket_pos..].to_string())
-                    } else {
-                        (msg.clone(), String::new())
+        let events: VecDeque<QueryRow> = snapshot
+            .recent_events
+            .into_iter()
+            .map(|ev| {
+                let msg = &ev.message;
+                let duration =
+                    parse_latency_ms(&ev.latency).map(|ms| Duration::from_secs_f64(ms / 1000.0));
+
+                let mut row = if let Some(rest) = msg.strip_prefix("ERR ") {
+                    let error = rest
+                        .split_once(": ")
+                        .map(|(code, message)| (code.to_string(), message.to_string()));
+                    let style = Style::default().fg(Color::Red).add_modifier(Modifier::BOLD);
+                    QueryRow {
+                        duration,
+                        error,
+                        ..QueryRow::new(RowKind::Error, msg.clone(), style)
                     }
+                } else if msg.starts_with("++ ") || msg.starts_with("-- ") {
+                    QueryRow::new(
+                        RowKind::Connection,
+                        msg.clone(),
+                        Style::default().fg(Color::DarkGray),
+                    )
+                } else if msg.starts_with("WARN:") {
+                    QueryRow::new(
+                        RowKind::Warning,
+                        msg.clone(),
+                        Style::default().fg(Color::Yellow),
+                    )
+                } else if msg.starts_with("SLOW TX: ") {
+                    QueryRow {
+                        duration,
+                        ..QueryRow::new(
+                            RowKind::SlowTransaction,
+                            msg.clone(),
+                            slow_transaction_style(),
+                        )
+                    }
+                } else if msg.starts_with("COPY: ") || msg.starts_with("COPY done: ") {
+                    QueryRow::new(
+                        RowKind::Copy { done: true },
+                        msg.clone(),
+                        Style::default().fg(Color::Cyan),
+                    )
+                } else if ev.sql.is_none() && ev.latency.is_empty() && msg.starts_with("NOTIFY ") {
+                    // A NOTIFY statement would have a latency; a delivered notification doesn't.
+                    QueryRow::new(RowKind::Notification, msg.clone(), notification_style())
                 } else {
-                    (msg.clone(), String::new())
-                };
+                    let (sql, params, rows_suffix) = match ev.sql {
+                        Some(sql) => (sql, ev.params, ev.rows_suffix),
+                        None => {
+                            let (sql, rows_suffix) = split_rows_suffix(msg);
+                            (sql.to_string(), String::new(), rows_suffix.to_string())
+                        }
+                    };
 
-                // Parse latency for style
-                let ms: f64 = ev.latency.trim_end_matches("ms").parse().unwrap_or(0.0);
-                let style = latency_style(ms, self.threshold_ms);
+                    // Parse latency for style
+                    let ms = parse_latency_ms(&ev.latency).unwrap_or(0.0);
+                    let style = self.scheme().style(ms, self.threshold_ms());
 
-                QueryRow {
-                    time: ev.time,
-                    instant: now,
-                    conn_id: ev.conn_id,
-                    latency: ev.latency,
-                    raw_sql: Some(sql),
-                    rows_suffix,
-                    display: String::new(),
-                    style,
-                }
-            }
-        }).collect();
+                    QueryRow {
+                        raw_sql: Some(sql),
+                        rows_suffix,
+                        params,
+                        duration,
+                        ..QueryRow::new(RowKind::Query, String::new(), style)
+                    }
+                };
+                row.time = ev.time;
+                row.instant = now;
+                row.conn_id = ev.conn_id;
+                row.latency = ev.latency;
+                row
+            })
+            .collect();
 
         // Extract filename for tab label
         let label = std::path::Path::new(path)
@@ -625,118 +2058,329 @@ impl TuiApp {
             scroll_offset: 0,
             auto_scroll: true,
             show_fingerprints: false,
+            time_format: TimeFormat::Absolute,
+            row_filter: RowFilter::All,
+            text_filter: None,
+            top_sort: TopSort::TotalTime,
+            range: None,
+            stats_only: false,
+            window: false,
         });
         self.active_tab = self.frozen_tabs.len(); // switch to new tab
 
-        self.push_status_message(format!("Imported snapshot from {path}"));
+        self.set_status(format!("Imported snapshot from {path}"), StatusTone::Done);
     }
 
-    fn push_status_message(&mut self, message: String) {
-        let now = chrono::Local::now();
-        self.events.push_back(QueryRow {
-            time: now.format("%H:%M:%S%.3f").to_string(),
-            instant: Instant::now(),
-            conn_id: 0,
-            latency: String::new(),
-            raw_sql: None,
-            rows_suffix: String::new(),
-            display: message,
-            style: Style::default().fg(Color::Cyan),
-        });
-        if self.auto_scroll {
-            self.scroll_to_bottom();
+    /// Narrow the active frozen tab to a time range; empty input clears it.
+    fn apply_time_range(&mut self, input: &str) {
+        let Some(tab) = self
+            .active_tab
+            .checked_sub(1)
+            .and_then(|i| self.frozen_tabs.get_mut(i))
+        else {
+            return;
+        };
+        if input.trim().is_empty() {
+            tab.range = None;
+            return;
         }
+        match TimeRange::parse(input) {
+            Some(range) => {
+                tab.range = Some(RangeView::new(range, &tab.events, &tab.stats));
+                tab.scroll_offset = 0;
+                tab.auto_scroll = false;
+            }
+            None => self.set_status(
+                format!("Invalid time range {input:?} — expected HH:MM:SS[.mmm]-HH:MM:SS[.mmm]"),
+                StatusTone::Failed,
+            ),
+        }
+    }
+
+    /// Rough size of what this session holds: rows, held events, aggregates and
+    /// frozen tabs. Shown in the header so long sessions know when to reset.
+    fn approx_memory_bytes(&self) -> usize {
+        let held = self.held_events.capacity() * size_of::<DisplayEvent>()
+            + self
+                .held_events
+                .iter()
+                .map(|e| match &e.kind {
+                    DisplayEventKind::Query { sql, .. } => sql.capacity(),
+                    DisplayEventKind::Error { sql, message, .. } => {
+                        sql.as_ref().map_or(0, String::capacity) + message.capacity()
+                    }
+                    DisplayEventKind::Warning(message) => message.capacity(),
+                    _ => 0,
+                })
+                .sum::<usize>();
+        let tabs: usize = self
+            .frozen_tabs
+            .iter()
+            .map(|tab| {
+                let range = tab.range.as_ref().map_or(0, |v| {
+                    rows_memory_bytes(&v.events) + v.stats.approx_memory_bytes()
+                });
+                rows_memory_bytes(&tab.events) + tab.stats.approx_memory_bytes() + range
+            })
+            .sum();
+        rows_memory_bytes(&self.events) + held + self.stats.approx_memory_bytes() + tabs
+    }
+
+    /// Header segment: "mem: ~4.2MB (312 fingerprints, 2 tabs)".
+    fn memory_summary(&self) -> String {
+        let tabs = match self.frozen_tabs.len() {
+            0 => String::new(),
+            1 => ", 1 tab".to_string(),
+            n => format!(", {n} tabs"),
+        };
+        format!(
+            "mem: ~{} ({} fingerprints{tabs})",
+            format_bytes(self.approx_memory_bytes()),
+            self.stats.fingerprints.len()
+        )
+    }
+
+    /// Send new connections to `addr`; open ones keep their upstream.
+    fn change_upstream(&mut self, addr: &str) {
+        match set_upstream(&self.upstream, addr) {
+            Ok(previous) if previous != addr.trim() => self.set_status(
+                format!(
+                    "Upstream changed to {} — open connections stay on {previous}",
+                    addr.trim()
+                ),
+                StatusTone::Done,
+            ),
+            Ok(_) => {}
+            Err(e) => self.set_status(e, StatusTone::Failed),
+        }
+    }
+
+    fn set_status(&mut self, message: impl Into<String>, tone: StatusTone) {
+        self.status = Some(StatusLine {
+            message: message.into(),
+            tone,
+            shown_at: Instant::now(),
+        });
+    }
+
+    /// Marker row in the event log, for things that happen to the stream
+    /// itself (a window ending, connection summaries) rather than key actions.
+    fn push_status_message(&mut self, message: String) {
+        self.push_row(QueryRow::status(message));
     }
 
     fn draw(&mut self, frame: &mut Frame) {
         let area = frame.area();
         let has_tabs = !self.frozen_tabs.is_empty();
 
-        // Layout: [tab_bar(1)?] + header(1) + query table (flex) + bottom panels (11) + footer(1)
-        let main_chunks = if has_tabs {
-            Layout::vertical([
-                Constraint::Length(1), // tab bar
-                Constraint::Length(1), // header
-                Constraint::Min(10),   // query table
-                Constraint::Length(11), // bottom panels
-                Constraint::Length(1), // footer
-            ])
-            .split(area)
-        } else {
-            Layout::vertical([
-                Constraint::Length(0), // no tab bar
-                Constraint::Length(1),
-                Constraint::Min(10),
-                Constraint::Length(11),
-                Constraint::Length(1),
-            ])
-            .split(area)
-        };
+        // Layout: [tab_bar(1)?] + header(1) + query table (flex) + [logs(8)?]
+        //         + bottom panels (11, or 1 compact) + [status(1)?] + footer(1)
+        let tab_bar_height = u16::from(has_tabs);
+        let compact = self
+            .compact
+            .unwrap_or(area.height < AUTO_COMPACT_BELOW_ROWS);
+        if self
+            .status
+            .as_ref()
+            .is_some_and(|s| s.shown_at.elapsed() >= STATUS_LINE_TIMEOUT)
+        {
+            self.status = None;
+        }
+        self.compact_drawn = compact;
+        let main_chunks = Layout::vertical([
+            Constraint::Length(tab_bar_height),
+            Constraint::Length(1),                                     // header
+            Constraint::Min(if compact { 1 } else { MIN_TABLE_ROWS }), // query table
+            Constraint::Length(if self.show_logs { LOG_PANE_HEIGHT } else { 0 }),
+            Constraint::Length(if compact { 1 } else { BOTTOM_PANELS_HEIGHT }),
+            Constraint::Length(u16::from(self.status.is_some())),
+            Constraint::Length(1), // footer
+        ])
+        .split(area);
+        if self.show_logs {
+            self.draw_logs(frame, main_chunks[3]);
+        }
 
         if has_tabs {
             self.draw_tab_bar(frame, main_chunks[0]);
         }
 
         // Build DrawContext for the active tab
+        let threshold_ms = self.threshold_ms();
+        let route = format!(":{} → {}", self.listen_port, self.upstream.read().unwrap());
+        let memory = self.memory_summary();
+        let highlight_row = self
+            .slowest
+            .filter(|j| j.tab == self.active_tab)
+            .map(|j| j.row);
         if self.active_tab == 0 {
             let qps = self.stats.qps();
+            let conn_churn = self.stats.conn_churn();
+            let pause_position = self.pause_position();
             let mut ctx = DrawContext {
                 events: &self.events,
                 fingerprints: &self.stats.fingerprints,
+                errors: &self.stats.errors,
                 latency_buckets: &self.stats.latency_buckets,
+                percentiles: self.stats.percentiles(),
                 total_queries: self.stats.total_queries,
                 total_errors: self.stats.total_errors,
                 active_connections: self.stats.active_connections,
+                in_flight: Some(self.stats.in_flight),
+                max_in_flight: Some(self.stats.max_in_flight),
+                pooled_connections: self.stats.live_pooled_connections(),
+                applications: &self.stats.applications,
+                client_hosts: &self.stats.client_hosts,
+                message_counts: &self.stats.message_counts,
                 first_query_at: self.stats.first_query_at,
                 scroll_offset: &mut self.scroll_offset,
                 auto_scroll: self.auto_scroll,
                 show_fingerprints: self.show_fingerprints,
+                time_format: self.time_format,
+                row_filter: self.row_filter,
+                text_filter: self.text_filter.as_ref(),
+                top_sort: self.top_sort,
+                row_sort: self.row_sort,
                 is_frozen: false,
                 qps: Some(qps),
+                conn_churn: Some(conn_churn),
+                conn_label: self.conn_label,
+                range_label: None,
+                scheme: &self.schemes[self.scheme],
+                threshold_ms,
+                freeze_elapsed: self.freeze_elapsed,
+                relative_coloring: self.relative_coloring,
+                stats_only: false,
+                highlight_row,
+                columns: &mut self.columns,
+                window_label: self.stats.window_label(Instant::now()),
             };
-            Self::draw_header_ctx(frame, main_chunks[1], &ctx, self.listen_port, &self.upstream, self.paused);
+            let flashing = self.flash_until.is_some_and(|t| Instant::now() < t);
+            Self::draw_header_ctx(
+                frame,
+                main_chunks[1],
+                &ctx,
+                &route,
+                &memory,
+                pause_position,
+                flashing,
+            );
             Self::draw_query_table_ctx(frame, main_chunks[2], &mut ctx);
-            Self::draw_bottom_panels_ctx(frame, main_chunks[3], &ctx);
+            if compact {
+                Self::draw_compact_summary_ctx(frame, main_chunks[4], &ctx);
+            } else if self.show_messages {
+                Self::draw_message_counts_ctx(frame, main_chunks[4], &ctx);
+            } else {
+                Self::draw_bottom_panels_ctx(frame, main_chunks[4], &ctx);
+            }
         } else if let Some(tab) = self.frozen_tabs.get_mut(self.active_tab - 1) {
+            let (events, stats) = match &tab.range {
+                Some(view) => (&view.events, &view.stats),
+                None => (&tab.events, &tab.stats),
+            };
+            let range_label = tab.range.as_ref().map(|v| {
+                format!(
+                    "{}–{}: {}/{} events",
+                    v.range.start.format("%H:%M:%S%.3f"),
+                    v.range.end.format("%H:%M:%S%.3f"),
+                    v.events.len(),
+                    tab.events.len(),
+                )
+            });
             let mut ctx = DrawContext {
-                events: &tab.events,
-                fingerprints: &tab.stats.fingerprints,
-                latency_buckets: &tab.stats.latency_buckets,
-                total_queries: tab.stats.total_queries,
-                total_errors: tab.stats.total_errors,
-                active_connections: tab.stats.active_connections,
-                first_query_at: tab.stats.first_query_at,
+                events,
+                fingerprints: &stats.fingerprints,
+                errors: &stats.errors,
+                latency_buckets: &stats.latency_buckets,
+                percentiles: stats.percentiles(),
+                total_queries: stats.total_queries,
+                total_errors: stats.total_errors,
+                active_connections: stats.active_connections,
+                in_flight: None,
+                max_in_flight: (stats.max_in_flight > 0).then_some(stats.max_in_flight),
+                pooled_connections: stats.pooled_connections,
+                applications: &stats.applications,
+                client_hosts: &stats.client_hosts,
+                message_counts: &stats.message_counts,
+                first_query_at: stats.first_query_at,
                 scroll_offset: &mut tab.scroll_offset,
                 auto_scroll: tab.auto_scroll,
                 show_fingerprints: tab.show_fingerprints,
+                time_format: tab.time_format,
+                row_filter: tab.row_filter,
+                text_filter: tab.text_filter.as_ref(),
+                top_sort: tab.top_sort,
+                row_sort: RowSort::Arrival,
                 is_frozen: true,
                 qps: None,
+                conn_churn: None,
+                conn_label: self.conn_label,
+                range_label,
+                scheme: &self.schemes[self.scheme],
+                threshold_ms,
+                freeze_elapsed: self.freeze_elapsed,
+                relative_coloring: self.relative_coloring,
+                stats_only: tab.stats_only,
+                highlight_row,
+                columns: &mut self.columns,
+                window_label: None,
             };
-            Self::draw_header_ctx(frame, main_chunks[1], &ctx, self.listen_port, &self.upstream, false);
+            Self::draw_header_ctx(frame, main_chunks[1], &ctx, &route, &memory, None, false);
             Self::draw_query_table_ctx(frame, main_chunks[2], &mut ctx);
-            Self::draw_bottom_panels_ctx(frame, main_chunks[3], &ctx);
+            if compact {
+                Self::draw_compact_summary_ctx(frame, main_chunks[4], &ctx);
+            } else if self.show_messages {
+                Self::draw_message_counts_ctx(frame, main_chunks[4], &ctx);
+            } else {
+                Self::draw_bottom_panels_ctx(frame, main_chunks[4], &ctx);
+            }
         }
 
-        self.draw_footer(frame, main_chunks[4]);
+        if let Some(status) = &self.status {
+            let style = match status.tone {
+                StatusTone::Info => Style::default().fg(Color::Cyan),
+                StatusTone::Done => Style::default().fg(Color::Green),
+                StatusTone::Failed => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            };
+            frame.render_widget(
+                Paragraph::new(Span::styled(format!(" {}", status.message), style)),
+                main_chunks[5],
+            );
+        }
+        self.draw_footer(frame, main_chunks[6]);
 
-        // Draw prompt overlay last (on top of everything)
-        if !matches!(self.input_mode, InputMode::Normal) {
-            self.draw_prompt(frame, area);
+        // Draw overlays last (on top of everything)
+        match self.input_mode {
+            InputMode::Normal => {}
+            InputMode::Help { .. } => self.draw_help(frame, area),
+            InputMode::Query { .. } => self.draw_query(frame, area),
+            _ => self.draw_prompt(frame, area),
         }
     }
 
     fn draw_tab_bar(&self, frame: &mut Frame, area: Rect) {
-        let active = Style::default().bg(Color::White).fg(Color::Black).add_modifier(Modifier::BOLD);
+        let active = Style::default()
+            .bg(Color::White)
+            .fg(Color::Black)
+            .add_modifier(Modifier::BOLD);
         let inactive = Style::default().fg(Color::DarkGray);
 
         let mut spans = vec![Span::styled(
             " Live ",
-            if self.active_tab == 0 { active } else { inactive },
+            if self.active_tab == 0 {
+                active
+            } else {
+                inactive
+            },
         )];
 
         for (idx, tab) in self.frozen_tabs.iter().enumerate() {
             spans.push(Span::raw(" "));
-            let style = if self.active_tab == idx + 1 { active } else { inactive };
+            let style = if self.active_tab == idx + 1 {
+                active
+            } else {
+                inactive
+            };
             spans.push(Span::styled(format!(" {} ", tab.label), style));
         }
 
@@ -746,138 +2390,530 @@ impl TuiApp {
         frame.render_widget(para, area);
     }
 
-    fn draw_header_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext, listen_port: u16, upstream: &str, paused: bool) {
-        let qps_str = ctx.qps.map(|q| format!("{q}")).unwrap_or_else(|| "—".into());
-        let frozen_str = if ctx.is_frozen { " [FROZEN]" } else { "" };
-        let paused_str = if paused { " [PAUSED]" } else { "" };
+    fn draw_header_ctx(
+        frame: &mut Frame,
+        area: Rect,
+        ctx: &DrawContext,
+        route: &str,
+        memory: &str,
+        pause_position: Option<(usize, usize, usize)>,
+        flashing: bool,
+    ) {
+        let qps_str = ctx.qps.map(format_qps).unwrap_or_else(|| "—".into());
+        let in_flight_str = match (ctx.in_flight, ctx.max_in_flight) {
+            (Some(now), Some(max)) => format!("in flight: {now} (max {max})"),
+            (None, Some(max)) => format!("max in flight: {max}"),
+            _ => String::new(),
+        };
+        let churn_str = ctx
+            .conn_churn
+            .map(|churn| format!("conn churn: {}/s", format_qps(churn)))
+            .unwrap_or_default();
+        let frozen_str = match (&ctx.range_label, ctx.is_frozen) {
+            (Some(range), _) => format!(" [FROZEN {range}]"),
+            (None, true) => " [FROZEN]".to_string(),
+            (None, false) => String::new(),
+        };
+        let paused_str = match pause_position {
+            Some((i, n, 0)) => format!(" [PAUSED event {i} of {n}]"),
+            Some((i, n, dropped)) => format!(" [PAUSED event {i} of {n}, {dropped} older dropped]"),
+            None => String::new(),
+        };
+
+        // Behind a pooler, conns are its server connections rather than app sessions.
+        let conns_str = match ctx.pooled_connections {
+            0 => ctx.active_connections.to_string(),
+            pooled => format!("{} ({pooled} pooled)", ctx.active_connections),
+        };
+
+        let percentiles_str = ctx
+            .percentiles
+            .map(|(p50, p95, p99)| {
+                let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+                format!(
+                    "p50/p95/p99: {:.1}/{:.1}/{:.1}ms",
+                    ms(p50),
+                    ms(p95),
+                    ms(p99)
+                )
+            })
+            .unwrap_or_default();
 
-        let header = format!(
-            " dbprobe ── :{} → {} ── conns: {} ── qps: {} ── total: {} ── errs: {}{}{} ",
-            listen_port, upstream, ctx.active_connections, qps_str,
-            ctx.total_queries, ctx.total_errors, frozen_str, paused_str,
-        );
+        // (priority, text) in display order. When the line is wider than the
+        // terminal, the lowest priorities are left out first; the PAUSED and
+        // FROZEN flags come before all of them and are never left out.
+        let mut segments: Vec<(u8, String)> = vec![
+            (9, route.to_string()),
+            (9, format!("conns: {conns_str}")),
+            (2, churn_str),
+            (3, in_flight_str),
+            (
+                1,
+                format!(
+                    "clients: {} apps, {} hosts",
+                    ctx.applications.len(),
+                    ctx.client_hosts.len()
+                ),
+            ),
+            (8, format!("qps: {qps_str}")),
+            (8, format!("total: {}", ctx.total_queries)),
+            (4, percentiles_str),
+            (8, format!("errs: {}", ctx.total_errors)),
+            (7, ctx.window_label.clone().unwrap_or_default()),
+            (5, memory.to_string()),
+        ];
+        segments.retain(|(_, text)| !text.is_empty());
+        let lead = format!(" dbprobe{paused_str}{frozen_str}");
+        let width = |segments: &[(u8, String)]| {
+            lead.chars().count()
+                + segments
+                    .iter()
+                    .map(|(_, text)| text.chars().count() + 4)
+                    .sum::<usize>()
+                + 1
+        };
+        while width(&segments) > area.width as usize {
+            let Some(lowest) = segments
+                .iter()
+                .enumerate()
+                .filter(|(_, (p, _))| *p < 9)
+                .min_by_key(|(_, (p, _))| *p)
+                .map(|(i, _)| i)
+            else {
+                break;
+            };
+            segments.remove(lowest);
+        }
+        let mut header = lead;
+        for (_, text) in &segments {
+            header.push_str(" ── ");
+            header.push_str(text);
+        }
+        header.push(' ');
 
-        let style = Style::default().bg(Color::Blue).fg(Color::White).add_modifier(Modifier::BOLD);
+        let bg = if flashing { Color::Red } else { Color::Blue };
+        let style = Style::default()
+            .bg(bg)
+            .fg(Color::White)
+            .add_modifier(Modifier::BOLD);
         let para = Paragraph::new(header).style(style);
         frame.render_widget(para, area);
     }
 
     fn draw_query_table_ctx(frame: &mut Frame, area: Rect, ctx: &mut DrawContext) {
+        if ctx.stats_only {
+            let placeholder = Paragraph::new(
+                "Stats-only tab — no events were kept (t freezes the event log too)",
+            )
+            .style(Style::default().fg(Color::DarkGray))
+            .block(Block::default().borders(Borders::ALL).title(" Queries "));
+            frame.render_widget(placeholder, area);
+            return;
+        }
+
         let inner_height = area.height.saturating_sub(3) as usize; // borders + header row
 
+        // Indices into events of the rows `E` leaves shown, in `o` order; scrolling moves through these.
+        let shown = shown_rows(ctx.events, ctx.row_filter, ctx.text_filter, ctx.row_sort);
+
         // Clamp scroll offset
-        let max_scroll = ctx.events.len().saturating_sub(inner_height);
+        let max_scroll = shown.len().saturating_sub(inner_height);
         if *ctx.scroll_offset > max_scroll {
             *ctx.scroll_offset = max_scroll;
         }
 
         let visible_start = *ctx.scroll_offset;
-        let visible_end = (visible_start + inner_height).min(ctx.events.len());
+        let visible_end = (visible_start + inner_height).min(shown.len());
 
         let show_fp = ctx.show_fingerprints;
-        let first_instant = ctx.first_query_at;
-        let rows: Vec<Row> = ctx.events
+        let cells: Vec<(usize, [String; 4], String)> = shown[visible_start..visible_end]
             .iter()
-            .skip(visible_start)
-            .take(visible_end - visible_start)
-            .map(|row| {
+            .map(|&i| {
+                let row = &ctx.events[i];
                 let text = match &row.raw_sql {
                     Some(sql) => {
-                        let s = if show_fp { crate::fingerprint::fingerprint(sql) } else { sql.clone() };
-                        format!("{s}{}", row.rows_suffix)
+                        let (s, params) = if show_fp {
+                            (crate::fingerprint::fingerprint(sql), "")
+                        } else {
+                            (sql.clone(), row.params.as_str())
+                        };
+                        let trace = row
+                            .trace_id
+                            .as_deref()
+                            .map(|id| format!(" trace:{}", short_trace_id(id)))
+                            .unwrap_or_default();
+                        format!("{s}{params}{}{trace}", row.rows_suffix)
                     }
                     None => row.display.clone(),
                 };
-                let elapsed = first_instant
-                    .and_then(|f| row.instant.checked_duration_since(f))
-                    .map(|d| {
-                        let ms = d.as_millis();
-                        if ms < 10_000 {
-                            format!("{ms}ms")
+                let elapsed = if ctx.freeze_elapsed {
+                    row.elapsed.clone()
+                } else {
+                    elapsed_cell(ctx.first_query_at, &ctx.events[0], row)
+                };
+                let fixed = [
+                    ctx.time_format.format(ctx.events, i),
+                    ctx.conn_label
+                        .format_query(row.conn_id, row.client_addr, row.seq),
+                    row.latency.clone(),
+                    elapsed,
+                ];
+                (i, fixed, text)
+            })
+            .collect();
+
+        let headers = [ctx.time_format.header(), "CONN", "LATENCY", "ELAPSED"];
+        let fitted = std::array::from_fn(|c| {
+            let widest = cells
+                .iter()
+                .map(|(_, fixed, _)| fixed[c].chars().count())
+                .max()
+                .unwrap_or(0);
+            widest.max(headers[c].len()) as u16
+        });
+        let widths = ctx
+            .columns
+            .resolve([12, ctx.conn_label.width(), 10, 8], fitted);
+
+        let rows: Vec<Row> = cells
+            .into_iter()
+            .map(|(i, fixed, text)| {
+                let row = &ctx.events[i];
+                Row::new(fixed.into_iter().map(Cell::from).chain([Cell::from(text)]))
+                    // Query colors follow the active scheme, so re-derive them at draw time.
+                    .style({
+                        let style = match (row.kind, row.duration) {
+                            (RowKind::Query, Some(d)) => query_style(
+                                ctx.scheme,
+                                d.as_secs_f64() * 1000.0,
+                                ctx.threshold_ms,
+                                row.baseline_ratio.filter(|_| ctx.relative_coloring),
+                            ),
+                            _ => row.style,
+                        };
+                        if ctx.highlight_row == Some(i) {
+                            style.add_modifier(Modifier::REVERSED)
                         } else {
-                            format!("{:.1}s", d.as_secs_f64())
+                            style
                         }
                     })
-                    .unwrap_or_default();
-                Row::new(vec![
-                    Cell::from(row.time.clone()),
-                    Cell::from(format!("{}", row.conn_id)),
-                    Cell::from(row.latency.clone()),
-                    Cell::from(elapsed),
-                    Cell::from(text),
-                ])
-                .style(row.style)
             })
             .collect();
 
         let scroll_indicator = if ctx.auto_scroll {
             "AUTO".to_string()
         } else {
-            format!("{}/{}", *ctx.scroll_offset + inner_height, ctx.events.len())
+            format!("{}/{}", *ctx.scroll_offset + inner_height, shown.len())
         };
+        let filter_note: String = [
+            ctx.row_filter.label(),
+            ctx.text_filter
+                .map(|t| format!("/{}", t.pattern))
+                .as_deref(),
+            ctx.row_sort.label(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|label| format!("[{label}] "))
+        .collect();
 
+        let focused = Style::default().add_modifier(Modifier::UNDERLINED | Modifier::REVERSED);
+        let header_cells = headers.iter().enumerate().map(|(c, &title)| {
+            let cell = Cell::from(title);
+            if ctx.columns.focus == Some(c) {
+                cell.style(focused)
+            } else {
+                cell
+            }
+        });
         let table = Table::new(
             rows,
-            [
-                Constraint::Length(12),
-                Constraint::Length(5),
-                Constraint::Length(10),
-                Constraint::Length(8),
-                Constraint::Min(30),
-            ],
+            widths
+                .iter()
+                .map(|&w| Constraint::Length(w))
+                .chain([Constraint::Min(30)]),
         )
         .header(
-            Row::new(vec!["TIME", "CONN", "LATENCY", "ELAPSED", "QUERY"])
-                .style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Cyan))
+            Row::new(header_cells.chain([Cell::from("QUERY")])).style(
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .fg(Color::Cyan),
+            ),
         )
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(format!(" Queries [{scroll_indicator}] "))
+                .title(format!(" Queries {filter_note}[{scroll_indicator}] ")),
         );
 
         frame.render_widget(table, area);
     }
 
     fn draw_bottom_panels_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext) {
-        let chunks = Layout::horizontal([
-            Constraint::Percentage(40),
-            Constraint::Percentage(60),
-        ])
-        .split(area);
-
-        Self::draw_latency_histogram_ctx(frame, chunks[0], ctx);
-        Self::draw_top_queries_ctx(frame, chunks[1], ctx);
-    }
-
-    fn draw_latency_histogram_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext) {
-        let labels = ["<1ms", "1-5ms", "5-10ms", "10-50ms", "50-100ms", ">100ms"];
-        let data: Vec<(&str, u64)> = labels
+        let slow_txs: Vec<&QueryRow> = ctx
+            .events
             .iter()
-            .zip(ctx.latency_buckets.iter())
-            .map(|(&label, &count)| (label, count))
+            .rev()
+            .filter(|r| r.kind == RowKind::SlowTransaction)
+            .take(area.height as usize)
             .collect();
 
-        let chart = BarChart::default()
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title(" Latency Distribution ")
-            )
-            .data(&data)
+        // Error summary and slow transactions only take space once there is something to show.
+        if ctx.errors.is_empty() && slow_txs.is_empty() {
+            let chunks =
+                Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                    .split(area);
+
+            Self::draw_latency_histogram_ctx(frame, chunks[0], ctx);
+            Self::draw_top_queries_ctx(frame, chunks[1], ctx);
+        } else {
+            let chunks = Layout::horizontal([
+                Constraint::Percentage(40),
+                Constraint::Percentage(35),
+                Constraint::Percentage(25),
+            ])
+            .split(area);
+
+            Self::draw_latency_histogram_ctx(frame, chunks[0], ctx);
+            Self::draw_top_queries_ctx(frame, chunks[1], ctx);
+            match (ctx.errors.is_empty(), slow_txs.is_empty()) {
+                (false, true) => Self::draw_error_summary_ctx(frame, chunks[2], ctx),
+                (true, false) => Self::draw_slow_transactions_ctx(frame, chunks[2], ctx, &slow_txs),
+                _ => {
+                    let side =
+                        Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+                            .split(chunks[2]);
+                    Self::draw_error_summary_ctx(frame, side[0], ctx);
+                    Self::draw_slow_transactions_ctx(frame, side[1], ctx, &slow_txs);
+                }
+            }
+        }
+    }
+
+    /// Compact layout: the bottom panels as one line — mini histogram, top query,
+    /// and counts for the error and slow-transaction panels it replaces.
+    fn draw_compact_summary_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext) {
+        let dim = Style::default().fg(Color::DarkGray);
+        let mut spans = vec![Span::styled(" latency ≤1ms ", dim)];
+        for (bar, lower_ms) in mini_histogram(ctx.latency_buckets)
+            .chars()
+            .zip(LATENCY_BUCKET_LOWER_MS)
+        {
+            let style = ctx.scheme.style(lower_ms, ctx.threshold_ms);
+            spans.push(Span::styled(
+                bar.to_string(),
+                style.remove_modifier(Modifier::BOLD),
+            ));
+        }
+        spans.push(Span::styled(" >100ms", dim));
+
+        let top = ctx.fingerprints.values().max_by_key(|q| q.total_duration);
+        if let Some(q) = top {
+            let avg_ms = q.total_duration.as_secs_f64() * 1000.0 / q.count.max(1) as f64;
+            spans.push(Span::styled(" │ top: ", dim));
+            spans.push(Span::raw(format!(
+                "{} ({}× avg {avg_ms:.1}ms)",
+                aggregate_key(&q.fingerprint, q.group.as_deref()),
+                q.count
+            )));
+        }
+        if !ctx.errors.is_empty() {
+            spans.push(Span::styled(" │ ", dim));
+            spans.push(Span::styled(
+                format!("{} error patterns", ctx.errors.len()),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        let slow_txs = ctx
+            .events
+            .iter()
+            .filter(|r| r.kind == RowKind::SlowTransaction)
+            .count();
+        if slow_txs > 0 {
+            spans.push(Span::styled(" │ ", dim));
+            spans.push(Span::styled(
+                format!("{slow_txs} slow tx"),
+                slow_transaction_style(),
+            ));
+        }
+
+        frame.render_widget(Paragraph::new(Line::from(spans)), area);
+    }
+
+    /// Most recent slow transactions, newest first.
+    fn draw_slow_transactions_ctx(
+        frame: &mut Frame,
+        area: Rect,
+        ctx: &DrawContext,
+        slow_txs: &[&QueryRow],
+    ) {
+        let rows: Vec<Row> = slow_txs
+            .iter()
+            .map(|r| {
+                // Drop the "SLOW TX: " prefix — the panel title already says it.
+                let detail = r.display.strip_prefix("SLOW TX: ").unwrap_or(&r.display);
+                Row::new(vec![
+                    Cell::from(r.time.clone()),
+                    Cell::from(ctx.conn_label.format(r.conn_id, r.client_addr)),
+                    Cell::from(detail.to_string()),
+                ])
+            })
+            .collect();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(12),
+                Constraint::Length(ctx.conn_label.width()),
+                Constraint::Min(10),
+            ],
+        )
+        .header(
+            Row::new(vec!["TIME", "CONN", "TRANSACTION"]).style(
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .fg(Color::Cyan),
+            ),
+        )
+        .style(slow_transaction_style())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Slow Transactions "),
+        );
+
+        frame.render_widget(table, area);
+    }
+
+    fn draw_error_summary_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext) {
+        let mut groups: Vec<_> = ctx.errors.values().collect();
+        groups.sort_unstable_by_key(|e| std::cmp::Reverse(e.count));
+
+        let rows: Vec<Row> = groups
+            .iter()
+            .map(|e| {
+                Row::new(vec![
+                    Cell::from(format!("{}", e.count)),
+                    Cell::from(e.code.clone()),
+                    Cell::from(e.pattern.clone()),
+                ])
+            })
+            .collect();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(6),
+                Constraint::Length(6),
+                Constraint::Min(10),
+            ],
+        )
+        .header(
+            Row::new(vec!["COUNT", "CODE", "ERROR"]).style(
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .fg(Color::Cyan),
+            ),
+        )
+        .style(Style::default().fg(Color::Red))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Errors (by pattern) "),
+        );
+
+        frame.render_widget(table, area);
+    }
+
+    fn draw_latency_histogram_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext) {
+        let labels = ["≤1ms", "1-5ms", "5-10ms", "10-50ms", "50-100ms", ">100ms"];
+        // Each bucket is colored by where its lower bound falls in the active scheme.
+        let bars: Vec<Bar> = labels
+            .iter()
+            .zip(LATENCY_BUCKET_LOWER_MS)
+            .zip(ctx.latency_buckets.iter())
+            .map(|((&label, lower_ms), &count)| {
+                let color = ctx
+                    .scheme
+                    .style(lower_ms, ctx.threshold_ms)
+                    .fg
+                    .unwrap_or(Color::Green);
+                Bar::default()
+                    .label(Line::from(label))
+                    .value(count)
+                    .style(Style::default().fg(color))
+            })
+            .collect();
+
+        let chart = BarChart::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" Latency Distribution [{}] ", ctx.scheme.name)),
+            )
+            .data(BarGroup::default().bars(&bars))
             .bar_width(7)
             .bar_gap(1)
-            .bar_style(Style::default().fg(Color::Green))
-            .value_style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD));
+            .value_style(
+                Style::default()
+                    .fg(Color::White)
+                    .add_modifier(Modifier::BOLD),
+            );
 
         frame.render_widget(chart, area);
     }
 
+    /// Wire messages by type, one table per direction — a diagnostics view of
+    /// what the clients actually send and what the parser sees.
+    fn draw_message_counts_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext) {
+        let chunks = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(area);
+        let protocol = ctx
+            .message_counts
+            .query_protocol()
+            .map(|p| format!("— {p} protocol "))
+            .unwrap_or_default();
+        let panels = [
+            (
+                Direction::Frontend,
+                format!(" Client → server messages {protocol}"),
+            ),
+            (Direction::Backend, " Server → client messages ".to_string()),
+        ];
+        for ((direction, title), chunk) in panels.into_iter().zip(chunks.iter()) {
+            let rows: Vec<Row> = ctx
+                .message_counts
+                .ranked(direction)
+                .into_iter()
+                .map(|(name, count)| {
+                    Row::new(vec![
+                        Cell::from(count.to_string()),
+                        Cell::from(name.to_string()),
+                    ])
+                })
+                .collect();
+            let table = Table::new(rows, [Constraint::Length(10), Constraint::Min(10)])
+                .header(
+                    Row::new(vec!["Count", "Message"])
+                        .style(Style::default().add_modifier(Modifier::BOLD)),
+                )
+                .block(Block::default().borders(Borders::ALL).title(title));
+            frame.render_widget(table, *chunk);
+        }
+    }
+
     fn draw_top_queries_ctx(frame: &mut Frame, area: Rect, ctx: &DrawContext) {
         let mut top: Vec<_> = ctx.fingerprints.values().cloned().collect();
-        top.sort_unstable_by(|a, b| b.total_duration.cmp(&a.total_duration));
+        ctx.top_sort.sort(&mut top);
         top.truncate(5);
         let inner_width = area.width.saturating_sub(2) as usize;
+        // With --group-by, a GROUP column tells the app's or connection's rows apart.
+        let group_width = top
+            .iter()
+            .filter_map(|q| q.group.as_ref())
+            .map(|g| g.chars().count())
+            .max()
+            .map(|w| w.clamp(5, 24));
 
         let mut rows: Vec<Row> = top
             .iter()
@@ -887,55 +2923,75 @@ impl TuiApp {
                 } else {
                     0.0
                 };
-                let fp_max_len = inner_width.saturating_sub(22);
-                let fp = if q.fingerprint.len() > fp_max_len {
-                    format!("{}..", &q.fingerprint[..fp_max_len.saturating_sub(2)])
-                } else {
-                    q.fingerprint.clone()
+                // Text results are the norm; call out the drivers that ask for binary.
+                let format = match q.result_format {
+                    Some(format @ (ResultFormat::Binary | ResultFormat::Mixed)) => {
+                        format!(" [{}]", format.label())
+                    }
+                    _ => String::new(),
                 };
-                Row::new(vec![
-                    Cell::from(fp),
-                    Cell::from(format!("{}", q.count)),
-                    Cell::from(format!("{avg_ms:.1}ms")),
-                ])
+                let fp_max_len = inner_width
+                    .saturating_sub(22 + format.len() + group_width.map_or(0, |w| w + 1));
+                let mut cells = vec![Cell::from(format!(
+                    "{}{format}",
+                    truncate(&q.fingerprint, fp_max_len)
+                ))];
+                if let Some(width) = group_width {
+                    cells.push(Cell::from(truncate(
+                        q.group.as_deref().unwrap_or(""),
+                        width,
+                    )));
+                }
+                cells.push(Cell::from(format!("{}", q.count)));
+                cells.push(Cell::from(format!("{avg_ms:.1}ms")));
+                Row::new(cells)
             })
             .collect();
 
         // Total row
         if ctx.total_queries > 0 {
             let total_count = ctx.total_queries;
-            let total_dur: Duration = ctx.fingerprints.values()
-                .map(|q| q.total_duration)
-                .sum();
+            let total_dur: Duration = ctx.fingerprints.values().map(|q| q.total_duration).sum();
             let total_avg = total_dur.as_secs_f64() * 1000.0 / total_count as f64;
             let unique = ctx.fingerprints.len();
+            let mut cells = vec![Cell::from(format!("TOTAL ({unique} unique)"))];
+            if group_width.is_some() {
+                cells.push(Cell::from(""));
+            }
+            cells.push(Cell::from(format!("{total_count}")));
+            cells.push(Cell::from(format!("{total_avg:.1}ms")));
             rows.push(
-                Row::new(vec![
-                    Cell::from(format!("TOTAL ({unique} unique)")),
-                    Cell::from(format!("{total_count}")),
-                    Cell::from(format!("{total_avg:.1}ms")),
-                ])
-                .style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Yellow))
+                Row::new(cells).style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::Yellow),
+                ),
             );
         }
 
-        let table = Table::new(
-            rows,
-            [
-                Constraint::Min(20),
-                Constraint::Length(8),
-                Constraint::Length(10),
-            ],
-        )
-        .header(
-            Row::new(vec!["QUERY", "COUNT", "AVG"])
-                .style(Style::default().add_modifier(Modifier::BOLD).fg(Color::Cyan))
-        )
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title(" Top Queries (by total time) ")
-        );
+        let mut widths = vec![
+            Constraint::Min(20),
+            Constraint::Length(8),
+            Constraint::Length(10),
+        ];
+        let mut header = vec!["QUERY", "COUNT", "AVG"];
+        if let Some(width) = group_width {
+            widths.insert(1, Constraint::Length(width as u16));
+            header.insert(1, "GROUP");
+        }
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(header).style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::Cyan),
+                ),
+            )
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" Top Queries (by {}) ", ctx.top_sort.label())),
+            );
 
         frame.render_widget(table, area);
     }
@@ -944,7 +3000,22 @@ impl TuiApp {
         let (title, buffer, cursor) = match &self.input_mode {
             InputMode::SavePrompt { buffer, cursor } => ("Save As", buffer.as_str(), *cursor),
             InputMode::ImportPrompt { buffer, cursor } => ("Import File", buffer.as_str(), *cursor),
-            InputMode::Normal => return,
+            InputMode::RangePrompt { buffer, cursor } => (
+                "Time Range (HH:MM:SS-HH:MM:SS, empty clears)",
+                buffer.as_str(),
+                *cursor,
+            ),
+            InputMode::UpstreamPrompt { buffer, cursor } => (
+                "Upstream for new connections (host:port)",
+                buffer.as_str(),
+                *cursor,
+            ),
+            InputMode::FilterPrompt { buffer, cursor, .. } => (
+                "Filter rows (regex, empty clears)",
+                buffer.as_str(),
+                *cursor,
+            ),
+            InputMode::Normal | InputMode::Help { .. } | InputMode::Query { .. } => return,
         };
 
         let width = 50u16.min(area.width.saturating_sub(4));
@@ -965,7 +3036,11 @@ impl TuiApp {
         let block = Block::default()
             .borders(Borders::ALL)
             .title(format!(" {title} "))
-            .title_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+            .title_style(
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            );
 
         let inner = block.inner(prompt_area);
         frame.render_widget(block, prompt_area);
@@ -973,8 +3048,14 @@ impl TuiApp {
         let input_line = Paragraph::new(visible_text);
         frame.render_widget(input_line, Rect::new(inner.x, inner.y, inner.width, 1));
 
-        let hint = Paragraph::new("Enter:confirm  Esc:cancel")
-            .style(Style::default().fg(Color::DarkGray));
+        let hint = match &self.input_mode {
+            InputMode::FilterPrompt {
+                error: Some(error), ..
+            } => Paragraph::new(format!("Invalid regex: {error}"))
+                .style(Style::default().fg(Color::Red)),
+            _ => Paragraph::new("Enter:confirm  Esc:cancel")
+                .style(Style::default().fg(Color::DarkGray)),
+        };
         frame.render_widget(hint, Rect::new(inner.x, inner.y + 1, inner.width, 1));
 
         // Position cursor
@@ -982,27 +3063,415 @@ impl TuiApp {
         frame.set_cursor_position((cursor_x, inner.y));
     }
 
+    /// Full-screen keybinding reference; scrolls when the terminal is too short.
+    fn draw_help(&mut self, frame: &mut Frame, area: Rect) {
+        let InputMode::Help { scroll } = &mut self.input_mode else {
+            return;
+        };
+        let lines = help_lines();
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(" Help ")
+            .title_bottom(Line::styled(
+                " j/k:scroll  Esc/?:close ",
+                Style::default().fg(Color::DarkGray),
+            ))
+            .title_style(
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            );
+        let visible = block.inner(area).height as usize;
+        *scroll = (*scroll).min(lines.len().saturating_sub(visible));
+
+        frame.render_widget(ratatui::widgets::Clear, area);
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(block)
+                .scroll((*scroll as u16, 0)),
+            area,
+        );
+    }
+
+    /// Full-screen SQL from `v`, wrapped; scrolls like the help.
+    fn draw_query(&mut self, frame: &mut Frame, area: Rect) {
+        let InputMode::Query { text, scroll } = &mut self.input_mode else {
+            return;
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(" Query (set $PAGER or $EDITOR to open it there) ")
+            .title_bottom(Line::styled(
+                " j/k:scroll  Esc/v:close ",
+                Style::default().fg(Color::DarkGray),
+            ))
+            .title_style(
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            );
+        let inner = block.inner(area);
+        let width = inner.width.max(1) as usize;
+        let wrapped: usize = text
+            .lines()
+            .map(|line| line.chars().count().div_ceil(width).max(1))
+            .sum();
+        *scroll = (*scroll).min(wrapped.saturating_sub(inner.height as usize));
+
+        let paragraph = Paragraph::new(text.as_str()).wrap(ratatui::widgets::Wrap { trim: false });
+        frame.render_widget(ratatui::widgets::Clear, area);
+        frame.render_widget(paragraph.block(block).scroll((*scroll as u16, 0)), area);
+    }
+
     fn draw_footer(&self, frame: &mut Frame, area: Rect) {
-        let help = if self.frozen_tabs.is_empty() {
-            " q:quit  j/k:scroll  G:bottom  g:top  f:fingerprint  p:pause  r:reset  s:save  i:import  t:new-tab ".to_string()
+        let help = if self.paused && self.active_tab == 0 {
+            " PAUSED  space:step  .:step 10  o:sort  p:resume  j/k:scroll  f:fingerprint  T:time  ?:help  q:quit ".to_string()
+        } else if self.frozen_tabs.is_empty() {
+            " ?:help  q:quit  j/k:scroll  G:bottom  g:top  *:slowest  v:view  /:filter  O:top-sort  E:errors  f:fingerprint  T:time  L:colors  c:compact  l:logs  m:messages  u:upstream  p:pause  r:reset  s:save  i:import  t:new-tab  F:stats-tab ".to_string()
         } else {
-            " q:quit  j/k:scroll  G:bottom  g:top  f:fingerprint  p:pause  r:reset  s:save  i:import  t:new-tab  Tab:switch  x:close ".to_string()
+            " ?:help  q:quit  j/k:scroll  G:bottom  g:top  *:slowest  v:view  /:filter  O:top-sort  E:errors  f:fingerprint  T:time  L:colors  c:compact  l:logs  m:messages  u:upstream  p:pause  r:reset  s:save  i:import  t:new-tab  F:stats-tab  Tab:switch  x:close  w:time-range ".to_string()
         };
-        let style = Style::default().fg(Color::DarkGray);
-        let para = Paragraph::new(help).style(style);
-        frame.render_widget(para, area);
+        let mut spans = Vec::new();
+        let unseen = self.logs.problem_count() - self.logs_problems_seen;
+        if unseen > 0 && !self.show_logs {
+            let warn = Style::default().fg(Color::Black).bg(Color::Yellow);
+            spans.push(Span::styled(
+                format!(" {unseen} new log warnings — l:logs "),
+                warn,
+            ));
+        }
+        let (row_filter, text_filter) =
+            match self.active_tab.checked_sub(1).map(|i| &self.frozen_tabs[i]) {
+                None => (self.row_filter, self.text_filter.as_ref()),
+                Some(tab) => (tab.row_filter, tab.text_filter.as_ref()),
+            };
+        if let Some(text) = text_filter {
+            let matches = shown_rows(
+                self.active_events(),
+                row_filter,
+                Some(text),
+                RowSort::Arrival,
+            )
+            .len();
+            let note = Style::default().fg(Color::Black).bg(Color::Magenta);
+            spans.push(Span::styled(
+                format!(" /{}: {matches} matches — Esc:clear ", text.pattern),
+                note,
+            ));
+        }
+        if let Some(jump) = self.slowest {
+            let note = Style::default().fg(Color::Black).bg(Color::Cyan);
+            spans.push(Span::styled(
+                format!(" slowest #{} of {} in buffer ", jump.rank + 1, jump.total),
+                note,
+            ));
+        }
+        spans.push(Span::styled(help, Style::default().fg(Color::DarkGray)));
+        frame.render_widget(Paragraph::new(Line::from(spans)), area);
+    }
+
+    /// dbprobe's own recent log lines, newest at the bottom, colored by level.
+    fn draw_logs(&mut self, frame: &mut Frame, area: Rect) {
+        self.logs_problems_seen = self.logs.problem_count();
+        let lines: Vec<Line> = self
+            .logs
+            .recent(area.height.saturating_sub(2) as usize)
+            .into_iter()
+            .map(|line| {
+                let level_style = match line.level {
+                    Level::ERROR => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                    Level::WARN => Style::default().fg(Color::Yellow),
+                    Level::INFO => Style::default().fg(Color::Green),
+                    Level::DEBUG => Style::default().fg(Color::Blue),
+                    Level::TRACE => Style::default().fg(Color::DarkGray),
+                };
+                Line::from(vec![
+                    Span::styled(
+                        format!("{} ", line.time),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(format!("{:<5} ", line.level), level_style),
+                    Span::raw(line.message),
+                ])
+            })
+            .collect();
+        let block = Block::default().borders(Borders::ALL).title(" Logs ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
+
+/// Lower bound of each latency histogram bucket, in ms.
+const LATENCY_BUCKET_LOWER_MS: [f64; 6] = [0.0, 1.0, 5.0, 10.0, 50.0, 100.0];
+
+/// One block character per histogram bucket, scaled to the fullest; empty buckets are blank.
+fn mini_histogram(buckets: &[u64; 6]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = buckets.iter().copied().max().unwrap_or(0).max(1);
+    buckets
+        .iter()
+        .map(|&count| match count {
+            0 => ' ',
+            _ => BLOCKS[(count * 7 / max) as usize],
+        })
+        .collect()
+}
+
+/// ELAPSED cell: time since the first query, in ms up to 10s.
+fn format_elapsed(d: Duration) -> String {
+    let ms = d.as_millis();
+    if ms < 10_000 {
+        format!("{ms}ms")
+    } else {
+        format!("{:.1}s", d.as_secs_f64())
+    }
+}
+
+/// "512B", "12.3KB", "4.2MB", "1.1GB".
+fn format_bytes(bytes: usize) -> String {
+    let mut value = bytes as f64;
+    for unit in ["B", "KB", "MB"] {
+        if value < 1024.0 {
+            return if unit == "B" {
+                format!("{bytes}B")
+            } else {
+                format!("{value:.1}{unit}")
+            };
+        }
+        value /= 1024.0;
     }
+    format!("{value:.1}GB")
 }
 
-fn latency_style(ms: f64, threshold_ms: u64) -> Style {
-    if ms >= threshold_ms as f64 {
-        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
-    } else if ms >= 50.0 {
-        Style::default().fg(Color::Red)
-    } else if ms >= 5.0 {
-        Style::default().fg(Color::Yellow)
+/// Parse a formatted latency cell ("12.3ms") back into milliseconds.
+fn parse_latency_ms(latency: &str) -> Option<f64> {
+    latency.trim().strip_suffix("ms")?.parse().ok()
+}
+
+/// Whole numbers for busy servers; one decimal once a long window yields fractional rates.
+fn format_qps(qps: f64) -> String {
+    if qps >= 10.0 || qps.fract() == 0.0 {
+        format!("{qps:.0}")
     } else {
-        Style::default().fg(Color::Green)
+        format!("{qps:.1}")
+    }
+}
+
+fn slow_transaction_style() -> Style {
+    Style::default().fg(Color::Magenta)
+}
+
+/// Lighter than slow transactions, which are also magenta.
+fn notification_style() -> Style {
+    Style::default().fg(Color::LightMagenta)
+}
+
+/// Latency color bands: green below `tiers[0]`, yellow below `tiers[1]`, red below
+/// `tiers[2]`, bold red from there on — or from the slow-query threshold, whichever is lower.
+#[derive(Clone, Debug, PartialEq)]
+struct LatencyScheme {
+    name: &'static str,
+    tiers: [f64; 3],
+}
+
+impl LatencyScheme {
+    /// Sub-millisecond point lookups; the original fixed tiers.
+    const OLTP: Self = Self {
+        name: "OLTP",
+        tiers: [5.0, 50.0, f64::INFINITY],
+    };
+    /// Reporting workloads where hundreds of ms is normal.
+    const OLAP: Self = Self {
+        name: "OLAP",
+        tiers: [100.0, 1000.0, 10_000.0],
+    };
+
+    fn custom(tiers: [f64; 3]) -> Self {
+        Self {
+            name: "custom",
+            tiers,
+        }
+    }
+
+    fn style(&self, ms: f64, threshold_ms: u64) -> Style {
+        if ms >= threshold_ms as f64 || ms >= self.tiers[2] {
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        } else if ms >= self.tiers[1] {
+            Style::default().fg(Color::Red)
+        } else if ms >= self.tiers[0] {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default().fg(Color::Green)
+        }
+    }
+
+    /// "OLAP (100/1000/10000ms)"
+    fn describe(&self) -> String {
+        let tiers: Vec<String> = self
+            .tiers
+            .iter()
+            .filter(|t| t.is_finite())
+            .map(|t| t.to_string())
+            .collect();
+        format!("{} ({}ms)", self.name, tiers.join("/"))
+    }
+}
+
+/// A query message from an older snapshot split into SQL and its trailing rows
+/// suffix (" [3]", " [3 rows, 40.0MB]", " [40.0MB]"). Only a bracket that looks
+/// like one counts, so SQL ending in e.g. `ARRAY['é']` stays whole.
+fn split_rows_suffix(message: &str) -> (&str, &str) {
+    let Some(inner) = message.strip_suffix(']') else {
+        return (message, "");
+    };
+    let Some(start) = inner.rfind(" [") else {
+        return (message, "");
+    };
+    let suffix = &inner[start + 2..];
+    let is_count = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let is_mb = |s: &str| {
+        s.strip_suffix("MB")
+            .is_some_and(|n| n.parse::<f64>().is_ok())
+    };
+    let looks_like_rows = match suffix.split_once(" rows, ") {
+        Some((count, size)) => is_count(count) && is_mb(size),
+        None => is_count(suffix) || is_mb(suffix),
+    };
+    match (looks_like_rows, message.get(..start), message.get(start..)) {
+        (true, Some(sql), Some(rows)) => (sql, rows),
+        _ => (message, ""),
+    }
+}
+
+/// Earlier runs of a fingerprint needed before its average is trusted as a baseline.
+const MIN_BASELINE_SAMPLES: u64 = 5;
+
+/// `--relative-coloring`: this many times a fingerprint's average is drawn bold red.
+const RELATIVE_OUTLIER: f64 = 3.0;
+
+/// Half of `RELATIVE_OUTLIER`'s slowdown, drawn yellow.
+const RELATIVE_SLOW: f64 = 1.5;
+
+/// `duration` as a multiple of the average of the fingerprint's earlier runs.
+/// The aggregates already include this run, so it's taken back out.
+/// With `--group-by`, the fingerprint's groups are added back together.
+fn fingerprint_baseline_ratio(
+    fingerprints: &HashMap<String, QueryAggregates>,
+    sql: &str,
+    duration: Duration,
+) -> Option<f64> {
+    let fp = crate::fingerprint::fingerprint(sql);
+    let (count, total) = match fingerprints.get(&fp) {
+        Some(agg) => (agg.count, agg.total_duration),
+        None => fingerprints
+            .values()
+            .filter(|q| q.fingerprint == fp)
+            .fold((0, Duration::ZERO), |(count, total), q| {
+                (count + q.count, total + q.total_duration)
+            }),
+    };
+    let earlier = count
+        .checked_sub(1)
+        .filter(|&n| n >= MIN_BASELINE_SAMPLES)?;
+    let average = total.saturating_sub(duration).as_secs_f64() / earlier as f64;
+    (average > 0.0).then(|| duration.as_secs_f64() / average)
+}
+
+/// A query row's color. With a baseline ratio (`--relative-coloring`), a query is
+/// judged against its own fingerprint's history; the global threshold still applies.
+fn query_style(
+    scheme: &LatencyScheme,
+    ms: f64,
+    threshold_ms: u64,
+    baseline_ratio: Option<f64>,
+) -> Style {
+    match baseline_ratio {
+        None => scheme.style(ms, threshold_ms),
+        Some(ratio) if ratio >= RELATIVE_OUTLIER || ms >= threshold_ms as f64 => {
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        }
+        Some(ratio) if ratio >= RELATIVE_SLOW => Style::default().fg(Color::Yellow),
+        Some(_) => Style::default().fg(Color::Green),
+    }
+}
+
+/// Parse `--latency-tiers`: three ascending millisecond bounds, e.g. `1,10,100`.
+pub fn parse_latency_tiers(input: &str) -> Result<[f64; 3], String> {
+    let values = input
+        .split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid tier {v:?}"))
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    let tiers: [f64; 3] = values
+        .try_into()
+        .map_err(|_| "expected three comma-separated values, e.g. 1,10,100".to_string())?;
+    if tiers[0] < 0.0 || !tiers.windows(2).all(|w| w[0] < w[1]) {
+        return Err("tiers must be non-negative and strictly ascending".into());
+    }
+    Ok(tiers)
+}
+
+/// Show `text` in `viewer` (`$PAGER` or `$EDITOR`, run through `sh` so "less -S"
+/// works), with the terminal handed back to the shell until it exits.
+fn open_in_viewer(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    viewer: &str,
+    text: &str,
+) -> anyhow::Result<()> {
+    let (path, mut file) = create_query_file()?;
+    let written = io::Write::write_all(&mut file, text.as_bytes());
+    drop(file);
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&path);
+        return Err(e.into());
+    }
+    restore_terminal(terminal);
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{viewer} \"$1\""))
+        .arg("sh")
+        .arg(&path)
+        .status();
+    let _ = std::fs::remove_file(&path);
+
+    enable_raw_mode()?;
+    terminal.backend_mut().execute(EnterAlternateScreen)?;
+    terminal.clear()?;
+    let status = status?;
+    if !status.success() {
+        anyhow::bail!("{viewer} exited with {status}");
+    }
+    Ok(())
+}
+
+/// A new file for the viewer under a random name in the temp dir, readable by
+/// this user only: the SQL can hold bound parameter values. Created with
+/// O_EXCL, so a file or symlink planted under the name is never written through.
+fn create_query_file() -> io::Result<(std::path::PathBuf, std::fs::File)> {
+    use std::hash::{BuildHasher, Hasher};
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut attempt = 0u32;
+    loop {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        hasher.write_u32(attempt);
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        let path = std::env::temp_dir().join(format!("dbprobe-query-{:016x}.sql", hasher.finish()));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(e) => return Err(e),
+        }
     }
 }
 
@@ -1017,9 +3486,8 @@ fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) {
 /// Receives ProxyMessages via the channel, processes stats internally.
 pub async fn run_tui(
     mut rx: mpsc::UnboundedReceiver<ProxyMessage>,
-    listen_port: u16,
-    upstream: String,
-    threshold_ms: u64,
+    options: TuiOptions,
+    stats_config: StatsConfig,
 ) -> anyhow::Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -1027,7 +3495,7 @@ pub async fn run_tui(
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let result = run_tui_loop(&mut terminal, &mut rx, listen_port, upstream, threshold_ms).await;
+    let result = run_tui_loop(&mut terminal, &mut rx, options, stats_config).await;
 
     // Always restore terminal, even if the loop returned an error.
     restore_terminal(&mut terminal);
@@ -1038,15 +3506,24 @@ pub async fn run_tui(
 async fn run_tui_loop(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     rx: &mut mpsc::UnboundedReceiver<ProxyMessage>,
-    listen_port: u16,
-    upstream: String,
-    threshold_ms: u64,
+    options: TuiOptions,
+    stats_config: StatsConfig,
 ) -> anyhow::Result<()> {
-    let mut app = TuiApp::new(listen_port, upstream, threshold_ms);
+    let mut app = TuiApp::new(options, stats_config);
 
     loop {
+        app.roll_window(Instant::now());
         terminal.draw(|frame| app.draw(frame))?;
 
+        // BEL is a control character — it rings without moving the cursor or
+        // touching the alternate screen, so it's safe to emit between frames.
+        if app.ring_pending {
+            app.ring_pending = false;
+            let backend = terminal.backend_mut();
+            io::Write::write_all(backend, b"\x07")?;
+            io::Write::flush(backend)?;
+        }
+
         // Poll for crossterm events
         if event::poll(Duration::from_millis(10))? {
             if let Event::Key(key) = event::read()? {
@@ -1054,28 +3531,63 @@ async fn run_tui_loop(
                 if app.should_quit {
                     break;
                 }
+                if let (Some(text), Some(viewer)) = (app.open_query.take(), app.viewer.clone()) {
+                    if let Err(e) = open_in_viewer(terminal, &viewer, &text) {
+                        app.set_status(format!("Viewer failed: {e}"), StatusTone::Failed);
+                    }
+                }
             }
         }
 
+        if let Some(summary) = app.conn_events.summary(Instant::now()) {
+            app.push_status_message(summary);
+        }
+        app.release_replay();
+
         // Drain proxy messages (non-blocking)
         loop {
             match rx.try_recv() {
                 Ok(msg) => {
                     match msg {
-                        ProxyMessage::ConnectionOpened { conn_id } => {
-                            let event = app.stats.connection_opened(conn_id);
-                            app.push_event(&event);
+                        ProxyMessage::ConnectionOpened {
+                            conn_id,
+                            client_addr,
+                        } => {
+                            for event in app.stats.connection_opened(conn_id, Some(client_addr)) {
+                                app.push_event(&event);
+                            }
                         }
                         ProxyMessage::ConnectionClosed { conn_id } => {
-                            if let Some(event) = app.stats.connection_dropped(conn_id) {
+                            for event in app.stats.connection_dropped(conn_id) {
                                 app.push_event(&event);
                             }
                         }
-                        ProxyMessage::Event { conn_id, event } => {
-                            if let Some(display_event) = app.stats.process_event(conn_id, event) {
+                        ProxyMessage::Event { conn_id, event, at } => {
+                            if let Some(display_event) = app.stats.process_event(conn_id, event, at)
+                            {
                                 app.push_event(&display_event);
                             }
                         }
+                        // Server-time estimates are a raw-output feature.
+                        ProxyMessage::UpstreamConnected { .. } => {}
+                        ProxyMessage::ClientCertificate { conn_id, subject } => {
+                            let event = app.stats.client_certificate(conn_id, subject);
+                            app.push_event(&event);
+                        }
+                        ProxyMessage::Warning { conn_id, message } => {
+                            let event = app.stats.proxy_warning(conn_id, message);
+                            app.push_event(&event);
+                        }
+                        ProxyMessage::ClientBackpressure {
+                            conn_id,
+                            waited,
+                            at,
+                        } => {
+                            if let Some(event) = app.stats.client_backpressure(conn_id, waited, at)
+                            {
+                                app.push_event(&event);
+                            }
+                        }
                     }
                 }
                 Err(mpsc::error::TryRecvError::Empty) => break,
@@ -1086,6 +3598,10 @@ async fn run_tui_loop(
             }
         }
 
+        if let Some(metrics) = &app.metrics {
+            metrics.publish(&app.stats);
+        }
+
         if app.should_quit {
             break;
         }
@@ -1093,3 +3609,1209 @@ async fn run_tui_loop(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtoEvent;
+
+    fn query_row(time: &str, sql: &str, ms: u64) -> QueryRow {
+        QueryRow {
+            kind: RowKind::Query,
+            time: time.into(),
+            raw_sql: Some(sql.into()),
+            duration: Some(Duration::from_millis(ms)),
+            ..QueryRow::status(String::new())
+        }
+    }
+
+    #[test]
+    fn test_time_format_cycles_offsets() {
+        let events: VecDeque<QueryRow> = [
+            query_row("23:59:59.500", "SELECT 1", 1),
+            query_row("23:59:59.750", "SELECT 2", 1),
+            query_row("00:00:01.000", "SELECT 3", 1),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(TimeFormat::Absolute.format(&events, 2), "00:00:01.000");
+        assert_eq!(TimeFormat::Relative.format(&events, 0), "+0.000s");
+        assert_eq!(TimeFormat::Relative.format(&events, 2), "+1.500s");
+        assert_eq!(TimeFormat::Delta.format(&events, 0), "+0.000s");
+        assert_eq!(TimeFormat::Delta.format(&events, 2), "+1.250s");
+        assert_eq!(TimeFormat::Delta.next(), TimeFormat::Absolute);
+    }
+
+    fn test_options() -> TuiOptions {
+        TuiOptions {
+            listen_port: 5433,
+            upstream: std::sync::Arc::new(std::sync::RwLock::new("localhost:5432".into())),
+            config: std::sync::Arc::new(std::sync::RwLock::new(crate::config::LiveConfig {
+                threshold_ms: 100,
+                bell_threshold_ms: None,
+            })),
+            bell_audible: false,
+            conn_label: ConnLabel::Id,
+            event_log: None,
+            metrics: None,
+            precision: 3,
+            latency_tiers: None,
+            compact_layout: false,
+            freeze_elapsed: false,
+            relative_coloring: false,
+            merge_errors: false,
+            snapshot: None,
+            replay: None,
+            logs: LogBuffer::new(10),
+            trace: None,
+            window_tabs: false,
+            conn_events: ConnEvents::Full,
+        }
+    }
+
+    #[test]
+    fn test_short_terminals_compact_without_overflow() {
+        let options = test_options();
+        let mut app = TuiApp::new(options, StatsConfig::default());
+        let draw_at = |app: &mut TuiApp, height| {
+            let mut terminal =
+                Terminal::new(ratatui::backend::TestBackend::new(100, height)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            app.compact_drawn
+        };
+
+        assert!(!draw_at(&mut app, 40));
+        assert!(draw_at(&mut app, 24));
+        assert!(draw_at(&mut app, 3));
+
+        // `c` overrides the automatic choice.
+        app.handle_key(KeyCode::Char('c'), KeyModifiers::NONE);
+        assert!(!draw_at(&mut app, 3));
+    }
+
+    #[test]
+    fn test_help_overlay_scrolls_and_swallows_keys() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        let screen = |app: &mut TuiApp| {
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(100, 12)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            terminal
+                .backend()
+                .buffer()
+                .content
+                .iter()
+                .map(|cell| cell.symbol())
+                .collect::<String>()
+        };
+
+        app.handle_key(KeyCode::Char('?'), KeyModifiers::NONE);
+        let top = screen(&mut app);
+        assert!(top.contains("Navigation") && !top.contains("Ctrl-C"));
+
+        // Scrolling past the end stops at the last page.
+        app.handle_key(KeyCode::Char('G'), KeyModifiers::NONE);
+        let bottom = screen(&mut app);
+        assert!(bottom.contains("Ctrl-C") && !bottom.contains("Navigation"));
+        app.handle_key(KeyCode::Char('k'), KeyModifiers::NONE);
+        assert!(!screen(&mut app).contains("Ctrl-C"));
+
+        // Keys go to the overlay, not the dashboard; q closes rather than quits.
+        app.handle_key(KeyCode::Char('t'), KeyModifiers::NONE);
+        app.handle_key(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert!(app.frozen_tabs.is_empty() && !app.should_quit);
+        assert!(matches!(app.input_mode, InputMode::Normal));
+        assert!(screen(&mut app).contains("?:help"));
+    }
+
+    #[test]
+    fn test_upstream_prompt_repoints_new_connections() {
+        let options = test_options();
+        let upstream = options.upstream.clone();
+        let mut app = TuiApp::new(options, StatsConfig::default());
+        let type_upstream = |app: &mut TuiApp, addr: &str| {
+            app.handle_key(KeyCode::Char('u'), KeyModifiers::NONE);
+            for _ in 0.."localhost:5432".len() {
+                app.handle_key(KeyCode::Backspace, KeyModifiers::NONE);
+            }
+            for c in addr.chars() {
+                app.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+            }
+            app.handle_key(KeyCode::Enter, KeyModifiers::NONE);
+        };
+
+        type_upstream(&mut app, "standby:5432");
+        assert_eq!(*upstream.read().unwrap(), "standby:5432");
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        assert!(screen.contains(":5433 → standby:5432"));
+
+        type_upstream(&mut app, "standby");
+        assert_eq!(*upstream.read().unwrap(), "standby:5432");
+        let status = app.status.as_ref().unwrap();
+        assert!(status.message.contains("expected host:port"));
+        assert_eq!(status.tone, StatusTone::Failed);
+    }
+
+    #[test]
+    fn test_status_messages_respect_max_events() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        for i in 0..MAX_EVENTS + 5 {
+            app.push_status_message(format!("status {i}"));
+        }
+        assert_eq!(app.events.len(), MAX_EVENTS);
+        assert_eq!(app.events.front().unwrap().display, "status 5");
+    }
+
+    #[test]
+    fn test_star_cycles_through_slowest_queries() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        for (i, ms) in [5, 80, 12, 300].into_iter().enumerate() {
+            app.events
+                .push_back(query_row("12:00:00.000", &format!("SELECT {i}"), ms));
+            app.events.push_back(QueryRow::status("status".into()));
+        }
+
+        let mut rows = Vec::new();
+        for _ in 0..5 {
+            app.handle_key(KeyCode::Char('*'), KeyModifiers::NONE);
+            rows.push(app.scroll_offset);
+        }
+        // 300ms, 80ms, 12ms, 5ms, then back to the slowest.
+        assert_eq!(rows, [6, 2, 4, 0, 6]);
+        assert!(!app.auto_scroll);
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(160, 40)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        assert!(screen.contains("slowest #1 of 4 in buffer"));
+
+        app.handle_key(KeyCode::Char('j'), KeyModifiers::NONE);
+        assert!(app.slowest.is_none());
+    }
+
+    #[test]
+    fn test_status_line_stays_out_of_the_event_log() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        let screen = |app: &mut TuiApp| {
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(120, 40)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            terminal
+                .backend()
+                .buffer()
+                .content()
+                .iter()
+                .map(|c| c.symbol())
+                .collect::<String>()
+        };
+
+        app.handle_key(KeyCode::Char('E'), KeyModifiers::NONE);
+        assert!(app.events.is_empty());
+        assert!(screen(&mut app).contains("Showing errors only"));
+
+        // The next key clears it; so does the timeout.
+        app.handle_key(KeyCode::Char('j'), KeyModifiers::NONE);
+        assert!(!screen(&mut app).contains("Showing errors only"));
+        app.import_from_path("/nonexistent/snapshot.json");
+        assert_eq!(app.status.as_ref().unwrap().tone, StatusTone::Failed);
+        app.status.as_mut().unwrap().shown_at -= STATUS_LINE_TIMEOUT;
+        assert!(!screen(&mut app).contains("Import failed"));
+        assert!(app.status.is_none());
+    }
+
+    #[test]
+    fn test_column_widths_adjust_and_auto_fit() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        for conn_id in [7, 123_456_789] {
+            app.events.push_back(QueryRow {
+                conn_id,
+                seq: Some(12),
+                latency: "1234.5ms".into(),
+                ..query_row("12:00:00.000", "SELECT 1", 1234)
+            });
+        }
+        let draw = |app: &mut TuiApp| {
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(160, 40)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            app.columns.drawn
+        };
+        let key = |app: &mut TuiApp, c| app.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+
+        assert_eq!(draw(&mut app), [12, 10, 10, 8]);
+        // "123456789/#12" clips at 10; with no focus, + widens CONN.
+        for _ in 0..3 {
+            key(&mut app, '+');
+        }
+        assert_eq!(draw(&mut app)[1], 13);
+        key(&mut app, ']');
+        key(&mut app, '-');
+        assert_eq!(draw(&mut app), [12, 13, 9, 8]);
+
+        // Auto-fit drops the manual widths and sizes to the widest cell or header.
+        key(&mut app, 'A');
+        assert_eq!(draw(&mut app), [12, 13, 8, 7]);
+        key(&mut app, '[');
+        key(&mut app, '[');
+        key(&mut app, '-');
+        assert_eq!(draw(&mut app), [11, 13, 8, 7]);
+        // Narrowing stops at the minimum.
+        for _ in 0..20 {
+            key(&mut app, '-');
+        }
+        assert_eq!(draw(&mut app)[0], MIN_COLUMN_WIDTH);
+    }
+
+    #[test]
+    fn test_paused_sort_orders_a_view_only() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        for (conn_id, ms) in [(3, 5), (1, 50), (2, 20), (1, 1)] {
+            app.events.push_back(QueryRow {
+                conn_id,
+                ..query_row("12:00:00.000", &format!("SELECT {ms}"), ms)
+            });
+        }
+        let order = |app: &TuiApp| -> Vec<String> {
+            shown_rows(
+                &app.events,
+                app.row_filter,
+                app.text_filter.as_ref(),
+                app.row_sort,
+            )
+            .into_iter()
+            .filter_map(|i| app.events[i].raw_sql.clone())
+            .collect()
+        };
+        let key = |app: &mut TuiApp, c| app.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+
+        // Live and running: nothing to sort.
+        key(&mut app, 'o');
+        assert_eq!(app.row_sort, RowSort::Arrival);
+
+        key(&mut app, 'p');
+        key(&mut app, 'o');
+        assert_eq!(
+            order(&app),
+            ["SELECT 50", "SELECT 20", "SELECT 5", "SELECT 1"]
+        );
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(160, 40)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        assert!(screen.contains("[sorted by latency ↓]"));
+        // `*` follows the sorted view: the slowest is now the top row.
+        key(&mut app, '*');
+        assert_eq!(app.scroll_offset, 0);
+
+        key(&mut app, 'o');
+        assert_eq!(
+            order(&app),
+            ["SELECT 50", "SELECT 1", "SELECT 20", "SELECT 5"]
+        );
+        // Arrival order itself is untouched, and resuming restores it.
+        assert_eq!(app.events[0].raw_sql.as_deref(), Some("SELECT 5"));
+        key(&mut app, 'p');
+        assert_eq!(app.row_sort, RowSort::Arrival);
+        assert_eq!(
+            order(&app),
+            ["SELECT 5", "SELECT 50", "SELECT 20", "SELECT 1"]
+        );
+    }
+
+    #[test]
+    fn test_merge_errors_collapses_identical_runs() {
+        let mut app = TuiApp::new(
+            TuiOptions {
+                merge_errors: true,
+                ..test_options()
+            },
+            StatsConfig::default(),
+        );
+        let error = |conn_id, message: &str| DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id,
+            client_addr: None,
+            kind: DisplayEventKind::Error {
+                sql: None,
+                duration: None,
+                code: "23505".into(),
+                message: message.into(),
+            },
+        };
+        // Same constraint, different key values: one normalized message.
+        for i in 0..52 {
+            app.append_row(&error(
+                1,
+                &format!(
+                    "duplicate key value violates unique constraint \"users_pkey\" Key (id)=({i})"
+                ),
+            ));
+        }
+        app.append_row(&error(
+            2,
+            "duplicate key value violates unique constraint \"users_pkey\" Key (id)=(1)",
+        ));
+        app.append_row(&error(1, "deadlock detected"));
+
+        assert_eq!(app.events.len(), 3);
+        assert_eq!(app.events[0].repeats, 52);
+        assert!(app.events[0].display.starts_with("ERR 23505 ×52: "));
+        assert_eq!(app.events[1].repeats, 1);
+
+        // A time range over the merged row still counts every error.
+        let range = TimeRange::parse("00:00:00-23:59:59.999").unwrap();
+        let view = RangeView::new(range, &app.events, &FrozenStats::default());
+        assert_eq!(view.stats.total_errors, 54);
+    }
+
+    #[test]
+    fn test_errors_only_filter_hides_other_rows() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        let row = |kind, display: &str| QueryRow {
+            kind,
+            display: display.into(),
+            ..QueryRow::status(String::new())
+        };
+        for i in 0..50 {
+            app.events
+                .push_back(query_row("12:00:00.000", &format!("SELECT {i}"), 1));
+        }
+        app.events.push_back(row(
+            RowKind::Error,
+            "ERROR 42P01: relation \"nope\" does not exist",
+        ));
+        app.events
+            .push_back(row(RowKind::Warning, "WARN: wide result"));
+        app.stats.total_queries = 50;
+
+        let draw = |app: &mut TuiApp| {
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(160, 40)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            terminal
+                .backend()
+                .buffer()
+                .content()
+                .iter()
+                .map(|c| c.symbol())
+                .collect::<String>()
+        };
+
+        app.handle_key(KeyCode::Char('E'), KeyModifiers::NONE);
+        let screen = draw(&mut app);
+        assert!(screen.contains("Queries [errors only]"));
+        assert!(screen.contains("relation \"nope\""));
+        assert!(!screen.contains("SELECT 0") && !screen.contains("WARN: wide result"));
+        // Nothing left for `*` to jump to.
+        app.handle_key(KeyCode::Char('*'), KeyModifiers::NONE);
+        assert!(app.slowest.is_none());
+
+        app.handle_key(KeyCode::Char('E'), KeyModifiers::NONE);
+        let screen = draw(&mut app);
+        assert!(screen.contains("Queries [errors + warnings]"));
+        assert!(screen.contains("WARN: wide result"));
+
+        // Per tab: a new tab keeps the filter, and clearing it on the live tab leaves the copy alone.
+        app.handle_key(KeyCode::Char('t'), KeyModifiers::NONE);
+        app.handle_key(KeyCode::Char('E'), KeyModifiers::NONE);
+        assert_eq!(app.row_filter, RowFilter::All);
+        assert_eq!(app.frozen_tabs[0].row_filter, RowFilter::ErrorsAndWarnings);
+        assert!(draw(&mut app).contains("SELECT 49"));
+        assert_eq!(
+            app.events
+                .iter()
+                .filter(|r| r.kind == RowKind::Query)
+                .count(),
+            50
+        );
+    }
+
+    #[test]
+    fn test_relative_coloring_flags_outliers_for_their_shape() {
+        let red = Style::default().fg(Color::Red).add_modifier(Modifier::BOLD);
+        for relative_coloring in [false, true] {
+            let mut app = TuiApp::new(
+                TuiOptions {
+                    relative_coloring,
+                    ..test_options()
+                },
+                StatsConfig::default(),
+            );
+            app.stats.connection_opened(1, None);
+            let start = Instant::now();
+            let run = |app: &mut TuiApp, i: u64, ms: u64| {
+                let at = start + Duration::from_millis(100 * i);
+                let sql = format!("SELECT * FROM users WHERE id = {i}");
+                app.stats.process_event(
+                    1,
+                    ProtoEvent::QueryStart {
+                        sql,
+                        sql_bytes: 0,
+                        portal: None,
+                        params: Vec::new(),
+                    },
+                    at,
+                );
+                let complete = ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                };
+                let event = app
+                    .stats
+                    .process_event(1, complete, at + Duration::from_millis(ms))
+                    .unwrap();
+                app.push_event(&event);
+                app.events.back().unwrap().clone()
+            };
+
+            // Too few earlier runs for a baseline yet.
+            assert_eq!(run(&mut app, 0, 2).baseline_ratio, None);
+            for i in 1..=5 {
+                run(&mut app, i, 2);
+            }
+            let usual = run(&mut app, 6, 2);
+            let outlier = run(&mut app, 7, 20);
+            assert_eq!(outlier.baseline_ratio, Some(10.0));
+            if relative_coloring {
+                assert_eq!(outlier.style, red);
+                assert_eq!(usual.style, Style::default().fg(Color::Green));
+            } else {
+                // 20ms is fine by the absolute tiers.
+                assert_ne!(outlier.style, red);
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_file_is_new_and_private() {
+        let (first, _) = create_query_file().unwrap();
+        let (second, _) = create_query_file().unwrap();
+        assert_ne!(first, second);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                std::fs::metadata(&first).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        let _ = std::fs::remove_file(first);
+        let _ = std::fs::remove_file(second);
+    }
+
+    #[test]
+    fn test_pause_holds_the_replay_and_counts_drops() {
+        let replay = ReplayControl::default();
+        let mut app = TuiApp::new(
+            TuiOptions {
+                replay: Some(replay.clone()),
+                ..test_options()
+            },
+            StatsConfig::default(),
+        );
+        let event = |conn_id| DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id,
+            client_addr: None,
+            kind: DisplayEventKind::ConnectionOpened,
+        };
+
+        app.toggle_pause();
+        assert!(replay.is_held());
+        // Nothing held yet: the step lets the replay play a frame, and shows its event.
+        app.step(1);
+        assert!(!replay.is_held());
+        app.push_event(&event(1));
+        app.push_event(&event(2));
+        assert_eq!(app.events.len(), 1);
+        assert_eq!(app.pause_position(), Some((1, 2, 0)));
+
+        // Held events over the cap are dropped oldest first, and counted.
+        for conn_id in 0..MAX_EVENTS as u64 {
+            app.push_event(&event(conn_id));
+        }
+        assert_eq!(app.pause_position(), Some((1, 1 + MAX_EVENTS, 1)));
+
+        app.toggle_pause();
+        assert!(!replay.is_held());
+        assert_eq!(app.pause_position(), None);
+    }
+
+    #[test]
+    fn test_narrow_header_keeps_state_flags() {
+        let stats_config = StatsConfig {
+            window: Some(Duration::from_secs(60)),
+            ..StatsConfig::default()
+        };
+        let mut app = TuiApp::new(test_options(), stats_config);
+        app.stats.connection_opened(1, None);
+        app.toggle_pause();
+        let header = |app: &mut TuiApp, width: u16| {
+            let mut terminal =
+                Terminal::new(ratatui::backend::TestBackend::new(width, 40)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            terminal.backend().buffer().content()[..width as usize]
+                .iter()
+                .map(|c| c.symbol())
+                .collect::<String>()
+        };
+
+        // Whole fields are left out, least useful first; the flags lead.
+        let narrow = header(&mut app, 120);
+        assert!(
+            narrow.starts_with(
+                " dbprobe [PAUSED event 0 of 0] ── :5433 → localhost:5432 ── conns: 1"
+            ),
+            "{narrow}"
+        );
+        assert!(narrow.trim_end().ends_with("errs: 0"), "{narrow}");
+        assert!(
+            !narrow.contains("clients:") && !narrow.contains("mem:"),
+            "{narrow}"
+        );
+
+        let wide = header(&mut app, 160);
+        assert!(
+            wide.trim_end()
+                .ends_with("errs: 0 ── window: 1m (0s elapsed)"),
+            "{wide}"
+        );
+    }
+
+    #[test]
+    fn test_window_keeps_finished_windows_as_tabs() {
+        let stats_config = StatsConfig {
+            window: Some(Duration::from_secs(60)),
+            ..StatsConfig::default()
+        };
+        let mut app = TuiApp::new(
+            TuiOptions {
+                window_tabs: true,
+                ..test_options()
+            },
+            stats_config,
+        );
+        let start = Instant::now();
+        app.stats.connection_opened(1, None);
+        app.stats.process_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: "SELECT 1".into(),
+                sql_bytes: 0,
+                portal: None,
+                params: Vec::new(),
+            },
+            start,
+        );
+        app.stats.process_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SELECT 1".into(),
+                rows: Some(1),
+                bytes: 0,
+            },
+            start,
+        );
+
+        app.roll_window(start + Duration::from_secs(30));
+        assert!(app.frozen_tabs.is_empty());
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(200, 40)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        assert!(screen.contains("window: 1m (0s elapsed)"));
+
+        app.roll_window(start + Duration::from_secs(60));
+        assert_eq!(app.frozen_tabs[0].stats.total_queries, 1);
+        assert_eq!(app.stats.total_queries, 0);
+        for minute in 2..=MAX_WINDOW_TABS as u64 + 1 {
+            app.roll_window(start + Duration::from_secs(60 * minute));
+        }
+        assert_eq!(app.frozen_tabs.len(), MAX_WINDOW_TABS);
+        assert!(app.frozen_tabs.iter().all(|t| t.window && t.stats_only));
+        // The query was in the first window, which has since been dropped.
+        assert!(app.frozen_tabs.iter().all(|t| t.stats.total_queries == 0));
+        assert_eq!(app.stats.total_queries, 0);
+        assert_eq!(app.active_tab, 0);
+    }
+
+    #[test]
+    fn test_stats_only_tab_skips_events() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        let now = Instant::now();
+        app.stats.connection_opened(1, None);
+        app.stats.process_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: "SELECT 1".into(),
+                sql_bytes: 0,
+                portal: None,
+                params: Vec::new(),
+            },
+            now,
+        );
+        app.stats.process_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SELECT 1".into(),
+                rows: Some(1),
+                bytes: 0,
+            },
+            now,
+        );
+        app.events
+            .push_back(query_row("12:00:00.000", "SELECT 1", 1));
+
+        app.handle_key(KeyCode::Char('F'), KeyModifiers::NONE);
+        assert_eq!(app.active_tab, 0);
+        let tab = &app.frozen_tabs[0];
+        assert!(tab.stats_only && tab.events.is_empty());
+        assert_eq!(tab.stats.total_queries, 1);
+        assert_eq!(tab.label, "Tab 1 [stats]");
+
+        app.handle_key(KeyCode::Tab, KeyModifiers::NONE);
+        app.handle_key(KeyCode::Char('w'), KeyModifiers::NONE);
+        assert!(matches!(app.input_mode, InputMode::Normal));
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        assert!(screen.contains("Tab 1 [stats]"));
+        assert!(screen.contains("Stats-only tab"));
+        assert!(screen.contains("select $n"));
+    }
+
+    #[test]
+    fn test_top_queries_sort_cycles_per_tab() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        let start = Instant::now();
+        app.stats.connection_opened(1, None);
+        for (sql, ms) in [
+            ("SELECT * FROM big", 500),
+            ("UPDATE t SET x = 1", 10),
+            ("UPDATE t SET x = 2", 10),
+            ("UPDATE t SET x = 3", 10),
+        ] {
+            app.stats.process_event(
+                1,
+                ProtoEvent::QueryStart {
+                    sql: sql.into(),
+                    sql_bytes: 0,
+                    portal: None,
+                    params: Vec::new(),
+                },
+                start,
+            );
+            let done = start + Duration::from_millis(ms);
+            app.stats.process_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "OK".into(),
+                    rows: None,
+                    bytes: 0,
+                },
+                done,
+            );
+        }
+        let screen = |app: &mut TuiApp| {
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(160, 40)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            terminal
+                .backend()
+                .buffer()
+                .content()
+                .iter()
+                .map(|c| c.symbol())
+                .collect::<String>()
+        };
+        // Whichever shape is listed first under the panel's header.
+        let first = |screen: &str| {
+            screen.find("select * from big").unwrap() < screen.find("update t set x = $n").unwrap()
+        };
+
+        let shown = screen(&mut app);
+        assert!(shown.contains("Top Queries (by total time)") && first(&shown));
+        app.handle_key(KeyCode::Char('O'), KeyModifiers::NONE);
+        let shown = screen(&mut app);
+        assert!(shown.contains("Top Queries (by count)") && !first(&shown));
+
+        // A new tab keeps the sort; changing it there leaves the live tab alone.
+        app.create_tab();
+        app.handle_key(KeyCode::Tab, KeyModifiers::NONE);
+        app.handle_key(KeyCode::Char('O'), KeyModifiers::NONE);
+        assert_eq!(app.frozen_tabs[0].top_sort, TopSort::AvgTime);
+        assert_eq!(app.top_sort, TopSort::Count);
+        let shown = screen(&mut app);
+        assert!(shown.contains("Top Queries (by avg time)") && first(&shown));
+    }
+
+    #[test]
+    fn test_replayed_snapshot_opens_as_tab() {
+        let path =
+            std::env::temp_dir().join(format!("dbprobe-tui-snapshot-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{ "version": 2, "total_queries": 4, "top_queries": [{ "fingerprint": "select $n", "count": 4, "avg_ms": 3.0 }] }"#).unwrap();
+        let app = TuiApp::new(
+            TuiOptions {
+                snapshot: Some(path.clone()),
+                ..test_options()
+            },
+            StatsConfig::default(),
+        );
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(app.active_tab, 1);
+        let stats = &app.frozen_tabs[0].stats;
+        assert_eq!(stats.total_queries, 4);
+        assert_eq!(
+            stats.fingerprints["select $n"].total_duration,
+            Duration::from_millis(12)
+        );
+    }
+
+    #[test]
+    fn test_freeze_elapsed_survives_reset() {
+        let render = |freeze_elapsed| {
+            let mut app = TuiApp::new(
+                TuiOptions {
+                    freeze_elapsed,
+                    ..test_options()
+                },
+                StatsConfig::default(),
+            );
+            let now = Instant::now();
+            app.stats.connection_opened(1, None);
+            let start = now - Duration::from_millis(12);
+            app.stats.process_event(
+                1,
+                ProtoEvent::QueryStart {
+                    sql: "SELECT 1".into(),
+                    sql_bytes: 0,
+                    portal: None,
+                    params: Vec::new(),
+                },
+                start,
+            );
+            let event = app
+                .stats
+                .process_event(
+                    1,
+                    ProtoEvent::QueryComplete {
+                        tag: "SELECT 1".into(),
+                        rows: Some(1),
+                        bytes: 0,
+                    },
+                    now,
+                )
+                .unwrap();
+            app.append_row(&event);
+            assert_eq!(app.events[0].elapsed, "0ms");
+
+            // After a reset the live column counts from the next query, which
+            // this row predates.
+            app.stats.reset();
+            std::thread::sleep(Duration::from_millis(5));
+            let later = Instant::now();
+            app.stats.process_event(
+                1,
+                ProtoEvent::QueryStart {
+                    sql: "SELECT 2".into(),
+                    sql_bytes: 0,
+                    portal: None,
+                    params: Vec::new(),
+                },
+                later,
+            );
+            let event = app
+                .stats
+                .process_event(
+                    1,
+                    ProtoEvent::QueryComplete {
+                        tag: "SELECT 1".into(),
+                        rows: Some(1),
+                        bytes: 0,
+                    },
+                    later,
+                )
+                .unwrap();
+            app.append_row(&event);
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(100, 40)).unwrap();
+            terminal.draw(|frame| app.draw(frame)).unwrap();
+            let symbols: Vec<&str> = terminal
+                .backend()
+                .buffer()
+                .content()
+                .iter()
+                .map(|c| c.symbol())
+                .collect();
+            let first_row = symbols
+                .chunks(100)
+                .map(|line| line.concat())
+                .find(|line| line.contains("SELECT 1 "))
+                .unwrap();
+            first_row.contains(" 0ms ")
+        };
+        assert!(render(true));
+        assert!(!render(false));
+    }
+
+    #[test]
+    fn test_imported_rows_show_wall_clock_elapsed() {
+        let path =
+            std::env::temp_dir().join(format!("dbprobe-tui-elapsed-{}.json", std::process::id()));
+        let events = [("23:59:58.000", "SELECT 1"), ("23:59:58.250", "SELECT 2"), ("00:00:11.000", "SELECT 3")]
+            .map(|(time, sql)| format!(r#"{{ "time": "{time}", "conn_id": 1, "latency": "1.0ms", "message": "{sql} [1]" }}"#));
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{ "version": 2, "recent_events": [{}] }}"#,
+                events.join(",")
+            ),
+        )
+        .unwrap();
+        let mut app = TuiApp::new(
+            TuiOptions {
+                snapshot: Some(path.clone()),
+                ..test_options()
+            },
+            StatsConfig::default(),
+        );
+        std::fs::remove_file(&path).ok();
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(100, 40)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let symbols: Vec<&str> = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        let lines: Vec<String> = symbols.chunks(100).map(|line| line.concat()).collect();
+        let elapsed_of = |sql: &str| {
+            let line = lines.iter().find(|l| l.contains(sql)).unwrap();
+            // TIME, CONN, LATENCY, ELAPSED, QUERY
+            line.split_whitespace().nth(3).map(str::to_string)
+        };
+        assert_eq!(elapsed_of("SELECT 1 ").as_deref(), Some("0ms"));
+        assert_eq!(elapsed_of("SELECT 2 ").as_deref(), Some("250ms"));
+        // Across midnight.
+        assert_eq!(elapsed_of("SELECT 3 ").as_deref(), Some("13.0s"));
+    }
+
+    #[test]
+    fn test_import_splits_rows_suffix_safely() {
+        assert_eq!(
+            split_rows_suffix("SELECT 'ü' [12]"),
+            ("SELECT 'ü'", " [12]")
+        );
+        assert_eq!(
+            split_rows_suffix("SELECT 1 [2 rows, 40.0MB]"),
+            ("SELECT 1", " [2 rows, 40.0MB]")
+        );
+        assert_eq!(
+            split_rows_suffix("SELECT ARRAY['é'] [ü]"),
+            ("SELECT ARRAY['é'] [ü]", "")
+        );
+        assert_eq!(split_rows_suffix("SELECT a [€]"), ("SELECT a [€]", ""));
+
+        let path =
+            std::env::temp_dir().join(format!("dbprobe-tui-utf8-{}.json", std::process::id()));
+        let events = [
+            // Before structured fields were saved: split from the message.
+            r#"{ "time": "12:00:00.000", "conn_id": 1, "latency": "1.0ms", "message": "SELECT tags['ü'] FROM t [3]" }"#,
+            r#"{ "time": "12:00:01.000", "conn_id": 1, "latency": "1.0ms", "message": "SELECT 'ß [x]'" }"#,
+            r#"{ "time": "12:00:02.000", "conn_id": 1, "latency": "1.0ms", "message": "SELECT $1 ($1='ä') [1]", "sql": "SELECT $1", "params": " ($1='ä')", "rows_suffix": " [1]" }"#,
+        ];
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{ "version": 2, "recent_events": [{}] }}"#,
+                events.join(",")
+            ),
+        )
+        .unwrap();
+        let app = TuiApp::new(
+            TuiOptions {
+                snapshot: Some(path.clone()),
+                ..test_options()
+            },
+            StatsConfig::default(),
+        );
+        std::fs::remove_file(&path).ok();
+
+        let rows: Vec<_> = app.frozen_tabs[0]
+            .events
+            .iter()
+            .map(|row| {
+                (
+                    row.raw_sql.as_deref().unwrap(),
+                    row.params.as_str(),
+                    row.rows_suffix.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("SELECT tags['ü'] FROM t", "", " [3]"),
+                ("SELECT 'ß [x]'", "", ""),
+                ("SELECT $1", " ($1='ä')", " [1]")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_view_query_picks_top_or_newest_row() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        app.viewer = None;
+        for i in 0..5 {
+            app.events
+                .push_back(query_row("12:00:00.000", &format!("SELECT {i}"), 1));
+        }
+        app.events[1].params = " ($1='x')".into();
+
+        // Following: the newest query.
+        app.handle_key(KeyCode::Char('v'), KeyModifiers::NONE);
+        assert!(matches!(&app.input_mode, InputMode::Query { text, .. } if text == "SELECT 4\n"));
+        app.handle_key(KeyCode::Esc, KeyModifiers::NONE);
+        assert!(matches!(app.input_mode, InputMode::Normal));
+
+        // Scrolled: the row at the top.
+        app.handle_key(KeyCode::Char('g'), KeyModifiers::NONE);
+        app.handle_key(KeyCode::Char('j'), KeyModifiers::NONE);
+        app.handle_key(KeyCode::Char('v'), KeyModifiers::NONE);
+        assert!(
+            matches!(&app.input_mode, InputMode::Query { text, .. } if text == "SELECT 1\n\n-- bound: ($1='x')\n")
+        );
+
+        // With a viewer configured, the loop gets the SQL to open instead.
+        app.input_mode = InputMode::Normal;
+        app.viewer = Some("less".into());
+        app.handle_key(KeyCode::Char('v'), KeyModifiers::NONE);
+        assert_eq!(
+            app.open_query.as_deref(),
+            Some("SELECT 1\n\n-- bound: ($1='x')\n")
+        );
+        assert!(matches!(app.input_mode, InputMode::Normal));
+    }
+
+    #[test]
+    fn test_regex_filter_is_per_tab() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        for sql in [
+            "SELECT * FROM users",
+            "SELECT * FROM orders",
+            "UPDATE users SET x = 1",
+        ] {
+            app.events.push_back(query_row("12:00:00.000", sql, 1));
+        }
+        let shown = |app: &TuiApp, events: &VecDeque<QueryRow>, tab: Option<usize>| {
+            let text = match tab {
+                None => app.text_filter.as_ref(),
+                Some(i) => app.frozen_tabs[i].text_filter.as_ref(),
+            };
+            shown_rows(events, RowFilter::All, text, RowSort::Arrival)
+        };
+
+        app.handle_key(KeyCode::Char('/'), KeyModifiers::NONE);
+        for c in "^SELECT.*users".chars() {
+            app.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        app.handle_key(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(matches!(app.input_mode, InputMode::Normal));
+        assert_eq!(shown(&app, &app.events, None), vec![0]);
+
+        // A new tab starts with the live filter, then keeps its own.
+        app.create_tab();
+        app.active_tab = 0;
+        app.handle_key(KeyCode::Esc, KeyModifiers::NONE);
+        assert!(app.text_filter.is_none());
+        assert_eq!(shown(&app, &app.events, None).len(), 3);
+        assert_eq!(shown(&app, &app.frozen_tabs[0].events, Some(0)), vec![0]);
+
+        // An invalid regex keeps the prompt open with the error, and the old filter.
+        app.active_tab = 1;
+        app.handle_key(KeyCode::Char('/'), KeyModifiers::NONE);
+        app.handle_key(KeyCode::Char('('), KeyModifiers::NONE);
+        app.handle_key(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(
+            matches!(&app.input_mode, InputMode::FilterPrompt { buffer, error: Some(_), .. } if buffer == "^SELECT.*users(")
+        );
+        assert_eq!(
+            app.frozen_tabs[0].text_filter.as_ref().unwrap().pattern,
+            "^SELECT.*users"
+        );
+    }
+
+    #[test]
+    fn test_memory_summary() {
+        assert_eq!(format_bytes(512), "512B");
+        assert_eq!(format_bytes(12_595), "12.3KB");
+        assert_eq!(format_bytes(4_404_019), "4.2MB");
+        assert_eq!(format_bytes(3 << 30), "3.0GB");
+
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        let empty = app.approx_memory_bytes();
+        for i in 0..100 {
+            app.events.push_back(query_row(
+                "12:00:00.000",
+                &format!("SELECT {i} FROM some_table"),
+                1,
+            ));
+        }
+        // Row slots are preallocated, so only the rows' strings add to the estimate.
+        assert!(app.approx_memory_bytes() >= empty + 100 * "SELECT 0 FROM some_table".len());
+        app.create_tab();
+        assert!(app.memory_summary().ends_with("(0 fingerprints, 1 tab)"));
+    }
+
+    #[test]
+    fn test_mini_histogram() {
+        assert_eq!(mini_histogram(&[0; 6]), "      ");
+        assert_eq!(mini_histogram(&[1, 70, 35, 0, 0, 7]), "▁█▄  ▁");
+    }
+
+    #[test]
+    fn test_latency_tiers() {
+        assert_eq!(parse_latency_tiers("1,10,100"), Ok([1.0, 10.0, 100.0]));
+        assert!(parse_latency_tiers("10,1,100").is_err());
+        assert!(parse_latency_tiers("1,10").is_err());
+        assert!(parse_latency_tiers("1,x,100").is_err());
+
+        let custom = LatencyScheme::custom([1.0, 10.0, 100.0]);
+        assert_eq!(custom.style(0.5, 1000).fg, Some(Color::Green));
+        assert_eq!(custom.style(5.0, 1000).fg, Some(Color::Yellow));
+        assert_eq!(custom.style(50.0, 1000), Style::default().fg(Color::Red));
+        assert!(custom
+            .style(150.0, 1000)
+            .add_modifier
+            .contains(Modifier::BOLD));
+        // The slow-query threshold still wins when it's below the top tier.
+        assert!(LatencyScheme::OLAP
+            .style(200.0, 100)
+            .add_modifier
+            .contains(Modifier::BOLD));
+        assert_eq!(LatencyScheme::OLTP.describe(), "OLTP (5/50ms)");
+    }
+
+    #[test]
+    fn test_save_writes_filtered_frozen_tab() {
+        let mut app = TuiApp::new(test_options(), StatsConfig::default());
+        let mut error = query_row("10:00:03.000", "SELECT broken", 1);
+        error.kind = RowKind::Error;
+        error.raw_sql = None;
+        error.display = "ERROR 42601: syntax error".to_string();
+        app.frozen_tabs.push(FrozenTab {
+            label: "Tab 1".to_string(),
+            events: [
+                query_row("09:59:59.000", "SELECT 1", 2),
+                query_row("10:00:01.000", "SELECT 2", 20),
+                error,
+                query_row("10:00:11.000", "SELECT 4", 2),
+            ]
+            .into_iter()
+            .collect(),
+            stats: FrozenStats::default(),
+            scroll_offset: 0,
+            auto_scroll: true,
+            show_fingerprints: false,
+            time_format: TimeFormat::Absolute,
+            row_filter: RowFilter::All,
+            text_filter: None,
+            top_sort: TopSort::TotalTime,
+            range: None,
+            stats_only: false,
+            window: false,
+        });
+        app.active_tab = 1;
+        app.apply_time_range("10:00:00-10:00:10");
+
+        let path =
+            std::env::temp_dir().join(format!("dbprobe-tui-save-{}.json", std::process::id()));
+        let saved = |app: &mut TuiApp| {
+            app.save_to_path(path.to_str().unwrap());
+            let snapshot: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            let messages: Vec<String> = snapshot["recent_events"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|e| e["message"].as_str().unwrap().to_string())
+                .collect();
+            (snapshot["total_queries"].as_u64().unwrap(), messages)
+        };
+
+        // The range keeps 10:00:01 and 10:00:03; aggregates follow it.
+        let (total, messages) = saved(&mut app);
+        assert_eq!(total, 1);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with("SELECT 2"));
+
+        app.cycle_row_filter();
+        let (_, messages) = saved(&mut app);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(messages, vec!["ERROR 42601: syntax error".to_string()]);
+    }
+
+    #[test]
+    fn test_time_range_parse() {
+        let range = TimeRange::parse("10:00:00-10:00:10.500").unwrap();
+        assert_eq!(range.start, NaiveTime::from_hms_opt(10, 0, 0).unwrap());
+        assert_eq!(
+            range.end,
+            NaiveTime::from_hms_milli_opt(10, 0, 10, 500).unwrap()
+        );
+        assert_eq!(
+            TimeRange::parse("10:00 10:05").unwrap().end,
+            NaiveTime::from_hms_opt(10, 5, 0).unwrap()
+        );
+        assert!(TimeRange::parse("10:05:00-10:00:00").is_none());
+        assert!(TimeRange::parse("garbage").is_none());
+    }
+
+    #[test]
+    fn test_range_view_recomputes_aggregates() {
+        let events: VecDeque<QueryRow> = [
+            query_row("09:59:59.000", "SELECT 1", 2),
+            query_row("10:00:01.000", "SELECT 2", 20),
+            query_row("10:00:05.000", "SELECT 3", 200),
+            query_row("10:00:11.000", "SELECT 4", 2),
+        ]
+        .into_iter()
+        .collect();
+
+        let range = TimeRange::parse("10:00:00-10:00:10").unwrap();
+        let view = RangeView::new(range, &events, &FrozenStats::default());
+        assert_eq!(view.events.len(), 2);
+        assert_eq!(view.stats.total_queries, 2);
+        assert_eq!(view.stats.latency_buckets, [0, 0, 0, 1, 0, 1]);
+        assert_eq!(view.stats.fingerprints.len(), 1);
+    }
+}
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+/// Sub-buckets per power of two. Each bucket spans at most 1/32 of its lower
+/// bound, so a reported percentile is within ~3% of the true latency.
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// Log-linear latency histogram (HDR-style) over microseconds, for percentiles.
+///
+/// Accuracy: unsampled, every latency is counted and a percentile is off only
+/// by bucket width (≤ 1/32 of the value). With `sampled`, latencies at or above
+/// the high-water mark are still all counted, one for one; only faster ones are
+/// thinned to 1 in `keep_one_in`, each kept one standing in for the rest. So
+/// any percentile that lands at or above the mark — p99 whenever at least 1% of
+/// queries are that slow — keeps the unsampled guarantee. Percentiles below
+/// the mark are off by at most `keep_one_in` queries' worth of rank.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyHistogram {
+    /// Weighted count per bucket, grown on demand.
+    counts: Vec<u64>,
+    total: u64,
+    sampling: Option<Sampling>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sampling {
+    high_water: Duration,
+    keep_one_in: u64,
+    /// Fast latencies seen since the last one kept.
+    skipped: u64,
+}
+
+impl LatencyHistogram {
+    /// Records every latency at or above `high_water`, and 1 in `keep_one_in` of
+    /// the faster ones. Deterministic, so a replay gives the same percentiles.
+    #[cfg(test)]
+    pub fn sampled(high_water: Duration, keep_one_in: u64) -> Self {
+        let keep_one_in = keep_one_in.max(1);
+        Self {
+            sampling: (keep_one_in > 1).then_some(Sampling {
+                high_water,
+                keep_one_in,
+                skipped: 0,
+            }),
+            ..Self::default()
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let weight = match &mut self.sampling {
+            Some(sampling) if latency < sampling.high_water => {
+                sampling.skipped += 1;
+                if sampling.skipped < sampling.keep_one_in {
+                    return;
+                }
+                sampling.skipped = 0;
+                sampling.keep_one_in
+            }
+            _ => 1,
+        };
+        let index = bucket_index(latency.as_micros().min(u64::MAX as u128) as u64);
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += weight;
+        self.total += weight;
+    }
+
+    /// Latency at quantile `q` (0.0..=1.0), or None before anything is recorded.
+    pub fn percentile(&self, q: f64) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(Duration::from_micros(bucket_midpoint(index)));
+            }
+        }
+        None
+    }
+
+    /// The three percentiles dbprobe reports, or None before anything is recorded.
+    pub fn p50_p95_p99(&self) -> Option<(Duration, Duration, Duration)> {
+        Some((
+            self.percentile(0.5)?,
+            self.percentile(0.95)?,
+            self.percentile(0.99)?,
+        ))
+    }
+
+    /// Forget everything recorded; sampling settings stay.
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+        if let Some(sampling) = &mut self.sampling {
+            sampling.skipped = 0;
+        }
+    }
+}
+
+/// Values below `SUB_BUCKETS` get a bucket each; above, each power of two is
+/// split into `SUB_BUCKETS` equal buckets.
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let exponent = 63 - micros.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub = (micros >> shift) - SUB_BUCKETS;
+    ((shift as u64 + 1) * SUB_BUCKETS + sub) as usize
+}
+
+/// Middle of a bucket's range, in microseconds.
+fn bucket_midpoint(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let low = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+    low + (1 << shift) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampled_p99_stays_accurate() {
+        // 99,000 fast queries spread over 1-10ms and 1,000 slow ones over 200-400ms.
+        let latencies: Vec<Duration> = (0..99_000u64)
+            .map(|i| Duration::from_micros(1_000 + (i * 7919) % 9_000))
+            .chain((0..1_000u64).map(|i| Duration::from_micros(200_000 + (i * 7919) % 200_000)))
+            .collect();
+        let mut sorted = latencies.clone();
+        sorted.sort();
+        let exact = |q: f64| sorted[((q * sorted.len() as f64).ceil() as usize).max(1) - 1];
+
+        let mut full = LatencyHistogram::default();
+        let mut sampled = LatencyHistogram::sampled(Duration::from_millis(100), 100);
+        for &latency in &latencies {
+            full.record(latency);
+            sampled.record(latency);
+        }
+
+        let within = |estimate: Duration, truth: Duration, tolerance: f64| {
+            let error = (estimate.as_secs_f64() - truth.as_secs_f64()).abs() / truth.as_secs_f64();
+            assert!(error <= tolerance, "{estimate:?} vs {truth:?}");
+        };
+        for q in [0.5, 0.99, 0.999] {
+            within(full.percentile(q).unwrap(), exact(q), 0.035);
+        }
+        // Everything past the mark was kept: the tail is as good as unsampled.
+        within(sampled.percentile(0.99).unwrap(), exact(0.99), 0.035);
+        within(sampled.percentile(0.999).unwrap(), exact(0.999), 0.035);
+        // The fast majority was thinned 100x; p50 is still close.
+        within(sampled.percentile(0.5).unwrap(), exact(0.5), 0.05);
+
+        sampled.clear();
+        assert_eq!(sampled.percentile(0.5), None);
+    }
+
+    #[test]
+    fn test_bucket_index_round_trips() {
+        for micros in [0, 1, 31, 32, 33, 63, 64, 1_000, 123_456, 10_000_000] {
+            let midpoint = bucket_midpoint(bucket_index(micros));
+            assert!(
+                midpoint.abs_diff(micros) as f64 <= micros as f64 / 32.0 + 1.0,
+                "{micros} -> {midpoint}"
+            );
+        }
+    }
+}
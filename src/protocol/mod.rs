@@ -1,9 +1,10 @@
+pub mod opaque;
 pub mod postgres;
 
 use std::fmt;
 
 /// Direction of a message in the proxy.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Direction {
     /// Client -> Server
     Frontend,
@@ -23,16 +24,94 @@ impl fmt::Display for Direction {
 /// Raw event from the protocol parser — one wire protocol message.
 #[derive(Clone, Debug)]
 pub enum ProtoEvent {
-    QueryStart { sql: String },
+    /// StartupMessage parameters (user, database, application_name, ...), in wire order.
+    Startup {
+        params: Vec<(String, String)>,
+    },
+    /// Simple Query or Execute. `sql_bytes` is the SQL's size on the wire, before
+    /// `sql` was truncated. `portal` is set for Execute (the unnamed portal is `""`),
+    /// and `params` holds the values its Bind supplied, formatted for display.
+    QueryStart {
+        sql: String,
+        sql_bytes: usize,
+        portal: Option<String>,
+        params: Vec<Option<String>>,
+    },
+    /// Simple Query under `--no-sql`: its size and how many statements it holds
+    /// (one CommandComplete comes back for each), without the text.
+    QueryStartNoSql {
+        statements: usize,
+        sql_bytes: usize,
+    },
+    /// Execute hit its row limit; the portal will be resumed by a later Execute.
+    PortalSuspended,
     QueryComplete {
         #[allow(dead_code)]
         tag: String,
         rows: Option<u64>,
+        /// Server bytes for the whole result, DataRows and all.
+        bytes: u64,
+    },
+    QueryError {
+        severity: String,
+        code: String,
+        message: String,
+    },
+    ConnectionReady {
+        status: TxStatus,
+    },
+    /// Parse, with the parameter type OIDs it declares (0 = left to the server).
+    ParseDetected {
+        sql: String,
+        param_types: Vec<u32>,
+    },
+    /// Server entered the COPY sub-protocol (CopyIn/CopyOut/CopyBoth response).
+    CopyStarted,
+    /// Cumulative CopyData payload bytes, emitted periodically during a COPY.
+    CopyProgress {
+        bytes: u64,
+    },
+    /// CopyDone — total CopyData payload bytes transferred.
+    CopyDone {
+        bytes: u64,
+    },
+    /// Client aborted a COPY FROM STDIN with CopyFail; the server answers with an ErrorResponse.
+    CopyFail {
+        bytes: u64,
+        reason: String,
+    },
+    /// A connection has prepared this many named statements without closing them.
+    StatementLeak {
+        open: u64,
+    },
+    /// RowDescription: the statement in flight returns rows this many columns wide.
+    RowDescription {
+        columns: u16,
+    },
+    /// Bind, with the result formats it asks for; the portal's Execute follows.
+    Bind {
+        portal: String,
+        result_format: ResultFormat,
     },
-    QueryError { severity: String, code: String, message: String },
-    ConnectionReady { status: TxStatus },
-    ParseDetected { sql: String },
     ConnectionClosed,
+    /// NotificationResponse: a NOTIFY on a channel this session LISTENs to. Can
+    /// arrive at any time, between or during queries.
+    Notification {
+        channel: String,
+        payload: String,
+    },
+    /// NoticeResponse: a WARNING, NOTICE or similar the server sent alongside a
+    /// query without failing it, e.g. "relation already exists, skipping".
+    Notice {
+        severity: String,
+        code: String,
+        message: String,
+    },
+    /// Wire messages parsed since the last report, by direction and message name.
+    /// Emitted once per read, not per message.
+    MessageCounts {
+        counts: Vec<(Direction, &'static str, u64)>,
+    },
     Unknown {
         #[allow(dead_code)]
         tag: u8,
@@ -47,20 +126,130 @@ pub enum TxStatus {
     Failed,
 }
 
+/// Result column formats a Bind asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultFormat {
+    Text,
+    Binary,
+    /// Some columns text, some binary.
+    Mixed,
+}
+
+impl ResultFormat {
+    /// From Bind's result format codes: none means all text, one applies to
+    /// every column, more give one per column. 0 is text, 1 binary.
+    pub fn from_codes(codes: &[i16]) -> Self {
+        match (codes.iter().all(|&c| c == 0), codes.iter().all(|&c| c == 1)) {
+            (true, _) => ResultFormat::Text,
+            (_, true) => ResultFormat::Binary,
+            _ => ResultFormat::Mixed,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ResultFormat::Text => "text",
+            ResultFormat::Binary => "binary",
+            ResultFormat::Mixed => "mixed",
+        }
+    }
+}
+
 /// Parses wire protocol for a given database. One instance per connection.
 pub trait ProtocolParser: Send + 'static {
-    fn try_parse(
-        &mut self,
-        buf: &[u8],
-        direction: Direction,
-    ) -> Option<(ProtoEvent, usize)>;
+    fn try_parse(&mut self, buf: &[u8], direction: Direction) -> Option<(ProtoEvent, usize)>;
 
     #[allow(dead_code)]
     fn protocol_name(&self) -> &'static str;
 
-    fn handle_startup_intercept(
-        &mut self,
-        buf: &[u8],
-        direction: Direction,
-    ) -> Option<Vec<u8>>;
+    /// Per-type message counts accumulated since the last call.
+    fn take_message_counts(&mut self) -> Vec<(Direction, &'static str, u64)> {
+        Vec::new()
+    }
+
+    fn handle_startup_intercept(&mut self, buf: &[u8], direction: Direction) -> Option<Vec<u8>>;
+}
+
+/// Wire protocols dbprobe can tell apart on a new connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Postgres,
+    /// Recognized from the server greeting; relayed without parsing for now.
+    MySql,
+}
+
+/// Postgres startup-phase request codes: v3.0 StartupMessage, SSLRequest,
+/// GSSENCRequest and CancelRequest.
+const POSTGRES_STARTUP_CODES: [u32; 4] = [196608, 80877103, 80877104, 80877102];
+
+impl Protocol {
+    /// Guess the protocol from the first bytes of whichever side spoke first.
+    /// A Postgres client opens with a length-prefixed startup packet; a MySQL
+    /// server opens with a v10 handshake (3-byte length, sequence 0, version 10).
+    pub fn detect(first: &[u8], direction: Direction) -> Option<Protocol> {
+        match direction {
+            Direction::Frontend if first.len() >= 8 => {
+                let length = u32::from_be_bytes([first[0], first[1], first[2], first[3]]);
+                let code = u32::from_be_bytes([first[4], first[5], first[6], first[7]]);
+                ((8..=10_000).contains(&length) && POSTGRES_STARTUP_CODES.contains(&code))
+                    .then_some(Protocol::Postgres)
+            }
+            Direction::Backend if first.len() >= 5 => {
+                let length = u32::from_le_bytes([first[0], first[1], first[2], 0]);
+                (length > 0 && first[3] == 0 && first[4] == 10).then_some(Protocol::MySql)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Postgres => "postgres",
+            Protocol::MySql => "mysql",
+        }
+    }
+
+    /// `capture_sql` false is `--no-sql`: queries are timed without copying their SQL.
+    pub fn parser(self, capture_sql: bool) -> Box<dyn ProtocolParser> {
+        match self {
+            Protocol::Postgres if capture_sql => Box::new(postgres::PostgresParser::new()),
+            Protocol::Postgres => Box::new(postgres::PostgresParser::without_sql()),
+            Protocol::MySql => Box::new(opaque::OpaqueParser::new(self.name())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_protocol() {
+        let startup = [0, 0, 0, 8, 0, 3, 0, 0];
+        let ssl_request = [0, 0, 0, 8, 4, 210, 22, 47];
+        assert_eq!(
+            Protocol::detect(&startup, Direction::Frontend),
+            Some(Protocol::Postgres)
+        );
+        assert_eq!(
+            Protocol::detect(&ssl_request, Direction::Frontend),
+            Some(Protocol::Postgres)
+        );
+
+        let mut greeting = vec![74, 0, 0, 0, 10];
+        greeting.extend_from_slice(b"8.0.36\0");
+        assert_eq!(
+            Protocol::detect(&greeting, Direction::Backend),
+            Some(Protocol::MySql)
+        );
+
+        // Too short, or something else entirely.
+        assert_eq!(Protocol::detect(&startup[..4], Direction::Frontend), None);
+        assert_eq!(
+            Protocol::detect(b"GET / HTTP/1.1\r\n", Direction::Frontend),
+            None
+        );
+        assert_eq!(Protocol::detect(b"+OK\r\n", Direction::Backend), None);
+    }
 }
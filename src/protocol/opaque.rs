@@ -0,0 +1,28 @@
+use super::{Direction, ProtoEvent, ProtocolParser};
+
+/// Stand-in for a protocol dbprobe can recognize but not parse yet: every byte
+/// is consumed unparsed, so the connection is relayed untouched.
+pub struct OpaqueParser {
+    name: &'static str,
+}
+
+impl OpaqueParser {
+    pub fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+}
+
+impl ProtocolParser for OpaqueParser {
+    fn try_parse(&mut self, buf: &[u8], _direction: Direction) -> Option<(ProtoEvent, usize)> {
+        let &tag = buf.first()?;
+        Some((ProtoEvent::Unknown { tag }, buf.len()))
+    }
+
+    fn protocol_name(&self) -> &'static str {
+        self.name
+    }
+
+    fn handle_startup_intercept(&mut self, _buf: &[u8], _direction: Direction) -> Option<Vec<u8>> {
+        None
+    }
+}
@@ -1,6 +1,9 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 
-use super::{Direction, ProtoEvent, ProtocolParser, TxStatus};
+use super::{Direction, ProtoEvent, ProtocolParser, ResultFormat, TxStatus};
+use crate::fingerprint::count_statements;
+use crate::truncate::truncate;
 use tracing::{debug, trace, warn};
 
 /// Connection phase state machine for PostgreSQL wire protocol.
@@ -16,29 +19,208 @@ enum ConnPhase {
     Ready,
 }
 
-const SSL_REQUEST_CODE: u32 = 80877103;
+pub(crate) const SSL_REQUEST_CODE: u32 = 80877103;
+pub(crate) const GSSENC_REQUEST_CODE: u32 = 80877104;
 const STARTUP_VERSION_3_0: u32 = 196608;
-const CANCEL_REQUEST_CODE: u32 = 80877102;
+pub(crate) const CANCEL_REQUEST_CODE: u32 = 80877102;
 
+/// SQL longer than this many characters is cut, with the truncation indicator.
 const MAX_SQL_LEN: usize = 4096;
 
+/// Emit a CopyProgress event every time this many more CopyData bytes pass.
+const COPY_PROGRESS_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Largest length field believed when parsing. Postgres itself refuses
+/// messages of 1GB or more, so anything bigger means the stream is out of step.
+const MAX_MESSAGE_LEN: u32 = 1 << 30;
+
+/// Statements and portals remembered per connection. Past this the oldest is
+/// forgotten: its Executes show as `<execute portal=...>` instead of the SQL.
+const MAX_TRACKED_NAMES: usize = 1000;
+
+/// Bound parameter values longer than this many characters are cut. Binary
+/// values are hex-encoded first, so this is about half as many bytes.
+const MAX_PARAM_LEN: usize = 64;
+
+/// Named statements open at once before a connection is flagged as leaking
+/// them; the warning repeats each time the count doubles.
+const STATEMENT_LEAK_THRESHOLD: u64 = 100;
+
+/// A prepared statement, as its Parse declared it.
+#[derive(Debug, Default)]
+struct Statement {
+    sql: String,
+    /// Size of the SQL as sent, before `sql` was truncated.
+    sql_bytes: usize,
+    /// Parameter type OIDs the client specified; 0 leaves the type to the server.
+    #[allow(dead_code)]
+    param_types: Vec<u32>,
+}
+
+/// A portal, as its Bind left it.
+#[derive(Debug, Default)]
+struct Portal {
+    stmt: String,
+    /// Bound values in parameter order, formatted for display; None is NULL.
+    params: Vec<Option<String>>,
+}
+
+/// Name → value map that forgets its oldest entry beyond `MAX_TRACKED_NAMES`,
+/// so a client that never closes anything can't grow dbprobe's memory.
+#[derive(Debug, Default)]
+struct NameMap<V> {
+    entries: HashMap<String, (u64, V)>,
+    next_seq: u64,
+}
+
+impl<V> NameMap<V> {
+    fn insert(&mut self, name: String, value: V) {
+        if self.entries.len() >= MAX_TRACKED_NAMES && !self.entries.contains_key(&name) {
+            // O(n), but only once the map is full — i.e. for a client that is leaking.
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (seq, _))| *seq)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.next_seq += 1;
+        self.entries.insert(name, (self.next_seq, value));
+    }
+
+    fn get(&self, name: &str) -> Option<&V> {
+        self.entries.get(name).map(|(_, value)| value)
+    }
+
+    fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[cfg(test)]
+    fn contains_key(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[cfg(test)]
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// In-flight COPY sub-protocol state.
+#[derive(Debug, Default)]
+struct CopyState {
+    bytes: u64,
+    next_report: u64,
+}
+
 pub struct PostgresParser {
     phase: ConnPhase,
-    /// Prepared statements: stmt_name -> SQL text.
-    statements: HashMap<String, String>,
-    /// Bound portals: portal_name -> stmt_name.
-    portals: HashMap<String, String>,
+    /// Set between CopyIn/CopyOut response and CopyDone.
+    copy: Option<CopyState>,
+    /// Prepared statements by name.
+    statements: NameMap<Statement>,
+    /// Bound portals by name.
+    portals: NameMap<Portal>,
+    /// Named statements prepared and not yet closed. Counted separately from
+    /// `statements`, which forgets old entries.
+    open_statements: u64,
+    /// `open_statements` level at which the next leak warning fires.
+    next_leak_warning: u64,
+    /// Messages parsed since `take_message_counts`, by direction and name.
+    message_counts: HashMap<(Direction, &'static str), u64>,
+    /// Server bytes since the last CommandComplete, ErrorResponse or
+    /// ReadyForQuery: the result of the query in flight.
+    result_bytes: u64,
+    /// False for `--no-sql`: queries are timed, but their SQL is neither copied
+    /// out of the buffer nor kept with prepared statements.
+    capture_sql: bool,
 }
 
 impl PostgresParser {
     pub fn new() -> Self {
         Self {
             phase: ConnPhase::AwaitingStartup,
-            statements: HashMap::new(),
-            portals: HashMap::new(),
+            copy: None,
+            statements: NameMap::default(),
+            portals: NameMap::default(),
+            open_statements: 0,
+            next_leak_warning: STATEMENT_LEAK_THRESHOLD,
+            message_counts: HashMap::new(),
+            result_bytes: 0,
+            capture_sql: true,
+        }
+    }
+
+    /// For `--no-sql`: QueryStart and ParseDetected carry an empty `sql`, with
+    /// `sql_bytes` still set.
+    pub fn without_sql() -> Self {
+        Self {
+            capture_sql: false,
+            ..Self::new()
+        }
+    }
+
+    /// Named statements went away; a leak warning can fire again once the count
+    /// climbs back past the threshold.
+    fn close_statements(&mut self, count: u64) {
+        self.open_statements = self.open_statements.saturating_sub(count);
+        while self.next_leak_warning / 2 >= STATEMENT_LEAK_THRESHOLD
+            && self.open_statements < self.next_leak_warning / 2
+        {
+            self.next_leak_warning /= 2;
         }
     }
 
+    /// `DEALLOCATE name`, `DEALLOCATE ALL` and `DISCARD ALL` close prepared
+    /// statements from SQL, as some poolers do.
+    fn track_deallocate(&mut self, sql: &str) {
+        let lower = sql.trim().trim_end_matches(';').to_ascii_lowercase();
+        let mut words = lower.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("deallocate"), Some("all"), None)
+            | (Some("deallocate"), Some("prepare"), Some("all"))
+            | (Some("discard"), Some("all"), None) => {
+                self.statements.clear();
+                self.portals.clear();
+                self.close_statements(self.open_statements);
+            }
+            (Some("deallocate"), Some("prepare"), Some(_))
+            | (Some("deallocate"), Some(_), None) => {
+                // Unquoted names fold to lowercase, like any SQL identifier.
+                let word = sql
+                    .trim()
+                    .trim_end_matches(';')
+                    .split_whitespace()
+                    .last()
+                    .unwrap_or_default();
+                let name = match word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+                    Some(quoted) => quoted.to_string(),
+                    None => word.to_ascii_lowercase(),
+                };
+                if self.statements.remove(&name) {
+                    self.close_statements(1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn count_message(&mut self, direction: Direction, name: &'static str) {
+        *self.message_counts.entry((direction, name)).or_default() += 1;
+    }
+
     /// Try to parse a startup message (no tag byte).
     fn try_parse_startup(&mut self, buf: &[u8]) -> Option<(ProtoEvent, usize)> {
         if buf.len() < 8 {
@@ -56,6 +238,7 @@ impl PostgresParser {
         }
 
         let version = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        self.count_message(Direction::Frontend, startup_message_name(version));
 
         match version {
             SSL_REQUEST_CODE => {
@@ -65,7 +248,8 @@ impl PostgresParser {
             STARTUP_VERSION_3_0 => {
                 debug!("StartupMessage v3.0");
                 self.phase = ConnPhase::Authenticating;
-                Some((ProtoEvent::Unknown { tag: 0 }, length))
+                let params = parse_startup_params(&buf[8..length]);
+                Some((ProtoEvent::Startup { params }, length))
             }
             CANCEL_REQUEST_CODE => {
                 debug!("CancelRequest");
@@ -91,9 +275,15 @@ impl PostgresParser {
         let tag = buf[0];
         let raw_length = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]);
 
-        if raw_length < 4 {
-            warn!("Invalid message length {raw_length} for tag '{}'", tag as char);
-            return Some((ProtoEvent::Unknown { tag }, 1));
+        if !(4..MAX_MESSAGE_LEN).contains(&raw_length) {
+            // Skip to the next byte that could start a message, rather than one
+            // byte at a time with a warning for each.
+            let skip = resync_offset(buf, direction);
+            warn!(
+                "Invalid message length {raw_length} for tag '{}', skipping {skip} bytes to resync",
+                tag as char
+            );
+            return Some((ProtoEvent::Unknown { tag }, skip));
         }
 
         let total_len = 1 + raw_length as usize; // tag byte + length (which includes itself)
@@ -102,6 +292,10 @@ impl PostgresParser {
         }
 
         let payload = &buf[5..total_len];
+        self.count_message(direction, message_name(direction, tag));
+        if direction == Direction::Backend {
+            self.result_bytes += total_len as u64;
+        }
         let event = self.parse_message(tag, payload, direction);
 
         Some((event, total_len))
@@ -110,78 +304,191 @@ impl PostgresParser {
     fn parse_message(&mut self, tag: u8, payload: &[u8], direction: Direction) -> ProtoEvent {
         match (direction, tag) {
             // Frontend: Simple Query
+            (Direction::Frontend, b'Q') if !self.capture_sql && !may_close_statements(payload) => {
+                let sql_bytes = payload.iter().position(|&b| b == 0).unwrap_or(0);
+                // An empty query still gets an answer: EmptyQueryResponse.
+                let statements = count_statements(&payload[..sql_bytes]).max(1);
+                ProtoEvent::QueryStartNoSql {
+                    statements,
+                    sql_bytes,
+                }
+            }
             (Direction::Frontend, b'Q') => {
                 let sql = extract_cstring(payload).unwrap_or_default();
-                let sql = truncate_sql(&sql);
+                let sql_bytes = sql.len();
+                let sql = truncate(&sql, MAX_SQL_LEN);
                 trace!("Query: {sql}");
-                ProtoEvent::QueryStart { sql }
+                self.track_deallocate(&sql);
+                ProtoEvent::QueryStart {
+                    sql,
+                    sql_bytes,
+                    portal: None,
+                    params: Vec::new(),
+                }
             }
 
             // Frontend: Parse (Extended Query Protocol)
             (Direction::Frontend, b'P') => {
-                // Format: stmt_name\0 sql\0 param_count(i16) param_types...
-                if let Some(name_end) = payload.iter().position(|&b| b == 0) {
-                    let stmt_name = String::from_utf8_lossy(&payload[..name_end]).into_owned();
-                    let rest = &payload[name_end + 1..];
-                    let sql = extract_cstring(rest).unwrap_or_default();
-                    let sql = truncate_sql(&sql);
-                    trace!("Parse (extended): stmt={stmt_name:?} sql={sql}");
-                    self.statements.insert(stmt_name, sql.clone());
-                    ProtoEvent::ParseDetected { sql }
+                let Some((stmt_name, sql, sql_bytes, param_types)) =
+                    parse_parse_body(payload, self.capture_sql)
+                else {
+                    warn!(
+                        "Malformed Parse message ({} bytes), ignoring",
+                        payload.len()
+                    );
+                    return ProtoEvent::Unknown { tag };
+                };
+                let sql = if self.capture_sql {
+                    truncate(&sql, MAX_SQL_LEN)
                 } else {
-                    ProtoEvent::Unknown { tag }
+                    sql
+                };
+                trace!(
+                    "Parse (extended): stmt={stmt_name:?} sql={sql} param_types={param_types:?}"
+                );
+                // The unnamed statement is replaced by every Parse; only named ones can leak.
+                if !stmt_name.is_empty() && self.statements.get(&stmt_name).is_none() {
+                    self.open_statements += 1;
+                }
+                self.statements.insert(
+                    stmt_name,
+                    Statement {
+                        sql: sql.clone(),
+                        sql_bytes,
+                        param_types: param_types.clone(),
+                    },
+                );
+                if self.open_statements >= self.next_leak_warning {
+                    self.next_leak_warning *= 2;
+                    return ProtoEvent::StatementLeak {
+                        open: self.open_statements,
+                    };
                 }
+                ProtoEvent::ParseDetected { sql, param_types }
             }
 
             // Frontend: Bind
-            (Direction::Frontend, b'B') => {
-                // Format: portal_name\0 stmt_name\0 ...
-                if let Some(portal_end) = payload.iter().position(|&b| b == 0) {
-                    let portal = String::from_utf8_lossy(&payload[..portal_end]).into_owned();
-                    let rest = &payload[portal_end + 1..];
-                    let stmt = extract_cstring(rest).unwrap_or_default();
-                    trace!("Bind: portal={portal:?} stmt={stmt:?}");
-                    self.portals.insert(portal, stmt);
+            (Direction::Frontend, b'B') => match parse_bind_body(payload) {
+                Some((portal, bound, result_format)) => {
+                    trace!(
+                        "Bind: portal={portal:?} stmt={:?} results={}",
+                        bound.stmt,
+                        result_format.label()
+                    );
+                    self.portals.insert(portal.clone(), bound);
+                    ProtoEvent::Bind {
+                        portal,
+                        result_format,
+                    }
                 }
-                ProtoEvent::Unknown { tag }
-            }
+                None => {
+                    warn!("Malformed Bind message ({} bytes), ignoring", payload.len());
+                    ProtoEvent::Unknown { tag }
+                }
+            },
 
             // Frontend: Execute
             (Direction::Frontend, b'E') => {
                 // Format: portal_name\0 max_rows(i32)
-                let portal = extract_cstring(payload).unwrap_or_default();
-                let sql = self.portals.get(&portal)
-                    .and_then(|stmt| self.statements.get(stmt))
-                    .cloned()
+                let mut reader = PayloadReader(payload);
+                let Some(portal) = reader.cstring().filter(|_| reader.i32().is_some()) else {
+                    warn!(
+                        "Malformed Execute message ({} bytes), ignoring",
+                        payload.len()
+                    );
+                    return ProtoEvent::Unknown { tag };
+                };
+                let bound = self.portals.get(&portal);
+                let statement = bound.and_then(|bound| self.statements.get(&bound.stmt));
+                let sql_bytes = statement.map_or(0, |stmt| stmt.sql_bytes);
+                let sql = statement
+                    .map(|stmt| stmt.sql.clone())
                     .unwrap_or_else(|| format!("<execute portal={portal:?}>"));
-                trace!("Execute: portal={portal:?} sql={sql}");
-                ProtoEvent::QueryStart { sql }
+                let params = bound.map(|bound| bound.params.clone()).unwrap_or_default();
+                trace!("Execute: portal={portal:?} sql={sql} params={params:?}");
+                ProtoEvent::QueryStart {
+                    sql,
+                    sql_bytes,
+                    portal: Some(portal),
+                    params,
+                }
             }
 
             // Frontend: Close
             (Direction::Frontend, b'C') => {
                 // Format: type_byte ('S' or 'P') name\0
-                if !payload.is_empty() {
-                    let close_type = payload[0];
-                    let name = extract_cstring(&payload[1..]).unwrap_or_default();
-                    match close_type {
-                        b'S' => { self.statements.remove(&name); }
-                        b'P' => { self.portals.remove(&name); }
-                        _ => {}
+                let mut reader = PayloadReader(payload);
+                match (reader.byte(), reader.cstring()) {
+                    (Some(b'S'), Some(name)) => {
+                        trace!("Close: type=S name={name:?}");
+                        self.statements.remove(&name);
+                        if !name.is_empty() {
+                            self.close_statements(1);
+                        }
                     }
-                    trace!("Close: type={} name={name:?}", close_type as char);
+                    (Some(b'P'), Some(name)) => {
+                        trace!("Close: type=P name={name:?}");
+                        self.portals.remove(&name);
+                    }
+                    _ => warn!(
+                        "Malformed Close message ({} bytes), ignoring",
+                        payload.len()
+                    ),
                 }
                 ProtoEvent::Unknown { tag }
             }
 
             // Frontend: Sync, Describe, Flush — transparent passthrough
-            (Direction::Frontend, b'S') | (Direction::Frontend, b'D') | (Direction::Frontend, b'H') => {
-                ProtoEvent::Unknown { tag }
-            }
+            (Direction::Frontend, b'S')
+            | (Direction::Frontend, b'D')
+            | (Direction::Frontend, b'H') => ProtoEvent::Unknown { tag },
 
             // Frontend: Terminate
             (Direction::Frontend, b'X') => ProtoEvent::ConnectionClosed,
 
+            // Backend: CopyInResponse / CopyOutResponse / CopyBothResponse
+            (Direction::Backend, b'G')
+            | (Direction::Backend, b'H')
+            | (Direction::Backend, b'W') => {
+                trace!("Copy response '{}'", tag as char);
+                self.copy = Some(CopyState {
+                    bytes: 0,
+                    next_report: COPY_PROGRESS_BYTES,
+                });
+                ProtoEvent::CopyStarted
+            }
+
+            // CopyData — client-bound for COPY TO, server-bound for COPY FROM
+            (_, b'd') => match self.copy.as_mut() {
+                Some(copy) => {
+                    copy.bytes += payload.len() as u64;
+                    if copy.bytes >= copy.next_report {
+                        copy.next_report = copy.bytes + COPY_PROGRESS_BYTES;
+                        ProtoEvent::CopyProgress { bytes: copy.bytes }
+                    } else {
+                        ProtoEvent::Unknown { tag }
+                    }
+                }
+                None => ProtoEvent::Unknown { tag },
+            },
+
+            // Frontend: CopyFail — client aborts a COPY FROM STDIN with a reason
+            (Direction::Frontend, b'f') => {
+                let reason = extract_cstring(payload).unwrap_or_default();
+                let bytes = self.copy.take().map_or(0, |copy| copy.bytes);
+                trace!("CopyFail after {bytes} bytes: {reason}");
+                ProtoEvent::CopyFail { bytes, reason }
+            }
+
+            // CopyDone — from whichever side was sending data
+            (_, b'c') => match self.copy.take() {
+                Some(copy) => {
+                    trace!("CopyDone: {} bytes", copy.bytes);
+                    ProtoEvent::CopyDone { bytes: copy.bytes }
+                }
+                None => ProtoEvent::Unknown { tag },
+            },
+
             // Backend: CommandComplete
             (Direction::Backend, b'C') => {
                 let tag_str = extract_cstring(payload).unwrap_or_default();
@@ -190,13 +497,73 @@ impl PostgresParser {
                 ProtoEvent::QueryComplete {
                     tag: tag_str,
                     rows,
+                    bytes: std::mem::take(&mut self.result_bytes),
+                }
+            }
+
+            // Backend: RowDescription — field count, then one descriptor per column
+            (Direction::Backend, b'T') => match PayloadReader(payload).count() {
+                Some(columns) => {
+                    trace!("RowDescription: {columns} columns");
+                    ProtoEvent::RowDescription {
+                        columns: columns as u16,
+                    }
+                }
+                None => ProtoEvent::Unknown { tag },
+            },
+
+            // Backend: PortalSuspended — answers an Execute in place of CommandComplete
+            (Direction::Backend, b's') => {
+                trace!("PortalSuspended");
+                ProtoEvent::PortalSuspended
+            }
+
+            // Backend: NotificationResponse — pid(i32) channel\0 payload\0
+            (Direction::Backend, b'A') => {
+                // Not part of any query's result, even when it arrives mid-result.
+                self.result_bytes = self.result_bytes.saturating_sub(5 + payload.len() as u64);
+                let mut reader = PayloadReader(payload);
+                let Some((channel, notify_payload)) = reader
+                    .i32()
+                    .and_then(|_| Some((reader.cstring()?, reader.cstring()?)))
+                else {
+                    warn!(
+                        "Malformed NotificationResponse ({} bytes), ignoring",
+                        payload.len()
+                    );
+                    return ProtoEvent::Unknown { tag };
+                };
+                trace!("Notification: channel={channel:?}");
+                ProtoEvent::Notification {
+                    channel,
+                    payload: truncate(&notify_payload, MAX_SQL_LEN),
                 }
             }
 
+            // Backend: NoticeResponse — same fields as ErrorResponse, but the query carries on
+            (Direction::Backend, b'N') => {
+                self.result_bytes = self.result_bytes.saturating_sub(5 + payload.len() as u64);
+                let (severity, code, message) = parse_error_response(payload);
+                trace!("Notice: {severity} {code} {message}");
+                ProtoEvent::Notice {
+                    severity,
+                    code,
+                    message,
+                }
+            }
+
+            // Backend: EmptyQueryResponse — completes an empty query string
+            (Direction::Backend, b'I') => ProtoEvent::QueryComplete {
+                tag: String::new(),
+                rows: None,
+                bytes: std::mem::take(&mut self.result_bytes),
+            },
+
             // Backend: ErrorResponse
             (Direction::Backend, b'E') => {
                 let (severity, code, message) = parse_error_response(payload);
                 trace!("Error: {severity} {code} {message}");
+                self.result_bytes = 0;
                 ProtoEvent::QueryError {
                     severity,
                     code,
@@ -217,6 +584,7 @@ impl PostgresParser {
                     }
                 };
 
+                self.result_bytes = 0;
                 if self.phase == ConnPhase::Authenticating {
                     self.phase = ConnPhase::Ready;
                     debug!("Connection authenticated, entering Ready phase");
@@ -231,11 +599,7 @@ impl PostgresParser {
 }
 
 impl ProtocolParser for PostgresParser {
-    fn try_parse(
-        &mut self,
-        buf: &[u8],
-        direction: Direction,
-    ) -> Option<(ProtoEvent, usize)> {
+    fn try_parse(&mut self, buf: &[u8], direction: Direction) -> Option<(ProtoEvent, usize)> {
         match self.phase {
             ConnPhase::AwaitingStartup | ConnPhase::AwaitingStartupAfterSslReject => {
                 if direction == Direction::Frontend {
@@ -244,9 +608,7 @@ impl ProtocolParser for PostgresParser {
                     self.try_parse_regular(buf, direction)
                 }
             }
-            ConnPhase::Authenticating | ConnPhase::Ready => {
-                self.try_parse_regular(buf, direction)
-            }
+            ConnPhase::Authenticating | ConnPhase::Ready => self.try_parse_regular(buf, direction),
         }
     }
 
@@ -254,11 +616,14 @@ impl ProtocolParser for PostgresParser {
         "postgres"
     }
 
-    fn handle_startup_intercept(
-        &mut self,
-        buf: &[u8],
-        direction: Direction,
-    ) -> Option<Vec<u8>> {
+    fn take_message_counts(&mut self) -> Vec<(Direction, &'static str, u64)> {
+        self.message_counts
+            .drain()
+            .map(|((direction, name), count)| (direction, name, count))
+            .collect()
+    }
+
+    fn handle_startup_intercept(&mut self, buf: &[u8], direction: Direction) -> Option<Vec<u8>> {
         if direction != Direction::Frontend {
             return None;
         }
@@ -284,24 +649,263 @@ impl ProtocolParser for PostgresParser {
     }
 }
 
-/// Extract a null-terminated C string from a byte slice.
-fn extract_cstring(buf: &[u8]) -> Option<String> {
-    let end = buf.iter().position(|&b| b == 0)?;
-    Some(String::from_utf8_lossy(&buf[..end]).into_owned())
+/// StartupMessage body: name/value C string pairs, ended by an empty name.
+fn parse_startup_params(buf: &[u8]) -> Vec<(String, String)> {
+    let mut reader = PayloadReader(buf);
+    let mut params = Vec::new();
+    while let Some(name) = reader.cstring().filter(|n| !n.is_empty()) {
+        let Some(value) = reader.cstring() else {
+            break;
+        };
+        params.push((name, value));
+    }
+    params
 }
 
-/// Truncate SQL to MAX_SQL_LEN, respecting UTF-8 char boundaries.
-fn truncate_sql(sql: &str) -> String {
-    if sql.len() <= MAX_SQL_LEN {
-        sql.to_string()
+/// Bounds-checked cursor over a message payload. Every read returns None
+/// rather than panicking when the payload is shorter than its fields claim.
+struct PayloadReader<'a>(&'a [u8]);
+
+impl PayloadReader<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        self.take(2).map(|b| i16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.take(4)
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// A non-negative i16 count.
+    fn count(&mut self) -> Option<usize> {
+        self.i16().and_then(|n| usize::try_from(n).ok())
+    }
+
+    fn cstring(&mut self) -> Option<String> {
+        let end = self.0.iter().position(|&b| b == 0)?;
+        let s = String::from_utf8_lossy(&self.0[..end]).into_owned();
+        self.0 = &self.0[end + 1..];
+        Some(s)
+    }
+
+    /// Step over a C string without copying it; returns its length.
+    fn skip_cstring(&mut self) -> Option<usize> {
+        let end = self.0.iter().position(|&b| b == 0)?;
+        self.0 = &self.0[end + 1..];
+        Some(end)
+    }
+}
+
+/// Parse: stmt_name\0 sql\0 param_count(i16) param_type(i32)*
+/// Returns the SQL and its size; without `capture_sql` the SQL is left empty.
+fn parse_parse_body(
+    payload: &[u8],
+    capture_sql: bool,
+) -> Option<(String, String, usize, Vec<u32>)> {
+    let mut reader = PayloadReader(payload);
+    let stmt_name = reader.cstring()?;
+    let (sql, sql_bytes) = if capture_sql {
+        let sql = reader.cstring()?;
+        let sql_bytes = sql.len();
+        (sql, sql_bytes)
     } else {
-        // Find the last char boundary at or before MAX_SQL_LEN
-        let mut end = MAX_SQL_LEN;
-        while end > 0 && !sql.is_char_boundary(end) {
-            end -= 1;
+        (String::new(), reader.skip_cstring()?)
+    };
+    let param_types = (0..reader.count()?)
+        .map(|_| reader.i32().map(|oid| oid as u32))
+        .collect::<Option<_>>()?;
+    Some((stmt_name, sql, sql_bytes, param_types))
+}
+
+/// Whether a simple query might be `DEALLOCATE` or `DISCARD`, which close
+/// prepared statements. Lets `--no-sql` skip copying every other query's SQL.
+fn may_close_statements(payload: &[u8]) -> bool {
+    let start = payload
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(payload.len());
+    let word = &payload[start..payload.len().min(start + 7)];
+    word.eq_ignore_ascii_case(b"dealloc") || word.eq_ignore_ascii_case(b"discard")
+}
+
+/// Name of a built-in type by OID, for the types a Parse declares. Covers what
+/// drivers commonly send; anything else shows as its number.
+pub fn type_name(oid: u32) -> Cow<'static, str> {
+    let name = match oid {
+        0 => "unspecified",
+        16 => "bool",
+        17 => "bytea",
+        18 => "char",
+        19 => "name",
+        20 => "int8",
+        21 => "int2",
+        23 => "int4",
+        25 => "text",
+        26 => "oid",
+        114 => "json",
+        700 => "float4",
+        701 => "float8",
+        1000 => "bool[]",
+        1005 => "int2[]",
+        1007 => "int4[]",
+        1009 => "text[]",
+        1016 => "int8[]",
+        1015 => "varchar[]",
+        1042 => "bpchar",
+        1043 => "varchar",
+        1082 => "date",
+        1083 => "time",
+        1114 => "timestamp",
+        1184 => "timestamptz",
+        1186 => "interval",
+        1700 => "numeric",
+        2950 => "uuid",
+        2951 => "uuid[]",
+        3802 => "jsonb",
+        _ => return Cow::Owned(oid.to_string()),
+    };
+    Cow::Borrowed(name)
+}
+
+/// Bind: portal\0 stmt\0 format_count(i16) format(i16)* param_count(i16)
+/// (len(i32) bytes)* result_format_count(i16) result_format(i16)*
+fn parse_bind_body(payload: &[u8]) -> Option<(String, Portal, ResultFormat)> {
+    let mut reader = PayloadReader(payload);
+    let portal = reader.cstring()?;
+    let stmt = reader.cstring()?;
+    let format_count = reader.count()?;
+    let formats: Vec<i16> = (0..format_count)
+        .map(|_| reader.i16())
+        .collect::<Option<_>>()?;
+    let param_count = reader.count()?;
+    let mut params = Vec::with_capacity(param_count);
+    for i in 0..param_count {
+        // No codes means all text; a single code applies to every parameter.
+        let binary = match formats.as_slice() {
+            [] => false,
+            [code] => *code == 1,
+            codes => *codes.get(i)? == 1,
+        };
+        // -1 is NULL with no value bytes; any other negative length is invalid.
+        match reader.i32()? {
+            -1 => params.push(None),
+            len => {
+                let value = reader.take(usize::try_from(len).ok()?)?;
+                params.push(Some(format_param(value, binary)));
+            }
         }
-        format!("{}...", &sql[..end])
     }
+    let result_formats = reader.count()?;
+    let codes: Vec<i16> = reader
+        .take(result_formats * 2)?
+        .chunks_exact(2)
+        .map(|code| i16::from_be_bytes([code[0], code[1]]))
+        .collect();
+    Some((
+        portal,
+        Portal { stmt, params },
+        ResultFormat::from_codes(&codes),
+    ))
+}
+
+/// A bound value for display: text as UTF-8, binary as `\x` hex like bytea.
+fn format_param(value: &[u8], binary: bool) -> String {
+    if !binary {
+        return truncate(&String::from_utf8_lossy(value), MAX_PARAM_LEN);
+    }
+    // Hex-encode only what can be shown, so a large blob costs nothing; past
+    // that many bytes the `\x` prefix pushes it over and it gets cut.
+    let shown = &value[..value.len().min(MAX_PARAM_LEN / 2)];
+    let hex: String = shown.iter().map(|b| format!("{b:02x}")).collect();
+    truncate(&format!("\\x{hex}"), MAX_PARAM_LEN)
+}
+
+/// Name of an untagged startup-phase message, from its protocol code.
+fn startup_message_name(version: u32) -> &'static str {
+    match version {
+        SSL_REQUEST_CODE => "SSLRequest",
+        STARTUP_VERSION_3_0 => "StartupMessage",
+        CANCEL_REQUEST_CODE => "CancelRequest",
+        GSSENC_REQUEST_CODE => "GSSENCRequest",
+        _ => "Unknown",
+    }
+}
+
+/// Protocol name of a tagged message; the same byte means different things per direction.
+fn message_name(direction: Direction, tag: u8) -> &'static str {
+    match (direction, tag) {
+        (Direction::Frontend, b'Q') => "Query",
+        (Direction::Frontend, b'P') => "Parse",
+        (Direction::Frontend, b'B') => "Bind",
+        (Direction::Frontend, b'E') => "Execute",
+        (Direction::Frontend, b'D') => "Describe",
+        (Direction::Frontend, b'C') => "Close",
+        (Direction::Frontend, b'S') => "Sync",
+        (Direction::Frontend, b'H') => "Flush",
+        (Direction::Frontend, b'X') => "Terminate",
+        (Direction::Frontend, b'F') => "FunctionCall",
+        (Direction::Frontend, b'f') => "CopyFail",
+        (Direction::Frontend, b'p') => "PasswordMessage",
+        (Direction::Backend, b'R') => "Authentication",
+        (Direction::Backend, b'K') => "BackendKeyData",
+        (Direction::Backend, b'S') => "ParameterStatus",
+        (Direction::Backend, b'Z') => "ReadyForQuery",
+        (Direction::Backend, b'T') => "RowDescription",
+        (Direction::Backend, b'D') => "DataRow",
+        (Direction::Backend, b'C') => "CommandComplete",
+        (Direction::Backend, b'E') => "ErrorResponse",
+        (Direction::Backend, b'N') => "NoticeResponse",
+        (Direction::Backend, b'A') => "NotificationResponse",
+        (Direction::Backend, b'1') => "ParseComplete",
+        (Direction::Backend, b'2') => "BindComplete",
+        (Direction::Backend, b'3') => "CloseComplete",
+        (Direction::Backend, b'n') => "NoData",
+        (Direction::Backend, b's') => "PortalSuspended",
+        (Direction::Backend, b'I') => "EmptyQueryResponse",
+        (Direction::Backend, b't') => "ParameterDescription",
+        (Direction::Backend, b'G') => "CopyInResponse",
+        (Direction::Backend, b'H') => "CopyOutResponse",
+        (Direction::Backend, b'W') => "CopyBothResponse",
+        (Direction::Backend, b'V') => "FunctionCallResponse",
+        (Direction::Backend, b'v') => "NegotiateProtocolVersion",
+        (_, b'd') => "CopyData",
+        (_, b'c') => "CopyDone",
+        _ => "Unknown",
+    }
+}
+
+/// Where the next plausible message starts after a bad one at the front of
+/// `buf`: a known tag for `direction` followed by a believable length. A tag
+/// too close to the end to check is taken, so the parser waits for more data.
+fn resync_offset(buf: &[u8], direction: Direction) -> usize {
+    (1..buf.len())
+        .find(|&i| {
+            message_name(direction, buf[i]) != "Unknown"
+                && buf.get(i + 1..i + 5).is_none_or(|len| {
+                    (4..MAX_MESSAGE_LEN)
+                        .contains(&u32::from_be_bytes([len[0], len[1], len[2], len[3]]))
+                })
+        })
+        .unwrap_or(buf.len())
+}
+
+/// Extract a null-terminated C string from a byte slice.
+fn extract_cstring(buf: &[u8]) -> Option<String> {
+    let end = buf.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&buf[..end]).into_owned())
 }
 
 /// Parse row count from a command complete tag.
@@ -409,6 +1013,28 @@ mod tests {
         assert_eq!(parser.phase, ConnPhase::Authenticating);
     }
 
+    #[test]
+    fn test_startup_params() {
+        let body = b"user\0alice\0application_name\0billing\0\0";
+        let mut buf = ((8 + body.len()) as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(&STARTUP_VERSION_3_0.to_be_bytes());
+        buf.extend_from_slice(body);
+
+        let mut parser = PostgresParser::new();
+        let (event, consumed) = parser.try_parse(&buf, Direction::Frontend).unwrap();
+        assert_eq!(consumed, buf.len());
+        match event {
+            ProtoEvent::Startup { params } => assert_eq!(
+                params,
+                vec![
+                    ("user".into(), "alice".into()),
+                    ("application_name".into(), "billing".into())
+                ]
+            ),
+            other => panic!("expected Startup, got {other:?}"),
+        }
+    }
+
     #[test]
     fn test_query_parse() {
         let mut parser = PostgresParser::new();
@@ -418,7 +1044,7 @@ mod tests {
         let result = parser.try_parse(&buf, Direction::Frontend);
 
         match result {
-            Some((ProtoEvent::QueryStart { sql }, consumed)) => {
+            Some((ProtoEvent::QueryStart { sql, .. }, consumed)) => {
                 assert_eq!(sql, "SELECT * FROM users");
                 assert_eq!(consumed, buf.len());
             }
@@ -435,7 +1061,7 @@ mod tests {
         let result = parser.try_parse(&buf, Direction::Backend);
 
         match result {
-            Some((ProtoEvent::QueryComplete { tag, rows }, _)) => {
+            Some((ProtoEvent::QueryComplete { tag, rows, .. }, _)) => {
                 assert_eq!(tag, "SELECT 5");
                 assert_eq!(rows, Some(5));
             }
@@ -490,25 +1116,94 @@ mod tests {
 
         let (event, consumed) = parser.try_parse(&buf, Direction::Frontend).unwrap();
         match event {
-            ProtoEvent::QueryStart { sql } => assert_eq!(sql, "SELECT 1"),
+            ProtoEvent::QueryStart { sql, .. } => assert_eq!(sql, "SELECT 1"),
             _ => panic!("Expected QueryStart"),
         }
 
-        let (event, _) = parser.try_parse(&buf[consumed..], Direction::Frontend).unwrap();
+        let (event, _) = parser
+            .try_parse(&buf[consumed..], Direction::Frontend)
+            .unwrap();
         match event {
-            ProtoEvent::QueryStart { sql } => assert_eq!(sql, "SELECT 2"),
+            ProtoEvent::QueryStart { sql, .. } => assert_eq!(sql, "SELECT 2"),
             _ => panic!("Expected QueryStart"),
         }
     }
 
+    #[test]
+    fn test_bad_length_resyncs_at_next_message() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        // A Query claiming 2 bytes, junk (including a tag byte with a bad length), then a real query.
+        let mut buf = vec![
+            b'Q', 0, 0, 0, 2, 0xff, 0x00, b'S', 0xff, 0xff, 0xff, 0xff, 0x01,
+        ];
+        let junk = buf.len();
+        buf.extend_from_slice(&make_query_message("SELECT 1"));
+
+        let (event, consumed) = parser.try_parse(&buf, Direction::Frontend).unwrap();
+        assert!(matches!(event, ProtoEvent::Unknown { tag: b'Q' }));
+        assert_eq!(consumed, junk);
+        match parser.try_parse(&buf[consumed..], Direction::Frontend) {
+            Some((ProtoEvent::QueryStart { sql, .. }, _)) => assert_eq!(sql, "SELECT 1"),
+            other => panic!("Expected QueryStart, got {other:?}"),
+        }
+
+        // An oversized length is just as implausible; a tag at the very end waits for more.
+        let buf = [b'D', 0x7f, 0xff, 0xff, 0xff, 0x01, b'D', 0];
+        let (_, consumed) = parser.try_parse(&buf, Direction::Backend).unwrap();
+        assert_eq!(consumed, 6);
+        assert!(parser
+            .try_parse(&buf[consumed..], Direction::Backend)
+            .is_none());
+    }
+
     #[test]
     fn test_truncate_sql_utf8_boundary() {
         // 4-byte UTF-8 char repeated — truncation must not split a codepoint
-        let s = "a".repeat(MAX_SQL_LEN - 1) + "\u{1F600}"; // emoji at the boundary
-        let result = truncate_sql(&s);
-        assert!(result.ends_with("..."));
-        // Must be valid UTF-8 (this would panic if we split mid-codepoint)
-        let _ = result.as_bytes();
+        let s = "a".repeat(MAX_SQL_LEN - 2) + "\u{1F600}\u{1F600}"; // emoji at the boundary
+        assert_eq!(truncate(&s, MAX_SQL_LEN), s);
+        let s = s + "\u{1F600}";
+        let result = truncate(&s, MAX_SQL_LEN);
+        assert_eq!(result.chars().count(), MAX_SQL_LEN);
+        assert!(result.ends_with("a\u{1F600}…"));
+    }
+
+    #[test]
+    fn test_oversized_query_reports_its_real_size() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+        let values: Vec<String> = (0..20_000).map(|i| i.to_string()).collect();
+        let sql = format!("SELECT * FROM users WHERE id IN ({})", values.join(", "));
+        let mut payload = sql.clone().into_bytes();
+        payload.push(0);
+
+        match parser.try_parse(&make_message(b'Q', &payload), Direction::Frontend) {
+            Some((
+                ProtoEvent::QueryStart {
+                    sql: shown,
+                    sql_bytes,
+                    ..
+                },
+                _,
+            )) => {
+                assert_eq!(sql_bytes, sql.len());
+                assert_eq!(shown.chars().count(), MAX_SQL_LEN);
+            }
+            other => panic!("Expected QueryStart, got {other:?}"),
+        }
+
+        // An Execute reports the size its Parse sent.
+        parser
+            .try_parse(&make_parse_message("s1", &sql), Direction::Frontend)
+            .unwrap();
+        parser
+            .try_parse(&make_bind_message("", "s1"), Direction::Frontend)
+            .unwrap();
+        match parser.try_parse(&make_execute_message(""), Direction::Frontend) {
+            Some((ProtoEvent::QueryStart { sql_bytes, .. }, _)) => assert_eq!(sql_bytes, sql.len()),
+            other => panic!("Expected QueryStart, got {other:?}"),
+        }
     }
 
     #[test]
@@ -523,7 +1218,12 @@ mod tests {
 
     /// Build a Parse message: 'P' + length + stmt_name\0 + sql\0 + 0(i16, no param types)
     fn make_parse_message(stmt_name: &str, sql: &str) -> Vec<u8> {
-        let payload_len = stmt_name.len() + 1 + sql.len() + 1 + 2; // name\0 sql\0 paramcount(i16)
+        make_typed_parse_message(stmt_name, sql, &[])
+    }
+
+    fn make_typed_parse_message(stmt_name: &str, sql: &str, param_types: &[u32]) -> Vec<u8> {
+        // name\0 sql\0 paramcount(i16) paramtype(i32)*
+        let payload_len = stmt_name.len() + 1 + sql.len() + 1 + 2 + 4 * param_types.len();
         let length = (payload_len + 4) as u32;
         let mut buf = Vec::new();
         buf.push(b'P');
@@ -532,7 +1232,10 @@ mod tests {
         buf.push(0);
         buf.extend_from_slice(sql.as_bytes());
         buf.push(0);
-        buf.extend_from_slice(&0u16.to_be_bytes()); // no param types
+        buf.extend_from_slice(&(param_types.len() as u16).to_be_bytes());
+        for oid in param_types {
+            buf.extend_from_slice(&oid.to_be_bytes());
+        }
         buf
     }
 
@@ -580,6 +1283,74 @@ mod tests {
         buf
     }
 
+    #[test]
+    fn test_parse_declared_param_types() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        let parse = make_typed_parse_message(
+            "s1",
+            "SELECT * FROM t WHERE id = $1 AND at > $2",
+            &[23, 1184],
+        );
+        let (event, consumed) = parser.try_parse(&parse, Direction::Frontend).unwrap();
+        assert_eq!(consumed, parse.len());
+        let ProtoEvent::ParseDetected { param_types, .. } = event else {
+            panic!("expected ParseDetected, got {event:?}");
+        };
+        assert_eq!(param_types, [23, 1184]);
+        assert_eq!(parser.statements.get("s1").unwrap().param_types, [23, 1184]);
+
+        let names: Vec<_> = [23, 1184, 0, 424242].into_iter().map(type_name).collect();
+        assert_eq!(names, ["int4", "timestamptz", "unspecified", "424242"]);
+
+        // Fewer OIDs than the count promises: malformed.
+        let mut short = make_typed_parse_message("s2", "SELECT $1", &[25]);
+        short.truncate(short.len() - 2);
+        let fixed_len = (short.len() - 1) as u32;
+        short[1..5].copy_from_slice(&fixed_len.to_be_bytes());
+        let (event, _) = parser.try_parse(&short, Direction::Frontend).unwrap();
+        assert!(matches!(event, ProtoEvent::Unknown { .. }));
+    }
+
+    #[test]
+    fn test_bind_result_formats() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+        let bind = |codes: &[i16]| {
+            let mut payload = b"p1\0s1\0".to_vec();
+            payload.extend_from_slice(&0i16.to_be_bytes()); // parameter formats
+            payload.extend_from_slice(&0i16.to_be_bytes()); // parameters
+            payload.extend_from_slice(&(codes.len() as i16).to_be_bytes());
+            for code in codes {
+                payload.extend_from_slice(&code.to_be_bytes());
+            }
+            make_message(b'B', &payload)
+        };
+
+        for (codes, expected) in [
+            (&[][..], ResultFormat::Text),
+            (&[1][..], ResultFormat::Binary),
+            (&[0, 0, 0][..], ResultFormat::Text),
+            (&[1, 1][..], ResultFormat::Binary),
+            (&[0, 1, 0][..], ResultFormat::Mixed),
+        ] {
+            match parser.try_parse(&bind(codes), Direction::Frontend) {
+                Some((
+                    ProtoEvent::Bind {
+                        portal,
+                        result_format,
+                    },
+                    _,
+                )) => {
+                    assert_eq!(portal, "p1");
+                    assert_eq!(result_format, expected, "{codes:?}");
+                }
+                other => panic!("Expected Bind, got {other:?}"),
+            }
+        }
+    }
+
     #[test]
     fn test_extended_bind_execute() {
         let mut parser = PostgresParser::new();
@@ -593,12 +1364,18 @@ mod tests {
         // Bind
         let bind = make_bind_message("", "s1");
         let (event, _) = parser.try_parse(&bind, Direction::Frontend).unwrap();
-        assert!(matches!(event, ProtoEvent::Unknown { .. }));
+        assert!(matches!(
+            event,
+            ProtoEvent::Bind {
+                result_format: ResultFormat::Text,
+                ..
+            }
+        ));
 
         // Execute should emit QueryStart with the SQL from Parse
         let exec = make_execute_message("");
         match parser.try_parse(&exec, Direction::Frontend) {
-            Some((ProtoEvent::QueryStart { sql }, _)) => {
+            Some((ProtoEvent::QueryStart { sql, .. }, _)) => {
                 assert_eq!(sql, "SELECT * FROM users");
             }
             other => panic!("Expected QueryStart, got {other:?}"),
@@ -620,7 +1397,7 @@ mod tests {
 
         let exec1 = make_execute_message("p1");
         match parser.try_parse(&exec1, Direction::Frontend) {
-            Some((ProtoEvent::QueryStart { sql }, _)) => {
+            Some((ProtoEvent::QueryStart { sql, .. }, _)) => {
                 assert_eq!(sql, "INSERT INTO t VALUES ($1)");
             }
             other => panic!("Expected QueryStart #1, got {other:?}"),
@@ -632,7 +1409,7 @@ mod tests {
 
         let exec2 = make_execute_message("p2");
         match parser.try_parse(&exec2, Direction::Frontend) {
-            Some((ProtoEvent::QueryStart { sql }, _)) => {
+            Some((ProtoEvent::QueryStart { sql, .. }, _)) => {
                 assert_eq!(sql, "INSERT INTO t VALUES ($1)");
             }
             other => panic!("Expected QueryStart #2, got {other:?}"),
@@ -663,4 +1440,523 @@ mod tests {
         parser.try_parse(&close_s, Direction::Frontend).unwrap();
         assert!(!parser.statements.contains_key("s1"));
     }
+
+    #[test]
+    fn test_without_sql_keeps_sizes_but_not_text() {
+        let mut parser = PostgresParser::without_sql();
+        parser.phase = ConnPhase::Ready;
+
+        for (sql, expected) in [
+            ("SELECT 1", 1),
+            ("SELECT 1; SELECT ';'; -- ;\n", 2),
+            ("", 1),
+        ] {
+            match parser.try_parse(&make_query_message(sql), Direction::Frontend) {
+                Some((
+                    ProtoEvent::QueryStartNoSql {
+                        statements,
+                        sql_bytes,
+                    },
+                    _,
+                )) => assert_eq!((statements, sql_bytes), (expected, sql.len())),
+                other => panic!("Expected QueryStartNoSql, got {other:?}"),
+            }
+        }
+
+        parser
+            .try_parse(
+                &make_parse_message("s1", "SELECT * FROM users"),
+                Direction::Frontend,
+            )
+            .unwrap();
+        parser
+            .try_parse(&make_bind_message("", "s1"), Direction::Frontend)
+            .unwrap();
+        match parser.try_parse(&make_execute_message(""), Direction::Frontend) {
+            Some((ProtoEvent::QueryStart { sql, sql_bytes, .. }, _)) => {
+                assert_eq!((sql.as_str(), sql_bytes), ("", 19))
+            }
+            other => panic!("Expected QueryStart, got {other:?}"),
+        }
+
+        // DEALLOCATE is still read, so statement tracking stays right.
+        assert_eq!(parser.open_statements, 1);
+        parser
+            .try_parse(&make_query_message("  deallocate s1"), Direction::Frontend)
+            .unwrap();
+        assert_eq!(parser.open_statements, 0);
+    }
+
+    #[test]
+    fn test_unclosed_statements_are_bounded_and_flagged() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        let mut leaks = Vec::new();
+        for i in 0..5000 {
+            let parse = make_parse_message(&format!("s{i}"), "SELECT 1");
+            if let Some((ProtoEvent::StatementLeak { open }, _)) =
+                parser.try_parse(&parse, Direction::Frontend)
+            {
+                leaks.push(open);
+            }
+            let bind = make_bind_message(&format!("p{i}"), &format!("s{i}"));
+            parser.try_parse(&bind, Direction::Frontend).unwrap();
+        }
+        assert_eq!(leaks, [100, 200, 400, 800, 1600, 3200]);
+        assert_eq!(parser.statements.len(), MAX_TRACKED_NAMES);
+        assert_eq!(parser.portals.len(), MAX_TRACKED_NAMES);
+        // The oldest were forgotten, the newest are still known.
+        assert!(!parser.statements.contains_key("s0"));
+        assert!(parser.statements.contains_key("s4999"));
+
+        // The unnamed statement never counts.
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+        for _ in 0..500 {
+            let (event, _) = parser
+                .try_parse(&make_parse_message("", "SELECT 1"), Direction::Frontend)
+                .unwrap();
+            assert!(matches!(event, ProtoEvent::ParseDetected { .. }));
+        }
+
+        // Closing brings the count back down, so a later leak is reported again.
+        for i in 0..100 {
+            parser
+                .try_parse(
+                    &make_parse_message(&format!("s{i}"), "SELECT 1"),
+                    Direction::Frontend,
+                )
+                .unwrap();
+        }
+        assert_eq!(parser.open_statements, 100);
+        for i in 0..50 {
+            parser
+                .try_parse(
+                    &make_close_message(b'S', &format!("s{i}")),
+                    Direction::Frontend,
+                )
+                .unwrap();
+        }
+        parser
+            .try_parse(&make_query_message("DEALLOCATE s50"), Direction::Frontend)
+            .unwrap();
+        assert_eq!(parser.open_statements, 49);
+        assert_eq!(parser.next_leak_warning, STATEMENT_LEAK_THRESHOLD);
+        parser
+            .try_parse(&make_query_message("DISCARD ALL"), Direction::Frontend)
+            .unwrap();
+        assert_eq!(parser.open_statements, 0);
+        assert!(parser.statements.is_empty());
+    }
+
+    fn make_message(tag: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![tag];
+        buf.extend_from_slice(&((payload.len() + 4) as u32).to_be_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    /// Every strict prefix of a valid message body, re-framed with an honest length.
+    fn truncations(message: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+        (0..message.len() - 5).map(|len| make_message(message[0], &message[5..5 + len]))
+    }
+
+    #[test]
+    fn test_truncated_parse_and_bind_are_ignored() {
+        for truncated in truncations(&make_parse_message("s1", "SELECT 1")) {
+            let mut parser = PostgresParser::new();
+            parser.phase = ConnPhase::Ready;
+            let (event, consumed) = parser.try_parse(&truncated, Direction::Frontend).unwrap();
+            assert!(
+                matches!(event, ProtoEvent::Unknown { tag: b'P' }),
+                "{truncated:?} gave {event:?}"
+            );
+            assert_eq!(consumed, truncated.len());
+            assert!(parser.statements.is_empty());
+        }
+
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+        parser
+            .try_parse(&make_parse_message("s1", "SELECT 1"), Direction::Frontend)
+            .unwrap();
+        for truncated in truncations(&make_bind_message("p1", "s1")) {
+            parser.try_parse(&truncated, Direction::Frontend).unwrap();
+            assert!(parser.portals.is_empty(), "{truncated:?} bound a portal");
+        }
+        for truncated in truncations(&make_execute_message("p1")) {
+            let (event, _) = parser.try_parse(&truncated, Direction::Frontend).unwrap();
+            assert!(
+                matches!(event, ProtoEvent::Unknown { tag: b'E' }),
+                "{truncated:?} gave {event:?}"
+            );
+        }
+        for truncated in truncations(&make_close_message(b'S', "s1")) {
+            parser.try_parse(&truncated, Direction::Frontend).unwrap();
+        }
+        assert!(parser.statements.contains_key("s1"));
+    }
+
+    #[test]
+    fn test_execute_carries_bound_params() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+        parser
+            .try_parse(
+                &make_parse_message(
+                    "s1",
+                    "SELECT * FROM users WHERE id = $1 AND name = $2 AND avatar = $3",
+                ),
+                Direction::Frontend,
+            )
+            .unwrap();
+
+        // Per-parameter formats: text, text, binary; the second value is NULL.
+        let mut payload = b"\0s1\0".to_vec();
+        payload.extend_from_slice(&3i16.to_be_bytes());
+        for code in [0i16, 0, 1] {
+            payload.extend_from_slice(&code.to_be_bytes());
+        }
+        payload.extend_from_slice(&3i16.to_be_bytes());
+        payload.extend_from_slice(&2i32.to_be_bytes());
+        payload.extend_from_slice(b"42");
+        payload.extend_from_slice(&(-1i32).to_be_bytes());
+        payload.extend_from_slice(&3i32.to_be_bytes());
+        payload.extend_from_slice(&[0x00, 0xab, 0xff]);
+        payload.extend_from_slice(&0i16.to_be_bytes());
+        parser
+            .try_parse(&make_message(b'B', &payload), Direction::Frontend)
+            .unwrap();
+
+        match parser.try_parse(&make_execute_message(""), Direction::Frontend) {
+            Some((ProtoEvent::QueryStart { params, .. }, _)) => {
+                assert_eq!(
+                    params,
+                    vec![Some("42".to_string()), None, Some("\\x00abff".to_string())]
+                );
+            }
+            other => panic!("Expected QueryStart, got {other:?}"),
+        }
+
+        // One format code covers every parameter; a long blob is cut.
+        let mut payload = b"\0s1\0".to_vec();
+        payload.extend_from_slice(&1i16.to_be_bytes());
+        payload.extend_from_slice(&1i16.to_be_bytes());
+        payload.extend_from_slice(&1i16.to_be_bytes());
+        payload.extend_from_slice(&1000i32.to_be_bytes());
+        payload.extend_from_slice(&[0x11; 1000]);
+        payload.extend_from_slice(&0i16.to_be_bytes());
+        parser
+            .try_parse(&make_message(b'B', &payload), Direction::Frontend)
+            .unwrap();
+
+        match parser.try_parse(&make_execute_message(""), Direction::Frontend) {
+            Some((ProtoEvent::QueryStart { params, .. }, _)) => {
+                let value = params[0].as_deref().unwrap();
+                assert!(
+                    value.starts_with("\\x1111") && value.ends_with('…'),
+                    "{value}"
+                );
+                assert_eq!(value.chars().count(), MAX_PARAM_LEN);
+            }
+            other => panic!("Expected QueryStart, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_bind_with_lying_lengths_is_ignored() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        // One parameter claiming 1000 bytes of value, with 3 present.
+        let mut payload = b"p1\0s1\0".to_vec();
+        payload.extend_from_slice(&0i16.to_be_bytes());
+        payload.extend_from_slice(&1i16.to_be_bytes());
+        payload.extend_from_slice(&1000i32.to_be_bytes());
+        payload.extend_from_slice(b"abc");
+        parser
+            .try_parse(&make_message(b'B', &payload), Direction::Frontend)
+            .unwrap();
+
+        // A negative parameter count.
+        let mut payload = b"p2\0s1\0".to_vec();
+        payload.extend_from_slice(&(-3i16).to_be_bytes());
+        parser
+            .try_parse(&make_message(b'B', &payload), Direction::Frontend)
+            .unwrap();
+
+        assert!(parser.portals.is_empty());
+
+        // A declared length beyond the buffer is just an incomplete message.
+        let mut parse = make_parse_message("s1", "SELECT 1");
+        parse.truncate(parse.len() - 3);
+        assert!(parser.try_parse(&parse, Direction::Frontend).is_none());
+    }
+
+    #[test]
+    fn test_execute_carries_portal_and_suspension() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        parser
+            .try_parse(
+                &make_parse_message("s1", "SELECT * FROM big"),
+                Direction::Frontend,
+            )
+            .unwrap();
+        parser
+            .try_parse(&make_bind_message("cur", "s1"), Direction::Frontend)
+            .unwrap();
+        match parser.try_parse(&make_execute_message("cur"), Direction::Frontend) {
+            Some((ProtoEvent::QueryStart { sql, portal, .. }, _)) => {
+                assert_eq!(sql, "SELECT * FROM big");
+                assert_eq!(portal.as_deref(), Some("cur"));
+            }
+            other => panic!("Expected QueryStart, got {other:?}"),
+        }
+
+        let (event, _) = parser
+            .try_parse(&make_message(b's', &[]), Direction::Backend)
+            .unwrap();
+        assert!(matches!(event, ProtoEvent::PortalSuspended));
+    }
+
+    #[test]
+    fn test_copy_in_multi_chunk() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        let (event, _) = parser
+            .try_parse(&make_message(b'G', &[0, 0, 0]), Direction::Backend)
+            .unwrap();
+        assert!(matches!(event, ProtoEvent::CopyStarted));
+
+        // Three 3 MiB chunks: progress fires once the 4 MiB mark is crossed
+        let chunk = vec![b'x'; 3 * 1024 * 1024];
+        let mut progress = Vec::new();
+        for _ in 0..3 {
+            let (event, _) = parser
+                .try_parse(&make_message(b'd', &chunk), Direction::Frontend)
+                .unwrap();
+            if let ProtoEvent::CopyProgress { bytes } = event {
+                progress.push(bytes);
+            }
+        }
+        assert_eq!(progress, vec![6 * 1024 * 1024]);
+
+        let (event, _) = parser
+            .try_parse(&make_message(b'c', &[]), Direction::Frontend)
+            .unwrap();
+        match event {
+            ProtoEvent::CopyDone { bytes } => assert_eq!(bytes, 9 * 1024 * 1024),
+            other => panic!("Expected CopyDone, got {other:?}"),
+        }
+        assert!(parser.copy.is_none());
+
+        let (event, _) = parser
+            .try_parse(&make_command_complete("COPY 3"), Direction::Backend)
+            .unwrap();
+        match event {
+            ProtoEvent::QueryComplete { rows, .. } => assert_eq!(rows, Some(3)),
+            other => panic!("Expected QueryComplete, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_message_counts() {
+        let mut parser = PostgresParser::new();
+        let mut frontend = make_startup_message(STARTUP_VERSION_3_0);
+        frontend.extend(make_query_message("BEGIN"));
+        for portal in ["", ""] {
+            frontend.extend(make_bind_message(portal, ""));
+            frontend.extend(make_execute_message(portal));
+        }
+        frontend.extend(make_message(b'S', &[]));
+        let mut backend = make_ready_for_query(b'I');
+        for _ in 0..3 {
+            backend.extend(make_command_complete("SELECT 1"));
+        }
+        backend.extend(make_message(b'S', b"a\0b\0"));
+
+        for (buf, direction) in [
+            (&frontend, Direction::Frontend),
+            (&backend, Direction::Backend),
+        ] {
+            let mut offset = 0;
+            while let Some((_, consumed)) = parser.try_parse(&buf[offset..], direction) {
+                offset += consumed;
+            }
+            assert_eq!(offset, buf.len());
+        }
+
+        let mut counts = parser.take_message_counts();
+        counts.sort_by_key(|&(direction, name, _)| (direction == Direction::Backend, name));
+        assert_eq!(
+            counts,
+            vec![
+                (Direction::Frontend, "Bind", 2),
+                (Direction::Frontend, "Execute", 2),
+                (Direction::Frontend, "Query", 1),
+                (Direction::Frontend, "StartupMessage", 1),
+                (Direction::Frontend, "Sync", 1),
+                (Direction::Backend, "CommandComplete", 3),
+                (Direction::Backend, "ParameterStatus", 1),
+                (Direction::Backend, "ReadyForQuery", 1),
+            ]
+        );
+        assert!(parser.take_message_counts().is_empty());
+    }
+
+    #[test]
+    fn test_copy_fail() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        parser
+            .try_parse(&make_message(b'G', &[0, 0, 0]), Direction::Backend)
+            .unwrap();
+        parser
+            .try_parse(&make_message(b'd', b"1\talice\n"), Direction::Frontend)
+            .unwrap();
+        let (event, _) = parser
+            .try_parse(
+                &make_message(b'f', b"client gave up\0"),
+                Direction::Frontend,
+            )
+            .unwrap();
+        match event {
+            ProtoEvent::CopyFail { bytes, reason } => {
+                assert_eq!(bytes, 8);
+                assert_eq!(reason, "client gave up");
+            }
+            other => panic!("Expected CopyFail, got {other:?}"),
+        }
+        assert!(parser.copy.is_none());
+
+        // The server's answer is an ordinary error, not part of the COPY.
+        let mut error = b"SERROR\0C57014\0MCOPY from stdin failed: client gave up\0".to_vec();
+        error.push(0);
+        let (event, _) = parser
+            .try_parse(&make_message(b'E', &error), Direction::Backend)
+            .unwrap();
+        assert!(matches!(event, ProtoEvent::QueryError { code, .. } if code == "57014"));
+    }
+
+    #[test]
+    fn test_row_description() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        // Two columns: name, table oid, attnum, type oid, typlen, typmod, format.
+        let mut body = 2i16.to_be_bytes().to_vec();
+        for name in [&b"id\0"[..], b"email\0"] {
+            body.extend_from_slice(name);
+            body.extend_from_slice(&[0; 18]);
+        }
+        let (event, _) = parser
+            .try_parse(&make_message(b'T', &body), Direction::Backend)
+            .unwrap();
+        assert!(matches!(event, ProtoEvent::RowDescription { columns: 2 }));
+    }
+
+    #[test]
+    fn test_notice_response() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        let notice = make_message(
+            b'N',
+            b"SNOTICE\0VNOTICE\0C42P07\0Mrelation \"t\" already exists, skipping\0\0",
+        );
+        match parser.try_parse(&notice, Direction::Backend) {
+            Some((
+                ProtoEvent::Notice {
+                    severity,
+                    code,
+                    message,
+                },
+                consumed,
+            )) => {
+                assert_eq!((severity.as_str(), code.as_str()), ("NOTICE", "42P07"));
+                assert_eq!(message, "relation \"t\" already exists, skipping");
+                assert_eq!(consumed, notice.len());
+            }
+            other => panic!("Expected Notice, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_notification_response() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+
+        let mut body = 4242i32.to_be_bytes().to_vec();
+        body.extend_from_slice(b"orders\0{\"id\": 7}\0");
+        let notify = make_message(b'A', &body);
+        match parser.try_parse(&notify, Direction::Backend) {
+            Some((ProtoEvent::Notification { channel, payload }, consumed)) => {
+                assert_eq!(
+                    (channel.as_str(), payload.as_str()),
+                    ("orders", "{\"id\": 7}")
+                );
+                assert_eq!(consumed, notify.len());
+            }
+            other => panic!("Expected Notification, got {other:?}"),
+        }
+        // Not part of the next query's result size.
+        let complete = make_command_complete("SELECT 0");
+        let Some((ProtoEvent::QueryComplete { bytes, .. }, _)) =
+            parser.try_parse(&complete, Direction::Backend)
+        else {
+            panic!("expected QueryComplete");
+        };
+        assert_eq!(bytes as usize, complete.len());
+
+        let truncated = make_message(b'A', &4242i32.to_be_bytes());
+        assert!(matches!(
+            parser.try_parse(&truncated, Direction::Backend),
+            Some((ProtoEvent::Unknown { tag: b'A' }, _))
+        ));
+    }
+
+    #[test]
+    fn test_result_bytes_belong_to_their_query() {
+        let mut parser = PostgresParser::new();
+        parser.phase = ConnPhase::Ready;
+        let mut backend = |buf: &[u8]| parser.try_parse(buf, Direction::Backend).unwrap().0;
+
+        // Two rows with a 1000-byte column each, then a small result.
+        let mut row = 1i16.to_be_bytes().to_vec();
+        row.extend_from_slice(&1000i32.to_be_bytes());
+        row.extend_from_slice(&[b'x'; 1000]);
+        let row = make_message(b'D', &row);
+        let description = make_message(b'T', &0i16.to_be_bytes());
+        let complete = make_command_complete("SELECT 2");
+        backend(&description);
+        backend(&row);
+        backend(&row);
+        let ProtoEvent::QueryComplete { bytes, .. } = backend(&complete) else {
+            panic!("expected QueryComplete");
+        };
+        assert_eq!(
+            bytes as usize,
+            description.len() + 2 * row.len() + complete.len()
+        );
+
+        let ProtoEvent::QueryComplete { bytes, .. } = backend(&make_command_complete("SELECT 0"))
+        else {
+            panic!("expected QueryComplete");
+        };
+        assert_eq!(bytes as usize, make_command_complete("SELECT 0").len());
+
+        // A failed query's partial rows don't carry over to the next one.
+        backend(&row);
+        backend(&make_message(b'E', b"SERROR\0C57014\0Mcanceled\0\0"));
+        backend(&make_ready_for_query(b'I'));
+        let ProtoEvent::QueryComplete { bytes, .. } = backend(&complete) else {
+            panic!("expected QueryComplete");
+        };
+        assert_eq!(bytes as usize, complete.len());
+    }
 }
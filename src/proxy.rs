@@ -1,52 +1,319 @@
-use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::AssertUnwindSafe;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
 use bytes::{Bytes, BytesMut};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
 
-use crate::protocol::postgres::PostgresParser;
-use crate::protocol::{Direction, ProtoEvent, ProtocolParser};
+use crate::capture::{Capture, FrameKind};
+use crate::control::Readiness;
+use crate::hexdump::Hexdump;
+use crate::protocol::opaque::OpaqueParser;
+use crate::protocol::{postgres, Direction, ProtoEvent, Protocol, ProtocolParser, TxStatus};
+use crate::tls::{self, ListenTls, UpstreamTls};
 
 pub enum ProxyMessage {
     Event {
         conn_id: u64,
         event: ProtoEvent,
+        /// When the message was parsed — latencies are measured from these,
+        /// not from when the consumer gets around to the channel.
+        at: Instant,
     },
     ConnectionOpened {
         conn_id: u64,
+        client_addr: SocketAddr,
     },
     ConnectionClosed {
         conn_id: u64,
     },
+    /// The TCP connect to the upstream took `connect_time`: about one network round trip.
+    UpstreamConnected {
+        conn_id: u64,
+        connect_time: Duration,
+    },
+    /// The client presented this certificate subject in the `--listen-tls` handshake.
+    ClientCertificate {
+        conn_id: u64,
+        subject: String,
+    },
+    /// Proxy-level condition worth surfacing to the user (not tied to a wire message).
+    Warning {
+        conn_id: u64,
+        message: String,
+    },
+    /// Server data waited `waited` for room in the client writer: the client is
+    /// reading slower than the server sends.
+    ClientBackpressure {
+        conn_id: u64,
+        waited: Duration,
+        at: Instant,
+    },
 }
 
 static CONN_COUNTER: AtomicU64 = AtomicU64::new(1);
 
+/// Where new connections are forwarded. The TUI and the control API can repoint
+/// it at runtime; connections already open stay on the upstream they dialed.
+pub type SharedUpstream = Arc<RwLock<String>>;
+
+/// Check that `addr` is `host:port` with a nonzero port.
+pub fn check_upstream_addr(addr: &str) -> Result<(), String> {
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p > 0) => {
+            Ok(())
+        }
+        _ => Err(format!("invalid upstream {addr:?}, expected host:port")),
+    }
+}
+
+/// Repoint new connections at `addr` (`host:port`). Returns the previous upstream.
+pub fn set_upstream(upstream: &SharedUpstream, addr: &str) -> Result<String, String> {
+    let addr = addr.trim();
+    check_upstream_addr(addr)?;
+    let previous = std::mem::replace(&mut *upstream.write().unwrap(), addr.to_string());
+    if previous != addr {
+        info!("Upstream changed: {previous} -> {addr} (open connections stay on {previous})");
+    }
+    Ok(previous)
+}
+
+/// Optional per-connection behavior, from the CLI.
+#[derive(Clone, Default)]
+pub struct ProxyOptions {
+    /// `--record`: copy all traffic to a capture file.
+    pub capture: Option<Capture>,
+    /// `--max-idle-in-transaction`: close connections left idle inside a transaction this long.
+    pub max_idle_in_transaction: Option<Duration>,
+    /// `--hexdump`: print relayed bytes to stderr.
+    pub hexdump: Option<Hexdump>,
+    /// `--announce`: tell Postgres clients about dbprobe with a ParameterStatus.
+    pub announce: bool,
+    /// `--probe-only`: show each client's handshake, then refuse it without dialing the upstream.
+    pub probe_only: bool,
+    /// `--no-sql`: time queries without extracting their SQL.
+    pub no_sql: bool,
+    /// `--upstream-tls`: encrypt the hop to the upstream.
+    pub upstream_tls: Option<UpstreamTls>,
+    /// `--listen-tls`: accept clients' SSLRequests and terminate TLS here.
+    pub listen_tls: Option<ListenTls>,
+}
+
+/// Set when the backend reports ReadyForQuery inside a transaction, cleared as
+/// soon as the client sends anything.
+type IdleSince = Arc<Mutex<Option<Instant>>>;
+
+type SharedParser = Arc<Mutex<Box<dyn ProtocolParser>>>;
+
+/// One direction of a relayed socket, plain TCP or TLS.
+type ReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+type WriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
+
+fn split_tcp(stream: TcpStream) -> (ReadHalf, WriteHalf) {
+    let (read, write) = stream.into_split();
+    (Box::new(read), Box::new(write))
+}
+
+fn split_io<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S) -> (ReadHalf, WriteHalf) {
+    let (read, write) = tokio::io::split(stream);
+    (Box::new(read), Box::new(write))
+}
+
+/// SQLSTATE idle_in_transaction_session_timeout, as Postgres itself reports it.
+const IDLE_IN_TRANSACTION_TIMEOUT: &str = "25P03";
+
+/// How often a connection with no open transaction is re-checked.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Server data held up this long by a full client writer counts as a stall.
+const SLOW_CLIENT_STALL: Duration = Duration::from_secs(1);
+
+/// State both relay directions of a connection share.
+#[derive(Clone)]
+struct RelayContext {
+    conn_id: u64,
+    parser: SharedParser,
+    events_tx: mpsc::UnboundedSender<ProxyMessage>,
+    capture: Option<Capture>,
+    hexdump: Option<Hexdump>,
+    idle_since: IdleSince,
+    /// Injected into the client-bound stream before the first ReadyForQuery.
+    announcement: Option<Announcement>,
+}
+
+/// Local addresses of our own upstream sockets. If the listener ever accepts a
+/// connection from one of these, dbprobe is proxying to itself.
+type UpstreamSockets = Arc<Mutex<HashSet<SocketAddr>>>;
+
+/// Refuse to start if `upstream` resolves to dbprobe's own listen socket.
+/// Every accepted connection would dial the proxy again, recursing until the
+/// process runs out of file descriptors.
+pub async fn check_upstream_loop(listen_port: u16, upstream: &str) -> anyhow::Result<()> {
+    // An unresolvable upstream is not a loop — connect errors are reported per connection.
+    let Ok(addrs) = tokio::net::lookup_host(upstream).await else {
+        return Ok(());
+    };
+
+    for addr in addrs {
+        if addr.port() == listen_port && is_local_ip(addr.ip()) {
+            anyhow::bail!(
+                "upstream {upstream} resolves to {addr}, which is dbprobe's own listen port :{listen_port} \
+                 — connections would loop back into the proxy"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// We listen on 0.0.0.0, so any address of this host reaches the listener.
+/// Binding an ephemeral UDP socket succeeds only for addresses owned by this host.
+fn is_local_ip(ip: IpAddr) -> bool {
+    ip.is_loopback() || ip.is_unspecified() || std::net::UdpSocket::bind((ip, 0)).is_ok()
+}
+
+/// How `wait_for_upstream` decides the upstream is usable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum HealthCheck {
+    /// TCP connect succeeds
+    Tcp,
+    /// The server answers an SSLRequest — it's actually speaking the Postgres protocol
+    Handshake,
+}
+
+/// Delay between failed upstream health check attempts.
+const HEALTH_CHECK_RETRY: Duration = Duration::from_millis(500);
+
+/// Block until the upstream passes `check`, retrying until `timeout` elapses.
+/// Run before binding the listener so clients never reach a proxy with no database behind it.
+pub async fn wait_for_upstream(
+    upstream: &str,
+    check: HealthCheck,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut attempts = 0u32;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let result = match tokio::time::timeout(remaining, probe_upstream(upstream, check)).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("timed out")),
+        };
+        attempts += 1;
+        match result {
+            Ok(()) => {
+                info!("Upstream {upstream} is ready");
+                return Ok(());
+            }
+            Err(e) if Instant::now() + HEALTH_CHECK_RETRY >= deadline => {
+                anyhow::bail!(
+                    "upstream {upstream} not ready after {attempts} attempts in {timeout:?}: {e}"
+                );
+            }
+            Err(e) => {
+                if attempts == 1 {
+                    info!("Waiting for upstream {upstream}: {e}");
+                }
+                tokio::time::sleep(HEALTH_CHECK_RETRY).await;
+            }
+        }
+    }
+}
+
+/// SSLRequest: length 8, code 80877103. Any Postgres server answers a single
+/// 'S' or 'N' without needing credentials.
+const SSL_REQUEST: [u8; 8] = [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f];
+
+async fn probe_upstream(upstream: &str, check: HealthCheck) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(upstream).await?;
+    if check == HealthCheck::Handshake {
+        // We close before negotiating anything further.
+        stream.write_all(&SSL_REQUEST).await?;
+        let mut reply = [0u8; 1];
+        stream.read_exact(&mut reply).await?;
+        if !matches!(reply[0], b'S' | b'N') {
+            anyhow::bail!("unexpected reply to SSLRequest: {:#04x}", reply[0]);
+        }
+    }
+    Ok(())
+}
+
 pub async fn run_proxy(
     listen_addr: &str,
-    upstream_addr: String,
+    upstream: SharedUpstream,
+    ready: Readiness,
+    options: ProxyOptions,
     tx: mpsc::UnboundedSender<ProxyMessage>,
 ) -> anyhow::Result<()> {
     let listener = TcpListener::bind(listen_addr).await?;
-    info!("Listening on {listen_addr}, forwarding to {upstream_addr}");
+    info!(
+        "Listening on {listen_addr}, forwarding to {}",
+        upstream.read().unwrap()
+    );
+    ready.store(true, Ordering::Relaxed);
+
+    let upstream_sockets: UpstreamSockets = Arc::new(Mutex::new(HashSet::new()));
 
     loop {
         let (client_stream, client_addr) = listener.accept().await?;
         let conn_id = CONN_COUNTER.fetch_add(1, Ordering::Relaxed);
-        let upstream_addr = upstream_addr.clone();
+        let upstream_addr = upstream.read().unwrap().clone();
+
+        // Runtime guard: the startup check can be defeated by DNS changes or port
+        // forwarding. If a client is one of our own upstream sockets, stop accepting.
+        if upstream_sockets.lock().unwrap().contains(&client_addr) {
+            let message = format!(
+                "connection loop detected: {client_addr} is dbprobe's own upstream socket \
+                 (upstream {upstream_addr} points back at the proxy) — refusing new connections"
+            );
+            error!("{message}");
+            let _ = tx.send(ProxyMessage::Warning { conn_id, message });
+            anyhow::bail!("upstream {upstream_addr} loops back into the proxy");
+        }
+
+        let upstream_sockets = upstream_sockets.clone();
+        let options = options.clone();
         let tx = tx.clone();
 
         debug!("New connection {conn_id} from {client_addr}");
-        let _ = tx.send(ProxyMessage::ConnectionOpened { conn_id });
+        let _ = tx.send(ProxyMessage::ConnectionOpened {
+            conn_id,
+            client_addr,
+        });
+        if let Some(capture) = &options.capture {
+            capture.record(
+                conn_id,
+                FrameKind::Opened,
+                client_addr.to_string().as_bytes(),
+            );
+        }
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(conn_id, client_stream, &upstream_addr, tx.clone()).await {
+            let capture = options.capture.clone();
+            let result = if options.probe_only {
+                probe_connection(conn_id, client_stream, &tx).await
+            } else {
+                handle_connection(
+                    conn_id,
+                    client_stream,
+                    &upstream_addr,
+                    &upstream_sockets,
+                    options,
+                    tx.clone(),
+                )
+                .await
+            };
+            if let Err(e) = result {
                 warn!("Connection {conn_id} error: {e}");
             }
+            if let Some(capture) = &capture {
+                capture.record(conn_id, FrameKind::Closed, &[]);
+            }
             let _ = tx.send(ProxyMessage::ConnectionClosed { conn_id });
             debug!("Connection {conn_id} closed");
         });
@@ -57,8 +324,11 @@ async fn handle_connection(
     conn_id: u64,
     client_stream: TcpStream,
     upstream_addr: &str,
+    upstream_sockets: &UpstreamSockets,
+    options: ProxyOptions,
     tx: mpsc::UnboundedSender<ProxyMessage>,
 ) -> anyhow::Result<()> {
+    let dialed = Instant::now();
     let upstream_stream = match tokio::time::timeout(
         std::time::Duration::from_secs(5),
         TcpStream::connect(upstream_addr),
@@ -75,15 +345,75 @@ async fn handle_connection(
             return Err(anyhow::anyhow!("upstream connect timeout"));
         }
     };
+    let _ = tx.send(ProxyMessage::UpstreamConnected {
+        conn_id,
+        connect_time: dialed.elapsed(),
+    });
+
+    // Per-connection loop check: the upstream peer is the very socket this client reached.
+    if let (Ok(upstream_peer), Ok(listen_local)) =
+        (upstream_stream.peer_addr(), client_stream.local_addr())
+    {
+        if upstream_peer == listen_local {
+            let message = format!("upstream {upstream_addr} ({upstream_peer}) is dbprobe's own listen address — closing looped connection");
+            let _ = tx.send(ProxyMessage::Warning { conn_id, message });
+            anyhow::bail!("upstream {upstream_addr} loops back into the proxy");
+        }
+    }
 
-    let (client_read, client_write) = client_stream.into_split();
-    let (upstream_read, upstream_write) = upstream_stream.into_split();
+    let upstream_local = upstream_stream.local_addr().ok();
+    if let Some(addr) = upstream_local {
+        upstream_sockets.lock().unwrap().insert(addr);
+    }
+
+    let protocol = detect_protocol(conn_id, &client_stream, &upstream_stream).await;
+    if protocol != Protocol::Postgres {
+        let message = format!(
+            "{} detected — not parsed yet, relaying bytes only",
+            protocol.name()
+        );
+        let _ = tx.send(ProxyMessage::Warning { conn_id, message });
+    }
+
+    // With either TLS option, encryption is settled on both sides before the relays start.
+    let started = async {
+        if options.upstream_tls.is_some() && protocol != Protocol::Postgres {
+            anyhow::bail!(
+                "--upstream-tls only negotiates Postgres SSL; refusing to relay {} in plaintext",
+                protocol.name()
+            );
+        }
+        let negotiate = protocol == Protocol::Postgres
+            && (options.listen_tls.is_some() || options.upstream_tls.is_some());
+        let client = if negotiate {
+            negotiate_client_encryption(conn_id, client_stream, options.listen_tls.as_ref(), &tx)
+                .await?
+        } else {
+            split_tcp(client_stream)
+        };
+        let upstream = match &options.upstream_tls {
+            Some(tls) => split_io(start_upstream_tls(upstream_stream, upstream_addr, tls).await?),
+            None => split_tcp(upstream_stream),
+        };
+        Ok((client, upstream))
+    };
+    let ((client_read, client_write), (upstream_read, upstream_write)) = match started.await {
+        Ok(halves) => halves,
+        Err(e) => {
+            let _ = tx.send(ProxyMessage::Warning {
+                conn_id,
+                message: e.to_string(),
+            });
+            if let Some(addr) = upstream_local {
+                upstream_sockets.lock().unwrap().remove(&addr);
+            }
+            return Err(e);
+        }
+    };
 
     // std::sync::Mutex is correct here: the critical section is pure CPU parsing (~us),
     // never crosses an await point, and avoids the overhead of tokio's async Mutex.
-    let parser = Arc::new(Mutex::new(
-        Box::new(PostgresParser::new()) as Box<dyn ProtocolParser>
-    ));
+    let parser = Arc::new(Mutex::new(protocol.parser(!options.no_sql)));
 
     let (intercept_tx, mut intercept_rx) = mpsc::channel::<Vec<u8>>(4);
     let (client_write_tx, mut client_write_rx) = mpsc::channel::<Bytes>(256);
@@ -93,12 +423,12 @@ async fn handle_connection(
         loop {
             tokio::select! {
                 Some(data) = client_write_rx.recv() => {
-                    if writer.write_all(&data).await.is_err() {
+                    if writer.write_all(&data).await.is_err() || writer.flush().await.is_err() {
                         break;
                     }
                 }
                 Some(data) = intercept_rx.recv() => {
-                    if writer.write_all(&data).await.is_err() {
+                    if writer.write_all(&data).await.is_err() || writer.flush().await.is_err() {
                         break;
                     }
                 }
@@ -107,84 +437,373 @@ async fn handle_connection(
         }
     });
 
-    let parser_fe = parser.clone();
-    let tx_fe = tx.clone();
-    let mut frontend_handle = tokio::spawn(async move {
-        relay_frontend(
-            client_read,
-            upstream_write,
-            parser_fe,
-            tx_fe,
-            conn_id,
-            intercept_tx,
-        )
-        .await
-    });
+    let context = RelayContext {
+        conn_id,
+        parser,
+        events_tx: tx.clone(),
+        capture: options.capture,
+        hexdump: options.hexdump,
+        idle_since: IdleSince::default(),
+        announcement: (options.announce && protocol == Protocol::Postgres).then(Announcement::new),
+    };
+    let idle_since = context.idle_since.clone();
+    let error_tx = client_write_tx.clone();
 
-    let parser_be = parser.clone();
-    let tx_be = tx;
-    let mut backend_handle = tokio::spawn(async move {
-        relay_backend(
-            upstream_read,
-            client_write_tx,
-            parser_be,
-            tx_be,
-            conn_id,
-        )
-        .await
+    let context_fe = context.clone();
+    let mut frontend_handle = tokio::spawn(async move {
+        relay_frontend(client_read, upstream_write, context_fe, intercept_tx).await
     });
+    let mut backend_handle =
+        tokio::spawn(async move { relay_backend(upstream_read, client_write_tx, context).await });
 
-    // Wait for either direction to finish, then clean up both.
-    tokio::select! {
-        _ = &mut frontend_handle => {}
-        _ = &mut backend_handle => {}
-    }
+    // Wait for either direction to finish (or the idle guard to fire), then clean up both.
+    let idle_for = tokio::select! {
+        _ = &mut frontend_handle => None,
+        _ = &mut backend_handle => None,
+        idle = idle_in_transaction_exceeded(&idle_since, options.max_idle_in_transaction) => Some(idle),
+    };
 
     // Abort all remaining tasks so we don't leak them.
     frontend_handle.abort();
     backend_handle.abort();
-    client_writer_handle.abort();
+
+    if let Some(idle) = idle_for {
+        let limit = options.max_idle_in_transaction.unwrap_or_default();
+        let message = format!(
+            "IDLE IN TRANSACTION for {:.1}s (limit {:.1}s) — terminating connection {conn_id}",
+            idle.as_secs_f64(),
+            limit.as_secs_f64()
+        );
+        warn!("{message}");
+        let _ = tx.send(ProxyMessage::Warning { conn_id, message });
+
+        // Tell the client why, the way the server would, and let the writer drain.
+        // Closing the upstream socket makes the server roll the transaction back.
+        let _ = error_tx.send(fatal_error_response(
+            IDLE_IN_TRANSACTION_TIMEOUT,
+            "terminating connection due to idle-in-transaction timeout (dbprobe --max-idle-in-transaction)",
+        )).await;
+        drop(error_tx);
+        let _ = tokio::time::timeout(Duration::from_secs(1), client_writer_handle).await;
+    } else {
+        client_writer_handle.abort();
+    }
+
+    if let Some(addr) = upstream_local {
+        upstream_sockets.lock().unwrap().remove(&addr);
+    }
 
     Ok(())
 }
 
-async fn relay_frontend(
-    mut reader: OwnedReadHalf,
-    mut writer: OwnedWriteHalf,
-    parser: Arc<Mutex<Box<dyn ProtocolParser>>>,
-    events_tx: mpsc::UnboundedSender<ProxyMessage>,
+/// SQLSTATE sqlserver_rejected_establishment_of_sqlconnection, for refused probes.
+const PROBE_REJECTED: &str = "08004";
+
+/// How long a probed client gets to send its StartupMessage.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `--probe-only`: read the client's startup packets, declining SSL and GSSAPI
+/// encryption so the StartupMessage arrives in the clear, report what it asked
+/// for, and close with a FATAL error. Nothing is forwarded.
+async fn probe_connection(
+    conn_id: u64,
+    mut client_stream: TcpStream,
+    tx: &mpsc::UnboundedSender<ProxyMessage>,
+) -> anyhow::Result<()> {
+    let mut parser = Protocol::Postgres.parser(true);
+    let mut declined = Vec::new();
+    let params = loop {
+        let packet = tokio::time::timeout(PROBE_TIMEOUT, read_startup_packet(&mut client_stream))
+            .await
+            .map_err(|_| anyhow::anyhow!("no StartupMessage within {PROBE_TIMEOUT:?}"))??;
+        let code = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        match code {
+            postgres::SSL_REQUEST_CODE | postgres::GSSENC_REQUEST_CODE => {
+                declined.push(if code == postgres::SSL_REQUEST_CODE {
+                    "ssl"
+                } else {
+                    "gssenc"
+                });
+                client_stream.write_all(b"N").await?;
+            }
+            postgres::CANCEL_REQUEST_CODE => {
+                let _ = tx.send(ProxyMessage::Warning {
+                    conn_id,
+                    message: "PROBE CancelRequest".to_string(),
+                });
+                return Ok(());
+            }
+            _ => match parser.try_parse(&packet, Direction::Frontend) {
+                Some((ProtoEvent::Startup { params }, _)) => break params,
+                _ => anyhow::bail!("unsupported startup protocol code {code}"),
+            },
+        }
+    };
+
+    let _ = tx.send(ProxyMessage::Warning {
+        conn_id,
+        message: probe_summary(&params, &declined),
+    });
+    let _ = tx.send(ProxyMessage::Event {
+        conn_id,
+        event: ProtoEvent::Startup { params },
+        at: Instant::now(),
+    });
+    client_stream
+        .write_all(&fatal_error_response(
+            PROBE_REJECTED,
+            "dbprobe --probe-only: handshake recorded, no database behind this proxy",
+        ))
+        .await?;
+    let _ = client_stream.shutdown().await;
+    Ok(())
+}
+
+/// With `--listen-tls` or `--upstream-tls`, the client's encryption requests
+/// are answered here instead of reaching the server, which may already be
+/// inside a TLS session of dbprobe's. GSSAPI is declined. SSL is accepted and
+/// the TLS handshake run with `--listen-tls`, and declined without it. A
+/// plaintext client's first other packet has been read by then, so the
+/// returned reader replays it before the rest.
+async fn negotiate_client_encryption(
     conn_id: u64,
+    mut client: TcpStream,
+    listen_tls: Option<&ListenTls>,
+    tx: &mpsc::UnboundedSender<ProxyMessage>,
+) -> anyhow::Result<(ReadHalf, WriteHalf)> {
+    let mut ssl_declined = false;
+    loop {
+        let packet = match read_startup_packet(&mut client).await {
+            Ok(packet) => packet,
+            Err(e) => {
+                let hung_up = e
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof);
+                if ssl_declined && hung_up {
+                    let _ = tx.send(ProxyMessage::Warning {
+                        conn_id,
+                        message: SSL_DECLINED_HANGUP.to_string(),
+                    });
+                }
+                return Err(e);
+            }
+        };
+        match (
+            u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            listen_tls,
+        ) {
+            (postgres::SSL_REQUEST_CODE, Some(tls)) => {
+                client.write_all(b"S").await?;
+                let stream = tls
+                    .accept(client)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("TLS handshake with client failed: {e}"))?;
+                if let Some(subject) = tls::client_subject(&stream) {
+                    let _ = tx.send(ProxyMessage::ClientCertificate { conn_id, subject });
+                }
+                return Ok(split_io(stream));
+            }
+            (code @ (postgres::SSL_REQUEST_CODE | postgres::GSSENC_REQUEST_CODE), _) => {
+                client.write_all(b"N").await?;
+                ssl_declined = code == postgres::SSL_REQUEST_CODE;
+            }
+            _ => {
+                let (read, write) = client.into_split();
+                return Ok((
+                    Box::new(std::io::Cursor::new(packet).chain(read)),
+                    Box::new(write),
+                ));
+            }
+        }
+    }
+}
+
+/// `--upstream-tls`: ask the server for SSL the way libpq does, then run the
+/// TLS handshake. A refusal or a failed handshake ends the connection.
+async fn start_upstream_tls(
+    mut stream: TcpStream,
+    upstream_addr: &str,
+    tls: &UpstreamTls,
+) -> anyhow::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    stream.write_all(&SSL_REQUEST).await?;
+    let mut reply = [0u8; 1];
+    stream.read_exact(&mut reply).await?;
+    match reply[0] {
+        b'S' => tls.connect(upstream_addr, stream).await.map_err(|e| {
+            anyhow::anyhow!("TLS handshake with upstream {upstream_addr} failed: {e}")
+        }),
+        b'N' => {
+            anyhow::bail!("upstream {upstream_addr} does not accept SSL, and --upstream-tls is set")
+        }
+        other => {
+            anyhow::bail!("upstream {upstream_addr}: unexpected reply to SSLRequest: {other:#04x}")
+        }
+    }
+}
+
+/// One untagged startup-phase packet: length, protocol code and body.
+async fn read_startup_packet(stream: &mut TcpStream) -> anyhow::Result<Vec<u8>> {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).await?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    if !(8..=10_000).contains(&length) {
+        anyhow::bail!("invalid startup packet length {length}");
+    }
+    let mut packet = header.to_vec();
+    packet.resize(length, 0);
+    stream.read_exact(&mut packet[8..]).await?;
+    Ok(packet)
+}
+
+/// `PROBE user=alice database=app application_name=psql ssl=declined`.
+fn probe_summary(params: &[(String, String)], declined: &[&str]) -> String {
+    let mut summary = String::from("PROBE");
+    for (name, value) in params {
+        summary.push_str(&format!(" {name}={value}"));
+    }
+    for kind in ["ssl", "gssenc"] {
+        let state = if declined.contains(&kind) {
+            "declined"
+        } else {
+            "not requested"
+        };
+        summary.push_str(&format!(" {kind}={state}"));
+    }
+    summary
+}
+
+/// How long to wait for either side's first bytes before assuming Postgres.
+const DETECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Peek at whichever side speaks first — the client for Postgres, the server
+/// for MySQL — and pick the protocol. Falls back to Postgres when unsure.
+async fn detect_protocol(conn_id: u64, client: &TcpStream, upstream: &TcpStream) -> Protocol {
+    let mut client_buf = [0u8; 16];
+    let mut upstream_buf = [0u8; 16];
+    let first = tokio::time::timeout(DETECT_TIMEOUT, async {
+        tokio::select! {
+            n = client.peek(&mut client_buf) => (Direction::Frontend, n),
+            n = upstream.peek(&mut upstream_buf) => (Direction::Backend, n),
+        }
+    })
+    .await;
+
+    let detected = match first {
+        Ok((Direction::Frontend, Ok(n))) => Protocol::detect(&client_buf[..n], Direction::Frontend),
+        Ok((Direction::Backend, Ok(n))) => Protocol::detect(&upstream_buf[..n], Direction::Backend),
+        _ => None,
+    };
+    match detected {
+        Some(protocol) => {
+            debug!(
+                "Connection {conn_id}: detected {} protocol",
+                protocol.name()
+            );
+            protocol
+        }
+        None => {
+            debug!("Connection {conn_id}: protocol not recognized, assuming postgres");
+            Protocol::Postgres
+        }
+    }
+}
+
+/// Resolves once the connection has been idle inside a transaction for longer
+/// than `limit`, with how long it has been idle. Never resolves without a limit.
+async fn idle_in_transaction_exceeded(idle_since: &IdleSince, limit: Option<Duration>) -> Duration {
+    let Some(limit) = limit else {
+        return std::future::pending().await;
+    };
+    loop {
+        let since = *idle_since.lock().unwrap();
+        match since {
+            Some(since) if since.elapsed() >= limit => return since.elapsed(),
+            Some(since) => tokio::time::sleep_until((since + limit).into()).await,
+            None => tokio::time::sleep(limit.min(IDLE_CHECK_INTERVAL)).await,
+        }
+    }
+}
+
+/// A FATAL ErrorResponse, for closing a client connection with a reason.
+fn fatal_error_response(code: &str, message: &str) -> Bytes {
+    let mut body = Vec::new();
+    for (field, value) in [
+        (b'S', "FATAL"),
+        (b'V', "FATAL"),
+        (b'C', code),
+        (b'M', message),
+    ] {
+        body.push(field);
+        body.extend_from_slice(value.as_bytes());
+        body.push(0);
+    }
+    body.push(0);
+
+    let mut msg = vec![b'E'];
+    msg.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+    msg.extend_from_slice(&body);
+    Bytes::from(msg)
+}
+
+/// Logged when a client closes right after its SSLRequest got 'N'.
+const SSL_DECLINED_HANGUP: &str = "client hung up after SSL was declined — it likely requires TLS (sslmode=require or stricter); \
+                                   start dbprobe with --listen-tls to accept it";
+
+async fn relay_frontend(
+    mut reader: ReadHalf,
+    mut writer: WriteHalf,
+    context: RelayContext,
     intercept_tx: mpsc::Sender<Vec<u8>>,
 ) -> anyhow::Result<()> {
+    let RelayContext {
+        conn_id,
+        parser,
+        events_tx,
+        capture,
+        hexdump,
+        idle_since,
+        ..
+    } = context;
     let mut buf = vec![0u8; 16384];
     let mut parse_buf = BytesMut::with_capacity(16384);
+    // The last thing the client sent was an SSLRequest we answered 'N'.
+    let mut ssl_declined = false;
 
     loop {
         let n = reader.read(&mut buf).await?;
         if n == 0 {
+            if ssl_declined {
+                let _ = events_tx.send(ProxyMessage::Warning {
+                    conn_id,
+                    message: SSL_DECLINED_HANGUP.to_string(),
+                });
+            }
             break;
         }
+        let at = Instant::now();
+        // Anything from the client ends an idle period.
+        *idle_since.lock().unwrap() = None;
+        if let Some(capture) = &capture {
+            capture.record(conn_id, FrameKind::Frontend, &buf[..n]);
+        }
+        if let Some(hexdump) = &hexdump {
+            hexdump.dump(conn_id, Direction::Frontend, &buf[..n]);
+        }
 
         parse_buf.extend_from_slice(&buf[..n]);
 
         // Check for SSL intercept before forwarding.
         // Lock is scoped so the MutexGuard is dropped before any .await.
         let intercept_response = {
-            let mut parser = parser.lock().unwrap();
+            let mut parser = lock_parser(&parser);
             parser.handle_startup_intercept(&parse_buf, Direction::Frontend)
         };
 
+        ssl_declined = false;
         if let Some(response) = intercept_response {
             intercept_tx.send(response).await.ok();
-            // Consume the SSLRequest from parse buffer
-            let length = if parse_buf.len() >= 4 {
-                u32::from_be_bytes([parse_buf[0], parse_buf[1], parse_buf[2], parse_buf[3]]) as usize
-            } else {
-                8
-            };
-            if parse_buf.len() >= length {
-                let _ = parse_buf.split_to(length);
-            }
+            consume_intercepted(&mut parse_buf);
+            ssl_declined = parse_buf.is_empty();
             // If there's leftover data after the SSLRequest, forward it to upstream.
             if !parse_buf.is_empty() {
                 writer.write_all(&parse_buf).await?;
@@ -192,23 +811,18 @@ async fn relay_frontend(
         } else {
             writer.write_all(&buf[..n]).await?;
         }
+        writer.flush().await?;
 
         // Parse events from buffer — collect under lock, send after release.
-        // Unknown events are filtered: they are discarded by stats and would
-        // unnecessarily grow the unbounded channel during large pipelines.
-        let events: Vec<ProtoEvent> = {
-            let mut parser = parser.lock().unwrap();
-            let mut collected = Vec::new();
-            while let Some((event, consumed)) = parser.try_parse(&parse_buf, Direction::Frontend) {
-                if !matches!(event, ProtoEvent::Unknown { .. }) {
-                    collected.push(event);
-                }
-                let _ = parse_buf.split_to(consumed);
-            }
-            collected
-        };
+        let events = parse_events(
+            &parser,
+            &mut parse_buf,
+            Direction::Frontend,
+            conn_id,
+            &events_tx,
+        );
         for event in events {
-            let _ = events_tx.send(ProxyMessage::Event { conn_id, event });
+            let _ = events_tx.send(ProxyMessage::Event { conn_id, event, at });
         }
     }
 
@@ -216,12 +830,19 @@ async fn relay_frontend(
 }
 
 async fn relay_backend(
-    mut reader: OwnedReadHalf,
+    mut reader: ReadHalf,
     writer_tx: mpsc::Sender<Bytes>,
-    parser: Arc<Mutex<Box<dyn ProtocolParser>>>,
-    events_tx: mpsc::UnboundedSender<ProxyMessage>,
-    conn_id: u64,
+    context: RelayContext,
 ) -> anyhow::Result<()> {
+    let RelayContext {
+        conn_id,
+        parser,
+        events_tx,
+        capture,
+        hexdump,
+        idle_since,
+        mut announcement,
+    } = context;
     let mut buf = vec![0u8; 16384];
     let mut parse_buf = BytesMut::with_capacity(16384);
 
@@ -230,29 +851,928 @@ async fn relay_backend(
         if n == 0 {
             break;
         }
+        let at = Instant::now();
+        if let Some(capture) = &capture {
+            capture.record(conn_id, FrameKind::Backend, &buf[..n]);
+        }
+        if let Some(hexdump) = &hexdump {
+            hexdump.dump(conn_id, Direction::Backend, &buf[..n]);
+        }
 
         // Forward immediately to client. Use Bytes to avoid a copy when possible.
-        if writer_tx.send(Bytes::copy_from_slice(&buf[..n])).await.is_err() {
+        let data = match &mut announcement {
+            Some(pending) => {
+                let (data, done) = pending.feed(&buf[..n]);
+                if done {
+                    announcement = None;
+                }
+                data
+            }
+            None => Bytes::copy_from_slice(&buf[..n]),
+        };
+        if !data.is_empty()
+            && send_to_client(&writer_tx, data, SLOW_CLIENT_STALL, conn_id, &events_tx)
+                .await
+                .is_err()
+        {
             break;
         }
 
         parse_buf.extend_from_slice(&buf[..n]);
 
-        let events: Vec<ProtoEvent> = {
-            let mut parser = parser.lock().unwrap();
-            let mut collected = Vec::new();
-            while let Some((event, consumed)) = parser.try_parse(&parse_buf, Direction::Backend) {
-                if !matches!(event, ProtoEvent::Unknown { .. }) {
-                    collected.push(event);
+        let events = parse_events(
+            &parser,
+            &mut parse_buf,
+            Direction::Backend,
+            conn_id,
+            &events_tx,
+        );
+        for event in events {
+            if let ProtoEvent::ConnectionReady { status } = &event {
+                // Waiting on the client inside a transaction (open or failed).
+                let mut idle_since = idle_since.lock().unwrap();
+                *idle_since = (*status != TxStatus::Idle).then_some(at);
+            }
+            let _ = events_tx.send(ProxyMessage::Event { conn_id, event, at });
+        }
+    }
+
+    Ok(())
+}
+
+/// Queue `data` for the client writer. A full channel means the client is
+/// reading slower than the server sends; waiting here is the flow control, and
+/// a wait longer than `stall_after` is reported. Nothing is locked across the
+/// wait, so the client's own traffic keeps flowing upstream meanwhile.
+async fn send_to_client(
+    writer_tx: &mpsc::Sender<Bytes>,
+    data: Bytes,
+    stall_after: Duration,
+    conn_id: u64,
+    events_tx: &mpsc::UnboundedSender<ProxyMessage>,
+) -> Result<(), mpsc::error::SendError<()>> {
+    let permit = match tokio::time::timeout(stall_after, writer_tx.reserve()).await {
+        Ok(permit) => permit?,
+        Err(_) => {
+            let at = Instant::now();
+            let _ = events_tx.send(ProxyMessage::ClientBackpressure {
+                conn_id,
+                waited: stall_after,
+                at,
+            });
+            writer_tx.reserve().await?
+        }
+    };
+    permit.send(data);
+    Ok(())
+}
+
+/// `--announce`: a `dbprobe_version` ParameterStatus, slipped in among the
+/// server's own just before its first ReadyForQuery. Until then the backend
+/// stream is passed on one complete message at a time, so the insertion point
+/// is never split across reads. The server doesn't know the parameter, so
+/// `SHOW dbprobe_version` still fails.
+#[derive(Clone)]
+struct Announcement {
+    message: Bytes,
+    /// Received bytes not yet forwarded: an incomplete message.
+    held: BytesMut,
+    /// The server has sent its first tagged message, so the startup is under way.
+    started: bool,
+}
+
+impl Announcement {
+    fn new() -> Self {
+        let mut body = b"dbprobe_version\0".to_vec();
+        body.extend_from_slice(env!("CARGO_PKG_VERSION").as_bytes());
+        body.push(0);
+        let mut message = vec![b'S'];
+        message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        message.extend_from_slice(&body);
+        Self {
+            message: Bytes::from(message),
+            held: BytesMut::new(),
+            started: false,
+        }
+    }
+
+    /// Take the next chunk from the server; returns what to send to the client
+    /// and whether the announcement has been made.
+    fn feed(&mut self, chunk: &[u8]) -> (Bytes, bool) {
+        self.held.extend_from_slice(chunk);
+        let mut pos = 0;
+        // Before the startup, the server only answers a GSSENCRequest, with one
+        // untagged byte that the client waits for: N to decline, or G to switch
+        // to GSSAPI encryption, which leaves nothing we could insert into.
+        if !self.started {
+            while self.held.get(pos) == Some(&b'N') {
+                pos += 1;
+            }
+            match self.held.get(pos) {
+                Some(b'G') => return (self.held.split().freeze(), true),
+                Some(_) => self.started = true,
+                None => return (self.held.split_to(pos).freeze(), false),
+            }
+        }
+        while self.held.len() >= pos + 5 {
+            let len = u32::from_be_bytes([
+                self.held[pos + 1],
+                self.held[pos + 2],
+                self.held[pos + 3],
+                self.held[pos + 4],
+            ]) as usize;
+            if self.held[pos] == b'Z' {
+                let mut out = BytesMut::with_capacity(self.held.len() + self.message.len());
+                out.extend_from_slice(&self.held[..pos]);
+                out.extend_from_slice(&self.message);
+                out.extend_from_slice(&self.held[pos..]);
+                self.held.clear();
+                return (out.freeze(), true);
+            }
+            if self.held.len() < pos + 1 + len {
+                break;
+            }
+            pos += 1 + len;
+        }
+        (self.held.split_to(pos).freeze(), false)
+    }
+}
+
+/// Drop an intercepted startup request (SSLRequest) from the front of `parse_buf`.
+pub(crate) fn consume_intercepted(parse_buf: &mut BytesMut) {
+    let length = if parse_buf.len() >= 4 {
+        u32::from_be_bytes([parse_buf[0], parse_buf[1], parse_buf[2], parse_buf[3]]) as usize
+    } else {
+        8
+    };
+    if parse_buf.len() >= length {
+        let _ = parse_buf.split_to(length);
+    }
+}
+
+/// Lock a connection's parser, even if a panic while it was held poisoned the mutex.
+fn lock_parser(parser: &SharedParser) -> MutexGuard<'_, Box<dyn ProtocolParser>> {
+    parser.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// `drain_events` under the connection's parser lock. A parser bug that panics
+/// costs only the parsing: the parser is swapped for an opaque one, so the
+/// connection is relayed unparsed from then on instead of dropped.
+fn parse_events(
+    parser: &SharedParser,
+    parse_buf: &mut BytesMut,
+    direction: Direction,
+    conn_id: u64,
+    events_tx: &mpsc::UnboundedSender<ProxyMessage>,
+) -> Vec<ProtoEvent> {
+    let mut parser = lock_parser(parser);
+    match std::panic::catch_unwind(AssertUnwindSafe(|| {
+        drain_events(parser.as_mut(), parse_buf, direction)
+    })) {
+        Ok(events) => events,
+        Err(_) => {
+            let name = parser.protocol_name();
+            *parser = Box::new(OpaqueParser::new(name));
+            parse_buf.clear();
+            let message =
+                format!("{name} parser panicked — relaying this connection unparsed from now on");
+            error!("Connection {conn_id}: {message}");
+            let _ = events_tx.send(ProxyMessage::Warning { conn_id, message });
+            Vec::new()
+        }
+    }
+}
+
+/// Parse every complete message in `parse_buf`, consuming it.
+/// Unknown events are filtered: they are discarded by stats and would
+/// unnecessarily grow the unbounded channel during large pipelines.
+pub(crate) fn drain_events(
+    parser: &mut dyn ProtocolParser,
+    parse_buf: &mut BytesMut,
+    direction: Direction,
+) -> Vec<ProtoEvent> {
+    let mut collected = Vec::new();
+    while let Some((event, consumed)) = parser.try_parse(parse_buf, direction) {
+        // A parse that consumes nothing would return the same event forever,
+        // with the parser's mutex held and the connection hung behind it.
+        if consumed == 0 {
+            warn!(
+                "BUG: {} parser returned an event without consuming input; dropping {} buffered bytes",
+                parser.protocol_name(),
+                parse_buf.len()
+            );
+            parse_buf.clear();
+            break;
+        }
+        if !matches!(event, ProtoEvent::Unknown { .. }) {
+            collected.push(event);
+        }
+        let _ = parse_buf.split_to(consumed);
+    }
+    let counts = parser.take_message_counts();
+    if !counts.is_empty() {
+        collected.push(ProtoEvent::MessageCounts { counts });
+    }
+    collected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement_goes_before_first_ready_for_query() {
+        let auth_ok = [b'R', 0, 0, 0, 8, 0, 0, 0, 0];
+        let status = b"S\0\0\0\x18server_version\x0016.2\0";
+        let ready = [b'Z', 0, 0, 0, 5, b'I'];
+        let mut stream = Vec::new();
+        stream.extend_from_slice(&auth_ok);
+        stream.extend_from_slice(status);
+        stream.extend_from_slice(&ready);
+
+        // Split mid-header and mid-message: only complete messages go out early.
+        let mut announcement = Announcement::new();
+        let (first, done) = announcement.feed(&stream[..3]);
+        assert!(first.is_empty() && !done);
+        let (second, done) = announcement.feed(&stream[3..20]);
+        assert_eq!(&second[..], &auth_ok);
+        assert!(!done);
+        let (third, done) = announcement.feed(&stream[20..]);
+        assert!(done);
+
+        let message = Announcement::new().message;
+        let mut expected = status.to_vec();
+        expected.extend_from_slice(&message);
+        expected.extend_from_slice(&ready);
+        assert_eq!(&third[..], &expected[..]);
+        assert!(message
+            .ends_with(format!("dbprobe_version\0{}\0", env!("CARGO_PKG_VERSION")).as_bytes()));
+    }
+
+    #[test]
+    fn test_announcement_passes_gssenc_reply_through() {
+        // libpq with gssencmode=prefer waits for this byte before its StartupMessage.
+        let mut announcement = Announcement::new();
+        let (reply, done) = announcement.feed(b"N");
+        assert_eq!(&reply[..], b"N");
+        assert!(!done);
+
+        let mut stream = vec![b'R', 0, 0, 0, 8, 0, 0, 0, 0];
+        stream.extend_from_slice(&[b'Z', 0, 0, 0, 5, b'I']);
+        let (out, done) = announcement.feed(&stream);
+        assert!(done);
+        assert!(out.starts_with(&stream[..9]) && out.ends_with(&stream[9..]));
+        assert_eq!(out.len(), stream.len() + announcement.message.len());
+
+        // Accepted: the rest is encrypted, so it all goes through untouched.
+        let mut announcement = Announcement::new();
+        assert_eq!(
+            announcement.feed(b"G\x01\x02"),
+            (Bytes::from_static(b"G\x01\x02"), true)
+        );
+    }
+
+    /// Parses its first message, then returns an event for any input without consuming it.
+    #[derive(Default)]
+    struct StuckParser {
+        stuck: bool,
+    }
+
+    impl ProtocolParser for StuckParser {
+        fn try_parse(&mut self, buf: &[u8], _direction: Direction) -> Option<(ProtoEvent, usize)> {
+            let &tag = buf.first()?;
+            if !std::mem::replace(&mut self.stuck, true) {
+                return Some((ProtoEvent::ConnectionClosed, 5));
+            }
+            Some((ProtoEvent::Unknown { tag }, 0))
+        }
+
+        fn protocol_name(&self) -> &'static str {
+            "stuck"
+        }
+
+        fn handle_startup_intercept(
+            &mut self,
+            _buf: &[u8],
+            _direction: Direction,
+        ) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    /// Panics on its first message, like a slicing bug would.
+    struct PanickingParser;
+
+    impl ProtocolParser for PanickingParser {
+        fn try_parse(&mut self, buf: &[u8], _direction: Direction) -> Option<(ProtoEvent, usize)> {
+            buf.first()?;
+            panic!("parser bug");
+        }
+
+        fn protocol_name(&self) -> &'static str {
+            "panicky"
+        }
+
+        fn handle_startup_intercept(
+            &mut self,
+            _buf: &[u8],
+            _direction: Direction,
+        ) -> Option<Vec<u8>> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_parse_panic_degrades_to_passthrough() {
+        let parser: SharedParser = Arc::new(Mutex::new(Box::new(PanickingParser)));
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let query = &b"Q\0\0\0\x0dSELECT 1\0"[..];
+
+        let mut parse_buf = BytesMut::from(query);
+        assert!(
+            parse_events(&parser, &mut parse_buf, Direction::Frontend, 7, &events_tx).is_empty()
+        );
+        assert!(parse_buf.is_empty());
+        assert!(!parser.is_poisoned());
+        assert!(
+            matches!(events_rx.try_recv(), Ok(ProxyMessage::Warning { conn_id: 7, message }) if message.starts_with("panicky parser panicked"))
+        );
+
+        // Later traffic on the connection is consumed without another panic or warning.
+        for direction in [Direction::Frontend, Direction::Backend] {
+            let mut parse_buf = BytesMut::from(query);
+            assert!(parse_events(&parser, &mut parse_buf, direction, 7, &events_tx).is_empty());
+            assert!(parse_buf.is_empty());
+        }
+        assert!(events_rx.try_recv().is_err());
+        assert_eq!(lock_parser(&parser).protocol_name(), "panicky");
+
+        // A mutex poisoned some other way still hands out the parser.
+        let poisoned = Arc::clone(&parser);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoned.lock().unwrap();
+            panic!("poison");
+        })
+        .join();
+        assert!(parser.is_poisoned());
+        let mut parse_buf = BytesMut::from(query);
+        assert!(
+            parse_events(&parser, &mut parse_buf, Direction::Frontend, 7, &events_tx).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_parse_without_progress_does_not_hang() {
+        let mut parse_buf = BytesMut::from(&b"X\0\0\0\x04Q\0\0\0\x0dSELECT 1\0"[..]);
+        let drained = drain_events(
+            &mut StuckParser::default(),
+            &mut parse_buf,
+            Direction::Frontend,
+        );
+        // What was parsed before the parser stalled is kept; the rest is dropped.
+        assert!(
+            matches!(drained[..], [ProtoEvent::ConnectionClosed]),
+            "{drained:?}"
+        );
+        assert!(parse_buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_self_referential_upstream_fails_startup() {
+        assert!(check_upstream_loop(5433, "127.0.0.1:5433").await.is_err());
+        assert!(check_upstream_loop(5433, "localhost:5433").await.is_err());
+        assert!(check_upstream_loop(5433, "0.0.0.0:5433").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_upstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 8];
+                if stream.read_exact(&mut request).await.is_ok() {
+                    let _ = stream.write_all(b"N").await;
                 }
-                let _ = parse_buf.split_to(consumed);
             }
-            collected
+        });
+
+        wait_for_upstream(&addr, HealthCheck::Tcp, Duration::from_secs(1))
+            .await
+            .unwrap();
+        wait_for_upstream(&addr, HealthCheck::Handshake, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        // Nothing listens on a just-released port.
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let err = wait_for_upstream(&closed, HealthCheck::Tcp, Duration::from_millis(600))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not ready"));
+    }
+
+    #[tokio::test]
+    async fn test_distinct_upstream_passes_startup() {
+        assert!(check_upstream_loop(5433, "127.0.0.1:5432").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_idle_in_transaction_is_terminated() {
+        // An upstream that opens a transaction and then waits forever.
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut startup = [0u8; 8];
+            stream.read_exact(&mut startup).await.unwrap();
+            stream.write_all(&[b'Z', 0, 0, 0, 5, b'T']).await.unwrap();
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (client_stream, _) = listener.accept().await.unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let options = ProxyOptions {
+            max_idle_in_transaction: Some(Duration::from_millis(200)),
+            ..Default::default()
         };
-        for event in events {
-            let _ = events_tx.send(ProxyMessage::Event { conn_id, event });
+        let sockets = UpstreamSockets::default();
+        let proxy = tokio::spawn(async move {
+            handle_connection(1, client_stream, &upstream_addr, &sockets, options, tx).await
+        });
+
+        // Protocol 3.0 startup with no parameters.
+        client.write_all(&[0, 0, 0, 8, 0, 3, 0, 0]).await.unwrap();
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .expect("connection was not closed")
+            .unwrap();
+        proxy.await.unwrap().unwrap();
+
+        assert_eq!(&received[..6], &[b'Z', 0, 0, 0, 5, b'T']);
+        assert_eq!(received[6], b'E');
+        assert!(String::from_utf8_lossy(&received[6..]).contains(IDLE_IN_TRANSACTION_TIMEOUT));
+
+        let mut warned = false;
+        while let Ok(message) = rx.try_recv() {
+            if let ProxyMessage::Warning {
+                conn_id: 1,
+                message,
+            } = message
+            {
+                assert!(message.contains("IDLE IN TRANSACTION"));
+                warned = true;
+            }
         }
+        assert!(warned);
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_client_leaving_after_ssl_decline_is_reported() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (client_stream, _) = listener.accept().await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sockets = UpstreamSockets::default();
+        let proxy = tokio::spawn(async move {
+            handle_connection(
+                1,
+                client_stream,
+                &upstream_addr,
+                &sockets,
+                ProxyOptions::default(),
+                tx,
+            )
+            .await
+        });
+
+        // An sslmode=require client: SSLRequest, 'N', hang up.
+        client
+            .write_all(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f])
+            .await
+            .unwrap();
+        let mut reply = [0u8; 1];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"N");
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), proxy)
+            .await
+            .expect("proxy did not finish")
+            .unwrap()
+            .unwrap();
+
+        let warnings: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|message| match message {
+                ProxyMessage::Warning { message, .. } => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.starts_with("client hung up after SSL was declined")),
+            "{warnings:?}"
+        );
+    }
+
+    /// A Postgres stand-in that wants SSL when `accept_ssl`: it answers the
+    /// SSLRequest, runs the TLS handshake as `localhost`, reads the
+    /// StartupMessage and replies ReadyForQuery. Returns the startup it read.
+    fn tls_upstream(
+        listener: TcpListener,
+        accept_ssl: bool,
+    ) -> (tokio::task::JoinHandle<Option<Vec<u8>>>, String) {
+        let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_pem = key.cert.pem();
+        let config = tokio_rustls::rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![key.cert.der().clone()],
+                tokio_rustls::rustls::pki_types::PrivateKeyDer::Pkcs8(
+                    key.key_pair.serialize_der().into(),
+                ),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 8];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, SSL_REQUEST);
+            if !accept_ssl {
+                stream.write_all(b"N").await.unwrap();
+                return None;
+            }
+            stream.write_all(b"S").await.unwrap();
+            let mut tls = acceptor.accept(stream).await.unwrap();
+            let mut startup = [0u8; 8];
+            tls.read_exact(&mut startup).await.unwrap();
+            tls.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+            tls.flush().await.unwrap();
+            let mut rest = Vec::new();
+            let _ = tls.read_to_end(&mut rest).await;
+            Some(startup.to_vec())
+        });
+        (handle, cert_pem)
+    }
+
+    #[tokio::test]
+    async fn test_upstream_tls_encrypts_the_server_hop() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = format!("localhost:{}", upstream.local_addr().unwrap().port());
+        let (server, cert_pem) = tls_upstream(upstream, true);
+        let ca =
+            std::env::temp_dir().join(format!("dbprobe-upstream-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca, cert_pem).unwrap();
+        let tls = UpstreamTls::new(Some(&ca), false).unwrap();
+        std::fs::remove_file(&ca).ok();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (client_stream, _) = listener.accept().await.unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let options = ProxyOptions {
+            upstream_tls: Some(tls),
+            ..Default::default()
+        };
+        let sockets = UpstreamSockets::default();
+        let proxy = tokio::spawn(async move {
+            handle_connection(1, client_stream, &upstream_addr, &sockets, options, tx).await
+        });
+
+        // A plaintext client: both its encryption requests are declined by dbprobe itself.
+        for code in [postgres::GSSENC_REQUEST_CODE, postgres::SSL_REQUEST_CODE] {
+            let mut request = 8u32.to_be_bytes().to_vec();
+            request.extend_from_slice(&code.to_be_bytes());
+            client.write_all(&request).await.unwrap();
+            let mut reply = [0u8; 1];
+            client.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"N");
+        }
+        client.write_all(&[0, 0, 0, 8, 0, 3, 0, 0]).await.unwrap();
+        let mut ready = [0u8; 6];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut ready))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ready, [b'Z', 0, 0, 0, 5, b'I']);
+
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), proxy)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(server.await.unwrap(), Some(vec![0, 0, 0, 8, 0, 3, 0, 0]));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_refusing_ssl_is_reported() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+        let (server, _) = tls_upstream(upstream, false);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (client_stream, _) = listener.accept().await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let options = ProxyOptions {
+            upstream_tls: Some(UpstreamTls::new(None, true).unwrap()),
+            ..Default::default()
+        };
+        let sockets = UpstreamSockets::default();
+        let proxy = tokio::spawn(async move {
+            handle_connection(1, client_stream, &upstream_addr, &sockets, options, tx).await
+        });
+
+        client.write_all(&[0, 0, 0, 8, 0, 3, 0, 0]).await.unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), proxy)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(result.is_err());
+        assert_eq!(server.await.unwrap(), None);
+        let warnings: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|message| match message {
+                ProxyMessage::Warning { message, .. } => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            warnings.iter().any(|w| w.contains("does not accept SSL")),
+            "{warnings:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_listen_tls_terminates_client_sessions() {
+        // A plaintext upstream that answers the StartupMessage with ReadyForQuery.
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut startup = [0u8; 8];
+            stream.read_exact(&mut startup).await.unwrap();
+            stream.write_all(&[b'Z', 0, 0, 0, 5, b'I']).await.unwrap();
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest).await;
+            startup
+        });
+
+        let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir();
+        let (cert_path, key_path) = (
+            dir.join(format!("dbprobe-listen-cert-{}.pem", std::process::id())),
+            dir.join(format!("dbprobe-listen-key-{}.pem", std::process::id())),
+        );
+        std::fs::write(&cert_path, key.cert.pem()).unwrap();
+        std::fs::write(&key_path, key.key_pair.serialize_pem()).unwrap();
+        // --tls-client-cert: a CA, and a client certificate it signed.
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let mut client_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        client_params.distinguished_name = rcgen::DistinguishedName::new();
+        client_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "app-service");
+        client_params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+        let ca_path = dir.join(format!("dbprobe-listen-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+        let listen_tls = ListenTls::new(&cert_path, &key_path, Some(&ca_path)).unwrap();
+        std::fs::remove_file(&cert_path).ok();
+        std::fs::remove_file(&key_path).ok();
+        std::fs::remove_file(&ca_path).ok();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (client_stream, _) = listener.accept().await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let options = ProxyOptions {
+            listen_tls: Some(listen_tls),
+            ..Default::default()
+        };
+        let sockets = UpstreamSockets::default();
+        let proxy = tokio::spawn(async move {
+            handle_connection(1, client_stream, &upstream_addr, &sockets, options, tx).await
+        });
+
+        client.write_all(&SSL_REQUEST).await.unwrap();
+        let mut reply = [0u8; 1];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"S");
+        let mut roots = tokio_rustls::rustls::RootCertStore::empty();
+        roots.add(key.cert.der().clone()).unwrap();
+        let config = tokio_rustls::rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![client_cert.der().clone()],
+                tokio_rustls::rustls::pki_types::PrivateKeyDer::Pkcs8(
+                    client_key.serialize_der().into(),
+                ),
+            )
+            .unwrap();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let name = tokio_rustls::rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        let mut client = connector.connect(name, client).await.unwrap();
+
+        client.write_all(&[0, 0, 0, 8, 0, 3, 0, 0]).await.unwrap();
+        client.flush().await.unwrap();
+        let mut ready = [0u8; 6];
+        tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut ready))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ready, [b'Z', 0, 0, 0, 5, b'I']);
+
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), proxy)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        // The upstream got the StartupMessage in the clear, and the parser saw it.
+        assert_eq!(server.await.unwrap(), [0, 0, 0, 8, 0, 3, 0, 0]);
+        let messages: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(messages.iter().any(|m| matches!(
+            m,
+            ProxyMessage::Event {
+                event: ProtoEvent::Startup { .. },
+                ..
+            }
+        )));
+        // The client certificate's subject is reported for the connection.
+        assert!(messages
+            .iter()
+            .any(|m| matches!(m, ProxyMessage::ClientCertificate { conn_id: 1, subject } if subject == "CN=app-service")));
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_handshake_and_refuses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (client_stream, _) = listener.accept().await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let probe = tokio::spawn(async move { probe_connection(3, client_stream, &tx).await });
+
+        client
+            .write_all(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f])
+            .await
+            .unwrap();
+        let mut reply = [0u8; 1];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"N");
+
+        let body = b"user\0alice\0database\0app\0application_name\0psql\0\0";
+        let mut startup = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        startup.extend_from_slice(&[0, 3, 0, 0]);
+        startup.extend_from_slice(body);
+        client.write_all(&startup).await.unwrap();
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .expect("probe did not close the connection")
+            .unwrap();
+        probe.await.unwrap().unwrap();
+
+        assert_eq!(received[0], b'E');
+        assert!(String::from_utf8_lossy(&received).contains(PROBE_REJECTED));
+        let Ok(ProxyMessage::Warning {
+            conn_id: 3,
+            message,
+        }) = rx.try_recv()
+        else {
+            panic!("expected the probe summary");
+        };
+        assert_eq!(
+            message,
+            "PROBE user=alice database=app application_name=psql ssl=declined gssenc=not requested"
+        );
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ProxyMessage::Event {
+                event: ProtoEvent::Startup { .. },
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_slow_client_is_reported_without_dropping_data() {
+        let (writer_tx, mut writer_rx) = mpsc::channel::<Bytes>(1);
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let stall = Duration::from_millis(20);
+
+        send_to_client(&writer_tx, Bytes::from_static(b"a"), stall, 7, &events_tx)
+            .await
+            .unwrap();
+        assert!(events_rx.try_recv().is_err());
+
+        // The channel is full until the "client" reads, well after the stall threshold.
+        let reader = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let mut received = Vec::new();
+            while let Some(data) = writer_rx.recv().await {
+                received.extend_from_slice(&data);
+            }
+            received
+        });
+        send_to_client(&writer_tx, Bytes::from_static(b"b"), stall, 7, &events_tx)
+            .await
+            .unwrap();
+        assert!(matches!(
+            events_rx.try_recv(),
+            Ok(ProxyMessage::ClientBackpressure { conn_id: 7, waited, .. }) if waited == stall
+        ));
+
+        drop(writer_tx);
+        assert_eq!(reader.await.unwrap(), b"ab");
+    }
+
+    #[tokio::test]
+    async fn test_new_connections_follow_upstream_changes() {
+        let (accepted_tx, mut accepted_rx) = mpsc::unbounded_channel();
+        let mut addrs = Vec::new();
+        for name in ["primary", "replica"] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap().to_string());
+            let accepted_tx = accepted_tx.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let _ = accepted_tx.send(name);
+                    tokio::spawn(async move {
+                        let mut rest = Vec::new();
+                        let _ = stream.read_to_end(&mut rest).await;
+                    });
+                }
+            });
+        }
+
+        let listen_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let upstream: SharedUpstream = Arc::new(RwLock::new(addrs[0].clone()));
+        let ready = Readiness::default();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (proxy_upstream, proxy_ready) = (upstream.clone(), ready.clone());
+        tokio::spawn(async move {
+            run_proxy(
+                &listen_addr.to_string(),
+                proxy_upstream,
+                proxy_ready,
+                ProxyOptions::default(),
+                tx,
+            )
+            .await
+        });
+        while !ready.load(Ordering::Relaxed) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let _first = TcpStream::connect(listen_addr).await.unwrap();
+        assert_eq!(accepted_rx.recv().await, Some("primary"));
+
+        assert_eq!(set_upstream(&upstream, &addrs[1]), Ok(addrs[0].clone()));
+        let _second = TcpStream::connect(listen_addr).await.unwrap();
+        assert_eq!(accepted_rx.recv().await, Some("replica"));
+
+        assert!(set_upstream(&upstream, "replica").is_err());
+        assert!(set_upstream(&upstream, ":5432").is_err());
+        assert_eq!(*upstream.read().unwrap(), addrs[1]);
+    }
 }
@@ -0,0 +1,829 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+use crate::capture::{CaptureDecoder, Frame, FrameKind};
+use crate::fingerprint::{fingerprint, operation, split_statements, Operation};
+use crate::output::{DisplayEvent, DisplayEventKind};
+use crate::protocol::postgres::{
+    PostgresParser, CANCEL_REQUEST_CODE, GSSENC_REQUEST_CODE, SSL_REQUEST_CODE,
+};
+use crate::protocol::Direction;
+use crate::proxy::drain_events;
+use crate::stats::{StatsCollector, StatsConfig};
+
+/// Added to the replayed StartupMessage's `options` unless `--shadow-writes`.
+const READ_ONLY_OPTION: &str = "-c default_transaction_read_only=on";
+
+/// Longest a replayed batch may take to reach ReadyForQuery before its
+/// connection is given up on.
+const BATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How `--shadow` replays a capture.
+#[derive(Clone, Debug)]
+pub struct ShadowOptions {
+    pub upstream: String,
+    /// `--shadow-writes`: also run statements that aren't SELECTs.
+    pub allow_writes: bool,
+}
+
+/// One recorded connection's client traffic, split into what the replay sends.
+#[derive(Debug, Default, PartialEq)]
+struct RecordedConn {
+    startup: Vec<u8>,
+    /// The client's first PasswordMessage, for cleartext password auth.
+    password: Option<Vec<u8>>,
+    batches: Vec<Batch>,
+}
+
+/// Messages sent together and answered by one ReadyForQuery: a simple Query,
+/// or an extended-protocol run up to its Sync.
+#[derive(Debug, Default, PartialEq)]
+struct Batch {
+    bytes: Vec<u8>,
+    /// Statements the batch executes, for the read-only check.
+    statements: Vec<String>,
+}
+
+impl Batch {
+    /// SELECTs only, and none that touch the read-only setting itself. This only
+    /// keeps obvious writes out of the report: a SELECT can still write (a
+    /// data-modifying CTE, SELECT INTO, a volatile function), so the server is
+    /// what enforces read-only, through `READ_ONLY_OPTION`. COPY is never
+    /// replayed: its data would have to be streamed back in step with the server.
+    fn is_read_only(&self) -> bool {
+        self.statements.iter().all(|sql| {
+            operation(sql) == Operation::Select
+                && !sql.to_ascii_lowercase().contains("transaction_read_only")
+        })
+    }
+
+    fn is_copy(&self) -> bool {
+        self.statements.iter().any(|sql| {
+            sql.trim_start()
+                .get(..4)
+                .is_some_and(|w| w.eq_ignore_ascii_case("copy"))
+        })
+    }
+}
+
+/// Completed queries and errors for one fingerprint, on one side of the comparison.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Tally {
+    count: u64,
+    total: Duration,
+    rows: u64,
+    errors: u64,
+}
+
+impl Tally {
+    fn add(&mut self, event: &DisplayEvent) {
+        match &event.kind {
+            DisplayEventKind::Query { duration, rows, .. } => {
+                self.count += 1;
+                self.total += *duration;
+                self.rows += rows.unwrap_or(0);
+            }
+            DisplayEventKind::Error { .. } => self.errors += 1,
+            _ => {}
+        }
+    }
+
+    fn avg_ms(&self) -> f64 {
+        self.total.as_secs_f64() * 1000.0 / self.count.max(1) as f64
+    }
+}
+
+/// Both sides of every fingerprint, plus what the replay left out.
+#[derive(Debug, Default)]
+pub struct ShadowComparison {
+    /// fingerprint -> (recorded, shadow).
+    fingerprints: BTreeMap<String, (Tally, Tally)>,
+    connections: usize,
+    batches_sent: usize,
+    batches_skipped: usize,
+    /// Connections that couldn't be replayed, with the reason.
+    failed: Vec<(u64, String)>,
+}
+
+impl ShadowComparison {
+    /// Fingerprints run the same number of times on both sides whose row totals
+    /// differ, or that errored more often in the shadow run.
+    pub fn mismatches(&self) -> usize {
+        self.fingerprints
+            .values()
+            .filter(|(recorded, shadow)| differs(recorded, shadow))
+            .count()
+    }
+
+    pub fn report(&self, options: &ShadowOptions) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "--- shadow comparison against {} ---",
+            options.upstream
+        );
+        let _ = write!(
+            out,
+            "Replayed {} batches on {} connections",
+            self.batches_sent, self.connections
+        );
+        if self.batches_skipped > 0 {
+            let reason = if options.allow_writes {
+                "COPY"
+            } else {
+                "writes and COPY; --shadow-writes runs writes"
+            };
+            let _ = write!(out, ", skipped {} ({reason})", self.batches_skipped);
+        }
+        let _ = writeln!(out);
+        for (conn_id, reason) in &self.failed {
+            let _ = writeln!(out, "FAILED     conn {conn_id}: {reason}");
+        }
+
+        // Differences first, then the rest by fingerprint.
+        let mut rows: Vec<_> = self
+            .fingerprints
+            .iter()
+            .filter(|(_, (recorded, _))| recorded.count + recorded.errors > 0)
+            .collect();
+        rows.sort_by_key(|(_, (recorded, shadow))| !differs(recorded, shadow));
+        for (fingerprint, (recorded, shadow)) in rows {
+            let status = if shadow.count + shadow.errors == 0 {
+                "NOT RUN"
+            } else if differs(recorded, shadow) {
+                "DIFFERS"
+            } else {
+                "OK"
+            };
+            let change = if recorded.count > 0 && shadow.count > 0 && recorded.avg_ms() > 0.0 {
+                format!(
+                    " ({:+.0}%)",
+                    (shadow.avg_ms() / recorded.avg_ms() - 1.0) * 100.0
+                )
+            } else {
+                String::new()
+            };
+            let errors = if recorded.errors + shadow.errors > 0 {
+                format!("  errors {} -> {}", recorded.errors, shadow.errors)
+            } else {
+                String::new()
+            };
+            let _ = writeln!(
+                out,
+                "{status:<10} avg {:.2}ms -> {:.2}ms{change}  rows {} -> {}{errors}  {fingerprint}",
+                recorded.avg_ms(),
+                shadow.avg_ms(),
+                recorded.rows,
+                shadow.rows,
+            );
+        }
+        out
+    }
+}
+
+fn differs(recorded: &Tally, shadow: &Tally) -> bool {
+    (recorded.count == shadow.count && recorded.count > 0 && recorded.rows != shadow.rows)
+        || shadow.errors > recorded.errors
+}
+
+/// `--shadow`: run the capture's client traffic against the upstream, one
+/// connection at a time, and compare each fingerprint's latency and row counts
+/// with the recording.
+pub async fn run_shadow(
+    frames: Vec<Frame>,
+    options: &ShadowOptions,
+    stats_config: StatsConfig,
+) -> ShadowComparison {
+    let mut comparison = ShadowComparison::default();
+    let mut tally = |side: usize, event: &DisplayEvent, sql: Option<&str>| {
+        let Some(sql) = sql else { return };
+        let entry = comparison.fingerprints.entry(fingerprint(sql)).or_default();
+        if side == 0 {
+            entry.0.add(event)
+        } else {
+            entry.1.add(event)
+        };
+    };
+
+    // The recording, through the same parser and stats as live traffic.
+    let base = Instant::now();
+    let mut decoder = CaptureDecoder::default();
+    let mut recorded = StatsCollector::with_config(stats_config.clone());
+    let mut pending_sql: HashMap<u64, Vec<String>> = HashMap::new();
+    for frame in &frames {
+        for event in decoder.events(frame) {
+            track_sql(&mut pending_sql, frame.conn_id, &event);
+            if let Some(display) = recorded.process_event(frame.conn_id, event, base + frame.offset)
+            {
+                let sql = completed_sql(&mut pending_sql, frame.conn_id, &display);
+                tally(0, &display, sql.as_deref());
+            }
+        }
+    }
+
+    let conns = split_connections(&frames);
+    let mut shadow = StatsCollector::with_config(stats_config);
+    let mut shadow_sql: HashMap<u64, Vec<String>> = HashMap::new();
+    for (conn_id, conn) in conns {
+        comparison.connections += 1;
+        shadow.connection_opened(conn_id, None);
+        let mut on_event =
+            |display: DisplayEvent, sql: Option<String>| tally(1, &display, sql.as_deref());
+        let result = replay_connection(
+            conn_id,
+            &conn,
+            options,
+            &mut shadow,
+            &mut shadow_sql,
+            &mut on_event,
+        )
+        .await;
+        match result {
+            Ok((sent, skipped)) => {
+                comparison.batches_sent += sent;
+                comparison.batches_skipped += skipped;
+            }
+            Err(e) => {
+                warn!("Shadow: conn {conn_id}: {e}");
+                comparison.failed.push((conn_id, e.to_string()));
+            }
+        }
+        shadow.connection_dropped(conn_id);
+        shadow_sql.remove(&conn_id);
+    }
+    comparison
+}
+
+/// Remember the SQL of each query as it starts, so its completion (which only
+/// carries a display string) can be put under the right fingerprint.
+fn track_sql(
+    pending: &mut HashMap<u64, Vec<String>>,
+    conn_id: u64,
+    event: &crate::protocol::ProtoEvent,
+) {
+    if let crate::protocol::ProtoEvent::QueryStart { sql, portal, .. } = event {
+        let statements = if portal.is_none() {
+            split_statements(sql)
+        } else {
+            Vec::new()
+        };
+        let queue = pending.entry(conn_id).or_default();
+        if statements.len() > 1 {
+            queue.extend(statements.into_iter().map(str::to_string));
+        } else {
+            queue.push(sql.clone());
+        }
+    }
+}
+
+fn completed_sql(
+    pending: &mut HashMap<u64, Vec<String>>,
+    conn_id: u64,
+    display: &DisplayEvent,
+) -> Option<String> {
+    match &display.kind {
+        DisplayEventKind::Query { sql, .. } => {
+            let queue = pending.get_mut(&conn_id)?;
+            let position = queue.iter().position(|q| q == sql)?;
+            Some(queue.remove(position))
+        }
+        DisplayEventKind::Error { .. } => {
+            let queue = pending.get_mut(&conn_id)?;
+            (!queue.is_empty()).then(|| queue.remove(0))
+        }
+        _ => None,
+    }
+}
+
+/// Send one recorded connection's batches to the upstream, each once the
+/// previous one's ReadyForQuery is in. Returns (batches sent, batches skipped).
+async fn replay_connection(
+    conn_id: u64,
+    conn: &RecordedConn,
+    options: &ShadowOptions,
+    stats: &mut StatsCollector,
+    pending_sql: &mut HashMap<u64, Vec<String>>,
+    on_event: &mut impl FnMut(DisplayEvent, Option<String>),
+) -> anyhow::Result<(usize, usize)> {
+    let mut stream = tokio::time::timeout(BATCH_TIMEOUT, TcpStream::connect(&options.upstream))
+        .await
+        .map_err(|_| anyhow::anyhow!("connecting to {} timed out", options.upstream))??;
+    let mut parser = PostgresParser::new();
+    let mut frontend = BytesMut::new();
+    let mut backend = BytesMut::new();
+    let mut incoming = BytesMut::new();
+
+    let mut feed =
+        |bytes: &[u8], buf: &mut BytesMut, direction: Direction, stats: &mut StatsCollector| {
+            buf.extend_from_slice(bytes);
+            for event in drain_events(&mut parser, buf, direction) {
+                if direction == Direction::Frontend {
+                    track_sql(pending_sql, conn_id, &event);
+                }
+                if let Some(display) = stats.process_event(conn_id, event, Instant::now()) {
+                    let sql = completed_sql(pending_sql, conn_id, &display);
+                    on_event(display, sql);
+                }
+            }
+        };
+    let startup = if options.allow_writes {
+        conn.startup.clone()
+    } else {
+        read_only_startup(&conn.startup)
+    };
+    feed(&startup, &mut frontend, Direction::Frontend, stats);
+    stream.write_all(&startup).await?;
+
+    // Authentication: trust, or the cleartext password the capture holds.
+    loop {
+        let message = read_message(&mut stream, &mut incoming).await?;
+        feed(&message, &mut backend, Direction::Backend, stats);
+        match (message[0], auth_code(&message)) {
+            (b'R', Some(0)) => {}
+            (b'R', Some(3)) => {
+                let password = conn
+                    .password
+                    .as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("upstream asked for a password and the capture has none"))?;
+                feed(password, &mut frontend, Direction::Frontend, stats);
+                stream.write_all(password).await?;
+            }
+            (b'R', Some(code)) => anyhow::bail!(
+                "upstream asked for {} authentication, which can't be replayed; use a role with trust or password auth",
+                if code == 5 { "MD5" } else if code == 10 { "SCRAM" } else { "unsupported" }
+            ),
+            (b'E', _) => anyhow::bail!("upstream refused the connection: {}", error_message(&message)),
+            (b'Z', _) => break,
+            _ => {}
+        }
+    }
+
+    let (mut sent, mut skipped) = (0, 0);
+    for batch in &conn.batches {
+        if batch.is_copy() || (!options.allow_writes && !batch.is_read_only()) {
+            skipped += 1;
+            continue;
+        }
+        feed(&batch.bytes, &mut frontend, Direction::Frontend, stats);
+        stream.write_all(&batch.bytes).await?;
+        sent += 1;
+
+        let answered = async {
+            loop {
+                let message = read_message(&mut stream, &mut incoming).await?;
+                let ready = message[0] == b'Z';
+                feed(&message, &mut backend, Direction::Backend, stats);
+                if ready {
+                    return anyhow::Ok(());
+                }
+            }
+        };
+        tokio::time::timeout(BATCH_TIMEOUT, answered)
+            .await
+            .map_err(|_| anyhow::anyhow!("no answer within {}s", BATCH_TIMEOUT.as_secs()))??;
+    }
+
+    // Terminate.
+    let _ = stream.write_all(&[b'X', 0, 0, 0, 4]).await;
+    Ok((sent, skipped))
+}
+
+/// `startup` with `READ_ONLY_OPTION` added to its `options` parameter, so every
+/// transaction on the connection is read-only and the server refuses writes.
+fn read_only_startup(startup: &[u8]) -> Vec<u8> {
+    let (Some(version), Some(params)) = (startup.get(4..8), startup.get(8..)) else {
+        return startup.to_vec();
+    };
+    let mut body = version.to_vec();
+    let mut has_options = false;
+    let mut fields = params.split(|&b| b == 0);
+    while let Some(key) = fields.next().filter(|key| !key.is_empty()) {
+        let value = fields.next().unwrap_or_default();
+        body.extend_from_slice(key);
+        body.push(0);
+        body.extend_from_slice(value);
+        if key == b"options" {
+            has_options = true;
+            if !value.is_empty() {
+                body.push(b' ');
+            }
+            body.extend_from_slice(READ_ONLY_OPTION.as_bytes());
+        }
+        body.push(0);
+    }
+    if !has_options {
+        body.extend_from_slice(b"options\0");
+        body.extend_from_slice(READ_ONLY_OPTION.as_bytes());
+        body.push(0);
+    }
+    body.push(0);
+    let mut packet = (body.len() as u32 + 4).to_be_bytes().to_vec();
+    packet.extend(body);
+    packet
+}
+
+/// Next whole tagged message from the server, tag and length included.
+async fn read_message(stream: &mut TcpStream, buf: &mut BytesMut) -> anyhow::Result<Vec<u8>> {
+    loop {
+        if buf.len() >= 5 {
+            let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+            if buf.len() > len {
+                return Ok(buf.split_to(len + 1).to_vec());
+            }
+        }
+        if stream.read_buf(buf).await? == 0 {
+            anyhow::bail!("upstream closed the connection");
+        }
+    }
+}
+
+/// The request code of an Authentication message.
+fn auth_code(message: &[u8]) -> Option<u32> {
+    message
+        .get(5..9)
+        .map(|code| u32::from_be_bytes([code[0], code[1], code[2], code[3]]))
+}
+
+/// The M field of an ErrorResponse.
+fn error_message(message: &[u8]) -> String {
+    message
+        .get(5..)
+        .unwrap_or_default()
+        .split(|&b| b == 0)
+        .find_map(|field| field.strip_prefix(b"M"))
+        .map(|m| String::from_utf8_lossy(m).into_owned())
+        .unwrap_or_default()
+}
+
+/// Each recorded connection's client traffic, in the order the connections opened.
+fn split_connections(frames: &[Frame]) -> Vec<(u64, RecordedConn)> {
+    let mut order = Vec::new();
+    let mut streams: HashMap<u64, Vec<u8>> = HashMap::new();
+    for frame in frames {
+        match frame.kind {
+            FrameKind::Opened => order.push(frame.conn_id),
+            FrameKind::Frontend => streams
+                .entry(frame.conn_id)
+                .or_default()
+                .extend_from_slice(&frame.data),
+            FrameKind::Backend | FrameKind::Closed => {}
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|conn_id| {
+            let conn = split_frontend(streams.get(&conn_id)?)?;
+            Some((conn_id, conn))
+        })
+        .collect()
+}
+
+/// Split a client's byte stream into its startup packet, password and batches.
+/// None for a connection with no startup packet (a cancel request, or cut off).
+fn split_frontend(data: &[u8]) -> Option<RecordedConn> {
+    let mut rest = data;
+    let mut conn = RecordedConn::default();
+    // Untagged startup-phase packets: SSL and GSSAPI requests come first and were declined.
+    loop {
+        let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let code = u32::from_be_bytes(rest.get(4..8)?.try_into().ok()?);
+        // Shorter than its own header: a corrupt capture, and `rest` would never advance.
+        if len < 8 {
+            return None;
+        }
+        let packet = rest.get(..len)?;
+        rest = &rest[len..];
+        match code {
+            SSL_REQUEST_CODE | GSSENC_REQUEST_CODE => continue,
+            CANCEL_REQUEST_CODE => return None,
+            _ => {
+                conn.startup = packet.to_vec();
+                break;
+            }
+        }
+    }
+
+    let mut statements: HashMap<String, String> = HashMap::new();
+    let mut portals: HashMap<String, String> = HashMap::new();
+    let mut batch = Batch::default();
+    while rest.len() >= 5 {
+        let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+        if len < 4 {
+            break;
+        }
+        let Some(message) = rest.get(..len + 1) else {
+            break;
+        };
+        rest = &rest[len + 1..];
+        let (tag, payload) = (message[0], &message[5..]);
+        let mut strings = payload
+            .split(|&b| b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned());
+        match tag {
+            b'p' => {
+                conn.password.get_or_insert_with(|| message.to_vec());
+            }
+            b'X' => {}
+            b'Q' => {
+                let sql = strings.next().unwrap_or_default();
+                let statements = split_statements(&sql)
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect();
+                conn.batches.push(Batch {
+                    bytes: message.to_vec(),
+                    statements,
+                });
+            }
+            _ => {
+                match tag {
+                    b'P' => {
+                        let (name, sql) = (
+                            strings.next().unwrap_or_default(),
+                            strings.next().unwrap_or_default(),
+                        );
+                        statements.insert(name, sql);
+                    }
+                    b'B' => {
+                        let (portal, stmt) = (
+                            strings.next().unwrap_or_default(),
+                            strings.next().unwrap_or_default(),
+                        );
+                        portals.insert(portal, stmt);
+                    }
+                    b'E' => {
+                        let portal = strings.next().unwrap_or_default();
+                        let sql = portals
+                            .get(&portal)
+                            .and_then(|stmt| statements.get(stmt))
+                            .cloned()
+                            .unwrap_or_default();
+                        batch.statements.push(sql);
+                    }
+                    _ => {}
+                }
+                batch.bytes.extend_from_slice(message);
+                if tag == b'S' {
+                    conn.batches.push(std::mem::take(&mut batch));
+                }
+            }
+        }
+    }
+    Some(conn)
+}
+
+/// Log what is about to happen, before any statement reaches the upstream.
+pub fn announce(path: &std::path::Path, options: &ShadowOptions) {
+    if options.allow_writes {
+        warn!(
+            "Shadow: replaying every recorded statement from {} against {}, INCLUDING WRITES",
+            path.display(),
+            options.upstream
+        );
+    } else {
+        info!(
+            "Shadow: replaying the SELECTs recorded in {} against {} (read-only; --shadow-writes runs writes too)",
+            path.display(),
+            options.upstream
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn message(tag: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![tag];
+        buf.extend_from_slice(&(payload.len() as u32 + 4).to_be_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    fn startup() -> Vec<u8> {
+        let mut body = 196_608u32.to_be_bytes().to_vec();
+        body.extend_from_slice(b"user\0app\0\0");
+        let mut packet = (body.len() as u32 + 4).to_be_bytes().to_vec();
+        packet.extend(body);
+        packet
+    }
+
+    /// A recorded SELECT returning 3 rows and a DELETE, each taking 10ms.
+    fn recording() -> Vec<Frame> {
+        recording_of(&[
+            ("SELECT * FROM users", "SELECT 3"),
+            ("DELETE FROM users", "DELETE 3"),
+        ])
+    }
+
+    /// One connection running each (sql, command tag) in turn, 10ms apiece.
+    fn recording_of(queries: &[(&str, &str)]) -> Vec<Frame> {
+        let mut seq = 0;
+        let mut frame = |kind, ms, data: Vec<u8>| {
+            seq += 1;
+            Frame {
+                kind,
+                seq,
+                conn_id: 1,
+                offset: Duration::from_millis(ms),
+                data,
+            }
+        };
+        let mut ready = message(b'R', &0u32.to_be_bytes());
+        ready.extend(message(b'Z', b"I"));
+        let answer = |tag: &[u8]| {
+            let mut bytes = message(b'C', tag);
+            bytes.extend(message(b'Z', b"I"));
+            bytes
+        };
+        let mut frames = vec![
+            frame(FrameKind::Opened, 0, b"127.0.0.1:40000".to_vec()),
+            frame(FrameKind::Frontend, 0, startup()),
+            frame(FrameKind::Backend, 1, ready),
+        ];
+        let mut ms = 10;
+        for (sql, tag) in queries {
+            frames.push(frame(
+                FrameKind::Frontend,
+                ms,
+                message(b'Q', format!("{sql}\0").as_bytes()),
+            ));
+            frames.push(frame(
+                FrameKind::Backend,
+                ms + 10,
+                answer(format!("{tag}\0").as_bytes()),
+            ));
+            ms += 20;
+        }
+        frames.push(frame(FrameKind::Frontend, ms, message(b'X', b"")));
+        frames.push(frame(FrameKind::Closed, ms, Vec::new()));
+        frames
+    }
+
+    /// A stand-in upstream: trust auth, and every query returns 2 rows. Like
+    /// Postgres, it refuses writes when the startup asks for read-only
+    /// transactions. Returns the startup packet and the queries it ran.
+    fn fake_upstream(listener: TcpListener) -> tokio::task::JoinHandle<(Vec<u8>, Vec<String>)> {
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = BytesMut::new();
+            while buf.len() < 4
+                || buf.len() < u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize
+            {
+                stream.read_buf(&mut buf).await.unwrap();
+            }
+            let startup = buf
+                .split_to(u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize)
+                .to_vec();
+            let read_only = startup
+                .windows(READ_ONLY_OPTION.len())
+                .any(|w| w == READ_ONLY_OPTION.as_bytes());
+            let mut ready = message(b'R', &0u32.to_be_bytes());
+            ready.extend(message(b'Z', b"I"));
+            stream.write_all(&ready).await.unwrap();
+
+            let mut queries = Vec::new();
+            loop {
+                let message_bytes = match read_message(&mut stream, &mut buf).await {
+                    Ok(m) => m,
+                    Err(_) => break,
+                };
+                match message_bytes[0] {
+                    b'Q' => {
+                        let sql =
+                            String::from_utf8_lossy(&message_bytes[5..message_bytes.len() - 1])
+                                .into_owned();
+                        let mut answer = if read_only
+                            && ["DELETE", "INSERT", "UPDATE"]
+                                .iter()
+                                .any(|w| sql.contains(w))
+                        {
+                            message(b'E', b"SERROR\0C25006\0Mcannot execute DELETE in a read-only transaction\0\0")
+                        } else {
+                            queries.push(sql);
+                            message(b'C', b"SELECT 2\0")
+                        };
+                        answer.extend(message(b'Z', b"I"));
+                        stream.write_all(&answer).await.unwrap();
+                    }
+                    b'X' => break,
+                    _ => {}
+                }
+            }
+            (startup, queries)
+        })
+    }
+
+    #[test]
+    fn test_split_frontend_batches_extended_queries() {
+        let mut data = 8u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&SSL_REQUEST_CODE.to_be_bytes());
+        data.extend(startup());
+        data.extend(message(b'P', b"s1\0UPDATE t SET x = $1\0\0\0"));
+        data.extend(message(b'B', b"\0s1\0\0\0\0\0\0\0"));
+        data.extend(message(b'E', b"\0\0\0\0\0"));
+        data.extend(message(b'S', b""));
+        data.extend(message(b'Q', b"SELECT 1; SELECT 2\0"));
+
+        let conn = split_frontend(&data).unwrap();
+        assert_eq!(conn.startup, startup());
+        assert_eq!(conn.batches.len(), 2);
+        assert_eq!(conn.batches[0].statements, ["UPDATE t SET x = $1"]);
+        assert!(!conn.batches[0].is_read_only());
+        assert_eq!(conn.batches[1].statements, ["SELECT 1", "SELECT 2"]);
+        assert!(conn.batches[1].is_read_only());
+    }
+
+    #[test]
+    fn test_split_frontend_stops_at_malformed_lengths() {
+        // An SSLRequest claiming length 0 would otherwise be skipped forever.
+        let mut data = 0u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&SSL_REQUEST_CODE.to_be_bytes());
+        data.extend(startup());
+        assert!(split_frontend(&data).is_none());
+
+        // A tagged message shorter than its length field ends the parse, keeping what came before.
+        let mut data = startup();
+        data.extend(message(b'Q', b"SELECT 1\0"));
+        data.extend_from_slice(&[b'Q', 0, 0, 0, 2, b'x']);
+        data.extend(message(b'Q', b"SELECT 2\0"));
+        let conn = split_frontend(&data).unwrap();
+        assert_eq!(conn.batches.len(), 1);
+        assert_eq!(conn.batches[0].statements, ["SELECT 1"]);
+    }
+
+    #[tokio::test]
+    async fn test_shadow_skips_writes_and_flags_row_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap().to_string();
+        let server = fake_upstream(listener);
+
+        let options = ShadowOptions {
+            upstream,
+            allow_writes: false,
+        };
+        let comparison = run_shadow(recording(), &options, StatsConfig::default()).await;
+        assert_eq!(server.await.unwrap().1, ["SELECT * FROM users"]);
+
+        assert_eq!(
+            (comparison.batches_sent, comparison.batches_skipped),
+            (1, 1)
+        );
+        assert_eq!(comparison.mismatches(), 1);
+        let report = comparison.report(&options);
+        assert!(report.contains("skipped 1 (writes and COPY"), "{report}");
+        assert!(report.contains("DIFFERS    avg 10.00ms"), "{report}");
+        assert!(
+            report.contains("rows 3 -> 2  select * from users"),
+            "{report}"
+        );
+        assert!(report.contains("NOT RUN"), "{report}");
+    }
+
+    #[tokio::test]
+    async fn test_shadow_asks_the_server_for_read_only_transactions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap().to_string();
+        let server = fake_upstream(listener);
+
+        // Looks like a SELECT, deletes every row.
+        let cte = "WITH d AS (DELETE FROM users RETURNING *) SELECT * FROM d";
+        let options = ShadowOptions {
+            upstream,
+            allow_writes: false,
+        };
+        let comparison = run_shadow(
+            recording_of(&[(cte, "SELECT 3")]),
+            &options,
+            StatsConfig::default(),
+        )
+        .await;
+        let (startup, executed) = server.await.unwrap();
+        assert!(executed.is_empty(), "{executed:?}");
+        assert!(startup.ends_with(b"user\0app\0options\0-c default_transaction_read_only=on\0\0"));
+        assert_eq!(comparison.batches_sent, 1);
+        assert!(comparison.report(&options).contains("errors 0 -> 1"));
+
+        // A client's own options are kept.
+        let mut body = 196_608u32.to_be_bytes().to_vec();
+        body.extend_from_slice(b"options\0-c search_path=app\0\0");
+        let mut packet = (body.len() as u32 + 4).to_be_bytes().to_vec();
+        packet.extend(body);
+        assert!(read_only_startup(&packet)
+            .ends_with(b"options\0-c search_path=app -c default_transaction_read_only=on\0\0"));
+        assert!(!Batch {
+            bytes: Vec::new(),
+            statements: vec![
+                "SELECT set_config('default_transaction_read_only', 'off', false)".into()
+            ]
+        }
+        .is_read_only());
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::round_ms;
+use crate::percentile::LatencyHistogram;
+use crate::protocol::Direction;
+use crate::stats::{aggregate_key, MessageCounts, QueryAggregates};
+
+/// Current snapshot format. Version 1 is the original, unversioned layout.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// What the TUI saves with `s` and imports with `i`; also read by `--baseline`
+/// and `dbprobe merge`. Missing fields fall back to defaults so older captures
+/// keep importing as fields are added; `migrate_snapshot` handles anything
+/// needing conversion.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub version: u32,
+    pub timestamp: String,
+    pub total_queries: u64,
+    pub total_errors: u64,
+    pub active_connections: u64,
+    /// Connections opened per second when saved; only the live tab has a current rate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conn_churn: Option<f64>,
+    /// Most queries in flight at once; merging keeps the highest.
+    pub max_in_flight: u64,
+    /// Distinct application_names and client IPs, sorted.
+    pub applications: Vec<String>,
+    pub client_hosts: Vec<String>,
+    /// Wire messages by type and direction.
+    pub message_counts: MessageCounts,
+    pub latency_buckets: LatencyBuckets,
+    pub top_queries: Vec<SnapshotQuery>,
+    pub recent_events: Vec<SnapshotEvent>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatencyBuckets {
+    pub under_1ms: u64,
+    pub ms_1_5: u64,
+    pub ms_5_10: u64,
+    pub ms_10_50: u64,
+    pub ms_50_100: u64,
+    pub over_100ms: u64,
+}
+
+impl LatencyBuckets {
+    pub fn from_array(buckets: &[u64; 6]) -> Self {
+        let [under_1ms, ms_1_5, ms_5_10, ms_10_50, ms_50_100, over_100ms] = *buckets;
+        Self {
+            under_1ms,
+            ms_1_5,
+            ms_5_10,
+            ms_10_50,
+            ms_50_100,
+            over_100ms,
+        }
+    }
+
+    /// In `StatsCollector::latency_buckets` order.
+    pub fn to_array(&self) -> [u64; 6] {
+        [
+            self.under_1ms,
+            self.ms_1_5,
+            self.ms_5_10,
+            self.ms_10_50,
+            self.ms_50_100,
+            self.over_100ms,
+        ]
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotQuery {
+    pub fingerprint: String,
+    /// Set when the run used `--group-by`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub count: u64,
+    pub avg_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    /// None in snapshots saved before it was kept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_ms: Option<f64>,
+}
+
+impl SnapshotQuery {
+    pub fn from_aggregates(q: QueryAggregates, precision: u32) -> Self {
+        let avg = if q.count > 0 {
+            q.total_duration.div_f64(q.count as f64)
+        } else {
+            Duration::ZERO
+        };
+        Self {
+            fingerprint: q.fingerprint,
+            group: q.group,
+            count: q.count,
+            avg_ms: round_ms(avg, precision),
+            min_ms: round_ms(q.min_duration, precision),
+            max_ms: round_ms(q.max_duration, precision),
+            p95_ms: q
+                .latencies
+                .percentile(0.95)
+                .map(|p95| round_ms(p95, precision)),
+        }
+    }
+
+    /// Aggregates as far as the snapshot can tell: the total is rebuilt from the
+    /// rounded average, and the latencies behind `p95_ms` are gone.
+    pub fn to_aggregates(&self) -> QueryAggregates {
+        QueryAggregates {
+            fingerprint: self.fingerprint.clone(),
+            group: self.group.clone(),
+            count: self.count,
+            total_duration: Duration::from_secs_f64(self.avg_ms * self.count as f64 / 1000.0),
+            min_duration: Duration::from_secs_f64(self.min_ms / 1000.0),
+            max_duration: Duration::from_secs_f64(self.max_ms / 1000.0),
+            columns: None,
+            result_format: None,
+            max_sql_bytes: 0,
+            latencies: LatencyHistogram::default(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotEvent {
+    /// Time of day, `HH:MM:SS.mmm`.
+    pub time: String,
+    pub conn_id: u64,
+    pub latency: String,
+    pub message: String,
+    /// For queries, `message` in parts, so an import doesn't have to re-split it.
+    /// Absent in snapshots from before they were stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sql: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub params: String,
+    /// As shown after the SQL: " [3]", " [3 rows, 40.0MB]".
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub rows_suffix: String,
+}
+
+/// Parse a snapshot of any supported version and bring it up to `SNAPSHOT_VERSION`.
+pub fn migrate_snapshot(content: &str) -> Result<Snapshot, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(content).map_err(|e| format!("invalid JSON: {e}"))?;
+    // Snapshots written before versioning have no field at all.
+    let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(1) as u32;
+    if version > SNAPSHOT_VERSION {
+        return Err(format!(
+            "snapshot format v{version} is newer than this dbprobe supports (v{SNAPSHOT_VERSION}) — upgrade dbprobe"
+        ));
+    }
+
+    let mut snapshot: Snapshot =
+        serde_json::from_value(value).map_err(|e| format!("invalid snapshot: {e}"))?;
+    // v1 -> v2: only the version field was added; defaults cover it.
+    snapshot.version = SNAPSHOT_VERSION;
+    Ok(snapshot)
+}
+
+/// Whether `path` holds a snapshot rather than a `--record` capture.
+pub fn is_snapshot_file(path: &Path) -> bool {
+    std::fs::read(path)
+        .is_ok_and(|content| content.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{'))
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading snapshot {}: {e}", path.display()))?;
+        migrate_snapshot(&content).map_err(|e| anyhow::anyhow!("snapshot {}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    /// One snapshot covering all of `snapshots`, e.g. one per shard: counters and
+    /// buckets are summed, fingerprints combined and recent events interleaved by
+    /// time of day. Each input only carries its own top queries, so a fingerprint
+    /// outside some input's top list is undercounted.
+    pub fn merge(snapshots: Vec<Snapshot>, precision: u32) -> Snapshot {
+        let mut merged = Snapshot {
+            version: SNAPSHOT_VERSION,
+            timestamp: chrono::Local::now().to_rfc3339(),
+            ..Snapshot::default()
+        };
+        let mut buckets = [0u64; 6];
+        let mut applications = BTreeSet::new();
+        let mut client_hosts = BTreeSet::new();
+        let mut fingerprints: HashMap<String, QueryAggregates> = HashMap::new();
+        // The p95 of combined inputs is at most the largest of theirs.
+        let mut p95s: HashMap<String, f64> = HashMap::new();
+
+        for snapshot in snapshots {
+            merged.total_queries += snapshot.total_queries;
+            merged.total_errors += snapshot.total_errors;
+            merged.active_connections += snapshot.active_connections;
+            merged.max_in_flight = merged.max_in_flight.max(snapshot.max_in_flight);
+            if let Some(churn) = snapshot.conn_churn {
+                *merged.conn_churn.get_or_insert(0.0) += churn;
+            }
+            for (sum, n) in buckets.iter_mut().zip(snapshot.latency_buckets.to_array()) {
+                *sum += n;
+            }
+            applications.extend(snapshot.applications);
+            client_hosts.extend(snapshot.client_hosts);
+            for (direction, counts) in [
+                (Direction::Frontend, &snapshot.message_counts.frontend),
+                (Direction::Backend, &snapshot.message_counts.backend),
+            ] {
+                for (name, &count) in counts {
+                    merged.message_counts.add(direction, name, count);
+                }
+            }
+            for q in &snapshot.top_queries {
+                if let Some(p95) = q.p95_ms {
+                    let max = p95s
+                        .entry(aggregate_key(&q.fingerprint, q.group.as_deref()))
+                        .or_insert(p95);
+                    *max = max.max(p95);
+                }
+                let q = q.to_aggregates();
+                match fingerprints.get_mut(&aggregate_key(&q.fingerprint, q.group.as_deref())) {
+                    Some(agg) => {
+                        agg.count += q.count;
+                        agg.total_duration += q.total_duration;
+                        agg.min_duration = agg.min_duration.min(q.min_duration);
+                        agg.max_duration = agg.max_duration.max(q.max_duration);
+                    }
+                    None => {
+                        fingerprints.insert(aggregate_key(&q.fingerprint, q.group.as_deref()), q);
+                    }
+                }
+            }
+            merged.recent_events.extend(snapshot.recent_events);
+        }
+
+        merged.latency_buckets = LatencyBuckets::from_array(&buckets);
+        merged.applications = applications.into_iter().collect();
+        merged.client_hosts = client_hosts.into_iter().collect();
+        let mut queries: Vec<QueryAggregates> = fingerprints.into_values().collect();
+        queries.sort_unstable_by_key(|q| std::cmp::Reverse(q.total_duration));
+        merged.top_queries = queries
+            .into_iter()
+            .map(|q| {
+                let p95_ms = p95s
+                    .get(&aggregate_key(&q.fingerprint, q.group.as_deref()))
+                    .copied();
+                SnapshotQuery {
+                    p95_ms,
+                    ..SnapshotQuery::from_aggregates(q, precision)
+                }
+            })
+            .collect();
+        // Stable, so events at the same instant keep their per-input order.
+        merged.recent_events.sort_by(|a, b| a.time.cmp(&b.time));
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1_SNAPSHOT: &str = r#"{
+        "timestamp": "2025-01-01T00:00:00+00:00",
+        "total_queries": 3,
+        "total_errors": 1,
+        "active_connections": 2,
+        "latency_buckets": {
+            "under_1ms": 1, "ms_1_5": 2, "ms_5_10": 0,
+            "ms_10_50": 0, "ms_50_100": 0, "over_100ms": 0
+        },
+        "top_queries": [
+            { "fingerprint": "select $n", "count": 3, "avg_ms": 1.5, "min_ms": 0.5, "max_ms": 2.5 }
+        ],
+        "recent_events": [
+            { "time": "00:00:00.000", "conn_id": 7, "latency": "1.5ms", "message": "SELECT 1 [1]" }
+        ]
+    }"#;
+
+    #[test]
+    fn test_migrate_v1_snapshot() {
+        let snapshot = migrate_snapshot(V1_SNAPSHOT).unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.total_queries, 3);
+        assert_eq!(snapshot.latency_buckets.ms_1_5, 2);
+        assert_eq!(snapshot.top_queries[0].fingerprint, "select $n");
+        assert_eq!(snapshot.top_queries[0].p95_ms, None);
+        assert_eq!(snapshot.recent_events[0].conn_id, 7);
+    }
+
+    #[test]
+    fn test_migrate_tolerates_missing_fields() {
+        let snapshot = migrate_snapshot(r#"{ "version": 2, "total_queries": 5 }"#).unwrap();
+        assert_eq!(snapshot.total_queries, 5);
+        assert!(snapshot.top_queries.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_version() {
+        let err = migrate_snapshot(r#"{ "version": 999 }"#).err().unwrap();
+        assert!(err.contains("newer"), "{err}");
+    }
+
+    #[test]
+    fn test_merge_combines_overlapping_fingerprints() {
+        let shard = |queries: Vec<(&str, u64, f64, f64, f64)>,
+                     buckets,
+                     events: Vec<(&str, u64)>,
+                     host: &str| {
+            let mut message_counts = MessageCounts::default();
+            message_counts.add(
+                Direction::Frontend,
+                "Query",
+                queries.iter().map(|q| q.1).sum(),
+            );
+            Snapshot {
+                total_queries: queries.iter().map(|q| q.1).sum(),
+                total_errors: 1,
+                active_connections: 2,
+                client_hosts: vec![host.to_string()],
+                message_counts,
+                latency_buckets: LatencyBuckets::from_array(&buckets),
+                top_queries: queries
+                    .into_iter()
+                    .map(
+                        |(fingerprint, count, avg_ms, min_ms, max_ms)| SnapshotQuery {
+                            fingerprint: fingerprint.to_string(),
+                            count,
+                            avg_ms,
+                            min_ms,
+                            max_ms,
+                            group: None,
+                            p95_ms: Some(max_ms),
+                        },
+                    )
+                    .collect(),
+                recent_events: events
+                    .into_iter()
+                    .map(|(time, conn_id)| SnapshotEvent {
+                        time: time.into(),
+                        conn_id,
+                        ..SnapshotEvent::default()
+                    })
+                    .collect(),
+                ..Snapshot::default()
+            }
+        };
+        let a = shard(
+            vec![
+                ("select $n", 3, 2.0, 1.0, 4.0),
+                ("update t", 1, 50.0, 50.0, 50.0),
+            ],
+            [1, 2, 0, 0, 1, 0],
+            vec![("10:00:00.000", 1), ("10:00:02.000", 1)],
+            "10.0.0.1",
+        );
+        let b = shard(
+            vec![("select $n", 1, 6.0, 6.0, 6.0)],
+            [0, 0, 1, 0, 0, 0],
+            vec![("10:00:01.000", 9)],
+            "10.0.0.2",
+        );
+
+        let merged = Snapshot::merge(vec![a, b], 3);
+        assert_eq!(merged.total_queries, 5);
+        assert_eq!(merged.total_errors, 2);
+        assert_eq!(merged.active_connections, 4);
+        assert_eq!(merged.client_hosts, ["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(merged.latency_buckets.to_array(), [1, 2, 1, 0, 1, 0]);
+        assert_eq!(merged.message_counts.frontend["Query"], 5);
+
+        // Sorted by total time: update (50ms) before select (3×2 + 6 = 12ms).
+        let fingerprints: Vec<_> = merged
+            .top_queries
+            .iter()
+            .map(|q| q.fingerprint.as_str())
+            .collect();
+        assert_eq!(fingerprints, ["update t", "select $n"]);
+        let select = &merged.top_queries[1];
+        assert_eq!(
+            (select.count, select.avg_ms, select.min_ms, select.max_ms),
+            (4, 3.0, 1.0, 6.0)
+        );
+        // The largest input p95 bounds the combined one.
+        assert_eq!(select.p95_ms, Some(6.0));
+
+        let conns: Vec<u64> = merged.recent_events.iter().map(|e| e.conn_id).collect();
+        assert_eq!(conns, [1, 9, 1]);
+    }
+}
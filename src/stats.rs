@@ -1,53 +1,463 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::fingerprint::fingerprint;
+use crate::fingerprint::{fingerprint, normalize_error, operation, selects_star, split_statements};
 use crate::output::{DisplayEvent, DisplayEventKind};
-use crate::protocol::{ProtoEvent, TxStatus};
+use crate::percentile::LatencyHistogram;
+use crate::protocol::postgres::type_name;
+use crate::protocol::{Direction, ProtoEvent, ResultFormat, TxStatus};
+use crate::tracecontext::trace_id;
+use crate::truncate::truncate;
+
+/// Tunables for aggregation, set once from the CLI.
+#[derive(Clone, Debug)]
+pub struct StatsConfig {
+    /// Sliding window over which `qps()` averages completions.
+    pub qps_window: Duration,
+    /// Transactions open longer than this (BEGIN to COMMIT/ROLLBACK) are flagged.
+    pub slow_tx_threshold: Duration,
+    /// `SELECT *` results at least this many columns wide are flagged.
+    pub wide_result_columns: u16,
+    /// Results at least this many bytes on the wire are flagged.
+    pub large_result_bytes: u64,
+    /// Queries whose SQL is at least this many bytes are flagged.
+    pub large_query_bytes: usize,
+    /// Report fingerprints that appear, or stop appearing, relative to this trailing window.
+    pub shape_window: Option<Duration>,
+    /// `--window`: reset the aggregates on this schedule.
+    pub window: Option<Duration>,
+    /// `--allowed-fingerprints`: the approved query shapes; anything else is flagged.
+    pub allowed_fingerprints: Option<Arc<HashSet<String>>>,
+    /// `--group-by`: what, besides the fingerprint, splits the query aggregates.
+    pub group_by: GroupBy,
+    /// `--churn-threshold`: connections opened per second (over `qps_window`) past
+    /// which a missing connection pooler is suspected.
+    pub churn_threshold: u32,
+    /// `--no-sql`: queries are timed but their SQL isn't kept, so nothing is
+    /// aggregated by fingerprint.
+    pub no_sql: bool,
+}
+
+/// How queries are bucketed into `QueryAggregates`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// One bucket per query shape.
+    #[default]
+    Fingerprint,
+    /// Per shape and `application_name`, to compare apps running the same query.
+    #[value(name = "fingerprint+app")]
+    FingerprintApp,
+    /// Per shape and connection.
+    #[value(name = "fingerprint+conn")]
+    FingerprintConn,
+}
+
+/// Map key of a query aggregate: the fingerprint, plus its group when grouping.
+pub fn aggregate_key(fingerprint: &str, group: Option<&str>) -> String {
+    match group {
+        Some(group) => format!("{fingerprint} [{group}]"),
+        None => fingerprint.to_string(),
+    }
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            qps_window: Duration::from_secs(1),
+            slow_tx_threshold: Duration::from_secs(1),
+            wide_result_columns: 50,
+            large_result_bytes: 10 * 1024 * 1024,
+            large_query_bytes: 64 * 1024,
+            shape_window: None,
+            window: None,
+            allowed_fingerprints: None,
+            group_by: GroupBy::Fingerprint,
+            churn_threshold: 50,
+            no_sql: false,
+        }
+    }
+}
 
 pub struct StatsCollector {
+    config: StatsConfig,
     connections: HashMap<u64, ConnState>,
     pub fingerprints: HashMap<String, QueryAggregates>,
-    pub latency_buckets: [u64; 6], // <1ms, 1-5, 5-10, 10-50, 50-100, 100+
+    /// Errors grouped by SQLSTATE + normalized message.
+    pub errors: HashMap<String, ErrorAggregates>,
+    pub latency_buckets: [u64; 6], // ≤1ms, ≤5, ≤10, ≤50, ≤100, 100+
+    /// Every latency, for percentiles the fixed buckets are too coarse for.
+    pub latency_histogram: LatencyHistogram,
+    /// Completed queries by SQL size: <1KB, 1-8KB, 8-64KB, 64KB-1MB, 1MB+.
+    pub sql_size_buckets: [u64; 5],
+    /// Latencies of every completed query added up, for the metrics histogram's `_sum`.
+    pub latency_sum: Duration,
     pub total_queries: u64,
     pub total_errors: u64,
+    /// Queries still waiting for CommandComplete when their connection went away.
+    pub abandoned_queries: u64,
+    /// Times server data stalled behind a client too slow to read it.
+    pub backpressure_stalls: u64,
+    /// Queries by fingerprint that `--allowed-fingerprints` doesn't list.
+    pub unexpected_shapes: HashMap<String, u64>,
+    /// Connections labeled as a pooler's server connections (see `POOLER_RESETS`).
+    pub pooled_connections: u64,
     pub active_connections: u64,
+    /// Queries sent and not yet answered, across all connections. Pipelined
+    /// Executes and each statement of a multi-statement Query count separately.
+    pub in_flight: u64,
+    /// Highest `in_flight` seen since the last reset.
+    pub max_in_flight: u64,
+    /// Distinct non-empty `application_name`s seen in startup messages.
+    pub applications: HashSet<String>,
+    /// Distinct client IPs seen at accept time.
+    pub client_hosts: HashSet<IpAddr>,
+    pub message_counts: MessageCounts,
     qps_window: VecDeque<Instant>,
+    /// Start or last reset: before a full `qps_window` has passed, `qps()` divides by the time since.
+    qps_since: Instant,
+    /// Connection opens within the last `qps_window`, for `conn_churn()`.
+    churn_window: VecDeque<Instant>,
+    /// Last churn warning, for `CHURN_WARN_INTERVAL`.
+    churn_warned_at: Option<Instant>,
     pub first_query_at: Option<Instant>,
     pub last_query_at: Option<Instant>,
+    shapes: Option<ShapeTracker>,
+    /// Start of the current `--window`. Manual resets don't move it.
+    window_started: Instant,
+}
+
+/// How often `ShapeTracker` looks for fingerprints that went quiet.
+const SHAPE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Rolling set of fingerprints seen within the last `window`, for `--shape-window`.
+/// Nothing counts as new until a full window has been observed, so startup
+/// doesn't report every fingerprint.
+struct ShapeTracker {
+    window: Duration,
+    /// First query seen; the learning period ends one window later.
+    started_at: Option<Instant>,
+    last_seen: HashMap<String, Instant>,
+    last_sweep: Option<Instant>,
+}
+
+impl ShapeTracker {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            started_at: None,
+            last_seen: HashMap::new(),
+            last_sweep: None,
+        }
+    }
+
+    /// Record a query; Some(fingerprint) if it's a shape the window hadn't seen.
+    fn observe(&mut self, sql: &str, now: Instant) -> Option<String> {
+        let started_at = *self.started_at.get_or_insert(now);
+        let fp = fingerprint(sql);
+        let known = self.last_seen.insert(fp.clone(), now).is_some();
+        (!known && now.saturating_duration_since(started_at) >= self.window).then_some(fp)
+    }
+
+    /// Forget fingerprints not seen for a whole window, returning them, at most
+    /// once per `SHAPE_SWEEP_INTERVAL`.
+    fn sweep(&mut self, now: Instant) -> Vec<String> {
+        if self
+            .last_sweep
+            .is_some_and(|t| now.saturating_duration_since(t) < SHAPE_SWEEP_INTERVAL)
+        {
+            return Vec::new();
+        }
+        self.last_sweep = Some(now);
+        let mut gone: Vec<String> = self
+            .last_seen
+            .iter()
+            .filter(|(_, &seen)| now.saturating_duration_since(seen) >= self.window)
+            .map(|(fp, _)| fp.clone())
+            .collect();
+        for fp in &gone {
+            self.last_seen.remove(fp);
+        }
+        gone.sort();
+        gone
+    }
 }
 
 struct ConnState {
     pending_queries: VecDeque<PendingQuery>,
     in_transaction: bool,
+    /// Peer address captured at accept time; None for connections seen mid-stream.
+    client_addr: Option<SocketAddr>,
+    application_name: Option<String>,
+    /// When the current COPY began, for throughput.
+    copy_started_at: Option<Instant>,
+    /// Statements since the connection was last idle; becomes a transaction once
+    /// ReadyForQuery reports one open.
+    tx: Option<TxTracker>,
+    /// Result formats of portals bound and not yet executed, by portal name.
+    bound_formats: HashMap<String, ResultFormat>,
+    /// Executes that returned PortalSuspended, by portal name. The next Execute of the
+    /// same portal continues the query, so its latency counts from the first Execute.
+    suspended_portals: HashMap<String, PendingQuery>,
+    /// From the startup options or `SET statement_timeout`; zero means disabled.
+    statement_timeout: Option<Duration>,
+    /// From the startup options: what `RESET` and `DEFAULT` go back to.
+    default_statement_timeout: Option<Duration>,
+    /// `SET LOCAL statement_timeout`, until the transaction ends.
+    local_statement_timeout: Option<Option<Duration>>,
+    /// Last slow-client warning, for `SLOW_CLIENT_WARN_INTERVAL`.
+    slow_client_warned_at: Option<Instant>,
+    /// Queries started on this connection, for numbering them.
+    queries_started: u64,
+    /// `DISCARD ALL` and friends: a pooler handing the connection to a new client.
+    session_resets: u32,
+    /// Labeled as a pooler's server connection.
+    pooled: bool,
+}
+
+/// A client that stays slow is warned about at most this often; every stall still counts.
+const SLOW_CLIENT_WARN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Shown in place of the SQL under `--no-sql`.
+pub const SQL_NOT_CAPTURED: &str = "<sql not captured>";
+
+/// High churn is warned about at most this often while it lasts.
+const CHURN_WARN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Session resets after which a connection is labeled as a pooler's. One could be
+/// an application tidying up; a second means the session is being handed around.
+const POOLER_RESETS: u32 = 2;
+
+impl ConnState {
+    fn new(client_addr: Option<SocketAddr>) -> Self {
+        Self {
+            pending_queries: VecDeque::new(),
+            in_transaction: false,
+            client_addr,
+            application_name: None,
+            copy_started_at: None,
+            tx: None,
+            bound_formats: HashMap::new(),
+            suspended_portals: HashMap::new(),
+            statement_timeout: None,
+            default_statement_timeout: None,
+            local_statement_timeout: None,
+            slow_client_warned_at: None,
+            queries_started: 0,
+            session_resets: 0,
+            pooled: false,
+        }
+    }
+
+    /// The statement_timeout in force, as far as this connection has shown us.
+    fn effective_statement_timeout(&self) -> Option<Duration> {
+        self.local_statement_timeout
+            .unwrap_or(self.statement_timeout)
+            .filter(|t| !t.is_zero())
+    }
+
+    fn apply_timeout_change(&mut self, change: TimeoutChange) {
+        match change {
+            TimeoutChange::Session(timeout) => {
+                self.statement_timeout = timeout.or(self.default_statement_timeout);
+                self.local_statement_timeout = None;
+            }
+            TimeoutChange::Local(timeout) => {
+                self.local_statement_timeout = Some(timeout.or(self.default_statement_timeout));
+            }
+        }
+    }
+
+    /// Account a finished statement towards the current (possible) transaction.
+    fn track_statement(&mut self, started_at: Instant, now: Instant) {
+        let tx = self.tx.get_or_insert_with(|| TxTracker::new(started_at));
+        if tx.timing.statements > 0 {
+            tx.timing.max_gap = tx
+                .timing
+                .max_gap
+                .max(started_at.saturating_duration_since(tx.last_end));
+        }
+        tx.timing.statements += 1;
+        tx.timing.busy += now.saturating_duration_since(started_at);
+        tx.last_end = now;
+    }
+}
+
+struct TxTracker {
+    started_at: Instant,
+    last_end: Instant,
+    /// ReadyForQuery reported 'T'/'E' — an explicit transaction, not a lone autocommit statement.
+    open: bool,
+    timing: TransactionTiming,
+}
+
+impl TxTracker {
+    fn new(started_at: Instant) -> Self {
+        Self {
+            started_at,
+            last_end: started_at,
+            open: false,
+            timing: TransactionTiming::default(),
+        }
+    }
+}
+
+/// Where a transaction's wall time went: executing statements vs waiting between them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct TransactionTiming {
+    pub statements: u32,
+    /// BEGIN start to COMMIT/ROLLBACK completion.
+    #[serde(rename = "duration_ms", serialize_with = "crate::output::serialize_ms")]
+    pub total: Duration,
+    /// Time spent executing statements.
+    #[serde(rename = "busy_ms", serialize_with = "crate::output::serialize_ms")]
+    pub busy: Duration,
+    /// Longest gap between one statement finishing and the next starting.
+    #[serde(rename = "max_gap_ms", serialize_with = "crate::output::serialize_ms")]
+    pub max_gap: Duration,
+}
+
+impl TransactionTiming {
+    /// Time spent between statements — client think time or lock waits.
+    pub fn idle(&self) -> Duration {
+        self.total.saturating_sub(self.busy)
+    }
 }
 
 struct PendingQuery {
     sql: String,
+    /// Size of the SQL as sent; `sql` may have been truncated.
+    sql_bytes: usize,
     started_at: Instant,
+    /// Portal for extended-protocol Executes; None for simple queries.
+    portal: Option<String>,
+    /// Values bound to the portal, for display; empty for simple queries.
+    params: Vec<Option<String>>,
+    /// Result width from the query's RowDescription, once it arrives.
+    columns: Option<u16>,
+    /// Text unless its Bind asked for binary columns.
+    result_format: Option<ResultFormat>,
+    /// A later statement of a multi-statement simple query: it starts running
+    /// when the one before it completes, so `started_at` is reset then.
+    chained: bool,
+    /// The query's number on its connection.
+    seq: u64,
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct QueryAggregates {
     pub fingerprint: String,
+    /// With `--group-by`, the other half of the key: `app=NAME` or `conn=ID`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
     pub count: u64,
     pub total_duration: Duration,
     pub min_duration: Duration,
     pub max_duration: Duration,
+    /// Result width from the latest RowDescription; None for statements that return no rows.
+    pub columns: Option<u16>,
+    /// Result format the latest run asked for.
+    pub result_format: Option<ResultFormat>,
+    /// Longest SQL seen for this shape, in bytes as sent.
+    pub max_sql_bytes: usize,
+    /// Every latency, for the per-shape p95 a snapshot keeps.
+    #[serde(skip)]
+    pub latencies: LatencyHistogram,
+}
+
+/// Wire messages seen, by protocol message name, per direction — e.g. Query vs
+/// Parse/Bind/Execute shows whether clients use the simple or extended protocol.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageCounts {
+    pub frontend: BTreeMap<String, u64>,
+    pub backend: BTreeMap<String, u64>,
+}
+
+impl MessageCounts {
+    pub fn add(&mut self, direction: Direction, name: &str, count: u64) {
+        let counts = match direction {
+            Direction::Frontend => &mut self.frontend,
+            Direction::Backend => &mut self.backend,
+        };
+        *counts.entry(name.to_string()).or_default() += count;
+    }
+
+    /// Names and counts for one direction, most frequent first.
+    pub fn ranked(&self, direction: Direction) -> Vec<(&str, u64)> {
+        let counts = match direction {
+            Direction::Frontend => &self.frontend,
+            Direction::Backend => &self.backend,
+        };
+        let mut ranked: Vec<(&str, u64)> = counts
+            .iter()
+            .map(|(name, &count)| (name.as_str(), count))
+            .collect();
+        ranked.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        ranked
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frontend.is_empty() && self.backend.is_empty()
+    }
+
+    /// "simple", "extended" or "mixed", from Query vs Execute messages; None before any.
+    pub fn query_protocol(&self) -> Option<&'static str> {
+        let simple = self.frontend.get("Query").is_some_and(|&n| n > 0);
+        let extended = self.frontend.get("Execute").is_some_and(|&n| n > 0);
+        match (simple, extended) {
+            (true, false) => Some("simple"),
+            (false, true) => Some("extended"),
+            (true, true) => Some("mixed"),
+            (false, false) => None,
+        }
+    }
+}
+
+/// Distinct raw messages kept per error group.
+const MAX_ERROR_EXAMPLES: usize = 5;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorAggregates {
+    pub code: String,
+    pub pattern: String,
+    pub count: u64,
+    /// Distinct raw messages behind this pattern, first few only.
+    pub examples: Vec<String>,
 }
 
 impl StatsCollector {
-    pub fn new() -> Self {
+    pub fn with_config(config: StatsConfig) -> Self {
         Self {
+            shapes: config.shape_window.map(ShapeTracker::new),
+            window_started: Instant::now(),
+            config,
             connections: HashMap::new(),
             fingerprints: HashMap::new(),
+            errors: HashMap::new(),
             latency_buckets: [0; 6],
+            latency_histogram: LatencyHistogram::default(),
+            sql_size_buckets: [0; 5],
+            latency_sum: Duration::ZERO,
             total_queries: 0,
             total_errors: 0,
+            abandoned_queries: 0,
+            backpressure_stalls: 0,
+            unexpected_shapes: HashMap::new(),
+            pooled_connections: 0,
             active_connections: 0,
+            in_flight: 0,
+            max_in_flight: 0,
+            applications: HashSet::new(),
+            client_hosts: HashSet::new(),
+            message_counts: MessageCounts::default(),
             qps_window: VecDeque::new(),
+            qps_since: Instant::now(),
+            churn_window: VecDeque::new(),
+            churn_warned_at: None,
             first_query_at: None,
             last_query_at: None,
         }
@@ -57,44 +467,216 @@ impl StatsCollector {
     /// Keeps connections and active_connections intact (live state).
     pub fn reset(&mut self) {
         self.fingerprints.clear();
+        self.errors.clear();
         self.latency_buckets = [0; 6];
+        self.latency_histogram.clear();
+        self.sql_size_buckets = [0; 5];
+        self.latency_sum = Duration::ZERO;
         self.total_queries = 0;
         self.total_errors = 0;
+        self.abandoned_queries = 0;
+        self.backpressure_stalls = 0;
+        self.unexpected_shapes.clear();
+        self.message_counts = MessageCounts::default();
         self.qps_window.clear();
+        self.qps_since = Instant::now();
+        self.churn_window.clear();
+        self.max_in_flight = self.in_flight;
         self.first_query_at = None;
         self.last_query_at = None;
+        // Distinct sources restart from whoever is still connected.
+        self.applications = self
+            .connections
+            .values()
+            .filter_map(|c| c.application_name.clone())
+            .collect();
+        self.client_hosts = self
+            .connections
+            .values()
+            .filter_map(|c| c.client_addr)
+            .map(|a| a.ip())
+            .collect();
+        self.pooled_connections = self.live_pooled_connections();
     }
 
-    pub fn process_event(&mut self, conn_id: u64, event: ProtoEvent) -> Option<DisplayEvent> {
-        let now = Instant::now();
-        let wall_time = chrono::Local::now();
+    /// Open connections currently labeled as a pooler's.
+    pub fn live_pooled_connections(&self) -> u64 {
+        self.connections.values().filter(|c| c.pooled).count() as u64
+    }
+
+    /// `--window`: true when the current window has ended, starting the next one.
+    /// The caller resets (after freezing or printing the finished window). Windows
+    /// stay on their original schedule, even across idle gaps.
+    pub fn roll_window(&mut self, now: Instant) -> bool {
+        let Some(window) = self.config.window else {
+            return false;
+        };
+        let elapsed = now.saturating_duration_since(self.window_started);
+        if elapsed < window {
+            return false;
+        }
+        self.window_started += window * (elapsed.as_nanos() / window.as_nanos()) as u32;
+        true
+    }
+
+    /// "window: 5m (3m elapsed)", when `--window` is set.
+    pub fn window_label(&self, now: Instant) -> Option<String> {
+        let window = self.config.window?;
+        let elapsed = now.saturating_duration_since(self.window_started);
+        let elapsed = match elapsed.as_secs() {
+            secs if secs >= 60 => Duration::from_secs(secs / 60 * 60),
+            secs => Duration::from_secs(secs),
+        };
+        Some(format!(
+            "window: {} ({} elapsed)",
+            format_span(window),
+            format_span(elapsed)
+        ))
+    }
+
+    /// Correlate an event observed at `at`; returns what, if anything, should be shown.
+    pub fn process_event(
+        &mut self,
+        conn_id: u64,
+        event: ProtoEvent,
+        at: Instant,
+    ) -> Option<DisplayEvent> {
+        // Look the address up first — ConnectionClosed removes the connection.
+        let client_addr = self.client_addr(conn_id);
+        let kind = self.apply_event(conn_id, event, at)?;
+        Some(DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id,
+            client_addr,
+            kind,
+        })
+    }
+
+    /// Aggregate an event without building anything for display (--stats-only).
+    pub fn record_event(&mut self, conn_id: u64, event: ProtoEvent, at: Instant) {
+        self.apply_event(conn_id, event, at);
+    }
 
+    /// Update aggregates and connection state; returns what, if anything, should be shown.
+    fn apply_event(
+        &mut self,
+        conn_id: u64,
+        event: ProtoEvent,
+        now: Instant,
+    ) -> Option<DisplayEventKind> {
         match event {
-            ProtoEvent::QueryStart { sql } => {
+            ProtoEvent::Startup { params } => {
+                if let Some(timeout) = startup_statement_timeout(&params) {
+                    let conn = self.ensure_conn(conn_id);
+                    conn.statement_timeout = Some(timeout);
+                    conn.default_statement_timeout = Some(timeout);
+                }
+                let app = params.into_iter().find(|(name, _)| name == "application_name").map(|(_, v)| v);
+                if let Some(app) = app.filter(|a| !a.is_empty()) {
+                    self.applications.insert(app.clone());
+                    self.ensure_conn(conn_id).application_name = Some(app);
+                }
+                None
+            }
+
+            ProtoEvent::QueryStart { sql, sql_bytes, portal: None, .. } => {
+                // Whatever truncation cut off belongs to the last statement.
+                let cut = sql_bytes.saturating_sub(sql.len());
+                // `SELECT 1; SELECT 2` gets a CommandComplete per statement, so queue each one.
+                let statements: Vec<String> = split_statements(&sql).into_iter().map(str::to_string).collect();
+                let statements = if statements.len() > 1 { statements } else { vec![sql] };
+                let count = statements.len();
+                let sized = statements.into_iter().enumerate().map(|(i, sql)| (sql.len() + if i + 1 == count { cut } else { 0 }, sql));
+                self.simple_query_started(conn_id, sized, now)
+            }
+
+            ProtoEvent::QueryStartNoSql { statements, sql_bytes } => {
+                // Without the text, the whole query's size goes on its first statement.
+                let sized = (0..statements).map(|i| (if i == 0 { sql_bytes } else { 0 }, String::new()));
+                self.simple_query_started(conn_id, sized, now)
+            }
+
+            ProtoEvent::QueryStart { sql, sql_bytes, portal, params } => {
                 let conn = self.ensure_conn(conn_id);
+                // Resuming a suspended portal continues the same query.
+                let resumed = portal
+                    .as_ref()
+                    .and_then(|p| conn.suspended_portals.remove(p))
+                    .filter(|q| q.sql == sql);
+                let resuming = resumed.is_some();
+                let bound_format = portal.as_ref().and_then(|p| conn.bound_formats.remove(p));
+                // A resumed portal keeps its number.
+                let seq = match &resumed {
+                    Some(q) => q.seq,
+                    None => {
+                        conn.queries_started += 1;
+                        conn.queries_started
+                    }
+                };
                 conn.pending_queries.push_back(PendingQuery {
+                    started_at: resumed.as_ref().map_or(now, |q| q.started_at),
+                    portal,
+                    params,
+                    result_format: resumed.as_ref().and_then(|q| q.result_format).or(bound_format),
+                    columns: resumed.and_then(|q| q.columns),
+                    chained: false,
+                    sql_bytes: sql_bytes.max(sql.len()),
                     sql,
-                    started_at: now,
+                    seq,
                 });
+                self.queries_sent(1);
+                if resuming {
+                    return None;
+                }
+                self.check_new_queries(conn_id, 1, now)
+            }
+
+            ProtoEvent::PortalSuspended => {
+                // Responses arrive in Execute order, so this answers the oldest pending Execute.
+                let conn = self.connections.get_mut(&conn_id)?;
+                let pending = conn.pending_queries.pop_front()?;
+                if let Some(portal) = pending.portal.clone() {
+                    conn.suspended_portals.insert(portal, pending);
+                }
+                self.in_flight = self.in_flight.saturating_sub(1);
                 None
             }
 
-            ProtoEvent::ParseDetected { sql } => {
+            ProtoEvent::ParseDetected { .. } if self.config.no_sql => None,
+            ProtoEvent::ParseDetected { sql, param_types } => {
                 // Parse != Execute — don't push to queue. Keep the warning for visibility.
-                Some(DisplayEvent {
-                    wall_time,
-                    conn_id,
-                    kind: DisplayEventKind::Warning(format!(
-                        "Extended query protocol: {}",
-                        truncate(&sql, 80)
-                    )),
-                })
+                let types = if param_types.is_empty() {
+                    String::new()
+                } else {
+                    let declared: Vec<String> =
+                        param_types.iter().enumerate().map(|(i, &oid)| format!("${} {}", i + 1, type_name(oid))).collect();
+                    format!(" [{}]", declared.join(", "))
+                };
+                Some(DisplayEventKind::Warning(format!(
+                    "Extended query protocol: {}{types}",
+                    truncate(&sql, 80)
+                )))
             }
 
-            ProtoEvent::QueryComplete { rows, .. } => {
+            ProtoEvent::QueryComplete { tag, rows, bytes } => {
                 let conn = self.connections.get_mut(&conn_id)?;
                 let pending = conn.pending_queries.pop_front()?;
+                self.in_flight = self.in_flight.saturating_sub(1);
+                if tag == "SET" || tag == "RESET" {
+                    if let Some(change) = statement_timeout_change(&pending.sql) {
+                        conn.apply_timeout_change(change);
+                    }
+                }
                 let duration = now - pending.started_at;
+                conn.track_statement(pending.started_at, now);
+                if let Some(next) = conn.pending_queries.front_mut().filter(|q| q.chained) {
+                    next.started_at = now;
+                }
+                let group = match self.config.group_by {
+                    GroupBy::Fingerprint => None,
+                    GroupBy::FingerprintApp => Some(format!("app={}", conn.application_name.as_deref().unwrap_or(""))),
+                    GroupBy::FingerprintConn => Some(format!("conn={conn_id}")),
+                };
 
                 self.total_queries += 1;
                 if self.first_query_at.is_none() {
@@ -102,39 +684,59 @@ impl StatsCollector {
                 }
                 self.last_query_at = Some(now);
                 self.record_latency(duration);
-                self.record_fingerprint(&pending.sql, duration);
+                self.sql_size_buckets[sql_size_bucket(pending.sql_bytes)] += 1;
                 self.qps_window.push_back(now);
+                if !self.config.no_sql {
+                    let agg = self.record_fingerprint(&pending.sql, group, duration);
+                    agg.max_sql_bytes = agg.max_sql_bytes.max(pending.sql_bytes);
+                    agg.columns = pending.columns.or(agg.columns);
+                    agg.result_format = pending.result_format.or(agg.result_format);
+                }
+                let sql = if self.config.no_sql { SQL_NOT_CAPTURED.to_string() } else { pending.sql };
 
-                Some(DisplayEvent {
-                    wall_time,
-                    conn_id,
-                    kind: DisplayEventKind::Query {
-                        sql: pending.sql,
-                        duration,
-                        rows,
-                    },
+                Some(DisplayEventKind::Query {
+                    operation: operation(&sql),
+                    trace_id: trace_id(&sql),
+                    sql,
+                    duration,
+                    rows,
+                    seq: pending.seq,
+                    params: pending.params,
+                    result_bytes: bytes,
+                    large_result: bytes >= self.config.large_result_bytes,
                 })
             }
 
             ProtoEvent::QueryError { severity, code, message } => {
                 self.total_errors += 1;
+                self.record_error(&code, &message);
 
                 // Pop the failed query from the front of the queue
                 let (sql, duration) = self.connections.get_mut(&conn_id)
-                    .and_then(|c| c.pending_queries.pop_front())
-                    .map(|p| (Some(p.sql), Some(now - p.started_at)))
+                    .and_then(|c| {
+                        let p = c.pending_queries.pop_front()?;
+                        c.track_statement(p.started_at, now);
+                        self.in_flight = self.in_flight.saturating_sub(1);
+                        Some(p)
+                    })
+                    .map(|p| ((!self.config.no_sql).then_some(p.sql), Some(now - p.started_at)))
                     .unwrap_or((None, None));
 
+                let message = if code == QUERY_CANCELED {
+                    let timeout = self.connections.get(&conn_id).and_then(ConnState::effective_statement_timeout);
+                    format!("{message} ({})", describe_cancel(timeout, duration))
+                } else if code == INVALID_AUTHORIZATION && requires_encryption(&message) {
+                    format!("{message} (the upstream requires SSL; start dbprobe with --upstream-tls)")
+                } else {
+                    message
+                };
+
                 if severity == "ERROR" || severity == "FATAL" {
-                    Some(DisplayEvent {
-                        wall_time,
-                        conn_id,
-                        kind: DisplayEventKind::Error {
-                            sql,
-                            duration,
-                            code,
-                            message,
-                        },
+                    Some(DisplayEventKind::Error {
+                        sql,
+                        duration,
+                        code,
+                        message,
                     })
                 } else {
                     None
@@ -145,141 +747,2181 @@ impl StatsCollector {
                 let conn = self.connections.get_mut(&conn_id)?;
                 conn.in_transaction = status == TxStatus::InTransaction;
                 // Clear any orphaned pending queries (error mid-pipeline skips remaining Executes)
+                self.in_flight = self.in_flight.saturating_sub(conn.pending_queries.len() as u64);
                 conn.pending_queries.clear();
-                None
+
+                if status == TxStatus::Idle {
+                    // Portals and SET LOCAL don't outlive their transaction.
+                    conn.suspended_portals.clear();
+                    conn.local_statement_timeout = None;
+                }
+                if status != TxStatus::Idle {
+                    conn.tx.get_or_insert_with(|| TxTracker::new(now)).open = true;
+                    return None;
+                }
+                // Back to idle: an explicit transaction just ended.
+                let tx = conn.tx.take().filter(|tx| tx.open)?;
+                let timing = TransactionTiming {
+                    total: now - tx.started_at,
+                    ..tx.timing
+                };
+                (timing.total >= self.config.slow_tx_threshold)
+                    .then_some(DisplayEventKind::SlowTransaction(timing))
             }
 
             ProtoEvent::ConnectionClosed => {
+                // Responses to queries sent before Terminate can still arrive; whatever
+                // is unanswered when the socket closes is reported by connection_dropped.
+                if self.connections.get(&conn_id).is_some_and(|c| !c.pending_queries.is_empty()) {
+                    return None;
+                }
                 self.connections.remove(&conn_id);
                 self.active_connections = self.active_connections.saturating_sub(1);
-                Some(DisplayEvent {
-                    wall_time,
-                    conn_id,
-                    kind: DisplayEventKind::ConnectionClosed,
+                Some(DisplayEventKind::ConnectionClosed)
+            }
+
+            ProtoEvent::CopyStarted => {
+                self.ensure_conn(conn_id).copy_started_at = Some(now);
+                None
+            }
+
+            ProtoEvent::CopyProgress { bytes } => {
+                let started = self.connections.get(&conn_id)?.copy_started_at?;
+                Some(DisplayEventKind::Copy { bytes, elapsed: now - started, done: false })
+            }
+
+            ProtoEvent::CopyDone { bytes } => {
+                let started = self.connections.get_mut(&conn_id)?.copy_started_at.take()?;
+                Some(DisplayEventKind::Copy { bytes, elapsed: now - started, done: true })
+            }
+
+            ProtoEvent::CopyFail { bytes, reason } => {
+                // The ErrorResponse that follows fails the COPY statement itself.
+                self.connections.get_mut(&conn_id)?.copy_started_at = None;
+                Some(DisplayEventKind::Warning(format!(
+                    "COPY aborted by client after {:.1}MB: {reason}",
+                    bytes as f64 / (1024.0 * 1024.0)
+                )))
+            }
+
+            ProtoEvent::StatementLeak { open } => Some(DisplayEventKind::Warning(format!(
+                "PREPARED STATEMENT LEAK? {open} named statements prepared and never closed on this connection"
+            ))),
+
+            ProtoEvent::RowDescription { columns } => {
+                // Rows describe the statement in flight: the oldest pending one.
+                let pending = self.connections.get_mut(&conn_id)?.pending_queries.front_mut()?;
+                pending.columns = Some(columns);
+                if columns < self.config.wide_result_columns || !selects_star(&pending.sql) {
+                    return None;
+                }
+                // Once per fingerprint: after the first completion its width is on record.
+                let fp = fingerprint(&pending.sql);
+                let seen_wide = self
+                    .fingerprints
+                    .get(&fp)
+                    .and_then(|agg| agg.columns)
+                    .is_some_and(|c| c >= self.config.wide_result_columns);
+                (!seen_wide).then(|| {
+                    DisplayEventKind::Warning(format!(
+                        "WIDE RESULT: {columns} columns from {} — list the columns you need instead of *",
+                        truncate(&fp, 80)
+                    ))
                 })
             }
 
+            ProtoEvent::Bind { portal, result_format } => {
+                self.ensure_conn(conn_id).bound_formats.insert(portal, result_format);
+                None
+            }
+
+            ProtoEvent::MessageCounts { counts } => {
+                for (direction, name, count) in counts {
+                    self.message_counts.add(direction, name, count);
+                }
+                None
+            }
+
+            ProtoEvent::Notification { channel, payload } => Some(DisplayEventKind::Notification { channel, payload }),
+
+            // Shown, but the query it came with still succeeds: not an error.
+            ProtoEvent::Notice { severity, code, message } => Some(DisplayEventKind::Warning(format!("{severity} {code}: {message}"))),
+
             ProtoEvent::Unknown { .. } => None,
         }
     }
 
-    pub fn connection_opened(&mut self, conn_id: u64) -> DisplayEvent {
+    /// A client connected: its open event, then a warning if connections are
+    /// churning faster than `--churn-threshold`.
+    pub fn connection_opened(
+        &mut self,
+        conn_id: u64,
+        client_addr: Option<SocketAddr>,
+    ) -> Vec<DisplayEvent> {
         self.active_connections += 1;
-        self.connections.insert(conn_id, ConnState {
-            pending_queries: VecDeque::new(),
-            in_transaction: false,
-        });
-        DisplayEvent {
+        if let Some(addr) = client_addr {
+            self.client_hosts.insert(addr.ip());
+        }
+        self.connections
+            .insert(conn_id, ConnState::new(client_addr));
+        let mut events = vec![DisplayEvent {
             wall_time: chrono::Local::now(),
             conn_id,
+            client_addr,
             kind: DisplayEventKind::ConnectionOpened,
+        }];
+
+        let now = Instant::now();
+        self.churn_window.push_back(now);
+        let churn = self.conn_churn_at(now);
+        let warned_recently = self
+            .churn_warned_at
+            .is_some_and(|last| now.saturating_duration_since(last) < CHURN_WARN_INTERVAL);
+        if churn > f64::from(self.config.churn_threshold) && !warned_recently {
+            self.churn_warned_at = Some(now);
+            let message = format!(
+                "conn churn: {churn:.1}/s, over --churn-threshold {}/s — many short-lived connections; \
+                 a connection pooler (e.g. PgBouncer) would save a backend startup per connection",
+                self.config.churn_threshold
+            );
+            events.push(self.proxy_warning(conn_id, message));
         }
+        events
     }
 
-    pub fn connection_dropped(&mut self, conn_id: u64) -> Option<DisplayEvent> {
-        if self.connections.remove(&conn_id).is_some() {
-            self.active_connections = self.active_connections.saturating_sub(1);
-            Some(DisplayEvent {
-                wall_time: chrono::Local::now(),
-                conn_id,
-                kind: DisplayEventKind::ConnectionClosed,
+    /// Queue a simple query's statements, given as (size on the wire, SQL).
+    fn simple_query_started(
+        &mut self,
+        conn_id: u64,
+        statements: impl Iterator<Item = (usize, String)>,
+        now: Instant,
+    ) -> Option<DisplayEventKind> {
+        let conn = self.ensure_conn(conn_id);
+        let mut count = 0;
+        for (sql_bytes, sql) in statements {
+            conn.queries_started += 1;
+            conn.pending_queries.push_back(PendingQuery {
+                sql_bytes,
+                sql,
+                started_at: now,
+                portal: None,
+                params: Vec::new(),
+                columns: None,
+                // The simple protocol only returns text.
+                result_format: Some(ResultFormat::Text),
+                chained: count > 0,
+                seq: conn.queries_started,
+            });
+            count += 1;
+        }
+        self.queries_sent(count as u64);
+        self.check_new_queries(conn_id, count, now)
+    }
+
+    fn queries_sent(&mut self, count: u64) {
+        self.in_flight += count;
+        self.max_in_flight = self.max_in_flight.max(self.in_flight);
+    }
+
+    /// Connections opened per second, over the same sliding window as `qps()`.
+    pub fn conn_churn(&mut self) -> f64 {
+        self.conn_churn_at(Instant::now())
+    }
+
+    fn conn_churn_at(&mut self, now: Instant) -> f64 {
+        let window = self.config.qps_window;
+        if let Some(cutoff) = now.checked_sub(window) {
+            while self.churn_window.front().is_some_and(|&t| t <= cutoff) {
+                self.churn_window.pop_front();
+            }
+        }
+        self.churn_window.len() as f64 / window.as_secs_f64()
+    }
+
+    /// The socket closed. Queries that never got a CommandComplete are counted as
+    /// abandoned and each gets a warning, ahead of the close event itself.
+    pub fn connection_dropped(&mut self, conn_id: u64) -> Vec<DisplayEvent> {
+        let Some(conn) = self.connections.remove(&conn_id) else {
+            return Vec::new();
+        };
+        self.active_connections = self.active_connections.saturating_sub(1);
+        self.abandoned_queries += conn.pending_queries.len() as u64;
+        self.in_flight = self
+            .in_flight
+            .saturating_sub(conn.pending_queries.len() as u64);
+        let now = Instant::now();
+        let event = |kind| DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id,
+            client_addr: conn.client_addr,
+            kind,
+        };
+        let mut events: Vec<DisplayEvent> = conn
+            .pending_queries
+            .iter()
+            .map(|q| {
+                event(DisplayEventKind::Warning(format!(
+                    "conn {conn_id}: query abandoned after {:.1}s: {}",
+                    now.saturating_duration_since(q.started_at).as_secs_f64(),
+                    truncate(&q.sql, 80)
+                )))
             })
-        } else {
-            None
+            .collect();
+        events.push(event(DisplayEventKind::ConnectionClosed));
+        events
+    }
+
+    /// Wrap a proxy-level warning (not derived from a wire message) as a display event.
+    pub fn proxy_warning(&self, conn_id: u64, message: String) -> DisplayEvent {
+        DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id,
+            client_addr: self.client_addr(conn_id),
+            kind: DisplayEventKind::Warning(message),
+        }
+    }
+
+    /// The client presented a certificate with this subject (`--tls-client-cert`).
+    pub fn client_certificate(&self, conn_id: u64, subject: String) -> DisplayEvent {
+        DisplayEvent {
+            wall_time: chrono::Local::now(),
+            conn_id,
+            client_addr: self.client_addr(conn_id),
+            kind: DisplayEventKind::ClientCertificate { subject },
+        }
+    }
+
+    /// The proxy waited on a slow client before it could forward server data.
+    pub fn client_backpressure(
+        &mut self,
+        conn_id: u64,
+        waited: Duration,
+        at: Instant,
+    ) -> Option<DisplayEvent> {
+        self.backpressure_stalls += 1;
+        let conn = self.ensure_conn(conn_id);
+        if conn
+            .slow_client_warned_at
+            .is_some_and(|last| at.saturating_duration_since(last) < SLOW_CLIENT_WARN_INTERVAL)
+        {
+            return None;
         }
+        conn.slow_client_warned_at = Some(at);
+        let message = format!(
+            "conn {conn_id}: slow client, backpressuring (server data waited over {} for the client to read)",
+            format_span(waited)
+        );
+        Some(self.proxy_warning(conn_id, message))
+    }
+
+    fn client_addr(&self, conn_id: u64) -> Option<SocketAddr> {
+        self.connections.get(&conn_id).and_then(|c| c.client_addr)
     }
 
     fn ensure_conn(&mut self, conn_id: u64) -> &mut ConnState {
-        self.connections.entry(conn_id).or_insert_with(|| ConnState {
-            pending_queries: VecDeque::new(),
-            in_transaction: false,
-        })
+        self.connections
+            .entry(conn_id)
+            .or_insert_with(|| ConnState::new(None))
+    }
+
+    /// `--large-query-kb` in bytes, for reports that flag large shapes.
+    pub fn large_query_bytes(&self) -> usize {
+        self.config.large_query_bytes
+    }
+
+    /// p50, p95 and p99 latency, or None before any query completes.
+    pub fn percentiles(&self) -> Option<(Duration, Duration, Duration)> {
+        self.latency_histogram.p50_p95_p99()
     }
 
     fn record_latency(&mut self, duration: Duration) {
-        let ms = duration.as_secs_f64() * 1000.0;
-        let bucket = match ms {
-            ms if ms < 1.0 => 0,
-            ms if ms < 5.0 => 1,
-            ms if ms < 10.0 => 2,
-            ms if ms < 50.0 => 3,
-            ms if ms < 100.0 => 4,
-            _ => 5,
-        };
-        self.latency_buckets[bucket] += 1;
+        self.latency_buckets[latency_bucket(duration)] += 1;
+        self.latency_histogram.record(duration);
+        self.latency_sum += duration;
     }
 
-    fn record_fingerprint(&mut self, sql: &str, duration: Duration) {
-        let fp = fingerprint(sql);
-        let agg = self.fingerprints.entry(fp.clone()).or_insert_with(|| QueryAggregates {
-            fingerprint: fp,
-            count: 0,
-            total_duration: Duration::ZERO,
-            min_duration: Duration::MAX,
-            max_duration: Duration::ZERO,
-        });
-        agg.count += 1;
-        agg.total_duration += duration;
-        agg.min_duration = agg.min_duration.min(duration);
-        agg.max_duration = agg.max_duration.max(duration);
+    /// Checks on the last `count` queries queued on a connection, in order:
+    /// `--large-query-kb`, `--allowed-fingerprints`, pooler detection, then
+    /// `--shape-window`. Every check runs and updates its state; the warnings
+    /// that fire are joined into one, in that order, so none is lost.
+    fn check_new_queries(
+        &mut self,
+        conn_id: u64,
+        count: usize,
+        now: Instant,
+    ) -> Option<DisplayEventKind> {
+        let warnings: Vec<String> = [
+            self.check_large_query(conn_id, count),
+            self.check_allowlist(conn_id, count),
+            self.check_pooler(conn_id, count),
+            self.track_shape(conn_id, count, now),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        (!warnings.is_empty()).then(|| DisplayEventKind::Warning(warnings.join(" | ")))
+    }
+
+    /// Flag the largest of the newest `count` queries when its SQL reaches
+    /// `large_query_bytes`. The size is as sent, not as truncated for display.
+    fn check_large_query(&self, conn_id: u64, count: usize) -> Option<String> {
+        let pending = &self.connections.get(&conn_id)?.pending_queries;
+        let largest = pending
+            .iter()
+            .skip(pending.len().saturating_sub(count))
+            .max_by_key(|q| q.sql_bytes)?;
+        if largest.sql_bytes < self.config.large_query_bytes {
+            return None;
+        }
+        Some(format!(
+            "conn {conn_id}: large query, {:.1}KB of SQL (often a generated IN-list or CTE): {}",
+            largest.sql_bytes as f64 / 1024.0,
+            truncate(&largest.sql, 120)
+        ))
+    }
+
+    /// Count session resets, labeling the connection as a pooler's once there
+    /// are `POOLER_RESETS`. Only looks at the statements; pooler-specific
+    /// startup parameters or connection reuse timing aren't considered.
+    fn check_pooler(&mut self, conn_id: u64, count: usize) -> Option<String> {
+        let conn = self.connections.get_mut(&conn_id)?;
+        let pending = &conn.pending_queries;
+        let resets = pending
+            .iter()
+            .skip(pending.len().saturating_sub(count))
+            .filter(|q| is_session_reset(&q.sql))
+            .count();
+        conn.session_resets += resets as u32;
+        if conn.pooled || conn.session_resets < POOLER_RESETS {
+            return None;
+        }
+        conn.pooled = true;
+        self.pooled_connections += 1;
+        Some(format!(
+            "conn {conn_id}: looks like a pooler connection ({} session resets); its queries come from many \
+             client sessions, so per-connection numbers describe the pooler, not one app",
+            conn.session_resets
+        ))
+    }
+
+    /// Count queries whose fingerprint isn't allowed, warning the first time each shape is seen.
+    fn check_allowlist(&mut self, conn_id: u64, count: usize) -> Option<String> {
+        let allowed = self.config.allowed_fingerprints.as_ref()?;
+        let pending = &self.connections.get(&conn_id)?.pending_queries;
+        let mut first_seen = Vec::new();
+        for q in pending.iter().skip(pending.len().saturating_sub(count)) {
+            let fp = fingerprint(&q.sql);
+            if allowed.contains(&fp) {
+                continue;
+            }
+            let seen = self.unexpected_shapes.entry(fp.clone()).or_insert(0);
+            *seen += 1;
+            if *seen == 1 {
+                first_seen.push(truncate(&fp, 120));
+            }
+        }
+        (!first_seen.is_empty()).then(|| {
+            format!(
+                "UNEXPECTED QUERY SHAPE (not allowed): {}",
+                first_seen.join("; ")
+            )
+        })
+    }
+
+    /// `--shape-window`: report the shapes of the `count` newest pending queries
+    /// that are new, else any shapes that have gone quiet.
+    fn track_shape(&mut self, conn_id: u64, count: usize, now: Instant) -> Option<String> {
+        let shapes = self.shapes.as_mut()?;
+        let pending = &self.connections.get(&conn_id)?.pending_queries;
+        let new: Vec<String> = pending
+            .iter()
+            .skip(pending.len().saturating_sub(count))
+            .filter_map(|q| shapes.observe(&q.sql, now))
+            .map(|fp| truncate(&fp, 120))
+            .collect();
+        if !new.is_empty() {
+            return Some(format!("new query shape: {}", new.join("; ")));
+        }
+        let gone = shapes.sweep(now);
+        if gone.is_empty() {
+            return None;
+        }
+        let window = format_span(shapes.window);
+        let listed: Vec<String> = gone.iter().map(|fp| truncate(fp, 80)).collect();
+        Some(format!(
+            "query shape{} gone (not seen in {window}): {}",
+            if gone.len() == 1 { "" } else { "s" },
+            listed.join("; ")
+        ))
+    }
+
+    fn record_fingerprint(
+        &mut self,
+        sql: &str,
+        group: Option<String>,
+        duration: Duration,
+    ) -> &mut QueryAggregates {
+        record_fingerprint_into(&mut self.fingerprints, sql, group, duration)
+    }
+
+    fn record_error(&mut self, code: &str, message: &str) {
+        record_error_into(&mut self.errors, code, message);
+    }
+
+    /// Queries per second averaged over the configured sliding window (default 1s).
+    /// Longer windows smooth out bursty, low-traffic workloads. Right after start
+    /// or a reset the window isn't full yet, so the rate is over the time since.
+    pub fn qps(&mut self) -> f64 {
+        self.qps_at(Instant::now())
     }
 
-    /// Queries per second over a sliding 1-second window.
-    pub fn qps(&mut self) -> u64 {
-        let cutoff = Instant::now() - Duration::from_secs(1);
-        // VecDeque is sorted by insertion time — pop expired entries from the front
-        while self.qps_window.front().is_some_and(|&t| t <= cutoff) {
-            self.qps_window.pop_front();
+    fn qps_at(&mut self, now: Instant) -> f64 {
+        let window = self.config.qps_window;
+        if let Some(cutoff) = now.checked_sub(window) {
+            // VecDeque is sorted by insertion time — pop expired entries from the front
+            while self.qps_window.front().is_some_and(|&t| t <= cutoff) {
+                self.qps_window.pop_front();
+            }
+        }
+        let span = window.min(now.saturating_duration_since(self.qps_since));
+        if span.is_zero() {
+            return 0.0;
         }
-        self.qps_window.len() as u64
+        self.qps_window.len() as f64 / span.as_secs_f64()
     }
 
     pub fn top_queries(&self, n: usize) -> Vec<QueryAggregates> {
         let mut queries: Vec<_> = self.fingerprints.values().cloned().collect();
-        queries.sort_unstable_by(|a, b| b.total_duration.cmp(&a.total_duration));
+        queries.sort_unstable_by_key(|q| std::cmp::Reverse(q.total_duration));
         queries.truncate(n);
         queries
     }
 
+    /// Rough heap footprint: aggregate maps plus per-connection state. Ignores
+    /// allocator overhead, so it's for spotting growth, not exact accounting.
+    pub fn approx_memory_bytes(&self) -> usize {
+        let connections = self.connections.capacity() * size_of::<(u64, ConnState)>()
+            + self
+                .connections
+                .values()
+                .flat_map(|c| c.pending_queries.iter().chain(c.suspended_portals.values()))
+                .map(|q| size_of::<PendingQuery>() + q.sql.capacity())
+                .sum::<usize>();
+        aggregates_memory_bytes(&self.fingerprints, &self.errors) + connections
+    }
+
     pub fn freeze(&self) -> FrozenStats {
         FrozenStats {
             fingerprints: self.fingerprints.clone(),
+            errors: self.errors.clone(),
             latency_buckets: self.latency_buckets,
+            latency_histogram: self.latency_histogram.clone(),
             total_queries: self.total_queries,
             total_errors: self.total_errors,
             active_connections: self.active_connections,
+            pooled_connections: self.live_pooled_connections(),
+            max_in_flight: self.max_in_flight,
+            applications: self.applications.clone(),
+            client_hosts: self.client_hosts.clone(),
+            message_counts: self.message_counts.clone(),
             first_query_at: self.first_query_at,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct FrozenStats {
     pub fingerprints: HashMap<String, QueryAggregates>,
+    pub errors: HashMap<String, ErrorAggregates>,
     pub latency_buckets: [u64; 6],
+    /// Empty for imported snapshots, which only carry the buckets.
+    pub latency_histogram: LatencyHistogram,
     pub total_queries: u64,
     pub total_errors: u64,
     pub active_connections: u64,
+    /// Of those, how many are labeled as a pooler's.
+    pub pooled_connections: u64,
+    /// Zero when unknown: for a time range, or a snapshot from before it was saved.
+    pub max_in_flight: u64,
+    pub applications: HashSet<String>,
+    pub client_hosts: HashSet<IpAddr>,
+    pub message_counts: MessageCounts,
     pub first_query_at: Option<Instant>,
 }
 
 impl FrozenStats {
+    /// Fold one completed query into these stats — used to rebuild aggregates
+    /// from a subset of recorded events (e.g. a time range of a frozen tab).
+    pub fn add_query(&mut self, sql: &str, duration: Duration) {
+        self.total_queries += 1;
+        self.latency_buckets[latency_bucket(duration)] += 1;
+        self.latency_histogram.record(duration);
+        record_fingerprint_into(&mut self.fingerprints, sql, None, duration);
+    }
+
+    /// p50, p95 and p99 as of the freeze, or None without latencies.
+    pub fn percentiles(&self) -> Option<(Duration, Duration, Duration)> {
+        self.latency_histogram.p50_p95_p99()
+    }
+
+    pub fn add_error(&mut self, code: &str, message: &str) {
+        self.total_errors += 1;
+        record_error_into(&mut self.errors, code, message);
+    }
+
     pub fn top_queries(&self, n: usize) -> Vec<QueryAggregates> {
         let mut queries: Vec<_> = self.fingerprints.values().cloned().collect();
-        queries.sort_unstable_by(|a, b| b.total_duration.cmp(&a.total_duration));
+        queries.sort_unstable_by_key(|q| std::cmp::Reverse(q.total_duration));
         queries.truncate(n);
         queries
     }
+
+    pub fn approx_memory_bytes(&self) -> usize {
+        aggregates_memory_bytes(&self.fingerprints, &self.errors)
+    }
 }
 
-fn truncate(s: &str, max: usize) -> String {
-    if s.len() <= max {
-        s.to_string()
-    } else {
-        let mut end = max;
-        while end > 0 && !s.is_char_boundary(end) {
-            end -= 1;
-        }
-        format!("{}...", &s[..end])
+/// Map slots plus the strings they own; fingerprints are stored twice (key and value).
+fn aggregates_memory_bytes(
+    fingerprints: &HashMap<String, QueryAggregates>,
+    errors: &HashMap<String, ErrorAggregates>,
+) -> usize {
+    let fingerprint_bytes = fingerprints.capacity() * size_of::<(String, QueryAggregates)>()
+        + fingerprints
+            .iter()
+            .map(|(k, q)| k.capacity() + q.fingerprint.capacity())
+            .sum::<usize>();
+    let error_bytes = errors.capacity() * size_of::<(String, ErrorAggregates)>()
+        + errors
+            .iter()
+            .map(|(k, e)| {
+                k.capacity()
+                    + e.code.capacity()
+                    + e.pattern.capacity()
+                    + e.examples
+                        .iter()
+                        .map(|x| size_of::<String>() + x.capacity())
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+    fingerprint_bytes + error_bytes
+}
+
+/// Histogram bucket for a latency: ≤1ms, ≤5, ≤10, ≤50, ≤100, 100+. Each bucket
+/// includes its upper bound, as a Prometheus `le` bucket does.
+fn latency_bucket(duration: Duration) -> usize {
+    [1, 5, 10, 50, 100]
+        .iter()
+        .position(|&ms| duration <= Duration::from_millis(ms))
+        .unwrap_or(5)
+}
+
+fn sql_size_bucket(bytes: usize) -> usize {
+    match bytes {
+        0..1024 => 0,
+        1024..8192 => 1,
+        8192..65536 => 2,
+        65536..1048576 => 3,
+        _ => 4,
+    }
+}
+
+fn record_fingerprint_into<'a>(
+    fingerprints: &'a mut HashMap<String, QueryAggregates>,
+    sql: &str,
+    group: Option<String>,
+    duration: Duration,
+) -> &'a mut QueryAggregates {
+    let fp = fingerprint(sql);
+    let agg = fingerprints
+        .entry(aggregate_key(&fp, group.as_deref()))
+        .or_insert_with(|| QueryAggregates {
+            fingerprint: fp,
+            group,
+            count: 0,
+            total_duration: Duration::ZERO,
+            min_duration: Duration::MAX,
+            max_duration: Duration::ZERO,
+            columns: None,
+            result_format: None,
+            max_sql_bytes: 0,
+            latencies: LatencyHistogram::default(),
+        });
+    agg.count += 1;
+    agg.total_duration += duration;
+    agg.min_duration = agg.min_duration.min(duration);
+    agg.max_duration = agg.max_duration.max(duration);
+    agg.latencies.record(duration);
+    agg
+}
+
+fn record_error_into(errors: &mut HashMap<String, ErrorAggregates>, code: &str, message: &str) {
+    let pattern = normalize_error(message);
+    let agg = errors
+        .entry(format!("{code} {pattern}"))
+        .or_insert_with(|| ErrorAggregates {
+            code: code.to_string(),
+            pattern,
+            count: 0,
+            examples: Vec::new(),
+        });
+    agg.count += 1;
+    if agg.examples.len() < MAX_ERROR_EXAMPLES && !agg.examples.iter().any(|m| m == message) {
+        agg.examples.push(message.to_string());
+    }
+}
+
+/// `DISCARD ALL`, `RESET ALL` or `DEALLOCATE ALL`: what poolers (pgbouncer's
+/// `server_reset_query`, pgpool's `reset_query_list`) run between clients.
+fn is_session_reset(sql: &str) -> bool {
+    let sql = sql.trim().trim_end_matches(';').to_ascii_lowercase();
+    let words: Vec<&str> = sql.split_whitespace().collect();
+    matches!(words[..], ["discard" | "reset" | "deallocate", "all"])
+}
+
+/// SQLSTATE for a canceled statement: statement_timeout, pg_cancel_backend or a client cancel request.
+const QUERY_CANCELED: &str = "57014";
+
+/// SQLSTATE for a rejected login, including a pg_hba.conf `hostssl` line refusing plaintext.
+const INVALID_AUTHORIZATION: &str = "28000";
+
+/// pg_hba.conf found no line for a plaintext connection: "..., no encryption"
+/// since PostgreSQL 14, "..., SSL off" before.
+fn requires_encryption(message: &str) -> bool {
+    message.starts_with("no pg_hba.conf entry")
+        && (message.ends_with("no encryption") || message.ends_with("SSL off"))
+}
+
+/// What a statement does to `statement_timeout`, if anything.
+#[derive(Debug, PartialEq)]
+enum TimeoutChange {
+    /// `SET [SESSION] statement_timeout` or `RESET`; None for the default.
+    Session(Option<Duration>),
+    /// `SET LOCAL statement_timeout`, until the transaction ends.
+    Local(Option<Duration>),
+}
+
+/// Recognize `SET [SESSION | LOCAL] statement_timeout {= | TO} value | DEFAULT`
+/// and `RESET statement_timeout` / `RESET ALL`.
+fn statement_timeout_change(sql: &str) -> Option<TimeoutChange> {
+    let sql = sql
+        .trim()
+        .trim_end_matches(';')
+        .to_ascii_lowercase()
+        .replace('=', " = ");
+    let mut words = sql.split_whitespace();
+    match words.next()? {
+        "reset" => matches!(words.next()?, "statement_timeout" | "all")
+            .then_some(TimeoutChange::Session(None)),
+        "set" => {
+            let mut name = words.next()?;
+            let local = name == "local";
+            if matches!(name, "local" | "session") {
+                name = words.next()?;
+            }
+            if name != "statement_timeout" || !matches!(words.next()?, "=" | "to") {
+                return None;
+            }
+            let value: Vec<&str> = words.collect();
+            let timeout = match value[..] {
+                ["default"] => None,
+                _ => Some(parse_timeout(&value.join(""))?),
+            };
+            Some(if local {
+                TimeoutChange::Local(timeout)
+            } else {
+                TimeoutChange::Session(timeout)
+            })
+        }
+        _ => None,
+    }
+}
+
+/// statement_timeout from the startup packet: a `statement_timeout` parameter, or
+/// `-c statement_timeout=...` / `--statement_timeout=...` in `options`.
+fn startup_statement_timeout(params: &[(String, String)]) -> Option<Duration> {
+    let mut found = None;
+    for (name, value) in params {
+        if name == "statement_timeout" {
+            found = parse_timeout(value).or(found);
+        } else if name == "options" {
+            let mut tokens = value.split_whitespace();
+            while let Some(token) = tokens.next() {
+                let setting = match token {
+                    "-c" => tokens.next().unwrap_or_default(),
+                    _ => token
+                        .strip_prefix("-c")
+                        .or_else(|| token.strip_prefix("--"))
+                        .unwrap_or_default(),
+                };
+                if let Some(value) = setting.strip_prefix("statement_timeout=") {
+                    found = parse_timeout(value).or(found);
+                }
+            }
+        }
+    }
+    found
+}
+
+/// A statement_timeout value: plain milliseconds or a number with a unit
+/// (`us`, `ms`, `s`, `min`, `h`, `d`), optionally quoted.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim().trim_matches(|c| c == '\'' || c == '"').trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let amount: f64 = value[..split].parse().ok()?;
+    let unit_ms = match value[split..].trim() {
+        "" | "ms" => 1.0,
+        "us" => 0.001,
+        "s" => 1000.0,
+        "min" => 60_000.0,
+        "h" => 3_600_000.0,
+        "d" => 86_400_000.0,
+        _ => return None,
+    };
+    Some(Duration::from_secs_f64(amount * unit_ms / 1000.0))
+}
+
+/// "statement_timeout 5s, hit after 5.0s" — how a 57014 cancellation relates to
+/// the connection's statement_timeout. Latency is measured at the proxy, so it
+/// runs a little over the server's own timer.
+fn describe_cancel(timeout: Option<Duration>, duration: Option<Duration>) -> String {
+    let Some(timeout) = timeout else {
+        return "no statement_timeout seen on this connection".to_string();
+    };
+    let setting = format!("statement_timeout {}", format_timeout(timeout));
+    match duration {
+        Some(d) if d >= timeout.mul_f64(0.95) => {
+            format!("likely {setting}, canceled after {:.1}s", d.as_secs_f64())
+        }
+        Some(d) => format!(
+            "{setting} not reached, canceled after {:.1}s",
+            d.as_secs_f64()
+        ),
+        None => setting,
+    }
+}
+
+fn format_timeout(timeout: Duration) -> String {
+    let ms = timeout.as_millis();
+    if ms.is_multiple_of(1000) {
+        format!("{}s", ms / 1000)
+    } else {
+        format!("{ms}ms")
+    }
+}
+
+/// `--window` length: `30s`, `5m`, `1h`, or plain seconds.
+pub fn parse_span(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let amount: u64 = s[..split]
+        .parse()
+        .map_err(|_| format!("expected e.g. 30s, 5m or 1h, got {s:?}"))?;
+    let secs = match &s[split..] {
+        "" | "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 3600,
+        unit => return Err(format!("unknown unit {unit:?}: use s, m or h")),
+    };
+    if secs == 0 {
+        return Err("must be at least 1s".to_string());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// "5m", "1h", "90s": the largest whole unit.
+fn format_span(span: Duration) -> String {
+    match span.as_secs() {
+        secs if secs >= 3600 && secs.is_multiple_of(3600) => format!("{}h", secs / 3600),
+        secs if secs >= 60 && secs.is_multiple_of(60) => format!("{}m", secs / 60),
+        secs => format!("{secs}s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collector() -> StatsCollector {
+        StatsCollector::with_config(StatsConfig::default())
+    }
+
+    /// A simple-protocol QueryStart for `sql`.
+    fn query_start(sql: impl Into<String>) -> ProtoEvent {
+        ProtoEvent::QueryStart {
+            sql: sql.into(),
+            sql_bytes: 0,
+            portal: None,
+            params: Vec::new(),
+        }
+    }
+
+    fn error(message: &str) -> ProtoEvent {
+        ProtoEvent::QueryError {
+            severity: "ERROR".into(),
+            code: "23505".into(),
+            message: message.into(),
+        }
+    }
+
+    #[test]
+    fn test_plaintext_rejection_mentions_ssl() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        let rejected = ProtoEvent::QueryError {
+            severity: "FATAL".into(),
+            code: "28000".into(),
+            message: r#"no pg_hba.conf entry for host "10.0.0.5", user "app", database "app", no encryption"#.into(),
+        };
+        let Some(DisplayEventKind::Error { message, .. }) =
+            stats.apply_event(1, rejected, Instant::now())
+        else {
+            panic!("expected an error");
+        };
+        assert!(
+            message.ends_with(
+                "no encryption (the upstream requires SSL; start dbprobe with --upstream-tls)"
+            ),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_copy_throughput_events() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        stats.process_event(1, query_start("COPY t FROM STDIN"), Instant::now());
+        assert!(stats
+            .process_event(1, ProtoEvent::CopyStarted, Instant::now())
+            .is_none());
+
+        let progress = stats
+            .process_event(1, ProtoEvent::CopyProgress { bytes: 4096 }, Instant::now())
+            .unwrap();
+        assert!(matches!(
+            progress.kind,
+            DisplayEventKind::Copy {
+                bytes: 4096,
+                done: false,
+                ..
+            }
+        ));
+
+        let done = stats
+            .process_event(1, ProtoEvent::CopyDone { bytes: 8192 }, Instant::now())
+            .unwrap();
+        assert!(matches!(
+            done.kind,
+            DisplayEventKind::Copy {
+                bytes: 8192,
+                done: true,
+                ..
+            }
+        ));
+
+        let complete = stats
+            .process_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "COPY 3".into(),
+                    rows: Some(3),
+                    bytes: 0,
+                },
+                Instant::now(),
+            )
+            .unwrap();
+        match complete.kind {
+            DisplayEventKind::Query { sql, rows, .. } => {
+                assert_eq!(sql, "COPY t FROM STDIN");
+                assert_eq!(rows, Some(3));
+            }
+            other => panic!("Expected Query, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_copy_fail_errors_the_copy_statement() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        let now = Instant::now();
+        stats.process_event(1, query_start("COPY t FROM STDIN"), now);
+        stats.process_event(1, ProtoEvent::CopyStarted, now);
+
+        let abort = stats
+            .process_event(
+                1,
+                ProtoEvent::CopyFail {
+                    bytes: 2 * 1024 * 1024,
+                    reason: "bad input file".into(),
+                },
+                now,
+            )
+            .unwrap();
+        match abort.kind {
+            DisplayEventKind::Warning(message) => {
+                assert_eq!(
+                    message,
+                    "COPY aborted by client after 2.0MB: bad input file"
+                )
+            }
+            other => panic!("Expected Warning, got {other:?}"),
+        }
+
+        let error = ProtoEvent::QueryError {
+            severity: "ERROR".into(),
+            code: "57014".into(),
+            message: "COPY from stdin failed: bad input file".into(),
+        };
+        match stats.process_event(1, error, now).unwrap().kind {
+            DisplayEventKind::Error { sql, code, .. } => {
+                assert_eq!(sql.as_deref(), Some("COPY t FROM STDIN"));
+                assert_eq!(code, "57014");
+            }
+            other => panic!("Expected Error, got {other:?}"),
+        }
+        // Nothing left pending for the next statement to be mistaken for.
+        stats.process_event(1, query_start("SELECT 1"), now);
+        let (sql, _) = complete(&mut stats, 1);
+        assert_eq!(sql, "SELECT 1");
+        assert!(stats
+            .process_event(1, ProtoEvent::CopyDone { bytes: 0 }, now)
+            .is_none());
+    }
+
+    fn run_statement(
+        stats: &mut StatsCollector,
+        sql: &str,
+        status: TxStatus,
+    ) -> Option<DisplayEvent> {
+        stats.process_event(1, query_start(sql), Instant::now());
+        stats.process_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: sql.into(),
+                rows: None,
+                bytes: 0,
+            },
+            Instant::now(),
+        );
+        stats.process_event(1, ProtoEvent::ConnectionReady { status }, Instant::now())
+    }
+
+    fn execute(stats: &mut StatsCollector, portal: &str, sql: &str) {
+        stats.process_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: sql.into(),
+                sql_bytes: 0,
+                portal: Some(portal.into()),
+                params: Vec::new(),
+            },
+            Instant::now(),
+        );
+    }
+
+    fn complete(stats: &mut StatsCollector, rows: u64) -> (String, u64) {
+        let event = stats
+            .process_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: format!("SELECT {rows}"),
+                    rows: Some(rows),
+                    bytes: 0,
+                },
+                Instant::now(),
+            )
+            .unwrap();
+        match event.kind {
+            DisplayEventKind::Query { sql, rows, .. } => (sql, rows.unwrap()),
+            other => panic!("Expected Query, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_interleaved_portals_attribute_to_their_statement() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+
+        // Cursor-style fetch on "a" (row limit) interleaved with a full execute of "b".
+        execute(&mut stats, "a", "SELECT * FROM big");
+        execute(&mut stats, "b", "SELECT * FROM small");
+        assert!(stats
+            .process_event(1, ProtoEvent::PortalSuspended, Instant::now())
+            .is_none());
+        assert_eq!(complete(&mut stats, 3), ("SELECT * FROM small".into(), 3));
+
+        std::thread::sleep(Duration::from_millis(20));
+        execute(&mut stats, "a", "SELECT * FROM big");
+        let event = stats
+            .process_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 2".into(),
+                    rows: Some(2),
+                    bytes: 0,
+                },
+                Instant::now(),
+            )
+            .unwrap();
+        match event.kind {
+            DisplayEventKind::Query { sql, duration, .. } => {
+                assert_eq!(sql, "SELECT * FROM big");
+                // Timed from the first Execute, not the resuming one.
+                assert!(duration >= Duration::from_millis(20));
+            }
+            other => panic!("Expected Query, got {other:?}"),
+        }
+        assert_eq!(stats.total_queries, 2);
+    }
+
+    #[test]
+    fn test_result_format_follows_the_portal_bind() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        let bind = |portal: &str, result_format| ProtoEvent::Bind {
+            portal: portal.into(),
+            result_format,
+        };
+
+        // Both bound before either runs; each Execute takes its own portal's format.
+        stats.process_event(1, bind("a", ResultFormat::Binary), Instant::now());
+        stats.process_event(1, bind("b", ResultFormat::Text), Instant::now());
+        execute(&mut stats, "b", "SELECT name FROM users");
+        execute(&mut stats, "a", "SELECT avatar FROM users");
+        complete(&mut stats, 1);
+        complete(&mut stats, 1);
+        stats.process_event(1, query_start("SELECT 1"), Instant::now());
+        complete(&mut stats, 1);
+
+        let format = |fp: &str| stats.fingerprints[fp].result_format;
+        assert_eq!(
+            format("select avatar from users"),
+            Some(ResultFormat::Binary)
+        );
+        assert_eq!(format("select name from users"), Some(ResultFormat::Text));
+        assert_eq!(format("select $n"), Some(ResultFormat::Text));
+    }
+
+    #[test]
+    fn test_slow_transaction_from_gaps_between_fast_statements() {
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            slow_tx_threshold: Duration::from_millis(40),
+            ..StatsConfig::default()
+        });
+        stats.connection_opened(1, None);
+
+        // Autocommit statements are never flagged, however far apart.
+        assert!(run_statement(&mut stats, "SELECT 1", TxStatus::Idle).is_none());
+
+        assert!(run_statement(&mut stats, "BEGIN", TxStatus::InTransaction).is_none());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(run_statement(&mut stats, "UPDATE t SET x = 1", TxStatus::InTransaction).is_none());
+        std::thread::sleep(Duration::from_millis(30));
+        let event =
+            run_statement(&mut stats, "COMMIT", TxStatus::Idle).expect("slow transaction flagged");
+
+        let DisplayEventKind::SlowTransaction(timing) = event.kind else {
+            panic!("Expected SlowTransaction, got {:?}", event.kind);
+        };
+        assert_eq!(timing.statements, 3);
+        assert!(timing.total >= Duration::from_millis(60));
+        assert!(timing.max_gap >= Duration::from_millis(30));
+        assert!(timing.idle() > timing.busy);
+
+        // A quick transaction stays under the threshold.
+        run_statement(&mut stats, "BEGIN", TxStatus::InTransaction);
+        assert!(run_statement(&mut stats, "COMMIT", TxStatus::Idle).is_none());
+    }
+
+    #[test]
+    fn test_multi_statement_simple_query_completes_each_statement() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(1, None);
+        stats.connection_opened(2, None);
+        let start = Instant::now();
+        stats.process_event(1, query_start("SELECT 1; SELECT 2; SELECT 3"), start);
+        stats.process_event(2, query_start("SELECT 4"), start);
+
+        let mut completed = Vec::new();
+        for (i, ms) in [(1, 10), (2, 30), (3, 60)] {
+            let at = start + Duration::from_millis(ms);
+            let tag = format!("SELECT {i}");
+            match stats
+                .process_event(
+                    1,
+                    ProtoEvent::QueryComplete {
+                        tag,
+                        rows: Some(1),
+                        bytes: 0,
+                    },
+                    at,
+                )
+                .map(|e| e.kind)
+            {
+                Some(DisplayEventKind::Query { sql, duration, .. }) => {
+                    completed.push((sql, duration))
+                }
+                other => panic!("Expected Query, got {other:?}"),
+            }
+        }
+        assert_eq!(
+            completed,
+            [
+                ("SELECT 1".to_string(), Duration::from_millis(10)),
+                ("SELECT 2".to_string(), Duration::from_millis(20)),
+                ("SELECT 3".to_string(), Duration::from_millis(30)),
+            ]
+        );
+        // A surplus CommandComplete finds nothing, and leaves other connections alone.
+        assert!(stats
+            .process_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0
+                },
+                start
+            )
+            .is_none());
+        stats.process_event(
+            1,
+            ProtoEvent::ConnectionReady {
+                status: TxStatus::Idle,
+            },
+            start,
+        );
+        let event = stats.process_event(
+            2,
+            ProtoEvent::QueryComplete {
+                tag: "SELECT 1".into(),
+                rows: Some(1),
+                bytes: 0,
+            },
+            start,
+        );
+        assert!(
+            matches!(event.map(|e| e.kind), Some(DisplayEventKind::Query { sql, .. }) if sql == "SELECT 4")
+        );
+        assert_eq!(stats.total_queries, 4);
+        assert_eq!(stats.fingerprints["select $n"].count, 4);
+    }
+
+    #[test]
+    fn test_statement_timeout_parsing() {
+        use TimeoutChange::{Local, Session};
+        let ms = |n| Some(Duration::from_millis(n));
+        assert_eq!(
+            statement_timeout_change("SET statement_timeout = 5000"),
+            Some(Session(ms(5000)))
+        );
+        assert_eq!(
+            statement_timeout_change("set session statement_timeout to '1.5s';"),
+            Some(Session(ms(1500)))
+        );
+        assert_eq!(
+            statement_timeout_change("SET LOCAL statement_timeout='1 min'"),
+            Some(Local(ms(60_000)))
+        );
+        assert_eq!(
+            statement_timeout_change("SET statement_timeout TO DEFAULT"),
+            Some(Session(None))
+        );
+        assert_eq!(statement_timeout_change("RESET ALL"), Some(Session(None)));
+        assert_eq!(statement_timeout_change("SET lock_timeout = 100"), None);
+        assert_eq!(
+            statement_timeout_change("SET statement_timeout = 'soon'"),
+            None
+        );
+
+        let params = |options: &str| {
+            vec![
+                ("user".to_string(), "app".to_string()),
+                ("options".to_string(), options.to_string()),
+            ]
+        };
+        assert_eq!(
+            startup_statement_timeout(&params("-c search_path=app -c statement_timeout=250")),
+            ms(250)
+        );
+        assert_eq!(
+            startup_statement_timeout(&params("-cstatement_timeout=2s")),
+            ms(2000)
+        );
+        assert_eq!(
+            startup_statement_timeout(&params("--statement_timeout=3min")),
+            ms(180_000)
+        );
+        assert_eq!(
+            startup_statement_timeout(&params("-c search_path=app")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_set_statement_timeout_annotates_cancellations() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(1, None);
+        let options = vec![(
+            "options".to_string(),
+            "-c statement_timeout=10s".to_string(),
+        )];
+        stats.process_event(1, ProtoEvent::Startup { params: options }, Instant::now());
+        assert_eq!(
+            stats.connections[&1].effective_statement_timeout(),
+            Some(Duration::from_secs(10))
+        );
+
+        let start = Instant::now();
+        stats.process_event(1, query_start("SET statement_timeout = '2s'"), start);
+        stats.process_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SET".into(),
+                rows: None,
+                bytes: 0,
+            },
+            start,
+        );
+        assert_eq!(
+            stats.connections[&1].effective_statement_timeout(),
+            Some(Duration::from_secs(2))
+        );
+
+        let cancel = |stats: &mut StatsCollector, ms| {
+            stats.process_event(1, query_start("SELECT pg_sleep(60)"), start);
+            let error = ProtoEvent::QueryError {
+                severity: "ERROR".into(),
+                code: "57014".into(),
+                message: "canceling statement due to statement timeout".into(),
+            };
+            match stats
+                .process_event(1, error, start + Duration::from_millis(ms))
+                .map(|e| e.kind)
+            {
+                Some(DisplayEventKind::Error { message, .. }) => message,
+                other => panic!("Expected Error, got {other:?}"),
+            }
+        };
+        assert!(cancel(&mut stats, 2004)
+            .ends_with("(likely statement_timeout 2s, canceled after 2.0s)"));
+        assert!(cancel(&mut stats, 300)
+            .ends_with("(statement_timeout 2s not reached, canceled after 0.3s)"));
+        // Grouping uses the server's message, not the annotation.
+        assert_eq!(stats.errors.len(), 1);
+
+        // RESET goes back to the startup value.
+        stats.process_event(1, query_start("RESET statement_timeout"), start);
+        stats.process_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "RESET".into(),
+                rows: None,
+                bytes: 0,
+            },
+            start,
+        );
+        assert!(cancel(&mut stats, 300)
+            .ends_with("(statement_timeout 10s not reached, canceled after 0.3s)"));
+
+        stats.process_event(1, query_start("SET statement_timeout = 0"), start);
+        stats.process_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SET".into(),
+                rows: None,
+                bytes: 0,
+            },
+            start,
+        );
+        assert!(cancel(&mut stats, 300).ends_with("(no statement_timeout seen on this connection)"));
+    }
+
+    #[test]
+    fn test_window_rolls_on_schedule() {
+        assert_eq!(parse_span("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_span("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_span("0s").is_err());
+        assert!(parse_span("5d").is_err());
+
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            window: Some(Duration::from_secs(300)),
+            ..StatsConfig::default()
+        });
+        let start = stats.window_started;
+        assert_eq!(
+            stats
+                .window_label(start + Duration::from_secs(200))
+                .unwrap(),
+            "window: 5m (3m elapsed)"
+        );
+        assert!(!stats.roll_window(start + Duration::from_secs(299)));
+        // A manual reset doesn't move the schedule.
+        stats.reset();
+        assert!(stats.roll_window(start + Duration::from_secs(300)));
+        assert!(!stats.roll_window(start + Duration::from_secs(301)));
+        // After an idle stretch the next window still starts on a boundary.
+        assert!(stats.roll_window(start + Duration::from_secs(1000)));
+        assert_eq!(
+            stats
+                .window_label(start + Duration::from_secs(1000))
+                .unwrap(),
+            "window: 5m (1m elapsed)"
+        );
+        assert!(StatsCollector::with_config(StatsConfig::default())
+            .window_label(start)
+            .is_none());
+    }
+
+    #[test]
+    fn test_qps_before_the_window_fills() {
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            qps_window: Duration::from_secs(10),
+            ..StatsConfig::default()
+        });
+        stats.connection_opened(1, None);
+        let start = stats.qps_since;
+        let run_at = |stats: &mut StatsCollector, ms: u64| {
+            let at = start + Duration::from_millis(ms);
+            stats.record_event(1, query_start("SELECT 1"), at);
+            stats.record_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                },
+                at,
+            );
+        };
+        for ms in [100, 200, 300, 400, 500] {
+            run_at(&mut stats, ms);
+        }
+        assert_eq!(stats.qps_at(start), 0.0);
+        // One second in, five queries is 5 qps, not 5 over the whole 10s window.
+        assert_eq!(stats.qps_at(start + Duration::from_secs(1)), 5.0);
+        for ms in [25_000, 26_000, 27_000, 28_000, 29_000] {
+            run_at(&mut stats, ms);
+        }
+        assert_eq!(stats.qps_at(start + Duration::from_secs(30)), 0.5);
+    }
+
+    #[test]
+    fn test_off_list_queries_are_flagged() {
+        let allowed = ["SELECT * FROM users WHERE id = 1", "BEGIN", "COMMIT"].map(fingerprint);
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            allowed_fingerprints: Some(Arc::new(allowed.into_iter().collect())),
+            ..StatsConfig::default()
+        });
+        stats.connection_opened(1, None);
+        let now = Instant::now();
+        let mut start = |sql: &str| stats.apply_event(1, query_start(sql), now);
+
+        assert!(start("SELECT * FROM users WHERE id = 7").is_none());
+        assert!(start("BEGIN").is_none());
+        let warning = start("SELECT * FROM users WHERE id = 7 OR 1=1");
+        assert!(matches!(warning, Some(DisplayEventKind::Warning(m))
+            if m == "UNEXPECTED QUERY SHAPE (not allowed): select * from users where id = $n or $n=$n"));
+        // Reported once per shape, counted every time.
+        assert!(start("SELECT * FROM users WHERE id = 8 OR 2=2").is_none());
+        let warning = start("COMMIT; DROP TABLE users");
+        assert!(
+            matches!(warning, Some(DisplayEventKind::Warning(m)) if m.ends_with(": drop table users"))
+        );
+
+        assert_eq!(stats.unexpected_shapes.len(), 2);
+        assert_eq!(
+            stats.unexpected_shapes["select * from users where id = $n or $n=$n"],
+            2
+        );
+    }
+
+    #[test]
+    fn test_queries_are_numbered_per_connection() {
+        let mut stats = collector();
+        let now = Instant::now();
+        let mut seqs = Vec::new();
+        let mut run = |stats: &mut StatsCollector, conn_id, event| {
+            if let Some(DisplayEventKind::Query { seq, .. }) =
+                stats.apply_event(conn_id, event, now)
+            {
+                seqs.push((conn_id, seq));
+            }
+        };
+        let start = |sql: &str, portal: Option<&str>| ProtoEvent::QueryStart {
+            sql: sql.into(),
+            sql_bytes: 0,
+            portal: portal.map(Into::into),
+            params: Vec::new(),
+        };
+        let complete = || ProtoEvent::QueryComplete {
+            tag: "SELECT 1".into(),
+            rows: Some(1),
+            bytes: 0,
+        };
+
+        run(&mut stats, 1, start("SELECT 1; SELECT 2", None));
+        run(&mut stats, 1, complete());
+        run(&mut stats, 1, complete());
+        run(&mut stats, 2, start("SELECT 1", None));
+        run(&mut stats, 2, complete());
+        // A suspended portal resumed by a second Execute is still one query.
+        run(&mut stats, 1, start("SELECT * FROM big", Some("p1")));
+        run(&mut stats, 1, ProtoEvent::PortalSuspended);
+        run(&mut stats, 1, start("SELECT * FROM big", Some("p1")));
+        run(&mut stats, 1, complete());
+        run(&mut stats, 1, start("SELECT 4", None));
+        run(&mut stats, 1, complete());
+
+        assert_eq!(seqs, [(1, 1), (1, 2), (2, 1), (1, 3), (1, 4)]);
+    }
+
+    #[test]
+    fn test_parse_note_names_declared_param_types() {
+        let mut stats = collector();
+        let parse = |param_types| ProtoEvent::ParseDetected {
+            sql: "SELECT $1, $2".into(),
+            param_types,
+        };
+        let Some(DisplayEventKind::Warning(note)) =
+            stats.apply_event(1, parse(vec![25, 99999]), Instant::now())
+        else {
+            panic!("expected a Parse note");
+        };
+        assert_eq!(
+            note,
+            "Extended query protocol: SELECT $1, $2 [$1 text, $2 99999]"
+        );
+        let Some(DisplayEventKind::Warning(note)) =
+            stats.apply_event(1, parse(Vec::new()), Instant::now())
+        else {
+            panic!("expected a Parse note");
+        };
+        assert_eq!(note, "Extended query protocol: SELECT $1, $2");
+    }
+
+    #[test]
+    fn test_repeated_session_resets_label_a_pooler_connection() {
+        let mut stats = collector();
+        let now = Instant::now();
+        stats.connection_opened(1, None);
+        stats.connection_opened(2, None);
+        let run = |stats: &mut StatsCollector, conn_id, sql: &str| {
+            let note = stats.apply_event(conn_id, query_start(sql), now);
+            stats.apply_event(
+                conn_id,
+                ProtoEvent::QueryComplete {
+                    tag: "OK".into(),
+                    rows: None,
+                    bytes: 0,
+                },
+                now,
+            );
+            note
+        };
+
+        // One reset is just an application tidying up.
+        assert!(run(&mut stats, 2, "SELECT 1").is_none());
+        assert!(run(&mut stats, 2, "discard all;").is_none());
+        assert!(run(&mut stats, 1, "DISCARD ALL").is_none());
+        assert!(run(&mut stats, 1, "SELECT 1").is_none());
+        assert_eq!(stats.pooled_connections, 0);
+
+        let Some(DisplayEventKind::Warning(note)) = run(&mut stats, 1, "RESET ALL") else {
+            panic!("expected a pooler note");
+        };
+        assert!(
+            note.starts_with("conn 1: looks like a pooler connection (2 session resets)"),
+            "{note}"
+        );
+        // Labeled once; later resets stay quiet.
+        assert!(run(&mut stats, 1, "DISCARD ALL").is_none());
+        assert!(run(&mut stats, 2, "RESET statement_timeout").is_none());
+        assert_eq!(
+            (stats.pooled_connections, stats.live_pooled_connections()),
+            (1, 1)
+        );
+        assert_eq!(stats.freeze().pooled_connections, 1);
+
+        // A new window recounts from the connections still open.
+        stats.connection_dropped(1);
+        stats.reset();
+        assert_eq!(stats.pooled_connections, 0);
+    }
+
+    #[test]
+    fn test_slow_client_warnings_are_rate_limited() {
+        let mut stats = collector();
+        stats.connection_opened(7, None);
+        let start = Instant::now();
+        let waited = Duration::from_secs(1);
+
+        let warning = stats.client_backpressure(7, waited, start).unwrap();
+        assert!(matches!(&warning.kind, DisplayEventKind::Warning(m)
+            if m == "conn 7: slow client, backpressuring (server data waited over 1s for the client to read)"));
+        assert!(stats
+            .client_backpressure(7, waited, start + Duration::from_secs(2))
+            .is_none());
+        assert!(stats
+            .client_backpressure(8, waited, start + Duration::from_secs(2))
+            .is_some());
+        assert!(stats
+            .client_backpressure(7, waited, start + SLOW_CLIENT_WARN_INTERVAL)
+            .is_some());
+        assert_eq!(stats.backpressure_stalls, 4);
+    }
+
+    #[test]
+    fn test_connection_churn_rate_warns_once() {
+        // A long window keeps the rate steady however slowly the test runs.
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            qps_window: Duration::from_secs(60),
+            churn_threshold: 1,
+            ..StatsConfig::default()
+        });
+        let mut warnings = Vec::new();
+        for conn_id in 1..=120 {
+            let events = stats.connection_opened(conn_id, None);
+            assert!(matches!(events[0].kind, DisplayEventKind::ConnectionOpened));
+            warnings.extend(events.into_iter().skip(1).map(|e| (e.conn_id, e.kind)));
+            stats.connection_dropped(conn_id);
+        }
+        assert!(
+            (stats.conn_churn() - 2.0).abs() < 1e-9,
+            "{}",
+            stats.conn_churn()
+        );
+        // Over 1/s from the 61st connection on, warned about once.
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            matches!(&warnings[0], (61, DisplayEventKind::Warning(m)) if m.starts_with("conn churn: 1.0/s, over --churn-threshold 1/s"))
+        );
+
+        stats.reset();
+        assert_eq!(stats.conn_churn(), 0.0);
+    }
+
+    #[test]
+    fn test_no_sql_times_queries_without_fingerprints() {
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            no_sql: true,
+            ..StatsConfig::default()
+        });
+        stats.connection_opened(1, None);
+        let start = Instant::now();
+        stats.process_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: String::new(),
+                sql_bytes: 20,
+                portal: None,
+                params: Vec::new(),
+            },
+            start,
+        );
+        let event = stats.process_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SELECT 1".into(),
+                rows: Some(1),
+                bytes: 0,
+            },
+            start + Duration::from_millis(3),
+        );
+        assert!(
+            matches!(event.map(|e| e.kind), Some(DisplayEventKind::Query { sql, duration, .. })
+            if sql == SQL_NOT_CAPTURED && duration == Duration::from_millis(3))
+        );
+        assert_eq!(stats.total_queries, 1);
+        assert_eq!(stats.latency_buckets[1], 1);
+        assert!(stats.fingerprints.is_empty());
+        assert!(stats
+            .process_event(
+                1,
+                ProtoEvent::ParseDetected {
+                    sql: String::new(),
+                    param_types: Vec::new()
+                },
+                start
+            )
+            .is_none());
+
+        // `SELECT 1; SELECT 2` answers twice; each completion is its own query.
+        stats.process_event(
+            1,
+            ProtoEvent::QueryStartNoSql {
+                statements: 2,
+                sql_bytes: 17,
+            },
+            start,
+        );
+        stats.process_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: String::new(),
+                sql_bytes: 8,
+                portal: Some(String::new()),
+                params: Vec::new(),
+            },
+            start,
+        );
+        let complete = || ProtoEvent::QueryComplete {
+            tag: "SELECT 1".into(),
+            rows: Some(1),
+            bytes: 0,
+        };
+        let durations: Vec<_> = [2, 5, 9]
+            .into_iter()
+            .filter_map(|ms| stats.process_event(1, complete(), start + Duration::from_millis(ms)))
+            .filter_map(|e| match e.kind {
+                DisplayEventKind::Query { duration, .. } => Some(duration.as_millis()),
+                _ => None,
+            })
+            .collect();
+        // The second statement's time starts when the first one finished.
+        assert_eq!(durations, [2, 3, 9]);
+        assert_eq!((stats.total_queries, stats.in_flight), (4, 0));
+    }
+
+    #[test]
+    fn test_notification_is_shown_but_not_counted() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        let notification = ProtoEvent::Notification {
+            channel: "orders".into(),
+            payload: "7".into(),
+        };
+        let event = stats.process_event(1, notification, Instant::now());
+        assert!(
+            matches!(event.map(|e| e.kind), Some(DisplayEventKind::Notification { channel, payload }) if channel == "orders" && payload == "7")
+        );
+        assert_eq!((stats.total_queries, stats.total_errors), (0, 0));
+        assert!(stats.fingerprints.is_empty());
+    }
+
+    #[test]
+    fn test_notice_is_a_warning_not_an_error() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        let notice = ProtoEvent::Notice {
+            severity: "NOTICE".into(),
+            code: "42P07".into(),
+            message: "relation \"t\" already exists, skipping".into(),
+        };
+        let event = stats.process_event(1, notice, Instant::now());
+        assert!(
+            matches!(event.map(|e| e.kind), Some(DisplayEventKind::Warning(m)) if m == "NOTICE 42P07: relation \"t\" already exists, skipping")
+        );
+        assert_eq!(stats.total_errors, 0);
+    }
+
+    #[test]
+    fn test_in_flight_high_water_mark() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        stats.connection_opened(2, None);
+        let now = Instant::now();
+        let complete = || ProtoEvent::QueryComplete {
+            tag: "SELECT 1".into(),
+            rows: Some(1),
+            bytes: 0,
+        };
+        stats.process_event(1, query_start("SELECT 1; SELECT 2"), now);
+        stats.process_event(2, query_start("SELECT 3"), now);
+        assert_eq!((stats.in_flight, stats.max_in_flight), (3, 3));
+
+        stats.process_event(1, complete(), now);
+        stats.process_event(2, complete(), now);
+        assert_eq!((stats.in_flight, stats.max_in_flight), (1, 3));
+        stats.process_event(2, query_start("SELECT 4"), now);
+        assert_eq!((stats.in_flight, stats.max_in_flight), (2, 3));
+        assert_eq!(stats.freeze().max_in_flight, 3);
+
+        stats.reset();
+        assert_eq!(stats.max_in_flight, 2);
+        stats.connection_dropped(1);
+        stats.process_event(2, complete(), now);
+        assert_eq!((stats.in_flight, stats.max_in_flight), (0, 2));
+    }
+
+    #[test]
+    fn test_dropped_connection_reports_abandoned_queries() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(7, None);
+        let start = Instant::now() - Duration::from_millis(3200);
+        stats.process_event(7, query_start("SELECT pg_sleep(10)"), start);
+        // Terminate with the query unanswered: wait for the socket to close.
+        assert!(stats
+            .process_event(7, ProtoEvent::ConnectionClosed, Instant::now())
+            .is_none());
+        assert_eq!(stats.active_connections, 1);
+
+        let events = stats.connection_dropped(7);
+        assert_eq!(events.len(), 2);
+        let DisplayEventKind::Warning(msg) = &events[0].kind else {
+            panic!("Expected Warning, got {:?}", events[0].kind);
+        };
+        assert!(msg.starts_with("conn 7: query abandoned after 3."), "{msg}");
+        assert!(msg.ends_with(": SELECT pg_sleep(10)"));
+        assert!(matches!(events[1].kind, DisplayEventKind::ConnectionClosed));
+        assert_eq!(stats.abandoned_queries, 1);
+        assert_eq!(stats.active_connections, 0);
+
+        // A clean close abandons nothing.
+        stats.connection_opened(8, None);
+        assert_eq!(stats.connection_dropped(8).len(), 1);
+        assert_eq!(stats.abandoned_queries, 1);
+    }
+
+    #[test]
+    fn test_distinct_applications_and_hosts() {
+        let mut stats = collector();
+        let startup = |app: &str| ProtoEvent::Startup {
+            params: vec![
+                ("user".into(), "app".into()),
+                ("application_name".into(), app.into()),
+            ],
+        };
+        for (conn_id, addr, app) in [
+            (1, "10.0.0.1:5000", "api"),
+            (2, "10.0.0.1:5001", "api"),
+            (3, "10.0.0.2:6000", ""),
+        ] {
+            stats.connection_opened(conn_id, Some(addr.parse().unwrap()));
+            stats.process_event(conn_id, startup(app), Instant::now());
+        }
+        stats.process_event(3, ProtoEvent::ConnectionClosed, Instant::now());
+        assert_eq!(stats.applications, HashSet::from(["api".to_string()]));
+        assert_eq!(stats.client_hosts.len(), 2);
+
+        // Reset keeps only the sources still connected.
+        stats.reset();
+        assert_eq!(stats.applications.len(), 1);
+        assert_eq!(
+            stats.client_hosts,
+            HashSet::from(["10.0.0.1".parse::<IpAddr>().unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_group_by_splits_aggregates() {
+        let startup = |app: &str| ProtoEvent::Startup {
+            params: vec![
+                ("user".into(), "app".into()),
+                ("application_name".into(), app.into()),
+            ],
+        };
+        let query = |id: u32| query_start(format!("SELECT * FROM t WHERE id = {id}"));
+        let done = || ProtoEvent::QueryComplete {
+            tag: "SELECT 1".into(),
+            rows: Some(1),
+            bytes: 0,
+        };
+
+        for (group_by, expected) in [
+            (
+                GroupBy::Fingerprint,
+                vec![("select * from t where id = $n", None, 3)],
+            ),
+            (
+                GroupBy::FingerprintApp,
+                vec![
+                    (
+                        "select * from t where id = $n [app=api]",
+                        Some("app=api"),
+                        2,
+                    ),
+                    (
+                        "select * from t where id = $n [app=worker]",
+                        Some("app=worker"),
+                        1,
+                    ),
+                ],
+            ),
+            (
+                GroupBy::FingerprintConn,
+                vec![
+                    ("select * from t where id = $n [conn=1]", Some("conn=1"), 1),
+                    ("select * from t where id = $n [conn=2]", Some("conn=2"), 1),
+                    ("select * from t where id = $n [conn=3]", Some("conn=3"), 1),
+                ],
+            ),
+        ] {
+            let mut stats = StatsCollector::with_config(StatsConfig {
+                group_by,
+                ..StatsConfig::default()
+            });
+            for (conn_id, app) in [(1, "api"), (2, "api"), (3, "worker")] {
+                stats.connection_opened(conn_id, None);
+                stats.record_event(conn_id, startup(app), Instant::now());
+                stats.record_event(conn_id, query(conn_id as u32), Instant::now());
+                stats.record_event(conn_id, done(), Instant::now());
+            }
+
+            let mut buckets: Vec<_> = stats
+                .fingerprints
+                .iter()
+                .map(|(key, q)| (key.as_str(), q.group.as_deref(), q.count))
+                .collect();
+            buckets.sort();
+            assert_eq!(buckets, expected, "{group_by:?}");
+            assert!(stats
+                .fingerprints
+                .values()
+                .all(|q| q.fingerprint == "select * from t where id = $n"));
+        }
+    }
+
+    #[test]
+    fn test_message_counts_accumulate() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        let now = Instant::now();
+        let batch = |counts: &[(Direction, &'static str, u64)]| ProtoEvent::MessageCounts {
+            counts: counts.to_vec(),
+        };
+        stats.record_event(
+            1,
+            batch(&[
+                (Direction::Frontend, "Query", 2),
+                (Direction::Backend, "DataRow", 10),
+            ]),
+            now,
+        );
+        assert_eq!(stats.message_counts.query_protocol(), Some("simple"));
+        stats.record_event(
+            2,
+            batch(&[
+                (Direction::Frontend, "Execute", 1),
+                (Direction::Backend, "DataRow", 5),
+            ]),
+            now,
+        );
+
+        assert_eq!(stats.message_counts.query_protocol(), Some("mixed"));
+        assert_eq!(
+            stats.message_counts.ranked(Direction::Backend),
+            vec![("DataRow", 15)]
+        );
+        assert_eq!(
+            stats.message_counts.ranked(Direction::Frontend),
+            vec![("Query", 2), ("Execute", 1)]
+        );
+        assert_eq!(stats.freeze().message_counts, stats.message_counts);
+
+        stats.reset();
+        assert!(stats.message_counts.is_empty());
+        assert_eq!(stats.message_counts.query_protocol(), None);
+    }
+
+    #[test]
+    fn test_errors_group_by_normalized_pattern() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        stats.process_event(
+            1,
+            error("duplicate key value violates unique constraint \"users_pkey\""),
+            Instant::now(),
+        );
+        stats.process_event(
+            1,
+            error("duplicate key value violates unique constraint \"orders_pkey\""),
+            Instant::now(),
+        );
+
+        assert_eq!(stats.errors.len(), 1);
+        let group = stats.errors.values().next().unwrap();
+        assert_eq!(group.code, "23505");
+        assert_eq!(group.count, 2);
+        assert_eq!(
+            group.pattern,
+            "duplicate key value violates unique constraint \"$I\""
+        );
+        assert_eq!(group.examples.len(), 2);
+        assert!(group.examples[1].contains("orders_pkey"));
+    }
+
+    #[test]
+    fn test_approx_memory_grows_with_fingerprints() {
+        let mut stats = collector();
+        stats.connection_opened(1, None);
+        let empty = stats.approx_memory_bytes();
+        let now = Instant::now();
+        for table in 0..50 {
+            stats.record_event(
+                1,
+                query_start(format!("SELECT * FROM t_{}", "x".repeat(table + 1))),
+                now,
+            );
+            stats.record_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                },
+                now,
+            );
+        }
+        let grown = stats.approx_memory_bytes();
+        // At least both copies of every fingerprint string.
+        assert!(grown >= empty + 50 * 2 * "select * from t_x".len());
+        assert!(stats.freeze().approx_memory_bytes() >= grown - empty);
+        stats.reset();
+        assert!(stats.approx_memory_bytes() < grown);
+    }
+
+    #[test]
+    fn test_wide_select_star_warns_once_per_fingerprint() {
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            wide_result_columns: 40,
+            ..StatsConfig::default()
+        });
+        stats.connection_opened(1, None);
+        let mut run = |sql: &str, columns| {
+            let now = Instant::now();
+            stats.record_event(1, query_start(sql), now);
+            let warning = stats.process_event(1, ProtoEvent::RowDescription { columns }, now);
+            stats.record_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                },
+                now,
+            );
+            warning.map(|e| match e.kind {
+                DisplayEventKind::Warning(message) => message,
+                other => panic!("expected a warning, got {other:?}"),
+            })
+        };
+
+        let warning = run("SELECT * FROM accounts WHERE id = 1", 80).unwrap();
+        assert!(warning.contains("80 columns from select * from accounts where id = $n"));
+        assert_eq!(run("SELECT * FROM accounts WHERE id = 2", 80), None);
+        assert_eq!(run("SELECT * FROM small WHERE id = 2", 3), None);
+        assert_eq!(
+            run(
+                &format!("SELECT {} FROM accounts", ["a"; 80].join(", ")),
+                80
+            ),
+            None
+        );
+
+        assert_eq!(
+            stats.fingerprints["select * from accounts where id = $n"].columns,
+            Some(80)
+        );
+        assert_eq!(
+            stats.fingerprints["select * from small where id = $n"].columns,
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_large_result_is_flagged() {
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            large_result_bytes: 1024,
+            ..StatsConfig::default()
+        });
+        stats.connection_opened(1, None);
+        let mut run = |bytes| {
+            let now = Instant::now();
+            stats.record_event(1, query_start("SELECT blob FROM files"), now);
+            match stats.process_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 2".into(),
+                    rows: Some(2),
+                    bytes,
+                },
+                now,
+            ) {
+                Some(DisplayEvent {
+                    kind:
+                        DisplayEventKind::Query {
+                            result_bytes,
+                            large_result,
+                            ..
+                        },
+                    ..
+                }) => (result_bytes, large_result),
+                other => panic!("expected a query, got {other:?}"),
+            }
+        };
+        assert_eq!(run(1023), (1023, false));
+        assert_eq!(run(40 << 20), (40 << 20, true));
+    }
+
+    #[test]
+    fn test_large_query_is_flagged_with_its_real_size() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(1, None);
+        let now = Instant::now();
+        let sql = "SELECT * FROM users WHERE id IN (1, 2, 3, …".to_string();
+
+        let small = stats.apply_event(
+            1,
+            ProtoEvent::QueryStart {
+                sql: "SELECT 1".into(),
+                sql_bytes: 8,
+                portal: None,
+                params: Vec::new(),
+            },
+            now,
+        );
+        assert!(small.is_none());
+        stats.apply_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SELECT 1".into(),
+                rows: Some(1),
+                bytes: 0,
+            },
+            now,
+        );
+
+        // Truncated for display, 200KB as sent.
+        let start = ProtoEvent::QueryStart {
+            sql,
+            sql_bytes: 200 * 1024,
+            portal: None,
+            params: Vec::new(),
+        };
+        match stats.apply_event(1, start, now) {
+            Some(DisplayEventKind::Warning(message)) => {
+                assert!(message.contains("large query, 200.0KB of SQL"), "{message}")
+            }
+            other => panic!("expected a warning, got {other:?}"),
+        }
+        stats.apply_event(
+            1,
+            ProtoEvent::QueryComplete {
+                tag: "SELECT 3".into(),
+                rows: Some(3),
+                bytes: 0,
+            },
+            now,
+        );
+
+        assert_eq!(stats.sql_size_buckets, [1, 0, 0, 1, 0]);
+        let agg = &stats.fingerprints[&fingerprint("SELECT * FROM users WHERE id IN (1, 2, 3, …")];
+        assert_eq!(agg.max_sql_bytes, 200 * 1024);
+    }
+
+    #[test]
+    fn test_new_query_checks_report_every_warning() {
+        let config = StatsConfig {
+            allowed_fingerprints: Some(Arc::new(HashSet::new())),
+            ..StatsConfig::default()
+        };
+        let mut stats = StatsCollector::with_config(config);
+        stats.connection_opened(1, None);
+        let start = ProtoEvent::QueryStart {
+            sql: "SELECT 1".into(),
+            sql_bytes: 200 * 1024,
+            portal: None,
+            params: Vec::new(),
+        };
+        // Large and not allowed: both warnings, in priority order.
+        match stats.apply_event(1, start, Instant::now()) {
+            Some(DisplayEventKind::Warning(message)) => {
+                let (large, unexpected) = message.split_once(" | ").expect("two warnings");
+                assert!(large.contains("large query"), "{message}");
+                assert!(
+                    unexpected.starts_with("UNEXPECTED QUERY SHAPE"),
+                    "{message}"
+                );
+            }
+            other => panic!("expected a warning, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_percentiles_survive_freeze_and_reset() {
+        let mut stats = StatsCollector::with_config(StatsConfig::default());
+        stats.connection_opened(1, None);
+        assert_eq!(stats.percentiles(), None);
+        let start = Instant::now();
+        // 1ms through 100ms, one query each.
+        for ms in 1..=100 {
+            stats.record_event(1, query_start("SELECT 1"), start);
+            let done = start + Duration::from_millis(ms);
+            stats.record_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                },
+                done,
+            );
+        }
+
+        let (p50, p95, p99) = stats.percentiles().unwrap();
+        let near = |d: Duration, ms: f64| (d.as_secs_f64() * 1000.0 - ms).abs() <= ms / 32.0;
+        assert!(
+            near(p50, 50.0) && near(p95, 95.0) && near(p99, 99.0),
+            "{p50:?} {p95:?} {p99:?}"
+        );
+
+        let frozen = stats.freeze();
+        stats.reset();
+        assert_eq!(stats.percentiles(), None);
+        assert_eq!(frozen.percentiles(), Some((p50, p95, p99)));
+    }
+
+    #[test]
+    fn test_shape_window_reports_new_and_gone_fingerprints() {
+        let window = Duration::from_secs(60);
+        let mut stats = StatsCollector::with_config(StatsConfig {
+            shape_window: Some(window),
+            ..StatsConfig::default()
+        });
+        stats.connection_opened(1, None);
+        let start = Instant::now();
+        let mut run = |sql: &str, secs: u64| {
+            let at = start + Duration::from_secs(secs);
+            let warning = stats.process_event(1, query_start(sql), at);
+            stats.record_event(
+                1,
+                ProtoEvent::QueryComplete {
+                    tag: "SELECT 1".into(),
+                    rows: Some(1),
+                    bytes: 0,
+                },
+                at,
+            );
+            warning.map(|e| match e.kind {
+                DisplayEventKind::Warning(message) => message,
+                other => panic!("expected a warning, got {other:?}"),
+            })
+        };
+
+        // The first window only learns what's normal.
+        assert_eq!(run("SELECT * FROM users WHERE id = 1", 0), None);
+        assert_eq!(run("SELECT * FROM orders WHERE id = 1", 30), None);
+        assert_eq!(run("SELECT * FROM users WHERE id = 2", 65), None);
+
+        let new_shape = run("SELECT id FROM users WHERE email = 'a@b.c'", 70).unwrap();
+        assert_eq!(
+            new_shape,
+            "new query shape: select id from users where email = $s"
+        );
+        assert_eq!(run("SELECT id FROM users WHERE email = 'x@y.z'", 75), None);
+
+        // orders was last seen at 30s.
+        let gone = run("SELECT * FROM users WHERE id = 3", 95).unwrap();
+        assert_eq!(
+            gone,
+            "query shape gone (not seen in 1m): select * from orders where id = $n"
+        );
+        assert_eq!(run("SELECT * FROM users WHERE id = 4", 96), None);
+        // Back after going quiet counts as new again.
+        assert!(run("SELECT * FROM orders WHERE id = 2", 97)
+            .unwrap()
+            .starts_with("new query shape"));
     }
 }
@@ -0,0 +1,210 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+};
+use tokio_rustls::{server, TlsAcceptor, TlsConnector};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// `--listen-tls`: the server side of the TLS session with each client. The
+/// session ends at dbprobe, which parses and forwards the plaintext.
+#[derive(Clone)]
+pub struct ListenTls {
+    acceptor: TlsAcceptor,
+}
+
+impl ListenTls {
+    /// `cert` is the PEM chain to present, leaf first; `key` its PEM private key.
+    /// With `client_ca` (`--tls-client-cert`), clients are asked for a certificate
+    /// signed by one of its CAs. Presenting none is allowed; a bad one fails the handshake.
+    pub fn new(cert: &Path, key: &Path, client_ca: Option<&Path>) -> anyhow::Result<Self> {
+        let certs = load_certs(cert)?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", key.display()))?;
+        let builder = match client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(path)? {
+                    roots
+                        .add(cert)
+                        .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .allow_unauthenticated()
+                    .build()
+                    .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+                ServerConfig::builder().with_client_cert_verifier(verifier)
+            }
+            None => ServerConfig::builder().with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", cert.display()))?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    /// Run the handshake with a client that was just told 'S'.
+    pub async fn accept(&self, stream: TcpStream) -> std::io::Result<server::TlsStream<TcpStream>> {
+        self.acceptor.accept(stream).await
+    }
+}
+
+/// Subject of the certificate a client presented ("CN=app-service"), if it
+/// presented one. Only set with `--tls-client-cert`, which asks for it.
+pub fn client_subject(stream: &server::TlsStream<TcpStream>) -> Option<String> {
+    let leaf = stream.get_ref().1.peer_certificates()?.first()?;
+    let (_, cert) = X509Certificate::from_der(leaf).ok()?;
+    Some(cert.subject().to_string())
+}
+
+/// `--upstream-tls`: the client side of the TLS session to the upstream. The
+/// proxy still sees plaintext; only the hop to the server is encrypted.
+#[derive(Clone)]
+pub struct UpstreamTls {
+    connector: TlsConnector,
+}
+
+impl UpstreamTls {
+    /// Verify the server against the CA certificates in `ca` (PEM), or against
+    /// the bundled Mozilla roots without one. `insecure` skips verification.
+    pub fn new(ca: Option<&Path>, insecure: bool) -> anyhow::Result<Self> {
+        let builder = ClientConfig::builder();
+        let config = if insecure {
+            let provider = Arc::new(crypto::ring::default_provider());
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+                .with_no_client_auth()
+        } else {
+            let mut roots = RootCertStore::empty();
+            match ca {
+                Some(path) => {
+                    for cert in load_certs(path)? {
+                        roots
+                            .add(cert)
+                            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+                    }
+                }
+                None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            }
+            builder.with_root_certificates(roots).with_no_client_auth()
+        };
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    /// Run the handshake on a connection whose server has agreed to SSL. The
+    /// certificate must name the host part of `upstream_addr`.
+    pub async fn connect(
+        &self,
+        upstream_addr: &str,
+        stream: TcpStream,
+    ) -> std::io::Result<TlsStream<TcpStream>> {
+        self.connector
+            .connect(server_name(upstream_addr)?, stream)
+            .await
+    }
+}
+
+/// `db.example.com:5432` -> `db.example.com`; `[::1]:5432` -> `::1`.
+fn server_name(upstream_addr: &str) -> std::io::Result<ServerName<'static>> {
+    let host = upstream_addr
+        .rsplit_once(':')
+        .map_or(upstream_addr, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host.to_string()).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{host:?}: {e}"))
+    })
+}
+
+/// Every certificate in a PEM file.
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("{}: no certificates found", path.display());
+    }
+    Ok(certs)
+}
+
+/// `--upstream-tls-insecure`: accept any server certificate. Signatures are
+/// still checked, so the session is encrypted, just not authenticated.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_name_from_upstream() {
+        assert_eq!(
+            server_name("db.example.com:5432").unwrap(),
+            ServerName::try_from("db.example.com").unwrap()
+        );
+        assert_eq!(
+            server_name("[::1]:5432").unwrap(),
+            ServerName::try_from("::1").unwrap()
+        );
+        assert!(server_name("bad name:5432").is_err());
+    }
+}
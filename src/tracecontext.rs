@@ -0,0 +1,90 @@
+/// Hex digits of the trace id shown in narrow places; enough to find the trace
+/// by prefix in Jaeger or Tempo.
+const SHORT_TRACE_ID_LEN: usize = 8;
+
+/// Trace id from a W3C `traceparent` that sqlcommenter put in the query's
+/// comments: `/*traceparent='00-<trace id>-<parent id>-<flags>'*/`. None when
+/// there isn't one, or it isn't well-formed.
+///
+/// SQL over the parser's length limit has already been cut, and a comment at the
+/// end of it goes with the cut.
+pub fn trace_id(sql: &str) -> Option<String> {
+    let mut rest = sql;
+    while let Some(start) = rest.find("/*") {
+        let body = &rest[start + 2..];
+        let end = body.find("*/").unwrap_or(body.len());
+        if let Some(id) = body[..end].split(',').find_map(traceparent_field) {
+            return Some(id);
+        }
+        rest = &body[end..];
+    }
+    None
+}
+
+/// `traceparent='...'` from a sqlcommenter key/value list, as its trace id.
+fn traceparent_field(field: &str) -> Option<String> {
+    let (key, value) = field.split_once('=')?;
+    if key.trim() != "traceparent" {
+        return None;
+    }
+    let value = value.trim().trim_matches('\'');
+    // version-trace_id-parent_id-flags; version ff is invalid.
+    let parts: Vec<&str> = value.split('-').collect();
+    let [version, trace, parent, flags] = parts[..] else {
+        return None;
+    };
+    let hex = |s: &str, len: usize| {
+        s.len() == len
+            && s.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+    let zero = |s: &str| s.bytes().all(|b| b == b'0');
+    let valid = hex(version, 2)
+        && version != "ff"
+        && hex(trace, 32)
+        && !zero(trace)
+        && hex(parent, 16)
+        && !zero(parent)
+        && hex(flags, 2);
+    valid.then(|| trace.to_string())
+}
+
+/// Leading digits of a trace id, for the TUI.
+pub fn short_trace_id(trace_id: &str) -> &str {
+    &trace_id[..trace_id.len().min(SHORT_TRACE_ID_LEN)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_id_from_sqlcommenter_comment() {
+        let sql = "SELECT * FROM orders WHERE id = $1 /*action='show',controller='orders',\
+                   traceparent='00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01'*/";
+        assert_eq!(
+            trace_id(sql).as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(short_trace_id(&trace_id(sql).unwrap()), "4bf92f35");
+
+        // Found in any comment, not just the last one.
+        let leading = "/* traceparent='00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00' */ SELECT 1 /* app */";
+        assert_eq!(
+            trace_id(leading).as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+
+        for sql in [
+            "SELECT 1",
+            "SELECT 'traceparent=00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01'",
+            "SELECT 1 /*traceparent='00-00000000000000000000000000000000-00f067aa0ba902b7-01'*/",
+            "SELECT 1 /*traceparent='ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01'*/",
+            "SELECT 1 /*traceparent='00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01'*/",
+            "SELECT 1 /*traceparent='00-4bf92f3577b34da6-00f067aa0ba902b7-01'*/",
+            "SELECT 1 /*traceparent='00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba9",
+        ] {
+            assert_eq!(trace_id(sql), None, "{sql}");
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::OnceLock;
+
+/// What `truncate` appends to cut text, unless `--ellipsis` says otherwise.
+pub const DEFAULT_INDICATOR: &str = "…";
+
+static INDICATOR: OnceLock<String> = OnceLock::new();
+
+/// `--ellipsis`: set once at startup, before any text is truncated.
+pub fn set_indicator(indicator: &str) {
+    let _ = INDICATOR.set(indicator.to_string());
+}
+
+fn indicator() -> &'static str {
+    INDICATOR.get().map_or(DEFAULT_INDICATOR, String::as_str)
+}
+
+/// Shorten `s` to at most `max` characters, indicator included. Used for SQL
+/// in the parser, warnings in stats, and TUI columns alike.
+pub fn truncate(s: &str, max: usize) -> String {
+    truncate_with(s, max, indicator())
+}
+
+/// Counts characters rather than bytes, so a cut never splits a code point and
+/// the result fits `max` terminal cells for narrow scripts. An indicator longer
+/// than `max` is returned on its own.
+pub fn truncate_with(s: &str, max: usize, indicator: &str) -> String {
+    if s.char_indices().nth(max).is_none() {
+        return s.to_string();
+    }
+    let keep = max.saturating_sub(indicator.chars().count());
+    let end = s.char_indices().nth(keep).map_or(s.len(), |(i, _)| i);
+    format!("{}{indicator}", &s[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_at_exact_boundaries() {
+        assert_eq!(truncate_with("abcde", 5, "…"), "abcde");
+        assert_eq!(truncate_with("abcdef", 5, "…"), "abcd…");
+        assert_eq!(truncate_with("abcdef", 5, "..."), "ab...");
+        assert_eq!(truncate_with("", 0, "…"), "");
+        assert_eq!(truncate_with("ab", 1, "..."), "...");
+
+        // Multi-byte characters count once each and are never split.
+        assert_eq!(truncate_with("żółw", 4, "…"), "żółw");
+        assert_eq!(truncate_with("żółwie", 4, "…"), "żół…");
+        assert_eq!(truncate_with("😀😀😀", 2, "…"), "😀…");
+        assert_eq!(truncate_with("a😀b", 2, ".."), "..");
+
+        let cut = truncate_with(&"é".repeat(100), 10, DEFAULT_INDICATOR);
+        assert_eq!(cut.chars().count(), 10);
+        assert!(cut.ends_with('…'));
+    }
+}